@@ -1,12 +1,24 @@
+use chrono::{DateTime, Utc};
+
 use crate::{FilmInput, FilmOutput, StudentInput, StudentOutput};
-use models::{Film, Student};
+use models::{Film, StageHours, Student};
 
 impl From<FilmInput> for Film {
     fn from(f: FilmInput) -> Film {
+        // Films are due at the very start of their due date.
+        let due_date = f
+            .due
+            .map(|d| DateTime::<Utc>::from_utc(d.and_hms(0, 0, 0), Utc));
+        let stage_hours =
+            StageHours::new(f.ae_hours, f.editor_hours, f.sound_hours, f.finish_hours);
+
         Self {
             name: f.code,
             priority: f.priority,
             group_number: f.group,
+            class: f.class,
+            due_date,
+            stage_hours,
             ..Default::default()
         }
     }
@@ -1,6 +1,8 @@
-use color_eyre::Result;
+use color_eyre::{eyre::eyre, Result};
+use once_cell::sync::OnceCell;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::time::{Duration, Instant};
 
 mod convertors;
 mod structs;
@@ -8,6 +10,24 @@ mod structs;
 use reqwest::tls::Version;
 pub use structs::{FilmInput, FilmOutput, StudentInput, StudentOutput};
 
+/// Told about every call made to the Slack API: its method, how long it took, and whether it
+/// worked.
+pub type SlackObserver = fn(&str, Duration, bool);
+
+static SLACK_OBSERVER: OnceCell<SlackObserver> = OnceCell::new();
+
+/// Reports every Slack API call made from here to `observer`, e.g. to record metrics. Only the
+/// first observer set is kept.
+pub fn observe_slack(observer: SlackObserver) {
+    let _ = SLACK_OBSERVER.set(observer);
+}
+
+#[derive(Debug, Deserialize)]
+struct SlackResponse {
+    ok: bool,
+    error: Option<String>,
+}
+
 /// Read from a url into csv. This will error out if deserialization fails!
 pub async fn from_url<'a, T: for<'de> Deserialize<'de>>(url: &'a str) -> Result<Vec<T>> {
     let token = std::env::var("OAUTH_TOKEN")?;
@@ -22,6 +42,11 @@ pub async fn from_url<'a, T: for<'de> Deserialize<'de>>(url: &'a str) -> Result<
     let bytes = text.as_str();
     dbg!(bytes);
 
+    from_str(&text)
+}
+
+/// Read csv text into structs. This will error out if deserialization fails!
+pub fn from_str<T: for<'de> Deserialize<'de>>(text: &str) -> Result<Vec<T>> {
     let mut films = vec![];
     let mut rdr = csv::Reader::from_reader(text.as_bytes());
     for result in rdr.deserialize() {
@@ -60,14 +85,33 @@ pub async fn to_slack(csv: String, title: &str, msg: &str, channel: &str) -> Res
     let client = reqwest::Client::builder()
         .min_tls_version(version)
         .build()?;
-    client
+    let start = Instant::now();
+    let res = client
         .post(url)
         .bearer_auth(token)
         .form(&params)
         .send()
-        .await?;
+        .await;
+    // Slack answers a refused upload with a 200, so it's the body that says whether it worked.
+    let res = match res {
+        Ok(res) => res.json::<SlackResponse>().await,
+        Err(e) => Err(e),
+    };
+    if let Some(observe) = SLACK_OBSERVER.get() {
+        observe(
+            "files.upload",
+            start.elapsed(),
+            matches!(&res, Ok(r) if r.ok),
+        );
+    }
 
-    Ok(())
+    match res? {
+        SlackResponse { ok: true, .. } => Ok(()),
+        SlackResponse { error, .. } => Err(eyre!(
+            "Slack refused the upload: {}",
+            error.unwrap_or_default()
+        )),
+    }
 }
 
 #[cfg(test)]
@@ -96,6 +140,27 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_read_schedule() -> Result<()> {
+        let text = "CODE,GROUP,PRIORITY,DUE,AE_HOURS,EDITOR_HOURS,SOUND_HOURS,FINISH_HOURS
+a,1,HIGH,2022-05-20,24,48,,12
+b,2,LOW,,,,,";
+        let films: Vec<FilmInput> = from_str(text)?;
+
+        assert_eq!(Some("2022-05-20".parse()?), films[0].due);
+        assert_eq!(Some(48), films[0].editor_hours);
+        assert_eq!(None, films[0].sound_hours);
+        assert_eq!(None, films[1].due);
+
+        // Scheduling columns are optional.
+        let films: Vec<FilmInput> = from_str("CODE,GROUP,PRIORITY\nc,3,HIGH")?;
+        assert_eq!("", films[0].class);
+        assert_eq!(None, films[0].due);
+        assert_eq!(None, films[0].ae_hours);
+
+        Ok(())
+    }
+
     #[test]
     fn test_write() -> Result<()> {
         let s = StudentOutput {
@@ -1,3 +1,4 @@
+use chrono::NaiveDate;
 use models::Priority;
 use serde::{Deserialize, Serialize};
 
@@ -8,6 +9,19 @@ pub struct FilmInput {
     pub code: String,
     pub group: i32,
     pub priority: Priority,
+    // The remaining columns are optional, and may be left out of the csv entirely.
+    #[serde(default)]
+    pub class: String,
+    #[serde(default)]
+    pub due: Option<NaiveDate>,
+    #[serde(default)]
+    pub ae_hours: Option<i32>,
+    #[serde(default)]
+    pub editor_hours: Option<i32>,
+    #[serde(default)]
+    pub sound_hours: Option<i32>,
+    #[serde(default)]
+    pub finish_hours: Option<i32>,
 }
 
 #[derive(Serialize, Deserialize, Default)]
@@ -0,0 +1,41 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use strum::{AsRefStr, EnumString};
+use uuid::Uuid;
+
+use crate::Role;
+
+/// One student's turn working one stage of a film.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Assignment {
+    pub id: Uuid,
+    pub film_name: String,
+    pub role: Role,
+    pub student_id: Uuid,
+    pub slack_id: String,
+    pub student_name: String,
+    pub assigned_at: DateTime<Utc>,
+    /// When the student handed the film on, however that happened.
+    pub delivered_at: Option<DateTime<Utc>>,
+    /// How the assignment ended. Unset while the student is still working on it.
+    pub outcome: Option<Outcome>,
+    /// When the job the student was handed joined the jobs_q. Unset if the film didn't come from
+    /// the jobs_q, as when it's moved between students.
+    pub queued_at: Option<DateTime<Utc>>,
+    /// When the student joined the wait_q, if they were handed the film from it.
+    pub waiting_since: Option<DateTime<Utc>>,
+}
+
+#[derive(AsRefStr, EnumString, Debug, Clone, Copy)]
+#[strum(serialize_all = "SCREAMING_SNAKE_CASE")]
+#[derive(Deserialize, Serialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum Outcome {
+    Delivered,
+    /// Delivered, but the next stage sent it back to be fixed.
+    SentBack,
+    /// Handed back to the jobs queue, by the student or an admin.
+    Released,
+    /// Moved to another student by an admin.
+    Reassigned,
+}
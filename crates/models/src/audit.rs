@@ -0,0 +1,51 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use strum::{AsRefStr, EnumString};
+use uuid::Uuid;
+
+/// A single change to the bot's state, and who made it.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct AuditEntry {
+    pub id: Uuid,
+    pub action: AuditAction,
+    /// Slack id of whoever acted, or the part of the bot that acted on its own.
+    pub actor: String,
+    /// The Slack event or API call the change came from.
+    pub source: String,
+    /// Slack id of the student affected, if any.
+    pub student: Option<String>,
+    pub film: Option<String>,
+    pub before: Option<Value>,
+    pub after: Option<Value>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(AsRefStr, EnumString, Debug, Clone, Copy)]
+#[strum(serialize_all = "SCREAMING_SNAKE_CASE")]
+#[derive(Deserialize, Serialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum AuditAction {
+    /// A film was added to the jobs queue.
+    Enqueue,
+    /// A student asked for work and was given a film.
+    Assign,
+    Deliver,
+    /// A student asked for work and joined the wait queue.
+    Wait,
+    /// A waiting student was given a film.
+    Drain,
+    /// Films or students were imported from a CSV.
+    Import,
+    Cancel,
+    Drop,
+    SendBack,
+    /// A film was taken back after being held too long.
+    Reclaim,
+    // Admin overrides
+    Unassign,
+    Reassign,
+    ForceAssign,
+    /// An inconsistency found by the integrity check was repaired.
+    Repair,
+}
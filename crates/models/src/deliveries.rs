@@ -0,0 +1,27 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::Role;
+
+/// What a student handed on to the next stage when they delivered their work.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Delivery {
+    pub id: Uuid,
+    pub film_name: String,
+    /// The stage that was delivered.
+    pub role: Role,
+    /// Slack id of the student who delivered it.
+    pub student: String,
+    /// Project files, cuts, etc.
+    pub links: Vec<String>,
+    pub notes: String,
+    pub created_at: DateTime<Utc>,
+}
+
+impl Delivery {
+    /// Whether there's nothing to pass on to the next stage.
+    pub fn is_empty(&self) -> bool {
+        self.links.is_empty() && self.notes.is_empty()
+    }
+}
@@ -0,0 +1,15 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// A Slack event which has been acknowledged, but not handled yet.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct PendingEvent {
+    /// Slack's id for the event, which stays the same when Slack retries it.
+    pub id: String,
+    /// Slack id of whoever caused the event. Their events are handled one at a time, in order.
+    pub user: String,
+    /// The event as Slack sent it.
+    pub payload: Value,
+    pub created_at: DateTime<Utc>,
+}
@@ -1,9 +1,10 @@
 #![allow(dead_code)]
 
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
-use crate::{Priority, Role, Roles};
+use crate::{Priority, Role, Roles, StageHours};
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize, Serialize)]
 pub struct Film {
@@ -13,6 +14,12 @@ pub struct Film {
     pub priority: Priority,
     pub roles: Roles,
     pub group_number: i32,
+    pub class: String,
+    /// When the finished film is needed, e.g. its screening date.
+    pub due_date: Option<DateTime<Utc>>,
+    pub stage_hours: StageHours,
+    /// When the film joined the jobs queue for its current stage.
+    pub queued_at: Option<DateTime<Utc>>,
 }
 
 impl Film {
@@ -25,17 +32,35 @@ impl Film {
         }
     }
 
-    /// Increments role and returns it.
-    pub fn increment_role(&mut self) -> Role {
+    /// Records who worked the current role, then increments role and returns it.
+    pub fn increment_role(&mut self, student: &str) -> Role {
         self.roles
-            .complete_role(self.current_role, self.name.clone());
+            .complete_role(self.current_role, student.to_string());
         self.current_role = self.roles.get_next_role();
         self.current_role
     }
 
+    /// Sends the film back to an earlier role, which has to be worked again.
+    pub fn reopen(&mut self, role: Role) {
+        self.roles.reopen(role);
+        self.current_role = self.roles.get_next_role();
+    }
+
     pub fn get_next_role(&self) -> Role {
         self.roles.get_next_role()
     }
+
+    /// The latest time work on the current stage can start while still finishing every
+    /// remaining stage by the due date.
+    pub fn deadline(&self) -> Option<DateTime<Utc>> {
+        let remaining = self.stage_hours.remaining_from(self.current_role);
+        self.due_date.map(|due| due - remaining)
+    }
+
+    /// Whether starting the current stage at `now` would miss the due date.
+    pub fn is_at_risk(&self, now: DateTime<Utc>) -> bool {
+        self.deadline().map_or(false, |deadline| now > deadline)
+    }
 }
 
 impl Default for Film {
@@ -47,6 +72,10 @@ impl Default for Film {
             current_role: Role::Ae,
             roles: Roles::default(),
             group_number: 0,
+            class: "".to_string(),
+            due_date: None,
+            stage_hours: StageHours::default(),
+            queued_at: None,
         }
     }
 }
@@ -1,7 +1,17 @@
+pub mod assignments;
+pub mod audit;
+pub mod deliveries;
+pub mod events;
 pub mod films;
+pub mod revisions;
 pub mod shared;
 pub mod students;
 
-pub use crate::shared::{Priority, Role, Roles};
+pub use crate::shared::{Priority, Role, Roles, StageHours};
+pub use assignments::{Assignment, Outcome};
+pub use audit::{AuditAction, AuditEntry};
+pub use deliveries::Delivery;
+pub use events::PendingEvent;
 pub use films::Film;
+pub use revisions::Revision;
 pub use students::Student;
@@ -0,0 +1,21 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::Role;
+
+/// A film sent back to an earlier stage because that stage wasn't finished.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Revision {
+    pub id: Uuid,
+    pub film_name: String,
+    /// The stage being redone.
+    pub role: Role,
+    /// Slack id of the student who sent the film back, whose assignment is paused.
+    pub from: String,
+    /// Slack id of the student who worked the stage, and has to fix it.
+    pub to: String,
+    pub note: String,
+    pub created_at: DateTime<Utc>,
+    pub resolved_at: Option<DateTime<Utc>>,
+}
@@ -1,3 +1,4 @@
+use chrono::Duration;
 use serde::{Deserialize, Serialize};
 use strum::{AsRefStr, EnumString};
 
@@ -20,6 +21,16 @@ pub struct Roles {
     pub finish: Option<String>,
 }
 
+#[derive(Debug, Default, Clone, Copy, Deserialize, Serialize, Hash, PartialEq, Eq)]
+#[serde(rename_all = "UPPERCASE")]
+/// Target number of hours each post-production stage should take.
+pub struct StageHours {
+    pub ae: Option<i32>,
+    pub editor: Option<i32>,
+    pub sound: Option<i32>,
+    pub finish: Option<i32>,
+}
+
 #[derive(AsRefStr, EnumString, Debug, Clone, Copy)]
 #[strum(serialize_all = "UPPERCASE")]
 #[derive(Deserialize, Serialize, PartialEq, Eq, Hash, PartialOrd, Ord)]
@@ -69,6 +80,28 @@ impl Roles {
         }
     }
 
+    /// Who, or what, worked the given role.
+    pub fn get(&self, role: Role) -> Option<&str> {
+        match role {
+            Role::Ae => self.ae.as_deref(),
+            Role::Editor => self.editor.as_deref(),
+            Role::Sound => self.sound.as_deref(),
+            Role::Finish => self.finish.as_deref(),
+            Role::Done => None,
+        }
+    }
+
+    /// Marks a role as not yet worked, so it has to be done again.
+    pub fn reopen(&mut self, role: Role) {
+        match role {
+            Role::Ae => self.ae = None,
+            Role::Editor => self.editor = None,
+            Role::Sound => self.sound = None,
+            Role::Finish => self.finish = None,
+            Role::Done => {}
+        }
+    }
+
     pub fn complete_role(&mut self, role: Role, film: String) {
         match role {
             Role::Ae => self.ae = Some(film),
@@ -79,3 +112,49 @@ impl Roles {
         }
     }
 }
+
+impl Role {
+    /// The stage worked before this one, if any.
+    pub fn prev(self) -> Option<Role> {
+        match self {
+            Role::Ae => None,
+            Role::Editor => Some(Role::Ae),
+            Role::Sound => Some(Role::Editor),
+            Role::Finish => Some(Role::Sound),
+            Role::Done => Some(Role::Finish),
+        }
+    }
+}
+
+impl StageHours {
+    #[rustfmt::skip]
+    pub fn new(
+        ae: Option<i32>,
+        editor: Option<i32>,
+        sound: Option<i32>,
+        finish: Option<i32>,
+    ) -> StageHours {
+        StageHours { ae, editor, sound, finish }
+    }
+
+    pub fn get(&self, role: Role) -> Option<i32> {
+        match role {
+            Role::Ae => self.ae,
+            Role::Editor => self.editor,
+            Role::Sound => self.sound,
+            Role::Finish => self.finish,
+            Role::Done => None,
+        }
+    }
+
+    /// Total target time for the given stage and every stage after it.
+    /// Stages without a target don't count towards the total.
+    pub fn remaining_from(&self, role: Role) -> Duration {
+        let hours: i32 = [Role::Ae, Role::Editor, Role::Sound, Role::Finish]
+            .into_iter()
+            .filter(|&r| r >= role)
+            .filter_map(|r| self.get(r))
+            .sum();
+        Duration::hours(hours.into())
+    }
+}
@@ -1,3 +1,4 @@
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
@@ -13,13 +14,19 @@ pub struct Student {
     pub roles: Roles,
     pub group_number: i32,
     pub class: String,
+    /// When the student was handed their current film.
+    pub assigned_at: Option<DateTime<Utc>>,
+    /// How many reminders or escalations have gone out for the current film.
+    pub escalation: i32,
+    /// Whether the current film was sent back to an earlier stage, and is waiting on a fix.
+    pub paused: bool,
 }
 
 impl Student {
-    /// Increments role and returns it.
-    pub fn increment_role(&mut self) -> Role {
+    /// Records which film the current role was worked on, then increments role and returns it.
+    pub fn increment_role(&mut self, film: &str) -> Role {
         self.roles
-            .complete_role(self.current_role, self.name.clone());
+            .complete_role(self.current_role, film.to_string());
         self.current_role = self.roles.get_next_role();
         self.current_role
     }
@@ -27,6 +34,28 @@ impl Student {
     pub fn get_next_role(&self) -> Role {
         self.roles.get_next_role()
     }
+
+    /// Hands the student a film to work on.
+    pub fn assign(&mut self, film: &str, now: DateTime<Utc>) {
+        self.current_film = Some(film.to_string());
+        self.assigned_at = Some(now);
+        self.escalation = 0;
+    }
+
+    /// Clears the student's current film.
+    pub fn unassign(&mut self) {
+        self.current_film = None;
+        self.assigned_at = None;
+        self.escalation = 0;
+        self.paused = false;
+    }
+
+    /// Picks the current film back up once it's been fixed, restarting the clock on it.
+    pub fn resume(&mut self, now: DateTime<Utc>) {
+        self.paused = false;
+        self.assigned_at = Some(now);
+        self.escalation = 0;
+    }
 }
 
 impl Default for Student {
@@ -40,6 +69,9 @@ impl Default for Student {
             roles: Roles::default(),
             group_number: 0,
             class: "".to_string(),
+            assigned_at: None,
+            escalation: 0,
+            paused: false,
         }
     }
 }
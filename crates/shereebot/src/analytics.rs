@@ -0,0 +1,373 @@
+//! Turnaround and throughput statistics, to find the slowest stage and the slowest students.
+//!
+//! Times come from the assignment history, which keeps when each stage joined the jobs_q and when
+//! its student joined the wait_q. Anything still in progress, like a film sitting in the jobs_q or
+//! a student holding a film, counts up to now.
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use strum::AsRefStr;
+
+use crate::{queue::QueueItem, store::Database, utils::hours, Result};
+use models::{Assignment, Film, Outcome, Role, Student};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, AsRefStr)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum Metric {
+    /// Hours a film's stage sat in the jobs_q before someone picked it up. Grouped by the film's
+    /// group and class.
+    JobsQueue,
+    /// Hours a student held a film before handing it on. Grouped by the student's group and
+    /// class, as are the rest.
+    Held,
+    /// Hours a student sat in the wait_q before being given a film.
+    WaitQueue,
+    /// Stages delivered per day.
+    Throughput,
+}
+
+/// What a row's figures are broken down by.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Key {
+    Role(Role),
+    Group(i32),
+    Class(String),
+    /// A student's slack id.
+    Student(String),
+}
+
+impl Key {
+    fn dimension(&self) -> &'static str {
+        match self {
+            Self::Role(_) => "role",
+            Self::Group(_) => "group",
+            Self::Class(_) => "class",
+            Self::Student(_) => "student",
+        }
+    }
+}
+
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Role(role) => write!(f, "{}", role.as_ref()),
+            Self::Group(group) => write!(f, "{group}"),
+            Self::Class(s) | Self::Student(s) => write!(f, "{s}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Row {
+    pub metric: Metric,
+    pub by: String,
+    pub key: String,
+    pub count: usize,
+    pub mean_hours: Option<f64>,
+    pub median_hours: Option<f64>,
+    pub p90_hours: Option<f64>,
+    /// Deliveries per day since the first assignment. Only set for throughput.
+    pub per_day: Option<f64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Report {
+    pub generated_at: DateTime<Utc>,
+    /// When the first film was handed out, which throughput is measured from.
+    pub since: Option<DateTime<Utc>>,
+    pub rows: Vec<Row>,
+}
+
+impl Report {
+    /// Every row as CSV, with a header.
+    pub fn to_csv(&self) -> color_eyre::Result<String> {
+        csv_parser::to_csv_string(self.rows.clone())
+    }
+}
+
+/// Everything the statistics are worked out from.
+struct Data {
+    films: Vec<Film>,
+    students: Vec<Student>,
+    history: Vec<Assignment>,
+    jobs: Vec<QueueItem>,
+    waiters: Vec<QueueItem>,
+}
+
+/// Works out every statistic from the database as it stands.
+#[tracing::instrument(skip_all)]
+pub async fn analyse(db: &Database) -> Result<Report> {
+    let data = Data {
+        films: db.list_films().await?,
+        students: db.list_students().await?,
+        history: db.list_assignments().await?,
+        jobs: db.get_queue(false).await?,
+        waiters: db.get_queue(true).await?,
+    };
+    Ok(report(&data, Utc::now()))
+}
+
+/// Who or what a figure is about, along with everything it can be broken down by.
+struct Subject {
+    role: Role,
+    group: i32,
+    class: String,
+    student: Option<String>,
+}
+
+impl Subject {
+    fn keys(&self) -> Vec<Key> {
+        let mut keys = vec![
+            Key::Role(self.role),
+            Key::Group(self.group),
+            Key::Class(self.class.clone()),
+        ];
+        keys.extend(self.student.clone().map(Key::Student));
+        keys
+    }
+}
+
+#[derive(Default)]
+struct Tally {
+    /// Hours each timed measurement took.
+    times: Vec<(Metric, Subject, f64)>,
+    /// One for every stage delivered.
+    deliveries: Vec<Subject>,
+}
+
+fn report(data: &Data, now: DateTime<Utc>) -> Report {
+    let tally = tally(data, now);
+    let since = data.history.iter().map(|a| a.assigned_at).min();
+    let days = since.map_or(1.0, |since| (hours(now - since) / 24.0).max(1.0));
+
+    let mut times: BTreeMap<(Metric, Key), Vec<f64>> = BTreeMap::new();
+    for (metric, subject, hours) in &tally.times {
+        for key in subject.keys() {
+            times.entry((*metric, key)).or_default().push(*hours);
+        }
+    }
+    let mut deliveries: BTreeMap<Key, usize> = BTreeMap::new();
+    for subject in &tally.deliveries {
+        for key in subject.keys() {
+            *deliveries.entry(key).or_default() += 1;
+        }
+    }
+
+    let timed = times.into_iter().map(|((metric, key), mut hours)| {
+        hours.sort_by(|a, b| a.partial_cmp(b).expect("hours are never NaN"));
+        let stat = |f: fn(&[f64]) -> f64| Some(round(f(&hours)));
+        Row {
+            metric,
+            by: key.dimension().to_string(),
+            key: key.to_string(),
+            count: hours.len(),
+            mean_hours: stat(|h| h.iter().sum::<f64>() / h.len() as f64),
+            median_hours: stat(|h| percentile(h, 0.5)),
+            p90_hours: stat(|h| percentile(h, 0.9)),
+            per_day: None,
+        }
+    });
+    let throughput = deliveries.into_iter().map(|(key, count)| Row {
+        metric: Metric::Throughput,
+        by: key.dimension().to_string(),
+        key: key.to_string(),
+        count,
+        mean_hours: None,
+        median_hours: None,
+        p90_hours: None,
+        per_day: Some(round(count as f64 / days)),
+    });
+
+    Report {
+        generated_at: now,
+        since,
+        rows: timed.chain(throughput).collect(),
+    }
+}
+
+fn tally(data: &Data, now: DateTime<Utc>) -> Tally {
+    let films: HashMap<_, _> = data.films.iter().map(|f| (f.name.as_str(), f)).collect();
+    let students: HashMap<_, _> = data
+        .students
+        .iter()
+        .map(|s| (s.slack_id.as_str(), s))
+        .collect();
+    let by_film = |film: &str, role| {
+        let film = films.get(film)?;
+        Some(Subject {
+            role,
+            group: film.group_number,
+            class: film.class.clone(),
+            student: None,
+        })
+    };
+    let by_student = |slack_id: &str, role| {
+        let student = students.get(slack_id)?;
+        Some(Subject {
+            role,
+            group: student.group_number,
+            class: student.class.clone(),
+            student: Some(slack_id.to_string()),
+        })
+    };
+    let since = |start: DateTime<Utc>, end: DateTime<Utc>| hours(end - start).max(0.0);
+
+    let mut tally = Tally::default();
+    let mut time = |metric, subject: Option<Subject>, hours| {
+        if let Some(subject) = subject {
+            tally.times.push((metric, subject, hours));
+        }
+    };
+
+    for a in &data.history {
+        let held = since(a.assigned_at, a.delivered_at.unwrap_or(now));
+        time(Metric::Held, by_student(&a.slack_id, a.role), held);
+        // Reassignments skip the jobs_q, so have no queued_at.
+        if let Some(queued) = a.queued_at {
+            let waited = since(queued, a.assigned_at);
+            time(Metric::JobsQueue, by_film(&a.film_name, a.role), waited);
+        }
+        if let Some(waiting) = a.waiting_since {
+            let waited = since(waiting, a.assigned_at);
+            time(Metric::WaitQueue, by_student(&a.slack_id, a.role), waited);
+        }
+    }
+    for job in &data.jobs {
+        let waited = since(job.created_at, now);
+        time(Metric::JobsQueue, by_film(&job.film_name, job.role), waited);
+    }
+    for w in &data.waiters {
+        let waited = since(w.created_at, now);
+        time(
+            Metric::WaitQueue,
+            by_student(&w.student_slack_id, w.role),
+            waited,
+        );
+    }
+
+    tally.deliveries = data
+        .history
+        .iter()
+        .filter(|a| matches!(a.outcome, Some(Outcome::Delivered | Outcome::SentBack)))
+        .filter_map(|a| by_student(&a.slack_id, a.role))
+        .collect();
+    tally
+}
+
+/// Nearest-rank percentile of sorted values.
+fn percentile(sorted: &[f64], p: f64) -> f64 {
+    let rank = (p * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+fn round(x: f64) -> f64 {
+    (x * 100.0).round() / 100.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::queue;
+    use chrono::Duration;
+    use models::Priority;
+    use uuid::Uuid;
+
+    fn assignment(student: &str, role: Role, start: i64, end: Option<i64>) -> Assignment {
+        let now = now();
+        Assignment {
+            id: Uuid::new_v4(),
+            film_name: "a".to_string(),
+            role,
+            student_id: Uuid::nil(),
+            slack_id: student.to_string(),
+            student_name: student.to_string(),
+            assigned_at: now - Duration::hours(start),
+            delivered_at: end.map(|h| now - Duration::hours(h)),
+            outcome: end.map(|_| Outcome::Delivered),
+            queued_at: None,
+            waiting_since: None,
+        }
+    }
+
+    // Every timestamp is taken relative to the same time, so hours come out whole.
+    fn now() -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339("2022-05-02T12:00:00Z")
+            .unwrap()
+            .into()
+    }
+
+    fn row<'a>(report: &'a Report, metric: Metric, key: &str) -> &'a Row {
+        let row = report
+            .rows
+            .iter()
+            .find(|r| r.metric == metric && r.key == key);
+        row.unwrap_or_else(|| panic!("no {metric:?} row for {key}"))
+    }
+
+    #[test]
+    fn check_report() {
+        let mut film = Film::new("a", Priority::High, 1);
+        film.class = "x".to_string();
+        let students: Vec<_> = ["U1", "U2"]
+            .iter()
+            .map(|id| Student {
+                slack_id: id.to_string(),
+                group_number: 2,
+                class: "x".to_string(),
+                ..Default::default()
+            })
+            .collect();
+
+        let mut job = queue::new_job(&Film::new("b", Priority::High, 1), "");
+        job.created_at = now() - Duration::hours(4);
+
+        // Queued 48 hours ago, U1 took 10 hours on AE and U2, who asked for work 30 hours ago,
+        // has been editing for 20.
+        let data = Data {
+            jobs: vec![job],
+            films: vec![film, Film::new("b", Priority::High, 1)],
+            students,
+            history: vec![
+                Assignment {
+                    queued_at: Some(now() - Duration::hours(48)),
+                    ..assignment("U1", Role::Ae, 46, Some(36))
+                },
+                Assignment {
+                    queued_at: Some(now() - Duration::hours(36)),
+                    waiting_since: Some(now() - Duration::hours(30)),
+                    ..assignment("U2", Role::Editor, 20, None)
+                },
+            ],
+            waiters: vec![],
+        };
+        let report = report(&data, now());
+
+        let held = row(&report, Metric::Held, "x");
+        assert_eq!((2, Some(15.0)), (held.count, held.mean_hours));
+        assert_eq!(Some(20.0), row(&report, Metric::Held, "U2").p90_hours);
+
+        // AE waited 2 hours, editing 16, and `b` has been waiting for 4.
+        let queued = row(&report, Metric::JobsQueue, "AE");
+        assert_eq!((2, Some(3.0)), (queued.count, queued.mean_hours));
+        assert_eq!(
+            (Some(2.0), Some(4.0)),
+            (queued.median_hours, queued.p90_hours)
+        );
+        assert_eq!(
+            Some(16.0),
+            row(&report, Metric::JobsQueue, "EDITOR").mean_hours
+        );
+        assert_eq!(Some(10.0), row(&report, Metric::WaitQueue, "U2").mean_hours);
+
+        let throughput = row(&report, Metric::Throughput, "AE");
+        assert_eq!((1, None), (throughput.count, throughput.mean_hours));
+        assert_eq!(Some(0.52), throughput.per_day);
+
+        let csv = report.to_csv().unwrap();
+        assert!(csv.starts_with("metric,by,key,count,mean_hours"));
+        assert!(csv.contains("throughput,role,AE,1,,,,0.52"));
+    }
+}
@@ -0,0 +1,102 @@
+//! Records who changed what in the audit_log.
+use chrono::Utc;
+use serde::Serialize;
+use serde_json::Value;
+use tracing::error;
+use uuid::Uuid;
+
+use crate::server::State;
+use models::{AuditAction, AuditEntry};
+
+/// Who asked for a change, and through which Slack event or API call.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Origin {
+    pub(crate) actor: String,
+    pub(crate) source: String,
+}
+
+impl Origin {
+    /// A change requested by a Slack user.
+    pub(crate) fn slack(user: &str, event_id: &str) -> Self {
+        Self {
+            actor: user.to_string(),
+            source: format!("slack:{event_id}"),
+        }
+    }
+
+    /// A change requested through the admin API.
+    pub(crate) fn api(call: &str) -> Self {
+        Self {
+            actor: "admin-api".to_string(),
+            source: format!("api:{call}"),
+        }
+    }
+
+    /// A change made with the admin CLI, by whoever's logged in.
+    pub(crate) fn cli(command: &str) -> Self {
+        let user = std::env::var("USER").unwrap_or_else(|_| "unknown".to_string());
+        Self {
+            actor: format!("cli:{user}"),
+            source: format!("cli:{command}"),
+        }
+    }
+
+    /// A change the bot made on its own, e.g. the scheduler reclaiming a film.
+    pub(crate) fn system(task: &str) -> Self {
+        Self {
+            actor: "shereebot".to_string(),
+            source: format!("system:{task}"),
+        }
+    }
+
+    /// Starts an audit entry for an action taken on behalf of this origin.
+    pub(crate) fn entry(
+        &self,
+        action: AuditAction,
+        student: Option<&str>,
+        film: Option<&str>,
+    ) -> AuditEntry {
+        AuditEntry {
+            id: Uuid::new_v4(),
+            action,
+            actor: self.actor.clone(),
+            source: self.source.clone(),
+            student: student.map(str::to_string),
+            film: film.map(str::to_string),
+            before: None,
+            after: None,
+            created_at: Utc::now(),
+        }
+    }
+}
+
+/// Appends an entry to the audit log, for things like imports that aren't a single change.
+/// Changes to films and students are audited by the store, in the same transaction as the change.
+/// Failures are only logged, as whatever's being audited has already happened.
+pub(crate) async fn record(state: &State, entry: AuditEntry) {
+    if let Err(e) = state.db.insert_audit(&entry).await {
+        error!("Failed to audit {entry:?}: {e}");
+    }
+}
+
+/// Serializes a value for the audit log's before/after columns.
+pub(crate) fn snapshot<T: Serialize>(value: &T) -> Option<Value> {
+    serde_json::to_value(value).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_entry() {
+        let entry = Origin::slack("U1", "Ev1").entry(AuditAction::Drop, Some("U1"), Some("a"));
+        assert_eq!("U1", entry.actor);
+        assert_eq!("slack:Ev1", entry.source);
+        assert_eq!(Some("a".to_string()), entry.film);
+
+        let entry = Origin::api("POST /admin/unassign").entry(AuditAction::Unassign, None, None);
+        assert_eq!("admin-api", entry.actor);
+        assert_eq!("api:POST /admin/unassign", entry.source);
+    }
+}
@@ -0,0 +1,19 @@
+use clap::Parser;
+use color_eyre::Result;
+
+use shbot::{
+    cli::{self, Args},
+    config,
+};
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    dotenv::dotenv().ok();
+    // Logs would get mixed in with the output, so only errors are reported.
+    color_eyre::install()?;
+
+    let args = Args::parse();
+    let cfg = config::new()?;
+
+    cli::run(args, &cfg).await
+}
@@ -0,0 +1,613 @@
+//! Admin CLI for operating the bot straight from its database, without going through Slack.
+//!
+//! The bot keeps its queues in memory, so it only sees changes made here once it's restarted.
+//! Unassigning and reassigning can't wait for that, so they go through the bot's admin API.
+use std::{fs, path::PathBuf, str::FromStr};
+
+use clap::{ArgEnum, Parser, Subcommand};
+use color_eyre::{eyre::eyre, Result};
+use csv_parser::{FilmInput, FilmOutput, StudentInput, StudentOutput};
+use serde::Serialize;
+
+use crate::{
+    analytics,
+    audit::Origin,
+    config::Config,
+    feasibility, forecast, integrity,
+    queue::Queue,
+    sim,
+    store::{self, Database},
+};
+use models::{AuditAction, AuditEntry, Film, Role, Student};
+
+#[derive(Debug, Parser)]
+#[clap(
+    name = "shbot-admin",
+    about = "Operates ShereeBot without going through Slack"
+)]
+pub struct Args {
+    /// Print JSON instead of tables.
+    #[clap(long, global = true)]
+    json: bool,
+    /// Where the running bot is, for commands which go through its admin API.
+    /// Defaults to SERVER_PORT on localhost.
+    #[clap(long, global = true)]
+    server: Option<String>,
+    #[clap(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    #[clap(flatten)]
+    Db(DbCommand),
+    /// Plays out a term with a made up cohort, entirely in memory. Doesn't touch the database.
+    Simulate {
+        #[clap(long, default_value = "12")]
+        students: usize,
+        #[clap(long, default_value = "3")]
+        groups: usize,
+        #[clap(long, default_value = "12")]
+        films: usize,
+        /// The same seed always plays out the same way.
+        #[clap(long, default_value = "0")]
+        seed: u64,
+    },
+}
+
+/// Commands which work on the configured database.
+#[derive(Debug, Subcommand)]
+enum DbCommand {
+    /// Lists films.
+    Films {
+        #[clap(long)]
+        class: Option<String>,
+        #[clap(long)]
+        group: Option<i32>,
+        /// Only films waiting on this stage.
+        #[clap(long, parse(try_from_str = parse_role))]
+        role: Option<Role>,
+    },
+    /// Lists students.
+    Students {
+        #[clap(long)]
+        class: Option<String>,
+        #[clap(long)]
+        group: Option<i32>,
+        /// Only students working this role.
+        #[clap(long, parse(try_from_str = parse_role))]
+        role: Option<Role>,
+        /// Only students currently working on a film.
+        #[clap(long)]
+        assigned: bool,
+    },
+    /// Lists the jobs queue in order, or the wait queue.
+    Queue {
+        #[clap(long)]
+        wait: bool,
+    },
+    /// Imports films or students from a CSV file.
+    Import {
+        #[clap(arg_enum)]
+        kind: Kind,
+        path: PathBuf,
+    },
+    /// Exports films or students as CSV, to a file or stdout.
+    Export {
+        #[clap(arg_enum)]
+        kind: Kind,
+        path: Option<PathBuf>,
+    },
+    /// Returns a student's film to the jobs queue, through the running bot.
+    Unassign {
+        /// Slack id of the student.
+        student: String,
+    },
+    /// Moves a film to a student who's eligible to work on it, through the running bot.
+    Reassign {
+        film: String,
+        /// Slack id of the student.
+        student: String,
+        /// Give them the film even if they aren't eligible.
+        #[clap(long)]
+        force: bool,
+    },
+    /// Shows which job a student would get if they requested work now, and why. Changes nothing.
+    DryRun {
+        /// Slack id of the student.
+        student: String,
+    },
+    /// Looks for inconsistencies in the database.
+    Check {
+        /// Fix whatever can be fixed safely.
+        #[clap(long)]
+        repair: bool,
+    },
+    /// Predicts whether every student can finish every role with the films there are.
+    Feasibility,
+    /// Estimates when every film and class will be finished.
+    Forecast,
+    /// How long jobs spend queued, held and waiting, and throughput per day, as CSV.
+    Analytics,
+    /// Brings the database schema up to date.
+    Migrate,
+}
+
+#[derive(Debug, Clone, Copy, ArgEnum)]
+enum Kind {
+    Films,
+    Students,
+}
+
+/// Makes a change through the running bot's admin API, so its in-memory queues don't fall out
+/// of step with the database.
+async fn admin_api(
+    cfg: &Config,
+    server: Option<String>,
+    path: &str,
+    body: serde_json::Value,
+) -> Result<String> {
+    let token = cfg
+        .admin_token
+        .as_ref()
+        .ok_or_else(|| eyre!("ADMIN_API_TOKEN has to be set to change assignments"))?;
+    let server = server.unwrap_or_else(|| format!("http://localhost:{}", cfg.server.port));
+    let url = format!("{}/admin/{path}", server.trim_end_matches('/'));
+
+    let res = reqwest::Client::new()
+        .post(&url)
+        .bearer_auth(token)
+        .json(&body)
+        .send()
+        .await?;
+    let status = res.status();
+    if !status.is_success() {
+        return Err(eyre!("{url} responded with {status}"));
+    }
+    // Errors come back as a 200, to keep Slack happy.
+    let res: serde_json::Value = res.json().await?;
+    match (res["message"].as_str(), res["error"].as_str()) {
+        (Some(msg), _) => Ok(msg.to_string()),
+        (None, Some(e)) => Err(eyre!("{e}")),
+        (None, None) => Err(eyre!("{url} responded with {res}")),
+    }
+}
+
+fn parse_role(s: &str) -> std::result::Result<Role, strum::ParseError> {
+    Role::from_str(&s.to_uppercase())
+}
+
+/// Runs a single admin command.
+pub async fn run(args: Args, cfg: &Config) -> Result<()> {
+    match args.command {
+        Command::Db(command) => run_db(command, args.json, args.server, cfg).await,
+        Command::Simulate {
+            students,
+            groups,
+            films,
+            seed,
+        } => simulate(args.json, students, groups, films, seed).await,
+    }
+}
+
+/// Runs a single admin command against the configured database.
+async fn run_db(
+    command: DbCommand,
+    json: bool,
+    server: Option<String>,
+    cfg: &Config,
+) -> Result<()> {
+    let db = store::connect(cfg)?;
+
+    match command {
+        DbCommand::Films { class, group, role } => {
+            let films: Vec<_> = db
+                .list_films()
+                .await?
+                .into_iter()
+                .filter(|f| class.as_ref().map_or(true, |c| &f.class == c))
+                .filter(|f| group.map_or(true, |g| f.group_number == g))
+                .filter(|f| role.map_or(true, |r| f.current_role == r))
+                .collect();
+            print_films(json, &films)?;
+        }
+        DbCommand::Students {
+            class,
+            group,
+            role,
+            assigned,
+        } => {
+            let students: Vec<_> = db
+                .list_students()
+                .await?
+                .into_iter()
+                .filter(|s| class.as_ref().map_or(true, |c| &s.class == c))
+                .filter(|s| group.map_or(true, |g| s.group_number == g))
+                .filter(|s| role.map_or(true, |r| s.current_role == r))
+                .filter(|s| !assigned || s.current_film.is_some())
+                .collect();
+            print_students(json, &students)?;
+        }
+        DbCommand::Queue { wait } => {
+            let headers = ["ID", "STUDENT", "FILM", "ROLE", "PRIORITY", "QUEUED"];
+            if wait {
+                let mut waiters = db.get_queue(true).await?;
+                waiters.sort_by_key(|w| w.created_at);
+                print(json, &waiters, &headers, |w| {
+                    vec![
+                        w.id.to_string(),
+                        w.student_slack_id.clone(),
+                        w.film_name.clone(),
+                        w.role.as_ref().to_string(),
+                        opt(w.priority.map(|p| p.as_ref().to_string())),
+                        w.created_at.format("%Y-%m-%d %H:%M").to_string(),
+                    ]
+                })?;
+            } else {
+                let jobs = Queue::from_db(db.clone(), &cfg.queue)
+                    .await?
+                    .list_jobs()
+                    .await;
+                let headers = [&headers[..], &["AT RISK"]].concat();
+                print(json, &jobs, &headers, |j| {
+                    let (job, at_risk) = (&j.job, if j.at_risk { "yes" } else { "" });
+                    vec![
+                        job.id.to_string(),
+                        job.student_slack_id.clone(),
+                        job.film_name.clone(),
+                        job.role.as_ref().to_string(),
+                        opt(job.effective_priority.map(|p| p.as_ref().to_string())),
+                        job.created_at.format("%Y-%m-%d %H:%M").to_string(),
+                        at_risk.to_string(),
+                    ]
+                })?;
+            }
+        }
+        DbCommand::Import { kind, path } => {
+            let text = fs::read_to_string(&path)?;
+            let origin = Origin::cli("import");
+            let import = match kind {
+                Kind::Films => import_films(&db, cfg, &origin, &text).await?,
+                Kind::Students => import_students(&db, &text).await?,
+            };
+
+            let mut entry = origin.entry(AuditAction::Import, None, None);
+            let (file, msg) = (path.display().to_string(), import.to_string());
+            entry.after = Some(serde_json::json!({ "file": file, "result": msg }));
+            record(&db, entry).await;
+            println!("{msg}");
+            if !import.failed.is_empty() {
+                return Err(eyre!("{} row(s) failed to import", import.failed.len()));
+            }
+        }
+        DbCommand::Export { kind, path } => {
+            let csv = match kind {
+                Kind::Films => {
+                    let films = db.list_films().await?.into_iter();
+                    csv_parser::to_csv_string(films.map(FilmOutput::from).collect())?
+                }
+                Kind::Students => {
+                    let students = db.list_students().await?.into_iter();
+                    csv_parser::to_csv_string(students.map(StudentOutput::from).collect())?
+                }
+            };
+            match path {
+                Some(path) => fs::write(path, csv)?,
+                None => print!("{csv}"),
+            }
+        }
+        DbCommand::Unassign { student } => {
+            let body = serde_json::json!({ "student": student });
+            println!("{}", admin_api(cfg, server, "unassign", body).await?);
+        }
+        DbCommand::Reassign {
+            film,
+            student,
+            force,
+        } => {
+            let path = if force { "force-assign" } else { "reassign" };
+            let body = serde_json::json!({ "film": film, "student": student });
+            println!("{}", admin_api(cfg, server, path, body).await?);
+        }
+        DbCommand::DryRun { student } => {
+            let queue = Queue::from_db(db.clone(), &cfg.queue).await?;
+            let dry_run = queue.dry_run(&student).await?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&dry_run)?);
+            } else {
+                println!("{dry_run}");
+            }
+        }
+        DbCommand::Check { repair } => {
+            let origin = Origin::cli("check");
+            let entry = repair.then(|| origin.entry(AuditAction::Repair, None, None));
+            let report = integrity::check(&db, entry.as_ref()).await?;
+
+            if json {
+                println!("{}", serde_json::to_string_pretty(&report)?);
+            } else {
+                println!("{report}");
+            }
+        }
+        DbCommand::Feasibility => {
+            let report = feasibility::check(&db).await?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&report)?);
+            } else {
+                println!("{report}");
+            }
+        }
+        DbCommand::Forecast => {
+            let queue = Queue::from_db(db.clone(), &cfg.queue).await?;
+            let forecast = forecast::forecast(&db, &queue).await?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&forecast)?);
+            } else {
+                println!("{forecast}");
+            }
+        }
+        DbCommand::Analytics => {
+            let report = analytics::analyse(&db).await?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&report)?);
+            } else {
+                print!("{}", report.to_csv()?);
+            }
+        }
+        DbCommand::Migrate => {
+            db.migrate().await?;
+            println!("Schema is up to date.");
+        }
+    }
+
+    Ok(())
+}
+
+async fn simulate(
+    json: bool,
+    students: usize,
+    groups: usize,
+    films: usize,
+    seed: u64,
+) -> Result<()> {
+    let scenario = sim::Scenario {
+        students,
+        groups,
+        films,
+        seed,
+        ..Default::default()
+    };
+    let report = sim::run(scenario).await?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        println!("{report}");
+    }
+    Ok(())
+}
+
+async fn import_films(db: &Database, cfg: &Config, origin: &Origin, text: &str) -> Result<Import> {
+    let films: Vec<Film> = csv_parser::from_str::<FilmInput>(text)?
+        .into_iter()
+        .map(Into::into)
+        .collect();
+    let queue = Queue::from_db(db.clone(), &cfg.queue).await?;
+
+    let mut import = Import::new("film");
+    for film in films {
+        match db.insert_film(&film).await {
+            Ok(f) => {
+                queue.insert_job(&f, "", origin).await?;
+                import.inserted.push(f.name);
+            }
+            Err(e) => import.add_err(film.name, e),
+        }
+    }
+    Ok(import)
+}
+
+async fn import_students(db: &Database, text: &str) -> Result<Import> {
+    let students: Vec<Student> = csv_parser::from_str::<StudentInput>(text)?
+        .into_iter()
+        .map(Into::into)
+        .collect();
+
+    let mut import = Import::new("student");
+    for s in students {
+        match db
+            .insert_student_from_csv(&s.name, s.group_number, &s.class)
+            .await
+        {
+            Ok(s) => import.inserted.push(s.name),
+            Err(e) => import.add_err(s.name, e),
+        }
+    }
+    Ok(import)
+}
+
+/// What happened to each row of an imported CSV.
+struct Import {
+    kind: &'static str,
+    inserted: Vec<String>,
+    /// Rows which were already in the database.
+    skipped: Vec<String>,
+    /// Rows which couldn't be inserted for any other reason, with why.
+    failed: Vec<String>,
+}
+
+impl Import {
+    fn new(kind: &'static str) -> Self {
+        Self {
+            kind,
+            inserted: vec![],
+            skipped: vec![],
+            failed: vec![],
+        }
+    }
+
+    /// Only duplicates are skipped. Anything else is a failure the admin needs to see.
+    fn add_err(&mut self, name: String, e: crate::Error) {
+        match e {
+            crate::Error::Duplicate(_) => self.skipped.push(name),
+            e => self.failed.push(format!("{name} ({e})")),
+        }
+    }
+}
+
+impl std::fmt::Display for Import {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Inserted {} {}(s)", self.inserted.len(), self.kind)?;
+        if !self.skipped.is_empty() {
+            let (n, names) = (self.skipped.len(), self.skipped.join(", "));
+            write!(f, ", skipped {n} duplicate(s): {names}")?;
+        }
+        if !self.failed.is_empty() {
+            let (n, rows) = (self.failed.len(), self.failed.join(", "));
+            write!(f, ", failed to insert {n}: {rows}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Appends to the audit log, warning rather than failing as the change has already been made.
+async fn record(db: &Database, entry: AuditEntry) {
+    if let Err(e) = db.insert_audit(&entry).await {
+        eprintln!(
+            "Warning: failed to record {:?} in the audit log: {e}",
+            entry.action
+        );
+    }
+}
+
+fn print_films(json: bool, films: &[Film]) -> Result<()> {
+    let headers = [
+        "NAME", "GROUP", "CLASS", "PRIORITY", "STAGE", "DUE", "AE", "EDITOR", "SOUND", "FINISH",
+    ];
+    print(json, films, &headers, |f| {
+        vec![
+            f.name.clone(),
+            f.group_number.to_string(),
+            f.class.clone(),
+            f.priority.as_ref().to_string(),
+            f.current_role.as_ref().to_string(),
+            opt(f.due_date.map(|d| d.format("%Y-%m-%d").to_string())),
+            opt(f.roles.ae.clone()),
+            opt(f.roles.editor.clone()),
+            opt(f.roles.sound.clone()),
+            opt(f.roles.finish.clone()),
+        ]
+    })
+}
+
+fn print_students(json: bool, students: &[Student]) -> Result<()> {
+    let headers = [
+        "SLACK ID", "NAME", "GROUP", "CLASS", "ROLE", "FILM", "ASSIGNED", "PAUSED",
+    ];
+    print(json, students, &headers, |s| {
+        vec![
+            s.slack_id.clone(),
+            s.name.clone(),
+            s.group_number.to_string(),
+            s.class.clone(),
+            s.current_role.as_ref().to_string(),
+            opt(s.current_film.clone()),
+            opt(s
+                .assigned_at
+                .map(|d| d.format("%Y-%m-%d %H:%M").to_string())),
+            if s.paused { "yes" } else { "" }.to_string(),
+        ]
+    })
+}
+
+/// Prints items as pretty JSON, or as a table with one row per item.
+fn print<T: Serialize>(
+    json: bool,
+    items: &[T],
+    headers: &[&str],
+    row: impl Fn(&T) -> Vec<String>,
+) -> Result<()> {
+    if json {
+        println!("{}", serde_json::to_string_pretty(items)?);
+    } else {
+        let rows: Vec<_> = items.iter().map(row).collect();
+        print!("{}", table(headers, &rows));
+    }
+    Ok(())
+}
+
+/// Lays out rows under their headers, with every column padded to its widest value.
+fn table(headers: &[&str], rows: &[Vec<String>]) -> String {
+    let mut widths: Vec<_> = headers.iter().map(|h| h.chars().count()).collect();
+    for row in rows {
+        for (w, cell) in widths.iter_mut().zip(row) {
+            *w = (*w).max(cell.chars().count());
+        }
+    }
+
+    let line = |cells: Vec<&str>| {
+        let cells: Vec<_> = cells
+            .iter()
+            .zip(&widths)
+            .map(|(c, &w)| format!("{c:w$}"))
+            .collect();
+        cells.join("  ").trim_end().to_string() + "\n"
+    };
+
+    let mut out = line(headers.to_vec());
+    for row in rows {
+        out += &line(row.iter().map(String::as_str).collect());
+    }
+    out
+}
+
+fn opt(value: Option<String>) -> String {
+    value.unwrap_or_else(|| "-".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_table() {
+        let rows = vec![
+            vec!["star wars".to_string(), "1".to_string()],
+            vec!["a".to_string(), "".to_string()],
+        ];
+        let expected = "NAME       GROUP\nstar wars  1\na\n";
+        assert_eq!(expected, table(&["NAME", "GROUP"], &rows));
+    }
+
+    #[test]
+    fn check_args() {
+        let args = Args::parse_from(["shbot-admin", "students", "--role", "ae", "--json"]);
+        assert!(args.json);
+        assert!(matches!(
+            args.command,
+            Command::Db(DbCommand::Students {
+                role: Some(Role::Ae),
+                ..
+            })
+        ));
+
+        let args = Args::try_parse_from(["shbot-admin", "import", "films"]);
+        assert!(args.is_err());
+
+        let args = Args::parse_from(["shbot-admin", "unassign", "U1", "--server", "http://bot"]);
+        assert_eq!(Some("http://bot".to_string()), args.server);
+    }
+
+    #[test]
+    fn check_import() {
+        let mut import = Import::new("film");
+        import.inserted.push("a".to_string());
+        import.add_err("b".to_string(), crate::Error::Duplicate("b".to_string()));
+        import.add_err("c".to_string(), crate::Error::InvalidArg("bad".to_string()));
+        assert_eq!(vec!["b".to_string()], import.skipped);
+        assert_eq!(1, import.failed.len());
+
+        let msg = import.to_string();
+        assert!(msg
+            .starts_with("Inserted 1 film(s), skipped 1 duplicate(s): b, failed to insert 1: c ("));
+    }
+}
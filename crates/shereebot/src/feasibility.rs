@@ -0,0 +1,336 @@
+//! Predicts whether the term can finish under the assignment rules, given the students and films
+//! there are, so shortages can be fixed before anyone's left waiting.
+//!
+//! Every student works each role once, and every film needs each role worked once. Students
+//! should get films outside their group which they haven't worked on before, and only bend those
+//! rules when nothing else is left.
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+
+use serde::Serialize;
+
+use crate::{store::Database, Result};
+use models::{Film, Role, Student};
+
+const ROLES: [Role; 4] = [Role::Ae, Role::Editor, Role::Sound, Role::Finish];
+
+/// Something that will stop every student finishing every role the way the rules intend.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Shortage {
+    /// More students still need a role than there are films left needing it, so some of them
+    /// will wait for work forever.
+    Films {
+        role: Role,
+        students: usize,
+        films: usize,
+    },
+    /// More films still need a role than there are students left to work it, so some films
+    /// won't be finished.
+    Students {
+        role: Role,
+        films: usize,
+        students: usize,
+    },
+    /// A group's students outnumber the films outside their group still needing a role, so
+    /// some of them will work on their own group's film.
+    Group {
+        group: i32,
+        role: Role,
+        students: usize,
+        films: usize,
+    },
+    /// A student has more roles left than films outside their group they haven't worked on, so
+    /// they'll work on a film twice.
+    Repeats {
+        student: String,
+        roles: usize,
+        films: usize,
+    },
+}
+
+impl Shortage {
+    /// Whether the term can't finish at all, rather than finishing by bending the rules.
+    pub fn is_blocking(&self) -> bool {
+        matches!(self, Self::Films { .. } | Self::Students { .. })
+    }
+
+    /// Films which would have to be added to make up this shortage.
+    fn films_short(&self) -> usize {
+        match self {
+            Self::Films {
+                students, films, ..
+            }
+            | Self::Group {
+                students, films, ..
+            } => students - films,
+            Self::Repeats { roles, films, .. } => roles - films,
+            Self::Students { .. } => 0,
+        }
+    }
+}
+
+impl fmt::Display for Shortage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let short = self.films_short();
+        match self {
+            Self::Films {
+                role,
+                students,
+                films,
+            } => write!(
+                f,
+                "{students} student(s) still need {}, but only {films} film(s) do: add {short} film(s)",
+                role.as_ref()
+            ),
+            Self::Students {
+                role,
+                films,
+                students,
+            } => write!(
+                f,
+                "{films} film(s) still need {}, but only {students} student(s) do: {} film(s) won't be finished",
+                role.as_ref(),
+                films - students
+            ),
+            Self::Group {
+                group,
+                role,
+                students,
+                films,
+            } => write!(
+                f,
+                "group {group} has {students} student(s) needing {}, but only {films} film(s) outside the group do: add {short} film(s) from other groups",
+                role.as_ref()
+            ),
+            Self::Repeats {
+                student,
+                roles,
+                films,
+            } => write!(
+                f,
+                "{student} has {roles} role(s) left, but only {films} film(s) outside their group they haven't worked on"
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Report {
+    pub shortages: Vec<Shortage>,
+    /// The fewest films that need adding, from groups other than the short ones, before every
+    /// student can be served.
+    pub films_to_add: usize,
+}
+
+impl Report {
+    /// Whether every student can finish every role without bending the rules.
+    pub fn is_feasible(&self) -> bool {
+        self.shortages.is_empty()
+    }
+
+    /// Whether every student can at least finish, even if some rules are bent.
+    pub fn can_finish(&self) -> bool {
+        !self.shortages.iter().any(Shortage::is_blocking)
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_feasible() {
+            return write!(f, "Every student can finish every role.");
+        }
+        if self.can_finish() {
+            write!(f, "Everyone can finish, but not without bending the rules:")?;
+        } else {
+            write!(f, "The term can't finish as it stands:")?;
+        }
+        for shortage in &self.shortages {
+            write!(f, "\n- {shortage}")?;
+        }
+        if self.films_to_add > 0 {
+            write!(
+                f,
+                "\nAdd at least {} film(s) to fix this.",
+                self.films_to_add
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Checks whether the students and films in the database can see the term through.
+#[tracing::instrument(skip(db))]
+pub async fn check(db: &Database) -> Result<Report> {
+    let films = db.list_films().await?;
+    let students = db.list_students().await?;
+
+    let mut worked = HashMap::new();
+    for s in students.iter().filter(|s| s.current_role != Role::Done) {
+        let films = db.get_worked_films(&s.id).await?;
+        let films: HashSet<_> = films.into_iter().map(|f| f.name).collect();
+        worked.insert(s.slack_id.clone(), films);
+    }
+
+    Ok(analyse(&films, &students, &worked))
+}
+
+/// Finds every shortage, given which films each student has worked on, keyed by slack id.
+fn analyse(
+    films: &[Film],
+    students: &[Student],
+    worked: &HashMap<String, HashSet<String>>,
+) -> Report {
+    let films: Vec<_> = films
+        .iter()
+        .filter(|f| f.current_role != Role::Done)
+        .collect();
+    let students: Vec<_> = students
+        .iter()
+        .filter(|s| s.current_role != Role::Done)
+        .collect();
+
+    let mut shortages = vec![];
+    for role in ROLES {
+        // Anyone who hasn't reached a role yet will need it later.
+        let needing: Vec<_> = students.iter().filter(|s| s.current_role <= role).collect();
+        let needed: Vec<_> = films.iter().filter(|f| f.current_role <= role).collect();
+        let (n_students, n_films) = (needing.len(), needed.len());
+        if n_students > n_films {
+            shortages.push(Shortage::Films {
+                role,
+                students: n_students,
+                films: n_films,
+            });
+        } else if n_films > n_students {
+            shortages.push(Shortage::Students {
+                role,
+                films: n_films,
+                students: n_students,
+            });
+        }
+
+        let mut by_group: BTreeMap<i32, usize> = BTreeMap::new();
+        for s in &needing {
+            *by_group.entry(s.group_number).or_default() += 1;
+        }
+        for (group, count) in by_group {
+            let outside = needed.iter().filter(|f| f.group_number != group).count();
+            if count > outside {
+                shortages.push(Shortage::Group {
+                    group,
+                    role,
+                    students: count,
+                    films: outside,
+                });
+            }
+        }
+    }
+
+    let none = HashSet::new();
+    for s in &students {
+        let roles = ROLES.iter().filter(|&&r| r >= s.current_role).count();
+        let worked = worked.get(&s.slack_id).unwrap_or(&none);
+        // The film they're working on now covers their current role.
+        let current = s.current_film.as_deref();
+        let roles = roles - usize::from(current.is_some());
+        let fresh = films
+            .iter()
+            .filter(|f| Some(f.name.as_str()) != current && !worked.contains(&f.name))
+            .filter(|f| f.group_number != s.group_number)
+            .count();
+        if roles > fresh {
+            shortages.push(Shortage::Repeats {
+                student: s.slack_id.clone(),
+                roles,
+                films: fresh,
+            });
+        }
+    }
+
+    let films_to_add = shortages
+        .iter()
+        .filter(|s| !matches!(s, Shortage::Repeats { .. }))
+        .map(Shortage::films_short)
+        .max()
+        .unwrap_or_default();
+    Report {
+        shortages,
+        films_to_add,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use models::Priority;
+
+    fn group_students(group: i32, count: usize) -> Vec<Student> {
+        (0..count)
+            .map(|i| Student {
+                slack_id: format!("G{group}S{i}"),
+                group_number: group,
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    fn group_films(group: i32, count: usize) -> Vec<Film> {
+        (0..count)
+            .map(|i| Film::new(&format!("g{group}f{i}"), Priority::High, group))
+            .collect()
+    }
+
+    #[test]
+    fn check_balanced() {
+        let students = [group_students(1, 4), group_students(2, 4)].concat();
+        let films = [group_films(1, 4), group_films(2, 4)].concat();
+        let report = analyse(&films, &students, &HashMap::new());
+        assert!(report.is_feasible(), "{report}");
+    }
+
+    #[test]
+    fn check_shortages() {
+        // Group 1 outnumbers the films outside it.
+        let students = [group_students(1, 5), group_students(2, 1)].concat();
+        let films = [group_films(1, 3), group_films(2, 3)].concat();
+        let report = analyse(&films, &students, &HashMap::new());
+        assert!(report.can_finish());
+        assert!(!report.is_feasible());
+        let expected = Shortage::Group {
+            group: 1,
+            role: Role::Ae,
+            students: 5,
+            films: 3,
+        };
+        assert_eq!(Some(&expected), report.shortages.first());
+        assert_eq!(2, report.films_to_add);
+        assert!(report.shortages.contains(&Shortage::Repeats {
+            student: "G2S0".to_string(),
+            roles: 4,
+            films: 3,
+        }));
+
+        // Too few films to go round at all.
+        let students = [group_students(1, 3), group_students(2, 3)].concat();
+        let mut films = [group_films(1, 4), group_films(2, 4)].concat();
+        films.truncate(5);
+        let report = analyse(&films, &students, &HashMap::new());
+        assert!(!report.can_finish());
+        // Group 1 is even shorter, with a single film outside it.
+        assert_eq!(2, report.films_to_add);
+
+        // Students who've moved on need fewer films.
+        let mut students = students;
+        students
+            .iter_mut()
+            .for_each(|s| s.current_role = Role::Done);
+        let report = analyse(&films, &students, &HashMap::new());
+        let expected = Shortage::Students {
+            role: Role::Ae,
+            films: 5,
+            students: 0,
+        };
+        assert_eq!(Some(&expected), report.shortages.first());
+    }
+}
@@ -0,0 +1,348 @@
+//! Estimates when each film, and each class, will be finished.
+//!
+//! Stages are assumed to take as long as they have on average so far, going by when assignments
+//! were handed out and delivered. Until a stage has been delivered at least once, each film's
+//! own target for it is used instead.
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+use chrono::{DateTime, Duration, Utc};
+use serde::Serialize;
+
+use crate::{
+    queue::{Queue, QueueItem},
+    store::Database,
+    utils::hours,
+    Result,
+};
+use models::{Assignment, Film, Outcome, Role, Student};
+
+const ROLES: [Role; 4] = [Role::Ae, Role::Editor, Role::Sound, Role::Finish];
+
+/// Hours a stage is assumed to take with neither history nor a target to go on.
+const DEFAULT_STAGE_HOURS: f64 = 72.0;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FilmForecast {
+    pub film: String,
+    pub class: String,
+    pub role: Role,
+    pub due_date: Option<DateTime<Utc>>,
+    /// When the film should be finished. Unset if it's finished already, or nobody is on their
+    /// way to work its current stage.
+    pub projected: Option<DateTime<Utc>>,
+    /// Whether the film is projected to miss its due date.
+    pub late: bool,
+}
+
+impl FilmForecast {
+    /// Whether the film is stuck waiting for students who aren't coming.
+    pub fn is_stalled(&self) -> bool {
+        self.role != Role::Done && self.projected.is_none()
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ClassForecast {
+    pub class: String,
+    pub films: usize,
+    pub finished: usize,
+    /// When the last film should be finished. Unset if every film is, or any film is stalled.
+    pub projected: Option<DateTime<Utc>>,
+    pub stalled: usize,
+    pub late: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Forecast {
+    pub generated_at: DateTime<Utc>,
+    /// Average hours taken to deliver each stage, for stages delivered at least once.
+    pub turnaround_hours: BTreeMap<Role, f64>,
+    pub classes: Vec<ClassForecast>,
+    pub films: Vec<FilmForecast>,
+}
+
+impl fmt::Display for Forecast {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let date = |d: &DateTime<Utc>| d.format("%Y-%m-%d %H:%M").to_string();
+
+        write!(f, "Average turnaround:")?;
+        if self.turnaround_hours.is_empty() {
+            write!(f, " nothing's been delivered yet")?;
+        }
+        for (role, hours) in &self.turnaround_hours {
+            write!(f, " {} {hours:.0}h", role.as_ref())?;
+        }
+
+        for c in &self.classes {
+            write!(
+                f,
+                "\nClass `{}`: {}/{} films finished",
+                c.class, c.finished, c.films
+            )?;
+            if let Some(projected) = &c.projected {
+                write!(f, ", all done by {}", date(projected))?;
+            }
+            if c.stalled > 0 {
+                write!(f, ", {} stalled waiting for students", c.stalled)?;
+            }
+            if c.late > 0 {
+                write!(f, ", {} late", c.late)?;
+            }
+        }
+
+        for film in self.films.iter().filter(|film| film.role != Role::Done) {
+            write!(f, "\n`{}` ({}): ", film.film, film.role.as_ref())?;
+            match &film.projected {
+                Some(projected) => write!(f, "done by {}", date(projected))?,
+                None => write!(f, "stalled")?,
+            }
+            if let Some(due) = &film.due_date {
+                write!(f, ", due {}", date(due))?;
+            }
+            if film.late {
+                write!(f, " :warning:")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Forecasts every film and class from the database and the jobs queue as they stand.
+#[tracing::instrument(skip_all)]
+pub(crate) async fn forecast(db: &Database, queue: &Queue) -> Result<Forecast> {
+    let films = db.list_films().await?;
+    let students = db.list_students().await?;
+    let jobs: Vec<_> = queue.list_jobs().await.into_iter().map(|j| j.job).collect();
+
+    let history = db.list_assignments().await?;
+
+    Ok(project(&films, &students, &jobs, &history, Utc::now()))
+}
+
+/// Projects when each film will be finished. `jobs` must be in the order they'll be handed out.
+fn project(
+    films: &[Film],
+    students: &[Student],
+    jobs: &[QueueItem],
+    history: &[Assignment],
+    now: DateTime<Utc>,
+) -> Forecast {
+    let turnaround = turnaround(history);
+    let stage_hours = |film: &Film, role: Role| {
+        turnaround
+            .get(&role)
+            .copied()
+            .or_else(|| film.stage_hours.get(role).map(f64::from))
+            .unwrap_or(DEFAULT_STAGE_HOURS)
+    };
+
+    // Students free to pick up each role now, and those working the stage before it.
+    let mut free: HashMap<Role, usize> = HashMap::new();
+    let mut holding: HashMap<Role, usize> = HashMap::new();
+    for s in students {
+        let count = match s.current_film {
+            Some(_) => holding.entry(s.current_role),
+            None => free.entry(s.current_role),
+        };
+        *count.or_default() += 1;
+    }
+    let holders: HashMap<_, _> = students
+        .iter()
+        .filter_map(|s| Some((s.current_film.as_deref()?, s)))
+        .collect();
+
+    let mut forecasts = vec![];
+    for film in films {
+        let role = film.current_role;
+        let mut forecast = FilmForecast {
+            film: film.name.clone(),
+            class: film.class.clone(),
+            role,
+            due_date: film.due_date,
+            projected: None,
+            late: false,
+        };
+        if role == Role::Done {
+            forecasts.push(forecast);
+            continue;
+        }
+
+        let this_stage = stage_hours(film, role);
+        let current = match holders.get(film.name.as_str()) {
+            Some(s) => {
+                let elapsed = s.assigned_at.map_or(0.0, |at| hours(now - at));
+                Some((this_stage - elapsed).max(0.0))
+            }
+            None => {
+                // Jobs ahead of this one go first, to whoever's free or finishing the stage before.
+                let ahead = jobs
+                    .iter()
+                    .filter(|j| j.role == role)
+                    .take_while(|j| j.film_name != film.name)
+                    .count();
+                let free = free.get(&role).copied().unwrap_or_default();
+                let prev = role.prev();
+                let coming = prev.and_then(|r| holding.get(&r)).copied();
+                match (prev, coming) {
+                    _ if ahead < free => Some(this_stage),
+                    (Some(prev), Some(coming)) => {
+                        let rounds = (ahead - free) / coming + 1;
+                        Some(rounds as f64 * stage_hours(film, prev) + this_stage)
+                    }
+                    _ => None,
+                }
+            }
+        };
+        let later: f64 = ROLES
+            .iter()
+            .filter(|&&r| r > role)
+            .map(|&r| stage_hours(film, r))
+            .sum();
+
+        forecast.projected = current.map(|h| now + duration(h + later));
+        forecast.late = match (forecast.projected, film.due_date) {
+            (Some(projected), Some(due)) => projected > due,
+            _ => false,
+        };
+        forecasts.push(forecast);
+    }
+    forecasts.sort_by(|a, b| (&a.class, &a.film).cmp(&(&b.class, &b.film)));
+
+    let mut classes: BTreeMap<&str, Vec<&FilmForecast>> = BTreeMap::new();
+    for f in &forecasts {
+        classes.entry(&f.class).or_default().push(f);
+    }
+    let classes = classes
+        .into_iter()
+        .map(|(class, films)| {
+            let stalled = films.iter().filter(|f| f.is_stalled()).count();
+            let projected = films.iter().filter_map(|f| f.projected).max();
+            ClassForecast {
+                class: class.to_string(),
+                films: films.len(),
+                finished: films.iter().filter(|f| f.role == Role::Done).count(),
+                projected: projected.filter(|_| stalled == 0),
+                stalled,
+                late: films.iter().filter(|f| f.late).count(),
+            }
+        })
+        .collect();
+
+    Forecast {
+        generated_at: now,
+        turnaround_hours: turnaround,
+        classes,
+        films: forecasts,
+    }
+}
+
+/// Average hours taken to deliver each stage. Films sent back were still delivered.
+fn turnaround(history: &[Assignment]) -> BTreeMap<Role, f64> {
+    let mut samples: BTreeMap<Role, Vec<f64>> = BTreeMap::new();
+    for a in history {
+        let delivered = matches!(a.outcome, Some(Outcome::Delivered | Outcome::SentBack));
+        if let (true, Some(at)) = (delivered, a.delivered_at) {
+            samples
+                .entry(a.role)
+                .or_default()
+                .push(hours(at - a.assigned_at));
+        }
+    }
+    samples
+        .into_iter()
+        .map(|(role, s)| (role, s.iter().sum::<f64>() / s.len() as f64))
+        .collect()
+}
+
+fn duration(hours: f64) -> Duration {
+    Duration::minutes((hours * 60.0).round() as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::queue;
+    use models::Priority;
+    use uuid::Uuid;
+
+    fn assignment(film: &str, role: Role, hours: i64, now: DateTime<Utc>) -> Assignment {
+        Assignment {
+            id: Uuid::new_v4(),
+            film_name: film.to_string(),
+            role,
+            student_id: Uuid::nil(),
+            slack_id: "U0".to_string(),
+            student_name: "old".to_string(),
+            assigned_at: now - Duration::hours(hours + 100),
+            delivered_at: Some(now - Duration::hours(100)),
+            outcome: Some(Outcome::Delivered),
+            queued_at: None,
+            waiting_since: None,
+        }
+    }
+
+    fn student(slack_id: &str, role: Role) -> Student {
+        Student {
+            slack_id: slack_id.to_string(),
+            current_role: role,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn check_project() {
+        let now = Utc::now();
+        let history = [
+            assignment("old", Role::Ae, 10, now),
+            assignment("old", Role::Ae, 20, now),
+            assignment("old", Role::Editor, 30, now),
+        ];
+
+        let mut films: Vec<_> = ["a", "b", "c"]
+            .iter()
+            .map(|name| Film::new(name, Priority::High, 1))
+            .collect();
+        films.iter_mut().for_each(|f| {
+            f.class = "x".to_string();
+            f.stage_hours.sound = Some(5);
+        });
+        films[2].due_date = Some(now + Duration::hours(10));
+
+        // One student is halfway through `a`, and another is free for `b`.
+        let mut holder = student("U1", Role::Ae);
+        holder.assign("a", now - Duration::hours(5));
+        let students = [holder, student("U2", Role::Ae)];
+        let jobs = [queue::new_job(&films[1], ""), queue::new_job(&films[2], "")];
+
+        let forecast = project(&films, &students, &jobs, &history, now);
+        assert_eq!(Some(&15.0), forecast.turnaround_hours.get(&Role::Ae));
+
+        // The rest of AE, then 30 hours editing, 5 sound and a default finish.
+        let later = 30.0 + 5.0 + DEFAULT_STAGE_HOURS;
+        assert_eq!(
+            Some(now + duration(10.0 + later)),
+            forecast.films[0].projected
+        );
+        assert_eq!(
+            Some(now + duration(15.0 + later)),
+            forecast.films[1].projected
+        );
+        // Nobody's left to pick up `c`.
+        assert!(forecast.films[2].is_stalled());
+        assert!(!forecast.films[2].late);
+
+        let class = &forecast.classes[0];
+        assert_eq!((3, 0, 1), (class.films, class.finished, class.stalled));
+        assert_eq!(None, class.projected);
+
+        // Once it's editing, `c` waits on whoever's doing AE, and misses its due date.
+        films[2].current_role = Role::Editor;
+        let jobs = [queue::new_job(&films[2], "")];
+        let forecast = project(&films, &students, &jobs, &history, now);
+        let expected = now + duration(15.0 + later);
+        assert_eq!(Some(expected), forecast.films[2].projected);
+        assert!(forecast.films[2].late);
+        assert_eq!(Some(expected), forecast.classes[0].projected);
+    }
+}
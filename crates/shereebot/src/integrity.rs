@@ -0,0 +1,401 @@
+//! Scans the database for state that no code path should leave behind, and repairs what can
+//! be repaired safely.
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use chrono::Utc;
+use serde::Serialize;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::{audit, queue::QueueItem, store::Database, Result};
+use models::{AuditEntry, Film, Role, Student};
+
+/// Something in the database which disagrees with the rest of it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Issue {
+    /// A student is working on a film, but there's no open assignment for it in their history.
+    MissingHistory { student: String, film: String },
+    /// A student is working on a film that doesn't exist.
+    UnknownFilm { student: String, film: String },
+    /// More than one student is working on the same film.
+    SharedFilm { film: String, students: Vec<String> },
+    /// A job in the jobs_q for a film that doesn't exist.
+    OrphanJob { id: Uuid, film: String },
+    /// A job whose role disagrees with the stage its film is at.
+    StaleJob {
+        id: Uuid,
+        film: String,
+        role: Role,
+        expected: Role,
+    },
+    /// A job for a film someone is already working on.
+    HeldJob {
+        id: Uuid,
+        film: String,
+        student: String,
+    },
+    /// A second job for the same stage of a film.
+    DuplicateJob { id: Uuid, film: String },
+    /// An unfinished film nobody is working on, with no job to hand it out.
+    Unqueued { film: String, role: Role },
+    /// A student in the wait_q who's already working on a film.
+    AssignedWaiter {
+        id: Uuid,
+        student: String,
+        film: String,
+    },
+}
+
+impl Issue {
+    /// Whether the store can repair this without losing anyone's work.
+    pub fn is_repairable(&self) -> bool {
+        !matches!(self, Self::MissingHistory { .. } | Self::SharedFilm { .. })
+    }
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingHistory { student, film } => {
+                write!(
+                    f,
+                    "{student} is working on {film}, but has no history for it"
+                )
+            }
+            Self::UnknownFilm { student, film } => {
+                write!(f, "{student} is working on {film}, which doesn't exist")
+            }
+            Self::SharedFilm { film, students } => {
+                write!(f, "{film} is held by {}", students.join(", "))
+            }
+            Self::OrphanJob { id, film } => {
+                write!(f, "job {id} is for {film}, which doesn't exist")
+            }
+            Self::StaleJob {
+                id,
+                film,
+                role,
+                expected,
+            } => write!(
+                f,
+                "job {id} is for {film}'s {} stage, but the film is at {}",
+                role.as_ref(),
+                expected.as_ref()
+            ),
+            Self::HeldJob { id, film, student } => {
+                write!(f, "job {id} is for {film}, which {student} is working on")
+            }
+            Self::DuplicateJob { id, film } => write!(f, "job {id} duplicates another for {film}"),
+            Self::Unqueued { film, role } => write!(
+                f,
+                "{film} is waiting on {}, but isn't queued or assigned",
+                role.as_ref()
+            ),
+            Self::AssignedWaiter { student, film, .. } => {
+                write!(f, "{student} is waiting for work, but is working on {film}")
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Finding {
+    #[serde(flatten)]
+    pub issue: Issue,
+    pub repaired: bool,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Report {
+    pub findings: Vec<Finding>,
+}
+
+impl Report {
+    pub fn is_clean(&self) -> bool {
+        self.findings.is_empty()
+    }
+
+    pub fn repaired(&self) -> usize {
+        self.findings.iter().filter(|f| f.repaired).count()
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_clean() {
+            return write!(f, "No issues found.");
+        }
+        write!(f, "Found {} issue(s):", self.findings.len())?;
+        for finding in &self.findings {
+            let status = if finding.repaired { " (repaired)" } else { "" };
+            write!(f, "\n- {}{status}", finding.issue)?;
+        }
+        Ok(())
+    }
+}
+
+/// Scans every table for inconsistencies, repairing those which can be fixed safely if given an
+/// audit entry to record each repair under.
+///
+/// Each repair re-reads its rows in the transaction that fixes them, and is skipped if the issue
+/// has gone away since the scan. Repairs only touch the database. A running server has to reload
+/// its queues afterwards.
+#[tracing::instrument(skip(db))]
+pub async fn check(db: &Database, repair: Option<&AuditEntry>) -> Result<Report> {
+    let films = db.list_films().await?;
+    let students = db.list_students().await?;
+    let jobs = db.get_queue(false).await?;
+    let waiters = db.get_queue(true).await?;
+
+    let mut open = HashSet::new();
+    for s in students.iter().filter(|s| s.current_film.is_some()) {
+        for a in db.get_student_history(&s.slack_id).await? {
+            if a.outcome.is_none() {
+                open.insert((s.slack_id.clone(), a.film_name));
+            }
+        }
+    }
+
+    let mut report = Report::default();
+    for issue in find_issues(&films, &students, &jobs, &waiters, &open) {
+        warn!("Integrity issue: {issue}");
+        let repaired = match repair {
+            Some(entry) if issue.is_repairable() => {
+                let entry = AuditEntry {
+                    id: Uuid::new_v4(),
+                    after: audit::snapshot(&issue),
+                    created_at: Utc::now(),
+                    ..entry.clone()
+                };
+                db.repair(&issue, &entry).await?
+            }
+            _ => false,
+        };
+        if repaired {
+            info!("Repaired: {issue}");
+        }
+        report.findings.push(Finding { issue, repaired });
+    }
+
+    Ok(report)
+}
+
+/// Finds every inconsistency between films, students and both queues.
+/// `open` holds the (student, film) pairs with an assignment in progress.
+fn find_issues(
+    films: &[Film],
+    students: &[Student],
+    jobs: &[QueueItem],
+    waiters: &[QueueItem],
+    open: &HashSet<(String, String)>,
+) -> Vec<Issue> {
+    let mut issues = vec![];
+    let films: HashMap<_, _> = films.iter().map(|f| (f.name.as_str(), f)).collect();
+
+    let mut holders: HashMap<&str, Vec<&Student>> = HashMap::new();
+    for s in students {
+        let film = match &s.current_film {
+            Some(f) => f,
+            None => continue,
+        };
+        let (student, film_name) = (s.slack_id.clone(), film.clone());
+        if !films.contains_key(film.as_str()) {
+            issues.push(Issue::UnknownFilm {
+                student,
+                film: film_name,
+            });
+            continue;
+        }
+        if !open.contains(&(student.clone(), film_name.clone())) {
+            issues.push(Issue::MissingHistory {
+                student,
+                film: film_name,
+            });
+        }
+        holders.entry(film).or_default().push(s);
+    }
+    let mut shared: Vec<_> = holders.iter().filter(|(_, h)| h.len() > 1).collect();
+    shared.sort_by_key(|&(film, _)| *film);
+    for (film, students) in shared {
+        let students = students.iter().map(|s| s.slack_id.clone()).collect();
+        let film = film.to_string();
+        issues.push(Issue::SharedFilm { film, students });
+    }
+
+    let mut queued = HashSet::new();
+    for job in jobs {
+        let (id, film_name) = (job.id, job.film_name.clone());
+        let film = match films.get(job.film_name.as_str()) {
+            Some(f) => f,
+            None => {
+                issues.push(Issue::OrphanJob {
+                    id,
+                    film: film_name,
+                });
+                continue;
+            }
+        };
+        if job.role != film.current_role {
+            let (role, expected) = (job.role, film.current_role);
+            issues.push(Issue::StaleJob {
+                id,
+                film: film_name,
+                role,
+                expected,
+            });
+        } else if let Some(s) = holders.get(film.name.as_str()).and_then(|h| h.first()) {
+            let student = s.slack_id.clone();
+            issues.push(Issue::HeldJob {
+                id,
+                film: film_name,
+                student,
+            });
+        } else if !queued.insert(film.name.as_str()) {
+            issues.push(Issue::DuplicateJob {
+                id,
+                film: film_name,
+            });
+        }
+    }
+
+    let mut unqueued: Vec<_> = films
+        .values()
+        .filter(|f| f.current_role != Role::Done)
+        .filter(|f| !queued.contains(f.name.as_str()) && !holders.contains_key(f.name.as_str()))
+        .map(|f| Issue::Unqueued {
+            film: f.name.clone(),
+            role: f.current_role,
+        })
+        .collect();
+    // Keeps reports stable between runs.
+    unqueued.sort_by_key(|i| i.to_string());
+    issues.extend(unqueued);
+
+    let assigned: HashMap<_, _> = students
+        .iter()
+        .filter_map(|s| Some((s.slack_id.as_str(), s.current_film.as_ref()?)))
+        .collect();
+    for w in waiters {
+        if let Some(film) = assigned.get(w.student_slack_id.as_str()) {
+            issues.push(Issue::AssignedWaiter {
+                id: w.id,
+                student: w.student_slack_id.clone(),
+                film: film.to_string(),
+            });
+        }
+    }
+
+    issues
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::queue;
+    use models::Priority;
+
+    fn student(slack_id: &str, film: Option<&str>) -> Student {
+        let mut s = Student {
+            slack_id: slack_id.to_string(),
+            ..Default::default()
+        };
+        if let Some(f) = film {
+            s.assign(f, chrono::Utc::now());
+        }
+        s
+    }
+
+    fn job(film: &Film, role: Role) -> QueueItem {
+        let mut job = queue::new_job(film, "");
+        job.role = role;
+        job
+    }
+
+    #[test]
+    fn check_find_issues() {
+        let a = Film::new("a", Priority::High, 1);
+        let b = Film::new("b", Priority::High, 1);
+        let mut done = Film::new("done", Priority::High, 1);
+        done.current_role = Role::Done;
+        let films = [a.clone(), b.clone(), done.clone()];
+
+        let open = HashSet::from([("U1".to_string(), "a".to_string())]);
+        let students = [student("U1", Some("a")), student("U2", None)];
+        let jobs = [job(&b, Role::Ae)];
+        let waiters = [job(&b, Role::Ae)];
+        assert!(find_issues(&films, &students, &jobs, &waiters, &open).is_empty());
+
+        let students = [
+            student("U1", Some("a")),
+            student("U2", Some("a")),
+            student("U3", Some("gone")),
+        ];
+        let gone = Film::new("gone", Priority::High, 1);
+        let jobs = [
+            job(&a, Role::Ae),
+            job(&b, Role::Editor),
+            job(&done, Role::Ae),
+            job(&gone, Role::Ae),
+        ];
+        let mut waiter = job(&b, Role::Ae);
+        waiter.student_slack_id = "U1".to_string();
+        let issues = find_issues(&films, &students, &jobs, &[waiter.clone()], &open);
+
+        let expected = [
+            Issue::MissingHistory {
+                student: "U2".to_string(),
+                film: "a".to_string(),
+            },
+            Issue::UnknownFilm {
+                student: "U3".to_string(),
+                film: "gone".to_string(),
+            },
+            Issue::SharedFilm {
+                film: "a".to_string(),
+                students: vec!["U1".to_string(), "U2".to_string()],
+            },
+            Issue::HeldJob {
+                id: jobs[0].id,
+                film: "a".to_string(),
+                student: "U1".to_string(),
+            },
+            Issue::StaleJob {
+                id: jobs[1].id,
+                film: "b".to_string(),
+                role: Role::Editor,
+                expected: Role::Ae,
+            },
+            Issue::StaleJob {
+                id: jobs[2].id,
+                film: "done".to_string(),
+                role: Role::Ae,
+                expected: Role::Done,
+            },
+            Issue::OrphanJob {
+                id: jobs[3].id,
+                film: "gone".to_string(),
+            },
+            Issue::Unqueued {
+                film: "b".to_string(),
+                role: Role::Ae,
+            },
+            Issue::AssignedWaiter {
+                id: waiter.id,
+                student: "U1".to_string(),
+                film: "a".to_string(),
+            },
+        ];
+        assert_eq!(expected.to_vec(), issues);
+
+        let jobs = [job(&b, Role::Ae), job(&b, Role::Ae)];
+        let students = [student("U1", Some("a"))];
+        let issues = find_issues(&films, &students, &jobs, &[], &open);
+        let expected = Issue::DuplicateJob {
+            id: jobs[1].id,
+            film: "b".to_string(),
+        };
+        assert_eq!(vec![expected], issues);
+    }
+}
@@ -1,8 +1,17 @@
+pub mod analytics;
+pub mod cli;
+pub mod feasibility;
+pub mod forecast;
+pub mod integrity;
 pub mod queue;
 pub mod server;
+pub mod sim;
 pub mod store;
 
+mod audit;
 mod manager;
+mod metrics;
+mod scheduler;
 mod slack;
 mod utils;
 
@@ -1,16 +1,27 @@
+use clap::Parser;
 use color_eyre::Result;
 use tracing::debug;
 
 use shbot::{config, logger, server};
 
+#[derive(Debug, Parser)]
+#[clap(name = "shbot", about = "Runs ShereeBot's server")]
+struct Args {
+    /// Keep everything in memory instead of the database. Nothing will be saved!
+    #[clap(long)]
+    demo: bool,
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     dotenv::dotenv().ok();
+    let args = Args::parse();
 
     logger::install(None);
     debug!("Loaded environment variables");
 
-    let cfg = config::new()?;
+    let mut cfg = config::new()?;
+    cfg.demo = args.demo;
 
     server::serve(&cfg).await?;
 
@@ -1,21 +1,28 @@
 #![allow(dead_code)]
 use std::collections::HashSet;
 
+use chrono::Utc;
 use csv_parser::{FilmInput, StudentInput};
 use futures::{future, stream::FuturesUnordered};
 use itertools::Itertools;
+use serde_json::json;
 use tracing::{debug, error, info, trace};
 
 use crate::{
+    audit::{self, Origin},
     queue::QueueItem,
+    scheduler,
     server::State,
-    slack::{app_mentions::Response, events::File},
+    slack::{self, app_mentions::Response, events::File},
+    utils::{correlation, rate_limit::Action},
     Error, Result,
 };
-use models::{Film, Priority, Student};
+use models::{AuditAction, Delivery, Film, Role, Student};
 
 pub(crate) struct Manager {
     state: State,
+    /// Who the manager is acting for, recorded in the audit log.
+    origin: Origin,
 }
 
 const DELIVER: &str = "Good job!! You've delivered your work.
@@ -23,8 +30,22 @@ const DELIVER: &str = "Good job!! You've delivered your work.
 When you're ready to pick up another job, just type `@ShereeBot request-work`.
 Then, I'll message you back when there's a job ready for you.";
 
+const CANCEL: &str = "No problem, I've taken you out of the line for work.
+Whenever you're ready, just type `request-work` again.";
+
+const DROP: &str = "No worries, I've handed that film back so someone else can pick it up.
+When you're ready for more work, just type `request-work`.";
+
+const SEND_BACK_ERR: &str = "Please let me know what needs fixing!
+Ex: `send-back the audio drops out at 2:30`";
+
 const INTERNAL_ERR: &str = "Something went wrong internally - please let Sheree know!";
 
+/// The internal error message, pointing at the logs for whatever went wrong.
+fn internal_err() -> String {
+    format!("{INTERNAL_ERR}{}", correlation::reference())
+}
+
 // const PRI_ERR: &str = "I couldn't read your command :cry:
 // Valid priority weights are `HIGH` and `LOW`.
 // Ex: `insert-films HIGH film1, film2, film3`";
@@ -33,22 +54,22 @@ const NO_WORK: &str = "No work is available yet :cry:
 I'll reply in this thread once I find some work for you!";
 
 impl Manager {
-    pub(crate) fn new(state: State) -> Self {
-        Self { state }
+    pub(crate) fn new(state: State, origin: Origin) -> Self {
+        Self { state, origin }
     }
 
     /// When a request comes in, polls the jobs queue for work to assign.
     /// Returns a formatted response to send back to the user
     #[tracing::instrument(skip(self, ts, channel))]
     pub async fn request_work(&self, slack_id: &str, ts: &str, channel: &str) -> String {
-        match self.state.queue.try_assign_job(slack_id, ts, channel).await {
+        let queue = &self.state.queue;
+        match queue
+            .try_assign_job(slack_id, ts, channel, &self.origin)
+            .await
+        {
             Ok(Some(j)) => {
-                format!(
-                    "<@{}> You've been assigned to work `{}` on `{}`!",
-                    slack_id,
-                    j.role.as_ref(),
-                    j.film_name
-                )
+                let handoff = handoff(&self.state, &j).await;
+                assignment_message(slack_id, &j, handoff.as_ref())
             }
             Ok(None) => NO_WORK.to_string(),
             Err(err) => {
@@ -56,41 +77,183 @@ impl Manager {
                     "You're all done! No more work for you :)".to_string()
                 } else {
                     error!("Error when requesting work: {}", err);
-                    INTERNAL_ERR.to_string()
+                    internal_err()
                 }
             }
         }
     }
 
-    /// Deliver work. On success, attempts to assign out jobs to the wait queue.
+    /// Deliver work, along with any links and notes for the next stage.
+    /// On success, attempts to assign out jobs to the wait queue.
     #[tracing::instrument(skip(self))]
-    pub async fn deliver_work(&self, slack_id: &str) -> String {
+    pub async fn deliver_work(&self, slack_id: &str, text: &str) -> String {
         debug!("Delivering work for {slack_id}");
         let student = match self.state.db.get_student(slack_id).await {
             Ok(u) => u,
             Err(e) => return report_error(e),
         };
+        let (links, notes) = slack::parse_links(text);
+
         let name = student.name.clone();
-        match self.state.queue.deliver(student, slack_id).await {
+        let queue = &self.state.queue;
+        match queue
+            .deliver(student, slack_id, &links, &notes, &self.origin)
+            .await
+        {
             Ok(_) => self.empty_wait_queue().await,
             Err(e) => return report_error(e),
         }
 
-        info!("Successful delivery for {}!", name);
+        info!("Successful delivery for {name}!");
 
         DELIVER.to_string()
     }
 
+    /// Hands a film the student was asked to fix back to whoever sent it back, along with
+    /// any links and notes for them.
+    #[tracing::instrument(skip(self))]
+    pub async fn resolve_revision(&self, slack_id: &str, text: &str) -> String {
+        let student = match self.state.db.get_student(slack_id).await {
+            Ok(s) => s,
+            Err(e) => return report_error(e),
+        };
+        let (links, notes) = slack::parse_links(text);
+
+        let r = match self
+            .state
+            .queue
+            .resolve_revision(&student, &links, &notes, &self.origin)
+            .await
+        {
+            Ok(r) => r,
+            Err(e) => return report_error(e),
+        };
+        info!("{} fixed {}", student.name, r.film_name);
+
+        let mut msg = format!(
+            "<@{}> <@{slack_id}> has fixed `{}`, so it's back with you!
+Once you're done, type `deliver-work` to hand it off.",
+            r.from, r.film_name
+        );
+        msg += &handoff_message(slack_id, r.role, &links, &notes);
+        self.notify(&r.from, msg).await;
+
+        format!(
+            "Thanks for fixing `{}`! I've handed it back to <@{}>.",
+            r.film_name, r.from
+        )
+    }
+
+    /// Sends the student's film back to whoever worked the previous stage, with a note on
+    /// what needs fixing.
+    #[tracing::instrument(skip(self))]
+    pub async fn send_back(&self, slack_id: &str, note: &str) -> String {
+        if note.is_empty() {
+            return SEND_BACK_ERR.to_string();
+        }
+        let mut student = match self.state.db.get_student(slack_id).await {
+            Ok(s) => s,
+            Err(e) => return report_error(e),
+        };
+        let queue = &self.state.queue;
+        let r = match queue.send_back(&mut student, note, &self.origin).await {
+            Ok(r) => r,
+            Err(e) => return report_error(e),
+        };
+        info!("{} sent {} back to {}", student.name, r.film_name, r.to);
+
+        let msg = format!(
+            "<@{}> <@{slack_id}> sent `{}` back to you, as `{}` isn't quite finished:
+> {note}
+Once you've fixed it, type `resolve-revision [links] [notes]` to hand it back.",
+            r.to,
+            r.film_name,
+            r.role.as_ref()
+        );
+        self.notify(&r.to, msg).await;
+
+        format!(
+            "I've sent `{}` back to <@{}> to fix. I'll let you know once it's back with you!",
+            r.film_name, r.to
+        )
+    }
+
+    /// Withdraws a student's request for work, taking them out of the wait queue.
+    #[tracing::instrument(skip(self))]
+    pub async fn cancel(&self, slack_id: &str) -> String {
+        let now = Utc::now();
+        let limiter = &self.state.limiter;
+        if let Err(e) = limiter
+            .check(&self.state.db, slack_id, Action::Cancel, now)
+            .await
+        {
+            return report_error(e);
+        }
+        if let Err(e) = self.state.queue.cancel_wait(slack_id, &self.origin).await {
+            return report_error(e);
+        }
+        limiter
+            .record(&self.state.db, slack_id, Action::Cancel, now)
+            .await;
+
+        info!("{slack_id} cancelled their request for work");
+        let msg = format!("<@{slack_id}> cancelled their request for work.");
+        scheduler::notify_admins(&self.state, &msg).await;
+
+        CANCEL.to_string()
+    }
+
+    /// Hands a student's film back to the jobs queue, where it keeps its original place.
+    #[tracing::instrument(skip(self))]
+    pub async fn drop_work(&self, slack_id: &str) -> String {
+        let now = Utc::now();
+        let limiter = &self.state.limiter;
+        if let Err(e) = limiter
+            .check(&self.state.db, slack_id, Action::Drop, now)
+            .await
+        {
+            return report_error(e);
+        }
+        let mut student = match self.state.db.get_student(slack_id).await {
+            Ok(s) => s,
+            Err(e) => return report_error(e),
+        };
+        if student.current_film.is_none() {
+            return "You don't have any work to drop!".to_string();
+        }
+        let queue = &self.state.queue;
+        let job = match queue
+            .release(&mut student, AuditAction::Drop, &self.origin)
+            .await
+        {
+            Ok(j) => j,
+            Err(e) => return report_error(e),
+        };
+        limiter
+            .record(&self.state.db, slack_id, Action::Drop, now)
+            .await;
+
+        info!("{} dropped {}", student.name, job.film_name);
+        let msg = format!(
+            "<@{slack_id}> dropped `{}`, so it's back in the queue.",
+            job.film_name
+        );
+        scheduler::notify_admins(&self.state, &msg).await;
+        self.empty_wait_queue().await;
+
+        DROP.to_string()
+    }
+
     /// After delivering the work, we'll try to assign jobs out to the wait queue.
     /// This is done in the background via a tokio task.
-    async fn empty_wait_queue(&self) {
+    pub(crate) async fn empty_wait_queue(&self) {
         let s = self.state.clone();
-        tokio::spawn(async move {
-            let (client, token) = (&s.req_client, &s.oauth_token);
-            match s.queue.try_empty_wait_queue().await {
+        let origin = self.origin.clone();
+        self.state.tasks.spawn(async move {
+            match s.queue.try_empty_wait_queue(&origin).await {
                 Ok(jobs) => {
                     for job in jobs {
-                        notify_waiter(client, token, job).await;
+                        notify_waiter(&s, job).await;
                     }
                 }
                 Err(e) => error!("bad things happened: {e}"),
@@ -98,14 +261,86 @@ impl Manager {
         });
     }
 
+    /// Takes a student's film back and returns it to the jobs queue.
+    #[tracing::instrument(skip(self))]
+    pub(crate) async fn unassign(&self, slack_id: &str) -> Result<String> {
+        let mut student = self.state.db.get_student(slack_id).await?;
+        if student.current_film.is_none() {
+            let e = format!("<@{slack_id}> isn't working on anything");
+            return Err(Error::NotFound(e));
+        }
+        let job = self
+            .state
+            .queue
+            .release(&mut student, AuditAction::Unassign, &self.origin)
+            .await?;
+        info!(
+            "Returned {} from {} to the queue",
+            job.film_name, student.name
+        );
+
+        let msg = format!(
+            "<@{slack_id}> `{}` has been handed back to the queue by an admin.
+When you're ready for more work, just type `request-work`.",
+            job.film_name
+        );
+        self.notify(slack_id, msg).await;
+        self.empty_wait_queue().await;
+
+        Ok(format!(
+            "Returned `{}` from <@{slack_id}> to the queue.",
+            job.film_name
+        ))
+    }
+
+    /// Moves a film to a specific student, as long as they're eligible to work on it.
+    #[tracing::instrument(skip(self))]
+    pub(crate) async fn reassign(&self, film_name: &str, slack_id: &str) -> Result<String> {
+        self.move_film(film_name, slack_id, false).await
+    }
+
+    /// Gives a film to a specific student, whether or not they're eligible to work on it.
+    #[tracing::instrument(skip(self))]
+    pub(crate) async fn force_assign(&self, film_name: &str, slack_id: &str) -> Result<String> {
+        self.move_film(film_name, slack_id, true).await
+    }
+
+    async fn move_film(&self, film_name: &str, slack_id: &str, force: bool) -> Result<String> {
+        let mut student = self.state.db.get_student(slack_id).await?;
+        let queue = &self.state.queue;
+        let (job, from) = queue
+            .reassign(film_name, &mut student, force, &self.origin)
+            .await?;
+
+        if let Some(from) = from {
+            let msg = format!(
+                "<@{}> `{film_name}` has been reassigned to someone else by an admin.
+When you're ready for more work, just type `request-work`.",
+                from.slack_id
+            );
+            self.notify(&from.slack_id, msg).await;
+        }
+        let handoff = handoff(&self.state, &job).await;
+        let msg = assignment_message(slack_id, &job, handoff.as_ref());
+        self.notify(slack_id, msg).await;
+
+        Ok(format!(
+            "Assigned `{film_name}` to <@{slack_id}> to work `{}`.",
+            job.role.as_ref()
+        ))
+    }
+
+    /// DMs a student, logging rather than failing if slack can't be reached.
+    async fn notify(&self, slack_id: &str, msg: String) {
+        let res = Response::new(slack_id.to_string(), msg, None);
+        if let Err(e) = self.state.slack.post(&res).await {
+            error!("Failed to message {slack_id}: {e}");
+        }
+    }
+
     /// Insert one film to the database.
-    pub async fn insert_film(
-        &self,
-        film_name: &str,
-        group: i32,
-        priority: Priority,
-    ) -> Result<Film> {
-        insert_film(self.state.clone(), film_name, group, priority).await
+    pub async fn insert_film(&self, film: &Film) -> Result<Film> {
+        insert_film(self.state.clone(), self.origin.clone(), film).await
     }
 
     /// Insert empty film to the database and to the jobs_q
@@ -118,10 +353,8 @@ impl Manager {
             .clone()
             .into_iter()
             .map(|f| {
-                let s = self.state.clone();
-                tokio::spawn(
-                    async move { insert_film(s, &f.name, f.group_number, f.priority).await },
-                )
+                let (s, origin) = (self.state.clone(), self.origin.clone());
+                tokio::spawn(async move { insert_film(s, origin, &f).await })
             })
             .collect();
 
@@ -238,7 +471,9 @@ impl Manager {
                     .map(Into::into)
                     .collect();
 
-                messages.push(self.insert_films(v).await);
+                let msg = self.insert_films(v).await;
+                self.audit_import(file, &msg).await;
+                messages.push(msg);
             } else if file.name.contains("student") {
                 info!("downloading students csv into db");
                 let v: Vec<Student> =
@@ -248,22 +483,25 @@ impl Manager {
                         .map(Into::into)
                         .collect();
 
-                messages.push(self.insert_students_from_csv(v).await)
+                let msg = self.insert_students_from_csv(v).await;
+                self.audit_import(file, &msg).await;
+                messages.push(msg);
             }
         }
 
         Ok(messages.into_iter().map(|m| m + "\n").collect())
     }
+
+    async fn audit_import(&self, file: &File, result: &str) {
+        let mut entry = self.origin.entry(AuditAction::Import, None, None);
+        entry.after = Some(json!({ "file": file.name, "result": result }));
+        audit::record(&self.state, entry).await;
+    }
 }
 
-async fn insert_film(
-    state: State,
-    film_name: &str,
-    group: i32,
-    priority: Priority,
-) -> Result<Film> {
-    match state.db.insert_film(film_name, group, priority).await {
-        Ok(f) => match state.queue.insert_job(&f, "").await {
+async fn insert_film(state: State, origin: Origin, film: &Film) -> Result<Film> {
+    match state.db.insert_film(film).await {
+        Ok(f) => match state.queue.insert_job(&f, "", &origin).await {
             Ok(_) => Ok(f),
             Err(e) => {
                 error!("Error inserting to queue: {e}");
@@ -278,29 +516,68 @@ async fn insert_film(
 }
 
 /// NOTE: You must manually attach a student slack id to the job when calling this!
-async fn notify_waiter(client: &reqwest::Client, token: &str, job: QueueItem) {
+async fn notify_waiter(state: &State, job: QueueItem) {
     info!("Notifying waiter: assigned out {}", job.film_name);
 
-    let msg = format!(
+    let handoff = handoff(state, &job).await;
+    let msg = assignment_message(&job.student_slack_id, &job, handoff.as_ref());
+    let res = Response::new(job.channel.unwrap(), msg, job.msg_ts);
+
+    if let Err(e) = state.slack.post(&res).await {
+        error!("{e}");
+    }
+}
+
+/// Looks up what the previous stage handed on to this job, if anything.
+/// Errors are only logged, as they shouldn't hold up an assignment.
+async fn handoff(state: &State, job: &QueueItem) -> Option<Delivery> {
+    let role = job.role.prev()?;
+    match state.db.get_delivery(&job.film_name, role).await {
+        Ok(d) => d,
+        Err(e) => {
+            error!("Failed to look up handoff for {}: {e}", job.film_name);
+            None
+        }
+    }
+}
+
+fn assignment_message(slack_id: &str, job: &QueueItem, handoff: Option<&Delivery>) -> String {
+    let mut msg = format!(
         "<@{}> You've been assigned to work `{}` on `{}`!",
-        job.student_slack_id,
+        slack_id,
         job.role.as_ref(),
         job.film_name
     );
-    let res = Response::new(job.channel.unwrap(), msg, job.msg_ts);
-    let send = client
-        .post("https://slack.com/api/chat.postMessage")
-        .bearer_auth(token)
-        .json(&res)
-        .send()
-        .await;
-
-    if let Err(e) = send {
-        error!("{e}");
+    if job.is_at_risk(Utc::now()) {
+        msg += "\n:warning: This film is behind schedule and may miss its due date.";
+    }
+    if let Some(d) = handoff {
+        msg += &handoff_message(&d.student, d.role, &d.links, &d.notes);
     }
+    msg
 }
 
-fn report_error(e: Error) -> String {
-    error!("{e}");
-    INTERNAL_ERR.to_string()
+/// Formats the notes and links left by the student who delivered a stage.
+fn handoff_message(slack_id: &str, role: Role, links: &[String], notes: &str) -> String {
+    let mut msg = String::new();
+    if !notes.is_empty() {
+        msg += &format!("\nNotes from <@{slack_id}> on `{}`:", role.as_ref());
+        notes.lines().for_each(|l| msg += &format!("\n> {l}"));
+    }
+    if !links.is_empty() {
+        msg += "\nLinks:";
+        links.iter().for_each(|l| msg += &format!("\n{l}"));
+    }
+    msg
+}
+
+/// Passes expected errors through to the user, and hides internal ones.
+pub(crate) fn report_error(e: Error) -> String {
+    match e {
+        Error::InvalidArg(msg) | Error::NotFound(msg) => msg,
+        e => {
+            error!("{e}");
+            internal_err()
+        }
+    }
 }
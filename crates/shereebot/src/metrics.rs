@@ -0,0 +1,194 @@
+//! Prometheus metrics, served at `/metrics`.
+//!
+//! Counters and histograms are recorded as things happen, from wherever they happen. Queue depths
+//! and pool usage are read off the server's state each time the metrics are scraped.
+use std::time::Duration;
+
+use chrono::Utc;
+use once_cell::sync::Lazy;
+use prometheus::{
+    core::Collector, Encoder, HistogramOpts, HistogramVec, IntCounterVec, IntGaugeVec, Opts,
+    Registry, TextEncoder,
+};
+
+use crate::{queue::QueueItem, server::InnerState, Error, Result};
+use models::Role;
+
+/// Waits run from minutes to days, so the buckets do too: 1m, 10m, 1h, 6h, 12h, 1d, 2d, 4d, 1w.
+const WAIT_BUCKETS: &[f64] = &[
+    60.0, 600.0, 3600.0, 21600.0, 43200.0, 86400.0, 172800.0, 345600.0, 604800.0,
+];
+
+static REGISTRY: Lazy<Registry> = Lazy::new(Registry::new);
+
+static QUEUE_DEPTH: Lazy<IntGaugeVec> = Lazy::new(|| {
+    let opts = Opts::new("shbot_queue_depth", "Items in each queue.");
+    register(IntGaugeVec::new(opts, &["queue", "role", "priority"]).unwrap())
+});
+
+static ASSIGNMENTS: Lazy<IntCounterVec> = Lazy::new(|| {
+    let opts = Opts::new("shbot_assignments_total", "Films handed out to students.");
+    register(IntCounterVec::new(opts, &["role"]).unwrap())
+});
+
+static DELIVERIES: Lazy<IntCounterVec> = Lazy::new(|| {
+    let opts = Opts::new("shbot_deliveries_total", "Stages delivered by students.");
+    register(IntCounterVec::new(opts, &["role"]).unwrap())
+});
+
+static QUEUE_WAIT: Lazy<HistogramVec> = Lazy::new(|| {
+    let opts = HistogramOpts::new(
+        "shbot_queue_wait_seconds",
+        "How long jobs and students sat in their queue before being matched up.",
+    )
+    .buckets(WAIT_BUCKETS.to_vec());
+    register(HistogramVec::new(opts, &["queue", "role"]).unwrap())
+});
+
+static SLACK_LATENCY: Lazy<HistogramVec> = Lazy::new(|| {
+    let opts = HistogramOpts::new(
+        "shbot_slack_request_duration_seconds",
+        "How long calls to the Slack API took.",
+    );
+    register(HistogramVec::new(opts, &["method"]).unwrap())
+});
+
+static SLACK_FAILURES: Lazy<IntCounterVec> = Lazy::new(|| {
+    let opts = Opts::new(
+        "shbot_slack_request_failures_total",
+        "Calls to the Slack API which failed or were refused.",
+    );
+    register(IntCounterVec::new(opts, &["method"]).unwrap())
+});
+
+static DB_POOL: Lazy<IntGaugeVec> = Lazy::new(|| {
+    let opts = Opts::new(
+        "shbot_db_pool_connections",
+        "Postgres connections: the pool's max size, its current size, how many are free, and \
+         how many requests are waiting for one.",
+    );
+    register(IntGaugeVec::new(opts, &["state"]).unwrap())
+});
+
+static HTTP_REQUESTS: Lazy<IntCounterVec> = Lazy::new(|| {
+    let opts = Opts::new("shbot_http_requests_total", "HTTP requests served.");
+    register(IntCounterVec::new(opts, &["status"]).unwrap())
+});
+
+static HTTP_LATENCY: Lazy<HistogramVec> = Lazy::new(|| {
+    let opts = HistogramOpts::new(
+        "shbot_http_request_duration_seconds",
+        "How long HTTP requests took to serve.",
+    );
+    register(HistogramVec::new(opts, &["status"]).unwrap())
+});
+
+fn register<T: Collector + Clone + 'static>(metric: T) -> T {
+    REGISTRY
+        .register(Box::new(metric.clone()))
+        .expect("metric names are unique");
+    metric
+}
+
+/// Records a job being handed out, and how long it was queued for.
+pub(crate) fn assigned(job: &QueueItem) {
+    ASSIGNMENTS.with_label_values(&[job.role.as_ref()]).inc();
+    observe_wait("jobs", job);
+}
+
+/// Records a waiting student being given a job, and how long they waited for it.
+pub(crate) fn served(waiter: &QueueItem) {
+    observe_wait("wait", waiter);
+}
+
+/// Records a stage being delivered.
+pub(crate) fn delivered(role: Role) {
+    DELIVERIES.with_label_values(&[role.as_ref()]).inc();
+}
+
+/// Records a call to the Slack API, by its method name, e.g. `chat.postMessage`.
+pub(crate) fn slack_call(method: &str, latency: Duration, ok: bool) {
+    SLACK_LATENCY
+        .with_label_values(&[method])
+        .observe(latency.as_secs_f64());
+    if !ok {
+        SLACK_FAILURES.with_label_values(&[method]).inc();
+    }
+}
+
+/// Records an HTTP request being served.
+pub(crate) fn http_response(status: u16, latency: Duration) {
+    let status = status.to_string();
+    HTTP_REQUESTS.with_label_values(&[&status]).inc();
+    HTTP_LATENCY
+        .with_label_values(&[&status])
+        .observe(latency.as_secs_f64());
+}
+
+fn observe_wait(queue: &str, item: &QueueItem) {
+    let waited = (Utc::now() - item.created_at).num_milliseconds().max(0);
+    QUEUE_WAIT
+        .with_label_values(&[queue, item.role.as_ref()])
+        .observe(waited as f64 / 1000.0);
+}
+
+/// Every metric in Prometheus' text format, with the queue and pool gauges brought up to date.
+pub(crate) async fn gather(state: &InnerState) -> Result<String> {
+    QUEUE_DEPTH.reset();
+    for (queue, q) in [("jobs", &state.queue.jobs_q), ("wait", &state.queue.wait_q)] {
+        for item in q.lock().await.iter() {
+            let priority = item.priority.as_ref().map_or("NONE", AsRef::as_ref);
+            QUEUE_DEPTH
+                .with_label_values(&[queue, item.role.as_ref(), priority])
+                .inc();
+        }
+    }
+
+    if let Some(status) = state.db.pool_status() {
+        for (label, value) in [
+            ("max", status.max_size as i64),
+            ("size", status.size as i64),
+            ("available", status.available.max(0) as i64),
+            ("waiting", (-status.available).max(0) as i64),
+        ] {
+            DB_POOL.with_label_values(&[label]).set(value);
+        }
+    }
+
+    let mut buf = vec![];
+    TextEncoder::new()
+        .encode(&REGISTRY.gather(), &mut buf)
+        .map_err(|e| Error::Unknown(e.to_string()))?;
+    String::from_utf8(buf).map_err(|e| Error::Unknown(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::queue;
+    use models::{Film, Priority};
+
+    #[tokio::test]
+    async fn check_gather() {
+        let state = InnerState::_new();
+        let film = Film::new("a", Priority::High, 1);
+        let mut job = queue::new_job(&film, "");
+        job.role = Role::Editor;
+        job.created_at = Utc::now() - chrono::Duration::hours(2);
+        state.queue.jobs_q.lock().await.push(job.clone());
+        assigned(&job);
+        delivered(Role::Ae);
+
+        let text = gather(&state).await.unwrap();
+        let depth = r#"shbot_queue_depth{priority="HIGH",queue="jobs",role="EDITOR"} 1"#;
+        assert!(text.contains(depth), "{text}");
+        assert!(text.contains(r#"shbot_assignments_total{role="EDITOR"}"#));
+        assert!(text.contains(r#"shbot_deliveries_total{role="AE"}"#));
+        assert!(text.contains("shbot_queue_wait_seconds_bucket"));
+
+        // Emptied queues don't leave stale depths behind.
+        state.queue.jobs_q.lock().await.clear();
+        let text = gather(&state).await.unwrap();
+        assert!(!text.contains("shbot_queue_depth{"));
+    }
+}
@@ -1,58 +1,326 @@
-use std::collections::{BinaryHeap, HashSet};
-use std::{cmp::Ordering, sync::Arc};
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::{cmp::Ordering, fmt, sync::Arc};
 
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, Duration, Utc};
 use color_eyre::eyre::eyre;
-use futures::lock::Mutex;
-use tracing::info;
+use futures::{future, lock::Mutex};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use strum::{AsRefStr, EnumString};
+use tracing::{error, info, warn};
 use uuid::Uuid;
 
-use crate::{store::Database, Error, Result};
-use models::{Film, Priority, Role, Student};
+use crate::{
+    audit::{self, Origin},
+    metrics,
+    store::Database,
+    Error, Result,
+};
+use models::{
+    Assignment, AuditAction, AuditEntry, Delivery, Film, Outcome, Priority, Revision, Role, Student,
+};
+
+mod matching;
 
 #[derive(Debug)]
 pub(crate) struct Queue {
     pub jobs_q: Q,
     pub wait_q: Q,
     db: Database,
+    aging: AgingPolicy,
+    ordering: JobOrdering,
 }
 
 type Q = Arc<Mutex<BinaryHeap<QueueItem>>>;
 
 // TODO: we didn't strongly type queue item variants and now it's annoying. maybe fix this?
 /// Non-generic queue, works for films and students both.
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
 pub struct QueueItem {
     pub id: Uuid,
     pub student_slack_id: String,
     pub film_name: String,
     pub role: Role,
+    pub group_number: Option<i32>,
+    pub class: Option<String>,
     pub priority: Option<Priority>,
+    /// Priority after aging has been applied. Only set while the item sits in a queue.
+    pub effective_priority: Option<Priority>,
+    /// Round-robin turn among jobs of the same priority. Only set while the item sits in a
+    /// queue with a fair `JobOrdering`.
+    pub round: Option<u32>,
+    /// Latest time work on this job can start without the film missing its due date.
+    pub deadline: Option<DateTime<Utc>>,
     pub msg_ts: Option<String>,
     pub channel: Option<String>,
     pub created_at: DateTime<Utc>,
 }
 
+impl QueueItem {
+    /// Time left before this job has to be started, if its film has a due date.
+    pub fn slack(&self, now: DateTime<Utc>) -> Option<Duration> {
+        self.deadline.map(|deadline| deadline - now)
+    }
+
+    /// Whether a job started at `now` is projected to miss its film's due date.
+    pub fn is_at_risk(&self, now: DateTime<Utc>) -> bool {
+        self.slack(now)
+            .map_or(false, |slack| slack < Duration::zero())
+    }
+}
+
+/// Raises the priority of jobs the longer they wait, so low priority films can't starve.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AgingPolicy {
+    /// How long a job waits before its priority is raised by a level.
+    /// Aging is disabled if unset.
+    pub interval: Option<Duration>,
+}
+
+impl AgingPolicy {
+    /// Priority levels, from lowest to highest.
+    const LEVELS: [Priority; 2] = [Priority::Low, Priority::High];
+
+    pub fn new(cfg: &crate::config::Queue) -> Self {
+        Self {
+            interval: cfg.aging_hours.map(Duration::hours),
+        }
+    }
+
+    /// Effective priority of a job created at `created_at`, as of `now`.
+    pub fn effective_priority(
+        &self,
+        priority: Priority,
+        created_at: DateTime<Utc>,
+        now: DateTime<Utc>,
+    ) -> Priority {
+        let interval = match self.interval {
+            Some(i) if i > Duration::zero() => i,
+            _ => return priority,
+        };
+        let steps = ((now - created_at).num_seconds() / interval.num_seconds()).max(0);
+
+        let level = Self::LEVELS.iter().position(|&p| p == priority);
+        let level = level.unwrap_or_default() as i64 + steps;
+        let level = level.min(Self::LEVELS.len() as i64 - 1);
+        Self::LEVELS[level as usize]
+    }
+
+    /// Recomputes every item's effective priority and rebuilds the heap.
+    /// Effective priorities drift as time passes, so this must be called before reading from it.
+    pub fn apply(&self, q: &mut BinaryHeap<QueueItem>, now: DateTime<Utc>) {
+        let items = std::mem::take(q).into_vec();
+        *q = items
+            .into_iter()
+            .map(|mut item| {
+                item.effective_priority = item
+                    .priority
+                    .map(|p| self.effective_priority(p, item.created_at, now));
+                item
+            })
+            .collect();
+    }
+}
+
+/// How jobs of the same priority are ordered against each other.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, AsRefStr, EnumString, Deserialize)]
+#[strum(serialize_all = "lowercase", ascii_case_insensitive)]
+#[serde(rename_all = "lowercase")]
+pub enum JobOrdering {
+    /// Earliest deadline, then oldest job first.
+    Priority,
+    /// Take turns between groups, so one group's films can't fill up the front of the queue.
+    Group,
+    /// Like `Group`, but groups from different classes get their own turns.
+    Class,
+}
+
+impl Default for JobOrdering {
+    fn default() -> Self {
+        Self::Priority
+    }
+}
+
+impl JobOrdering {
+    /// The lane a job takes turns in.
+    fn lane(&self, item: &QueueItem) -> (Option<String>, Option<i32>) {
+        match self {
+            Self::Class => (item.class.clone(), item.group_number),
+            _ => (None, item.group_number),
+        }
+    }
+
+    /// Assigns every item its round-robin turn within its role and priority, and rebuilds the
+    /// heap. Each role takes turns separately, as its jobs only go to students on that role.
+    pub fn apply(&self, q: &mut BinaryHeap<QueueItem>) {
+        let mut items = std::mem::take(q).into_vec();
+        items.iter_mut().for_each(|item| item.round = None);
+
+        if *self != Self::Priority {
+            // Highest first, so each lane's turns are handed out in its own queue order.
+            items.sort_by(|a, b| b.cmp(a));
+
+            let mut turns = HashMap::new();
+            for item in &mut items {
+                let priority = item.effective_priority.or(item.priority);
+                let key = (item.role, priority, self.lane(item));
+                let turn = turns.entry(key).or_insert(0);
+                item.round = Some(*turn);
+                *turn += 1;
+            }
+        }
+        *q = items.into_iter().collect();
+    }
+}
+
+/// A job as reported in queue listings.
+#[derive(Debug, Clone, Serialize)]
+pub struct JobListing {
+    #[serde(flatten)]
+    pub job: QueueItem,
+    pub slack_hours: Option<i64>,
+    pub at_risk: bool,
+}
+
+impl JobListing {
+    fn new(job: QueueItem, now: DateTime<Utc>) -> Self {
+        let slack_hours = job.slack(now).map(|s| s.num_hours());
+        let at_risk = job.is_at_risk(now);
+        Self {
+            job,
+            slack_hours,
+            at_risk,
+        }
+    }
+}
+
 impl PartialOrd for QueueItem {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
         Some(self.cmp(other))
     }
 }
 
+// Missing fields always sort the same way, so the order stays total whatever's set: no priority
+// is below every priority, and no round or deadline comes after every round or deadline.
 impl Ord for QueueItem {
     fn cmp(&self, other: &Self) -> Ordering {
-        if let (Some(prio), Some(prio_other)) = (self.priority, other.priority) {
-            match prio.cmp(&prio_other) {
-                Ordering::Equal => {} // If priority is equal, check timestamp.
-                ord => return ord,
-            }
+        let priority = |item: &Self| item.effective_priority.or(item.priority);
+        let round = |item: &Self| item.round.unwrap_or(u32::MAX);
+
+        priority(self)
+            .cmp(&priority(other))
+            // Lower rounds first, so groups take turns.
+            .then_with(|| round(other).cmp(&round(self)))
+            // Earliest deadline first: the job with the least slack is the most urgent.
+            .then_with(|| match (self.deadline, other.deadline) {
+                (Some(deadline), Some(deadline_other)) => deadline_other.cmp(&deadline),
+                (Some(_), None) => Ordering::Greater,
+                (None, Some(_)) => Ordering::Less,
+                (None, None) => Ordering::Equal,
+            })
+            .then_with(|| other.created_at.cmp(&self.created_at))
+            .then_with(|| other.film_name.cmp(&self.film_name))
+    }
+}
+
+/// Everything needed to decide whether a student may take a given job.
+#[derive(Debug)]
+pub(crate) struct Eligibility {
+    role: Role,
+    worked_films: HashSet<String>,
+    unique_films_exist: bool,
+}
+
+impl Eligibility {
+    /// Only assign if role is correct.
+    /// Then, only assign if student hasn't worked on the film before,
+    /// BUT assign anyways if there are no unique films left.
+    pub(crate) fn allows(&self, job: &QueueItem) -> bool {
+        self.allows_film(&job.film_name, job.role)
+    }
+
+    /// Whether the student may work `role` on the given film.
+    pub(crate) fn allows_film(&self, film_name: &str, role: Role) -> bool {
+        self.rejects(film_name, role).is_none()
+    }
+
+    /// Why the student may not work `role` on the given film, if they may not.
+    fn rejects(&self, film_name: &str, role: Role) -> Option<Rejection> {
+        if role != self.role {
+            return Some(Rejection::WrongRole);
+        }
+        let worked_on_film = self.worked_films.contains(film_name);
+        (worked_on_film && self.unique_films_exist).then(|| Rejection::WorkedBefore)
+    }
+}
+
+/// Why a job wasn't handed to a student.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, AsRefStr)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum Rejection {
+    /// The job is for a different stage than the one the student is on.
+    WrongRole,
+    /// The student has worked on the film before, and there are films outside their group they
+    /// haven't.
+    WorkedBefore,
+    /// The student could take the job, but a job ahead of it in the queue was picked instead.
+    LowerPriority,
+}
+
+/// A job looked at while working out what a student would be given.
+#[derive(Debug, Clone, Serialize)]
+pub struct Considered {
+    #[serde(flatten)]
+    pub job: QueueItem,
+    /// Whether the film is from the student's own group. That doesn't rule it out, but it
+    /// decides whether films they've worked on before are off limits.
+    pub own_group: bool,
+    /// Why the student wouldn't get this job. None for the job they would get.
+    pub rejected: Option<Rejection>,
+}
+
+/// What requesting work would do for a student, worked out without changing anything.
+#[derive(Debug, Clone, Serialize)]
+pub struct DryRun {
+    pub student: String,
+    pub role: Role,
+    /// The job they'd be given, or None if they'd join the wait queue.
+    pub job: Option<QueueItem>,
+    /// Every job in the queue, in the order they're considered.
+    pub considered: Vec<Considered>,
+}
+
+impl fmt::Display for DryRun {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let student = &self.student;
+        match (&self.job, self.role) {
+            (_, Role::Done) => return write!(f, "<@{student}> is done, so wouldn't get any work."),
+            (Some(job), role) => write!(
+                f,
+                "<@{student}> would be given `{}` to work {}.",
+                job.film_name,
+                role.as_ref()
+            )?,
+            (None, role) => write!(
+                f,
+                "<@{student}> would wait for {} work, as no job fits.",
+                role.as_ref()
+            )?,
         }
 
-        match other.created_at.cmp(&self.created_at) {
-            Ordering::Equal => {}
-            ord => return ord,
+        for c in &self.considered {
+            let reason = c.rejected.as_ref().map_or("picked", AsRef::as_ref);
+            let own_group = if c.own_group { ", own group" } else { "" };
+            write!(
+                f,
+                "\n`{}` ({}{own_group}): {}",
+                c.job.film_name,
+                c.job.role.as_ref(),
+                reason.replace('_', " ")
+            )?;
         }
-        other.film_name.cmp(&self.film_name)
+        Ok(())
     }
 }
 
@@ -61,69 +329,352 @@ impl Queue {
         Self {
             jobs_q: Arc::new(Mutex::new(BinaryHeap::new())),
             wait_q: Arc::new(Mutex::new(BinaryHeap::new())),
-            db: crate::store::new_mock(),
+            db: crate::store::new_memory(),
+            aging: AgingPolicy::default(),
+            ordering: JobOrdering::default(),
         }
     }
 }
 
 impl Queue {
-    pub(crate) async fn from_db(db: Database) -> Result<Self> {
+    pub(crate) async fn from_db(db: Database, cfg: &crate::config::Queue) -> Result<Self> {
         let wait_q = db.get_queue(true).await?.into_iter().collect();
         let film_q = db.get_queue(false).await?.into_iter().collect();
         Ok(Self {
             jobs_q: Arc::new(Mutex::new(film_q)),
             wait_q: Arc::new(Mutex::new(wait_q)),
             db,
+            aging: AgingPolicy::new(cfg),
+            ordering: cfg.ordering,
         })
     }
 
+    /// Re-reads both queues from the database, e.g. after they've been repaired.
+    pub(crate) async fn reload(&self) -> Result<()> {
+        let wait_q = self.db.get_queue(true).await?.into_iter().collect();
+        let jobs_q = self.db.get_queue(false).await?.into_iter().collect();
+        *self.wait_q.lock().await = wait_q;
+        *self.jobs_q.lock().await = jobs_q;
+        Ok(())
+    }
+
     /// Updates film/student roles and adds film to the jobs_q.
-    pub(crate) async fn deliver(&self, mut student: Student, slack_id: &str) -> Result<()> {
-        let curr_film = match student.current_film {
-            Some(ref f) => f,
-            None => return Err(Error::Internal(eyre!("Impossible state"))),
+    pub(crate) async fn deliver(
+        &self,
+        mut student: Student,
+        slack_id: &str,
+        links: &[String],
+        notes: &str,
+        origin: &Origin,
+    ) -> Result<()> {
+        let mut film = self.current_film(&student).await?;
+        check_not_paused(&student)?;
+
+        let delivery = new_delivery(&film.name, film.current_role, &student, links, notes);
+        let role = film.current_role;
+        let before = student.clone();
+        film.increment_role(&student.name);
+        student.increment_role(&film.name);
+        student.unassign();
+
+        // Finished films have no more stages to queue.
+        let job = (film.current_role != Role::Done).then(|| new_job(&film, slack_id));
+        let mut entry = origin.entry(AuditAction::Deliver, Some(slack_id), Some(&film.name));
+        entry.before = audit::snapshot(&before);
+        entry.after = audit::snapshot(&student);
+        self.db
+            .deliver_film(&student, &film, job.as_ref(), delivery.as_ref(), &entry)
+            .await?;
+        metrics::delivered(role);
+        if let Some(job) = job {
+            warn_if_at_risk(&job);
+            self.jobs_q.lock().await.push(job);
+        }
+
+        Ok(())
+    }
+
+    /// Takes a student's film away and returns it to the jobs_q at its original priority.
+    /// The job keeps the time it was first queued, so it doesn't lose its place.
+    pub(crate) async fn release(
+        &self,
+        student: &mut Student,
+        action: AuditAction,
+        origin: &Origin,
+    ) -> Result<QueueItem> {
+        let film = self.current_film(student).await?;
+        check_not_paused(student)?;
+        let mut job = new_job(&film, "");
+        if let Some(queued_at) = film.queued_at {
+            job.created_at = queued_at;
+        }
+
+        let mut entry = origin.entry(action, Some(&student.slack_id), Some(&film.name));
+        entry.before = audit::snapshot(student);
+        entry.after = audit::snapshot(&job);
+        self.db.release_film(student, &film, &job, &entry).await?;
+        student.unassign();
+        self.jobs_q.lock().await.push(job.clone());
+
+        Ok(job)
+    }
+
+    /// Sends a student's film back to whoever worked the previous stage, and pauses the
+    /// student's assignment until it's fixed.
+    pub(crate) async fn send_back(
+        &self,
+        student: &mut Student,
+        note: &str,
+        origin: &Origin,
+    ) -> Result<Revision> {
+        let mut film = self.current_film(student).await?;
+        check_not_paused(student)?;
+
+        let role = match film.current_role.prev() {
+            Some(r) => r,
+            None => {
+                let e = format!(
+                    "`{}` is on its first stage, so there's nothing to send back!",
+                    film.name
+                );
+                return Err(Error::InvalidArg(e));
+            }
         };
+        let to = self.previous_student(&film, role).await?;
+        if to.id == student.id {
+            let e = format!(
+                "You worked `{}` on `{}` yourself!",
+                role.as_ref(),
+                film.name
+            );
+            return Err(Error::InvalidArg(e));
+        }
 
-        let mut film = match self.db.get_film(curr_film).await? {
+        film.reopen(role);
+        let revision = Revision {
+            id: Uuid::new_v4(),
+            film_name: film.name.clone(),
+            role,
+            from: student.slack_id.clone(),
+            to: to.slack_id,
+            note: note.to_string(),
+            created_at: Utc::now(),
+            resolved_at: None,
+        };
+        let mut entry = origin.entry(
+            AuditAction::SendBack,
+            Some(&student.slack_id),
+            Some(&film.name),
+        );
+        entry.before = audit::snapshot(student);
+        entry.after = audit::snapshot(&revision);
+        self.db.send_back(&film, &revision, &entry).await?;
+        student.paused = true;
+
+        Ok(revision)
+    }
+
+    /// Hands a fixed film back to the student who sent it back.
+    pub(crate) async fn resolve_revision(
+        &self,
+        student: &Student,
+        links: &[String],
+        notes: &str,
+        origin: &Origin,
+    ) -> Result<Revision> {
+        let revision = match self.db.get_revision(&student.slack_id).await? {
+            Some(r) => r,
+            None => {
+                let e = "You haven't been asked to fix anything!".to_string();
+                return Err(Error::NotFound(e));
+            }
+        };
+        let mut film = match self.db.get_film(&revision.film_name).await? {
             Some(f) => f,
             None => return Err(Error::Internal(eyre!("Impossible state"))),
         };
 
-        film.increment_role();
-        student.increment_role();
-        self.db.update_film(&film).await?;
-        self.db.update_student(&student).await?;
-        self.insert_job(&film, slack_id).await?;
+        let delivery = new_delivery(&film.name, revision.role, student, links, notes);
+        film.increment_role(&student.name);
+        let mut entry = origin.entry(
+            AuditAction::Deliver,
+            Some(&student.slack_id),
+            Some(&film.name),
+        );
+        entry.before = audit::snapshot(student);
+        entry.after = audit::snapshot(&revision);
+        self.db
+            .resolve_revision(&film, &revision, delivery.as_ref(), &entry)
+            .await?;
 
-        Ok(())
+        Ok(revision)
+    }
+
+    /// Finds the student who last handed on the given role of a film.
+    async fn previous_student(&self, film: &Film, role: Role) -> Result<Student> {
+        let history = self.db.get_film_history(&film.name).await?;
+        let handed_on = |a: &&Assignment| {
+            a.role == role && matches!(a.outcome, Some(Outcome::Delivered | Outcome::SentBack))
+        };
+        if let Some(a) = history.iter().rev().find(handed_on) {
+            let students = self.db.list_students().await?;
+            if let Some(s) = students.into_iter().find(|s| s.id == a.student_id) {
+                return Ok(s);
+            }
+        }
+
+        let e = format!(
+            "I couldn't find who worked `{}` on `{}`",
+            role.as_ref(),
+            film.name
+        );
+        Err(Error::NotFound(e))
+    }
+
+    /// Removes a student's request for work from the wait_q.
+    pub(crate) async fn cancel_wait(&self, slack_id: &str, origin: &Origin) -> Result<QueueItem> {
+        let mut wait_q = self.wait_q.lock().await;
+
+        let (mut waits, rest): (Vec<_>, Vec<_>) = std::mem::take(&mut *wait_q)
+            .into_iter()
+            .partition(|w| w.student_slack_id == slack_id);
+        wait_q.extend(rest);
+
+        let waiter = match waits.pop() {
+            Some(w) => w,
+            None => {
+                let e = "You aren't waiting for any work!";
+                return Err(Error::NotFound(e.into()));
+            }
+        };
+        let mut entry = origin.entry(AuditAction::Cancel, Some(slack_id), None);
+        entry.before = audit::snapshot(&waiter);
+        let cancel = self.db.delete_from_queue(&waiter.id, true, Some(&entry));
+        // There should only ever be one, but clear out any strays too.
+        let strays = waits
+            .iter()
+            .map(|w| self.db.delete_from_queue(&w.id, true, None));
+        if let Err(e) = future::try_join(cancel, future::try_join_all(strays)).await {
+            waits.push(waiter);
+            wait_q.extend(waits);
+            return Err(e);
+        }
+
+        Ok(waiter)
+    }
+
+    async fn current_film(&self, student: &Student) -> Result<Film> {
+        let curr_film = match student.current_film {
+            Some(ref f) => f,
+            None => {
+                let e = "You don't have any work to deliver!";
+                return Err(Error::NotFound(e.into()));
+            }
+        };
+
+        match self.db.get_film(curr_film).await? {
+            Some(f) => Ok(f),
+            None => Err(Error::Internal(eyre!("Impossible state"))),
+        }
     }
 
     /// Returns Vec of jobs.
     /// We must manually attach student slack id to the successes so we can
     /// notify them in the async case.
-    pub(crate) async fn try_empty_wait_queue(&self) -> Result<Vec<QueueItem>> {
+    ///
+    /// Rather than serving waiters one at a time, all waiters and jobs are matched up at once so
+    /// that an early waiter can't take the only film a later waiter could work on.
+    pub(crate) async fn try_empty_wait_queue(&self, origin: &Origin) -> Result<Vec<QueueItem>> {
         let mut wait_q = self.wait_q.lock().await;
+        let mut jobs_q = self.jobs_q.lock().await;
+
+        // Both lists are in priority order, which the matching uses as preference order.
+        self.refresh(&mut jobs_q);
+        let waiters = pop_all(&mut wait_q);
+        let jobs = pop_all(&mut jobs_q);
+
+        let mut students = vec![];
+        let mut edges = vec![];
+        for waiter in &waiters {
+            let res = self.find_eligible_jobs(waiter, &jobs).await;
+            let (student, eligible) = match res {
+                Ok(r) => r,
+                Err(e) => {
+                    wait_q.extend(waiters);
+                    jobs_q.extend(jobs);
+                    return Err(e);
+                }
+            };
+            students.push(student);
+            edges.push(eligible);
+        }
+
+        let matches = matching::max_matching(&edges, jobs.len());
+
+        let mut jobs: Vec<_> = jobs.into_iter().map(Some).collect();
         let mut successes = vec![];
-        let mut recycle = vec![];
 
-        while let Some(waiter) = wait_q.pop() {
-            let id = &waiter.student_slack_id;
-            let ts = &waiter.msg_ts.clone().unwrap_or_default();
-            let channel = &waiter.channel.clone().unwrap_or_default();
-            match self.try_assign_job(id, ts, channel).await {
-                Ok(None) => recycle.push(waiter),
-                Ok(Some(mut job)) => {
-                    job.student_slack_id = id.to_string();
-                    successes.push(job)
+        let pairs = waiters.into_iter().zip(students).zip(matches);
+        for ((waiter, mut student), matched) in pairs {
+            let job = match matched.and_then(|j| jobs[j].take()) {
+                Some(job) => job,
+                None => {
+                    wait_q.push(waiter);
+                    continue;
                 }
-                Err(e) => return Err(e),
             };
+
+            let served = QueueItem {
+                student_slack_id: waiter.student_slack_id.clone(),
+                msg_ts: waiter.msg_ts.clone(),
+                channel: waiter.channel.clone(),
+                ..job.clone()
+            };
+            let mut entry = origin.entry(
+                AuditAction::Drain,
+                Some(&waiter.student_slack_id),
+                Some(&job.film_name),
+            );
+            entry.before = audit::snapshot(&student);
+            entry.after = audit::snapshot(&served);
+            if let Err(e) = self.assign(&mut student, &job, &entry).await {
+                error!(
+                    "Failed to assign {} to {}: {e}",
+                    job.film_name, student.name
+                );
+                wait_q.push(waiter);
+                jobs_q.push(job);
+                continue;
+            }
+            info!("Assigned {} to {}", student.name, job.film_name);
+            warn_if_at_risk(&job);
+            metrics::served(&waiter);
+            successes.push(served);
         }
-        wait_q.extend(recycle);
+        jobs_q.extend(jobs.into_iter().flatten());
 
         Ok(successes)
     }
 
+    /// Returns the waiting student, along with the index of every job they're allowed to take.
+    async fn find_eligible_jobs(
+        &self,
+        waiter: &QueueItem,
+        jobs: &[QueueItem],
+    ) -> Result<(Student, Vec<usize>)> {
+        let student = self.db.get_student(&waiter.student_slack_id).await?;
+        let eligibility = self.eligibility(&student).await?;
+
+        let eligible = jobs
+            .iter()
+            .enumerate()
+            .filter(|(_, job)| eligibility.allows(job))
+            .map(|(i, _)| i)
+            .collect();
+
+        Ok((student, eligible))
+    }
+
     /// Attempt to give a student a job to do. If successful, go ahead and
     /// update the student and film and delete from the db queue.
     pub(crate) async fn try_assign_job(
@@ -131,87 +682,256 @@ impl Queue {
         slack_id: &str,
         ts: &str,
         channel: &str,
+        origin: &Origin,
     ) -> Result<Option<QueueItem>> {
         let mut student = self.db.get_student(slack_id).await?;
-        let group = student.group_number;
-        let role = student.current_role;
 
-        if role == Role::Done {
+        if student.current_role == Role::Done {
             let e = "You're done! No more work to do :)";
             return Err(Error::Duplicate(e.into()));
         }
 
         info!("Trying to assign job: now retrieving all films student has worked!");
+        let eligibility = self.eligibility(&student).await?;
 
-        // A student should not work the same job twice, unless this is not possible.
-        let eligible = self.db.get_films_exclusionary(group, role).await?;
-        let eligible: HashSet<_> = eligible.iter().map(|f| f.name.clone()).collect();
-        let worked_films = self.db.get_worked_films(&student.id).await?;
-        let worked_films: HashSet<_> = worked_films.iter().map(|f| f.name.clone()).collect();
+        info!("Searching for eligible jobs...");
+        let job_to_do = self.get_job(&eligibility).await;
 
-        let mut eligible_films_exist = false;
-        for film in &eligible {
-            // If student has not worked an eligible film, unique films still exist.
-            if !worked_films.contains(film) {
-                eligible_films_exist = true;
-                break;
+        // If there was no suitable job found, insert student into the wait queue
+        let job = match job_to_do {
+            Some(job) => job,
+            None => {
+                info!("No job found - inserting {} to the wait_q", &student.name);
+                // NOTE:  don't increment until they deliver!
+                let role = student.current_role;
+                self.insert_waiter(role, ts, channel, &student.slack_id, origin)
+                    .await?;
+                return Ok(None);
             }
+        };
+
+        info!("Updating student and film records and removing job from queue");
+        let mut entry = origin.entry(AuditAction::Assign, Some(slack_id), Some(&job.film_name));
+        entry.before = audit::snapshot(&student);
+        entry.after = audit::snapshot(&job);
+        if let Err(e) = self.assign(&mut student, &job, &entry).await {
+            self.jobs_q.lock().await.push(job);
+            return Err(e);
         }
 
-        // NOTE:  don't increment until they deliver!
+        info!("Assigned {} to {}", student.name, job.film_name);
+        warn_if_at_risk(&job);
+        Ok(Some(job))
+    }
+
+    /// Gathers everything needed to decide which jobs a student may take.
+    async fn eligibility(&self, student: &Student) -> Result<Eligibility> {
         let role = student.current_role;
 
-        info!("Searching for eligible jobs...");
-        let job_to_do = self.get_job(worked_films, role, eligible_films_exist).await;
+        // A student should not work the same job twice, unless this is not possible.
+        let eligible = self
+            .db
+            .get_films_exclusionary(student.group_number, role)
+            .await?;
+        let worked_films = self.db.get_worked_films(&student.id).await?;
+        let worked_films: HashSet<_> = worked_films.into_iter().map(|f| f.name).collect();
 
-        // If there was no suitable job found, insert student into the wait queue
-        if job_to_do.is_none() {
-            info!("No job found - inserting {} to the wait_q", &student.name);
-            self.insert_waiter(role, ts, channel, &student.slack_id)
-                .await?;
-            return Ok(None);
-        }
+        // If student has not worked an eligible film, unique films still exist.
+        let unique_films_exist = eligible.iter().any(|f| !worked_films.contains(&f.name));
 
-        info!("Updating student and film records and removing job from queue");
+        Ok(Eligibility {
+            role,
+            worked_films,
+            unique_films_exist,
+        })
+    }
 
-        // ...otherwise, remove job from db and add a students_films record.
-        // Also, update the student and film records to reflect the current state.
-        let job = job_to_do.unwrap();
+    /// Removes a job from the db and adds a students_films record.
+    /// Also, updates the student record to reflect the current state.
+    async fn assign(
+        &self,
+        student: &mut Student,
+        job: &QueueItem,
+        audit: &AuditEntry,
+    ) -> Result<()> {
         match self.db.get_film(&job.film_name).await? {
             Some(film) => {
-                student.current_film = Some(film.name);
-                self.db.insert_student_films(&student.id, &film.id).await?;
-                self.db.update_student(&student).await?;
-                self.db.delete_from_queue(&job.id, false).await?;
+                self.db.assign_film(student, &film, job, audit).await?;
+                student.assign(&film.name, Utc::now());
+                metrics::assigned(job);
+                Ok(())
             }
-            None => return Err(Error::Internal(eyre!("Impossible state"))),
+            None => Err(Error::Internal(eyre!("Impossible state"))),
         }
-
-        info!("Assigned {} to {}", student.name, job.film_name);
-        Ok(Some(job))
     }
 
-    async fn get_job(
+    /// Moves a film to a specific student, either from whoever is working on it or from the
+    /// jobs_q. Unless `force` is set, the student must be eligible to work on it.
+    ///
+    /// Returns the job as assigned, along with the student the film was taken from, if any.
+    pub(crate) async fn reassign(
         &self,
-        worked_films: HashSet<String>,
-        role: Role,
-        eligible_films_exist: bool,
-    ) -> Option<QueueItem> {
+        film_name: &str,
+        to: &mut Student,
+        force: bool,
+        origin: &Origin,
+    ) -> Result<(QueueItem, Option<Student>)> {
+        if let Some(ref film) = to.current_film {
+            let e = format!("{} is already working on `{film}`", to.name);
+            return Err(Error::InvalidArg(e));
+        }
+        let film = match self.db.get_film(film_name).await? {
+            Some(f) => f,
+            None => return Err(Error::NotFound(format!("No film named `{film_name}`"))),
+        };
+
+        // Even a forced assignment has to be for the stage the student's on, or they'd be left
+        // working a film their progress doesn't match.
+        if film.current_role != to.current_role {
+            let e = format!(
+                "`{film_name}` needs `{}`, but {} is on `{}`",
+                film.current_role.as_ref(),
+                to.name,
+                to.current_role.as_ref()
+            );
+            return Err(Error::InvalidArg(e));
+        }
+        if !force {
+            let eligibility = self.eligibility(to).await?;
+            if !eligibility.allows_film(&film.name, film.current_role) {
+                let e = format!(
+                    "{} can't work `{}` on `{film_name}`",
+                    to.name,
+                    film.current_role.as_ref()
+                );
+                return Err(Error::InvalidArg(e));
+            }
+        }
+
+        let students = self.db.list_students().await?;
+        let holder = students
+            .into_iter()
+            .find(|s| s.current_film.as_deref() == Some(film_name));
+
+        let action = if force {
+            AuditAction::ForceAssign
+        } else {
+            AuditAction::Reassign
+        };
+        let entry = |job: &QueueItem, from: Option<&Student>| {
+            let mut after = to.clone();
+            after.assign(&film.name, Utc::now());
+            let mut entry = origin.entry(action, Some(&to.slack_id), Some(film_name));
+            entry.before = audit::snapshot(&json!({ "student": to, "holder": from }));
+            entry.after = audit::snapshot(&json!({ "student": after, "job": job }));
+            entry
+        };
+
+        if let Some(mut from) = holder {
+            check_not_paused(&from)?;
+            let job = new_job(&film, &to.slack_id);
+            let entry = entry(&job, Some(&from));
+            self.db.transfer_film(&from, to, &film, &entry).await?;
+            from.unassign();
+            to.assign(&film.name, Utc::now());
+            info!("Moved {film_name} from {} to {}", from.name, to.name);
+            return Ok((job, Some(from)));
+        }
+
+        let job = match self.take_job(film_name).await {
+            Some(job) => job,
+            None => {
+                let e = format!("`{film_name}` isn't in the queue or assigned to anyone");
+                return Err(Error::InvalidArg(e));
+            }
+        };
+        let job = QueueItem {
+            student_slack_id: to.slack_id.clone(),
+            ..job
+        };
+        let entry = entry(&job, None);
+        if let Err(e) = self.db.assign_film(to, &film, &job, &entry).await {
+            self.jobs_q.lock().await.push(job);
+            return Err(e);
+        }
+        to.assign(&film.name, Utc::now());
+        // assign_film took them out of the stored wait queue, so drop them from ours as well.
+        let mut wait_q = self.wait_q.lock().await;
+        let rest: Vec<_> = std::mem::take(&mut *wait_q)
+            .into_iter()
+            .filter(|w| w.student_slack_id != to.slack_id)
+            .collect();
+        wait_q.extend(rest);
+        drop(wait_q);
+        info!("Assigned {film_name} to {} from the jobs queue", to.name);
+
+        Ok((job, None))
+    }
+
+    /// Removes a film's job from the jobs_q, if it's there.
+    async fn take_job(&self, film_name: &str) -> Option<QueueItem> {
+        let mut jobs_q = self.jobs_q.lock().await;
+
+        let (mut taken, rest): (Vec<_>, Vec<_>) = std::mem::take(&mut *jobs_q)
+            .into_iter()
+            .partition(|j| j.film_name == film_name);
+        jobs_q.extend(rest);
+
+        taken.pop()
+    }
+
+    /// Works out which job `try_assign_job` would hand a student, and why every other job would
+    /// be passed over. Neither the queues nor the database are changed.
+    pub(crate) async fn dry_run(&self, slack_id: &str) -> Result<DryRun> {
+        let student = self
+            .db
+            .find_student(slack_id)
+            .await?
+            .ok_or_else(|| Error::NotFound(format!("<@{slack_id}> isn't a student")))?;
+        let mut dry_run = DryRun {
+            student: student.slack_id.clone(),
+            role: student.current_role,
+            job: None,
+            considered: vec![],
+        };
+        if student.current_role == Role::Done {
+            return Ok(dry_run);
+        }
+
+        let eligibility = self.eligibility(&student).await?;
+        let mut jobs_q = self.jobs_q.lock().await.clone();
+        self.refresh(&mut jobs_q);
+
+        // Jobs come off the queue in the same order `get_job` pops them.
+        let mut jobs = jobs_q.into_sorted_vec();
+        jobs.reverse();
+        for job in jobs {
+            let rejected = eligibility
+                .rejects(&job.film_name, job.role)
+                .or_else(|| dry_run.job.is_some().then(|| Rejection::LowerPriority));
+            if rejected.is_none() {
+                dry_run.job = Some(job.clone());
+            }
+            let own_group = job.group_number == Some(student.group_number);
+            dry_run.considered.push(Considered {
+                job,
+                own_group,
+                rejected,
+            });
+        }
+        Ok(dry_run)
+    }
+
+    async fn get_job(&self, eligibility: &Eligibility) -> Option<QueueItem> {
         let mut work_q = self.jobs_q.lock().await;
         let mut recycle = vec![];
+        self.refresh(&mut work_q);
 
         let mut eligible_job: Option<QueueItem> = None;
 
         // Search queue for a job to work on, recycle entries that don't fit.
         while let Some(job) = work_q.pop() {
-            let worked_on_film = worked_films.contains(&job.film_name);
-
-            let eligible = job.role == role;
-
-            // Only assign if role is correct.
-            // Then, only assign if student hasn't worked on the film before,
-            // BUT assign anyways if there are no unique films left.
-            if eligible && (!worked_on_film || !eligible_films_exist) {
+            if eligibility.allows(&job) {
                 eligible_job = Some(job);
                 break;
             } else {
@@ -223,22 +943,38 @@ impl Queue {
         eligible_job
     }
 
-    pub(crate) async fn insert_job(&self, f: &Film, slack_id: &str) -> Result<QueueItem> {
+    /// Reapplies aging and round-robin turns to the jobs queue.
+    fn refresh(&self, jobs_q: &mut BinaryHeap<QueueItem>) {
+        self.aging.apply(jobs_q, Utc::now());
+        self.ordering.apply(jobs_q);
+    }
+
+    /// Lists all jobs in the order they'll be handed out.
+    pub(crate) async fn list_jobs(&self) -> Vec<JobListing> {
         let mut jobs_q = self.jobs_q.lock().await;
+        let now = Utc::now();
+        self.refresh(&mut jobs_q);
 
-        let job = QueueItem {
-            id: Uuid::new_v4(),
-            student_slack_id: slack_id.to_string(),
-            film_name: f.name.clone(),
-            channel: None,
-            msg_ts: None,
-            priority: Some(f.priority),
-            created_at: Utc::now(),
-            role: f.current_role,
-        };
+        let mut jobs = jobs_q.clone().into_sorted_vec();
+        jobs.reverse();
+        jobs.into_iter().map(|j| JobListing::new(j, now)).collect()
+    }
 
+    pub(crate) async fn insert_job(
+        &self,
+        f: &Film,
+        slack_id: &str,
+        origin: &Origin,
+    ) -> Result<QueueItem> {
+        let mut jobs_q = self.jobs_q.lock().await;
+
+        let job = new_job(f, slack_id);
+        warn_if_at_risk(&job);
+
+        let mut entry = origin.entry(AuditAction::Enqueue, None, Some(&f.name));
+        entry.after = audit::snapshot(&job);
         jobs_q.push(job.clone());
-        self.db.insert_to_queue(job, false).await
+        self.db.insert_to_queue(job, false, Some(&entry)).await
     }
 
     async fn insert_waiter(
@@ -247,6 +983,7 @@ impl Queue {
         msg_ts: &str,
         channel: &str,
         slack_id: &str,
+        origin: &Origin,
     ) -> Result<QueueItem> {
         let mut wait_q = self.wait_q.lock().await;
 
@@ -254,21 +991,93 @@ impl Queue {
             id: Uuid::new_v4(),
             student_slack_id: slack_id.to_string(),
             film_name: "".to_string(),
+            group_number: None,
+            class: None,
             channel: Some(channel.to_string()),
             msg_ts: Some(msg_ts.to_string()),
             priority: None,
+            effective_priority: None,
+            round: None,
+            deadline: None,
             created_at: Utc::now(),
             role,
         };
+        let mut entry = origin.entry(AuditAction::Wait, Some(slack_id), None);
+        entry.after = audit::snapshot(&waiter);
         wait_q.push(waiter.clone());
-        self.db.insert_to_queue(waiter, true).await
+        self.db.insert_to_queue(waiter, true, Some(&entry)).await
+    }
+}
+
+pub(crate) fn new_job(f: &Film, slack_id: &str) -> QueueItem {
+    QueueItem {
+        id: Uuid::new_v4(),
+        student_slack_id: slack_id.to_string(),
+        film_name: f.name.clone(),
+        group_number: Some(f.group_number),
+        class: Some(f.class.clone()),
+        channel: None,
+        msg_ts: None,
+        priority: Some(f.priority),
+        effective_priority: None,
+        round: None,
+        deadline: f.deadline(),
+        created_at: Utc::now(),
+        role: f.current_role,
+    }
+}
+
+/// Any notes and links the student left for the next stage, if they left any.
+fn new_delivery(
+    film_name: &str,
+    role: Role,
+    student: &Student,
+    links: &[String],
+    notes: &str,
+) -> Option<Delivery> {
+    let delivery = Delivery {
+        id: Uuid::new_v4(),
+        film_name: film_name.to_string(),
+        role,
+        student: student.slack_id.clone(),
+        links: links.to_vec(),
+        notes: notes.to_string(),
+        created_at: Utc::now(),
+    };
+    if delivery.is_empty() {
+        return None;
+    }
+    Some(delivery)
+}
+
+/// Films sent back to an earlier stage can't move on until they're fixed.
+fn check_not_paused(student: &Student) -> Result<()> {
+    if !student.paused {
+        return Ok(());
     }
+    let film = student.current_film.as_deref().unwrap_or_default();
+    let e = format!("`{film}` was sent back to an earlier stage, and is waiting on a fix.");
+    Err(Error::InvalidArg(e))
+}
+
+fn warn_if_at_risk(job: &QueueItem) {
+    if job.is_at_risk(Utc::now()) {
+        warn!(
+            "{} ({}) is projected to miss its due date",
+            job.film_name,
+            job.role.as_ref()
+        );
+    }
+}
+
+/// Empties a heap, returning its items in priority order.
+fn pop_all(q: &mut BinaryHeap<QueueItem>) -> Vec<QueueItem> {
+    std::iter::from_fn(|| q.pop()).collect()
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use chrono::{DateTime, Duration, Utc};
 
     #[test]
     // Queue should pop high priority items, then earliest items, then alphabetical.
@@ -295,11 +1104,407 @@ mod tests {
         }
     }
 
+    #[test]
+    // Within a priority, jobs with the least slack go first, then jobs without a due date.
+    fn check_deadline_order() {
+        let yesterday = Utc::now() - Duration::days(1);
+        let today = Utc::now();
+
+        let mut late = get_job("late", Priority::High, today);
+        late.deadline = Some(today + Duration::days(3));
+        let mut soon = get_job("soon", Priority::High, today);
+        soon.deadline = Some(today + Duration::hours(1));
+        let mut low = get_job("low", Priority::Low, today);
+        low.deadline = Some(yesterday);
+
+        let mut jobs = vec![
+            low,
+            get_job("undated", Priority::High, yesterday),
+            late,
+            soon,
+        ];
+        let mut job_queue: BinaryHeap<QueueItem> = jobs.clone().into_iter().collect();
+
+        while let (Some(expected), Some(actual)) = (jobs.pop(), job_queue.pop()) {
+            assert_eq!(expected, actual);
+        }
+    }
+
+    #[test]
+    fn check_at_risk() {
+        let now = Utc::now();
+        let mut job = get_job("a", Priority::High, now);
+        assert!(!job.is_at_risk(now));
+
+        job.deadline = Some(now + Duration::hours(1));
+        assert!(!job.is_at_risk(now));
+        assert!(job.is_at_risk(now + Duration::hours(2)));
+    }
+
+    #[test]
+    fn check_effective_priority() {
+        let policy = AgingPolicy {
+            interval: Some(Duration::hours(2)),
+        };
+        let now = Utc::now();
+
+        let aged = policy.effective_priority(Priority::Low, now - Duration::hours(2), now);
+        assert_eq!(Priority::High, aged);
+        let fresh = policy.effective_priority(Priority::Low, now - Duration::hours(1), now);
+        assert_eq!(Priority::Low, fresh);
+        let high = policy.effective_priority(Priority::High, now - Duration::days(9), now);
+        assert_eq!(Priority::High, high);
+
+        let disabled = AgingPolicy::default();
+        let low = disabled.effective_priority(Priority::Low, now - Duration::days(9), now);
+        assert_eq!(Priority::Low, low);
+    }
+
+    #[test]
+    // A low priority job should be served within a bounded time, even while a new high
+    // priority job arrives every hour and only one job is served per hour.
+    fn check_bounded_wait() {
+        let start = Utc::now();
+        let hours_to_serve = |policy: AgingPolicy| {
+            let mut q: BinaryHeap<_> = vec![
+                get_job("low", Priority::Low, start),
+                get_job("high-0", Priority::High, start),
+            ]
+            .into_iter()
+            .collect();
+
+            for hour in 1..100 {
+                let now = start + Duration::hours(hour);
+                q.push(get_job(&format!("high-{hour}"), Priority::High, now));
+                policy.apply(&mut q, now);
+                if q.pop().unwrap().film_name == "low" {
+                    return Some(hour);
+                }
+            }
+            None
+        };
+
+        let aging = AgingPolicy {
+            interval: Some(Duration::hours(3)),
+        };
+        let served = hours_to_serve(aging).expect("low priority job starved");
+        assert!(served <= 3, "served after {served} hours");
+
+        assert_eq!(None, hours_to_serve(AgingPolicy::default()));
+    }
+
+    #[test]
+    // Groups should take turns within a priority, instead of the oldest group going first.
+    fn check_round_robin() {
+        let now = Utc::now();
+        let job = |name: &str, group: i32, class: &str, hours_ago: i64| {
+            let mut j = get_job(name, Priority::High, now - Duration::hours(hours_ago));
+            j.group_number = Some(group);
+            j.class = Some(class.to_string());
+            j
+        };
+        let jobs = vec![
+            job("a1", 1, "x", 9),
+            job("a2", 1, "x", 8),
+            job("a3", 1, "x", 7),
+            job("b1", 2, "x", 2),
+            job("c1", 1, "y", 1),
+            get_job("low", Priority::Low, now - Duration::days(1)),
+        ];
+        let order = |ordering: JobOrdering| {
+            let mut q: BinaryHeap<_> = jobs.clone().into_iter().collect();
+            ordering.apply(&mut q);
+            std::iter::from_fn(|| q.pop())
+                .map(|j| j.film_name)
+                .collect::<Vec<_>>()
+        };
+
+        let priority = order(JobOrdering::Priority);
+        assert_eq!(vec!["a1", "a2", "a3", "b1", "c1", "low"], priority);
+
+        let group = order(JobOrdering::Group);
+        assert_eq!(vec!["a1", "b1", "a2", "a3", "c1", "low"], group);
+
+        let class = order(JobOrdering::Class);
+        assert_eq!(vec!["a1", "b1", "c1", "a2", "a3", "low"], class);
+
+        // Another role's jobs don't use up a group's turns.
+        let mut editing = job("e1", 2, "x", 10);
+        editing.role = Role::Editor;
+        let mut q: BinaryHeap<_> = jobs.iter().cloned().chain([editing]).collect();
+        JobOrdering::Group.apply(&mut q);
+        let rounds: HashMap<_, _> = q.into_iter().map(|j| (j.film_name, j.round)).collect();
+        assert_eq!(Some(0), rounds["e1"]);
+        assert_eq!(Some(0), rounds["b1"]);
+    }
+
+    #[test]
+    // Items with and without priorities, rounds and deadlines must still be totally ordered,
+    // or the heap's order is undefined.
+    fn check_total_order() {
+        let now = Utc::now();
+        let mut items = vec![];
+        for priority in [None, Some(Priority::Low), Some(Priority::High)] {
+            for round in [None, Some(0), Some(1)] {
+                for deadline in [None, Some(now), Some(now + Duration::days(1))] {
+                    let mut item = get_job("a", Priority::High, now);
+                    item.priority = priority;
+                    item.round = round;
+                    item.deadline = deadline;
+                    items.push(item);
+                }
+            }
+        }
+
+        for a in &items {
+            for b in &items {
+                assert_eq!(a.cmp(b), b.cmp(a).reverse());
+                for c in &items {
+                    if a <= b && b <= c {
+                        assert!(a <= c, "{a:?} <= {b:?} <= {c:?}");
+                    }
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    // A film moves through a student and on to its next stage, as it would against postgres.
+    async fn check_assign_and_deliver() -> Result<()> {
+        let queue = Queue::_new();
+        let film = queue
+            .db
+            .insert_film(&Film::new("a", Priority::High, 1))
+            .await?;
+        queue.insert_job(&film, "", &origin()).await?;
+        queue.db.insert_student("U1", "U1").await?;
+        queue.db.insert_student("U2", "U2").await?;
+
+        let job = queue
+            .try_assign_job("U1", "", "", &origin())
+            .await?
+            .unwrap();
+        assert_eq!("a", job.film_name);
+        assert!(queue.db.get_queue(false).await?.is_empty());
+
+        // Nothing's left for a second student, so they wait.
+        assert_eq!(None, queue.try_assign_job("U2", "", "", &origin()).await?);
+        assert_eq!(1, queue.db.get_queue(true).await?.len());
+
+        let student = queue.db.get_student("U1").await?;
+        queue.deliver(student, "U1", &[], "", &origin()).await?;
+        let film = queue.db.get_film("a").await?.unwrap();
+        assert_eq!(Some("U1"), film.roles.get(Role::Ae));
+        assert_eq!(Role::Editor, film.current_role);
+
+        let jobs = queue.list_jobs().await;
+        assert_eq!(
+            vec![Role::Editor],
+            jobs.iter().map(|j| j.job.role).collect::<Vec<_>>()
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    // An early waiter who could take either job mustn't take the only one a later waiter can.
+    async fn check_drain_matching() -> Result<()> {
+        let queue = Queue::_new();
+        let mut students = vec![];
+        for slack_id in ["U1", "U2"] {
+            let mut student = queue.db.insert_student_from_csv(slack_id, 2, "").await?;
+            student.slack_id = slack_id.to_string();
+            queue.db.update_student(&student).await?;
+            students.push(student);
+        }
+        // Nothing's available yet, so both wait, U1 first.
+        for slack_id in ["U1", "U2"] {
+            assert_eq!(
+                None,
+                queue.try_assign_job(slack_id, "", "", &origin()).await?
+            );
+        }
+
+        // U2 has already worked on "b", but not "a", which is first in the queue.
+        let a = queue
+            .db
+            .insert_film(&Film::new("a", Priority::High, 1))
+            .await?;
+        let b = queue
+            .db
+            .insert_film(&Film::new("b", Priority::Low, 1))
+            .await?;
+        queue
+            .db
+            .insert_student_films(&students[1].id, &b.id, Role::Ae)
+            .await?;
+        queue.insert_job(&a, "", &origin()).await?;
+        queue.insert_job(&b, "", &origin()).await?;
+
+        let served: Vec<_> = queue
+            .try_empty_wait_queue(&origin())
+            .await?
+            .into_iter()
+            .map(|j| (j.student_slack_id, j.film_name))
+            .collect();
+        let expected = vec![("U1", "b"), ("U2", "a")];
+        let expected: Vec<_> = expected
+            .into_iter()
+            .map(|(s, f)| (s.to_string(), f.to_string()))
+            .collect();
+        assert_eq!(expected, served);
+        assert!(queue.wait_q.lock().await.is_empty());
+        assert!(queue.jobs_q.lock().await.is_empty());
+        assert!(queue.db.get_queue(true).await?.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    // A film goes back to whoever actually worked the stage, even after they've been renamed
+    // and someone else shares their old name.
+    async fn check_send_back() -> Result<()> {
+        let queue = Queue::_new();
+        let film = queue
+            .db
+            .insert_film(&Film::new("a", Priority::High, 1))
+            .await?;
+        queue.insert_job(&film, "", &origin()).await?;
+        let mut students = vec![];
+        for (slack_id, role) in [("U1", Role::Ae), ("U2", Role::Editor)] {
+            let mut student = queue.db.insert_student_from_csv("sam", 2, "").await?;
+            student.slack_id = slack_id.to_string();
+            student.current_role = role;
+            queue.db.update_student(&student).await?;
+            students.push(student);
+        }
+
+        queue
+            .try_assign_job("U1", "", "", &origin())
+            .await?
+            .unwrap();
+        let student = queue.db.get_student("U1").await?;
+        queue.deliver(student, "U1", &[], "", &origin()).await?;
+        let mut renamed = queue.db.get_student("U1").await?;
+        renamed.name = "samantha".to_string();
+        queue.db.update_student(&renamed).await?;
+
+        queue
+            .try_assign_job("U2", "", "", &origin())
+            .await?
+            .unwrap();
+        let mut editor = queue.db.get_student("U2").await?;
+        let revision = queue.send_back(&mut editor, "no audio", &origin()).await?;
+        assert_eq!(("U2", "U1"), (revision.from.as_str(), revision.to.as_str()));
+
+        // The editor can't deliver until the fix is in, and has nothing to fix themselves.
+        let res = queue
+            .deliver(editor.clone(), "U2", &[], "", &origin())
+            .await;
+        assert!(matches!(res, Err(Error::InvalidArg(_))), "{res:?}");
+        let res = queue.resolve_revision(&editor, &[], "", &origin()).await;
+        assert!(matches!(res, Err(Error::NotFound(_))), "{res:?}");
+
+        let fixer = queue.db.get_student("U1").await?;
+        assert_eq!(
+            revision.id,
+            queue.resolve_revision(&fixer, &[], "", &origin()).await?.id
+        );
+        let editor = queue.db.get_student("U2").await?;
+        assert!(!editor.paused);
+        queue.deliver(editor, "U2", &[], "", &origin()).await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    // Forcing skips the eligibility rules, but not the student's stage.
+    async fn check_force_assign() -> Result<()> {
+        let queue = Queue::_new();
+        let mut editing = queue
+            .db
+            .insert_film(&Film::new("a", Priority::High, 1))
+            .await?;
+        editing.current_role = Role::Editor;
+        queue.db.update_film(&editing).await?;
+        queue.insert_job(&editing, "", &origin()).await?;
+        let film = queue
+            .db
+            .insert_film(&Film::new("b", Priority::High, 1))
+            .await?;
+        queue.insert_job(&film, "", &origin()).await?;
+
+        let mut student = queue.db.insert_student_from_csv("bob", 1, "").await?;
+        let res = queue.reassign("a", &mut student, true, &origin()).await;
+        assert!(matches!(res, Err(Error::InvalidArg(_))), "{res:?}");
+        assert_eq!(None, student.current_film);
+        assert_eq!(2, queue.jobs_q.lock().await.len());
+
+        let (job, from) = queue.reassign("b", &mut student, true, &origin()).await?;
+        assert_eq!(("b", Role::Ae), (job.film_name.as_str(), job.role));
+        assert!(from.is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn check_dry_run() -> Result<()> {
+        let queue = Queue::_new();
+        for (name, priority, group) in [("a", Priority::Low, 1), ("b", Priority::High, 2)] {
+            let film = queue
+                .db
+                .insert_film(&Film::new(name, priority, group))
+                .await?;
+            queue.insert_job(&film, "", &origin()).await?;
+        }
+        let mut editing = queue
+            .db
+            .insert_film(&Film::new("c", Priority::High, 2))
+            .await?;
+        editing.current_role = Role::Editor;
+        queue.insert_job(&editing, "", &origin()).await?;
+
+        let mut student = queue.db.insert_student_from_csv("bob", 1, "").await?;
+        student.slack_id = "U1".to_string();
+        queue.db.update_student(&student).await?;
+
+        let dry_run = queue.dry_run("U1").await?;
+        assert_eq!(
+            Some("b"),
+            dry_run.job.as_ref().map(|j| j.film_name.as_str())
+        );
+        let rejected: Vec<_> = dry_run
+            .considered
+            .iter()
+            .map(|c| (c.job.film_name.as_str(), c.own_group, c.rejected))
+            .collect();
+        let expected = vec![
+            ("b", false, None),
+            ("c", false, Some(Rejection::WrongRole)),
+            ("a", true, Some(Rejection::LowerPriority)),
+        ];
+        assert_eq!(expected, rejected);
+
+        // Nothing was handed out.
+        assert_eq!(3, queue.db.get_queue(false).await?.len());
+        assert_eq!(3, queue.jobs_q.lock().await.len());
+        assert!(queue.db.get_student("U1").await?.current_film.is_none());
+
+        assert!(queue.dry_run("U2").await.is_err());
+        Ok(())
+    }
+
+    fn origin() -> Origin {
+        Origin::system("test")
+    }
+
     fn get_job(name: &str, priority: Priority, date: DateTime<Utc>) -> QueueItem {
         QueueItem {
             id: Uuid::new_v4(),
             film_name: name.to_string(),
+            group_number: Some(0),
+            class: Some("".to_string()),
             priority: Some(priority),
+            effective_priority: None,
+            round: None,
+            deadline: None,
             created_at: date,
             student_slack_id: "".to_string(),
             role: Role::Ae,
@@ -0,0 +1,91 @@
+//! Bipartite matching between waiting students and available jobs.
+
+/// Finds a maximum matching between waiters and jobs.
+///
+/// `edges[w]` lists every job index waiter `w` is allowed to take. Waiters are matched in index
+/// order and each adjacency list is tried in order, so as long as both are sorted by queue
+/// priority, a higher priority waiter is never dropped in favor of a lower priority one, and
+/// higher priority jobs are handed out first.
+///
+/// Returns, for each waiter, the index of the job they were matched with.
+pub(crate) fn max_matching(edges: &[Vec<usize>], num_jobs: usize) -> Vec<Option<usize>> {
+    let mut owners: Vec<Option<usize>> = vec![None; num_jobs];
+
+    for waiter in 0..edges.len() {
+        let mut visited = vec![false; num_jobs];
+        augment(waiter, edges, &mut visited, &mut owners);
+    }
+
+    let mut matches = vec![None; edges.len()];
+    for (job, owner) in owners.into_iter().enumerate() {
+        if let Some(waiter) = owner {
+            matches[waiter] = Some(job);
+        }
+    }
+    matches
+}
+
+/// Searches for an augmenting path starting at `waiter`, bumping earlier waiters onto other jobs
+/// when that frees up a job for this one.
+fn augment(
+    waiter: usize,
+    edges: &[Vec<usize>],
+    visited: &mut [bool],
+    owners: &mut [Option<usize>],
+) -> bool {
+    for &job in &edges[waiter] {
+        if visited[job] {
+            continue;
+        }
+        visited[job] = true;
+
+        let free = match owners[job] {
+            Some(owner) => augment(owner, edges, visited, owners),
+            None => true,
+        };
+        if free {
+            owners[job] = Some(waiter);
+            return true;
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    // The first waiter could do either job, the second only the first one.
+    // Greedy assignment would leave the second waiter without work.
+    fn beats_greedy() {
+        let edges = vec![vec![0, 1], vec![0]];
+        let matches = max_matching(&edges, 2);
+
+        assert_eq!(vec![Some(1), Some(0)], matches);
+    }
+
+    #[test]
+    // When there isn't enough work to go around, earlier waiters win.
+    fn respects_waiter_order() {
+        let edges = vec![vec![0], vec![0], vec![0, 1]];
+        let matches = max_matching(&edges, 2);
+
+        assert_eq!(vec![Some(0), None, Some(1)], matches);
+    }
+
+    #[test]
+    // With more jobs than waiters, the earliest jobs are handed out first.
+    fn respects_job_order() {
+        let edges = vec![vec![0, 1, 2], vec![1, 2]];
+        let matches = max_matching(&edges, 3);
+
+        assert_eq!(vec![Some(0), Some(1)], matches);
+    }
+
+    #[test]
+    fn empty() {
+        assert!(max_matching(&[], 3).is_empty());
+        assert_eq!(vec![None, None], max_matching(&[vec![], vec![]], 0));
+    }
+}
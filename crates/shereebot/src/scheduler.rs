@@ -0,0 +1,284 @@
+//! Background task which chases up students who've held onto a film for too long.
+use chrono::{DateTime, Duration, Utc};
+use tokio::{sync::watch, task::JoinHandle};
+use tracing::{error, info, info_span, warn, Instrument};
+
+use crate::{
+    audit::Origin, config, manager::Manager, server::State, slack::app_mentions::Response,
+    utils::correlation, Result,
+};
+use models::{AuditAction, Student};
+
+/// An escalation step taken against a stalled assignment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Step {
+    /// Reminds the student. Holds how many reminders went out before this one.
+    Remind(usize),
+    NotifyAdmins,
+    /// Takes the film back and returns it to the jobs_q.
+    Reclaim,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct ReclaimPolicy {
+    check_every: std::time::Duration,
+    /// Every step, ordered by how long after assignment it's taken.
+    steps: Vec<(Duration, Step)>,
+}
+
+impl ReclaimPolicy {
+    pub(crate) fn new(cfg: &config::Reclaim) -> Self {
+        let mut remind_hours = cfg.remind_hours.clone();
+        remind_hours.sort_unstable();
+
+        let mut steps: Vec<_> = remind_hours
+            .into_iter()
+            .enumerate()
+            .map(|(i, h)| (Duration::hours(h), Step::Remind(i)))
+            .collect();
+        if let Some(h) = cfg.escalate_hours {
+            steps.push((Duration::hours(h), Step::NotifyAdmins));
+        }
+        if let Some(h) = cfg.reclaim_hours {
+            steps.push((Duration::hours(h), Step::Reclaim));
+        }
+        // Stable, so reminders still go out before escalating when configured for the same time.
+        steps.sort_by_key(|&(after, _)| after);
+
+        let check_every = std::time::Duration::from_secs(cfg.check_minutes.max(1) * 60);
+        Self { check_every, steps }
+    }
+
+    /// The next step due for a student, if any. Steps are taken one at a time, so a student is
+    /// always reminded before their film is taken back.
+    pub(crate) fn next_step(&self, student: &Student, now: DateTime<Utc>) -> Option<Step> {
+        student.current_film.as_ref()?;
+        // The clock restarts once the film is fixed.
+        if student.paused {
+            return None;
+        }
+        let assigned_at = student.assigned_at?;
+        let &(after, step) = self.steps.get(student.escalation as usize)?;
+
+        (now - assigned_at >= after).then(|| step)
+    }
+}
+
+/// Periodically checks every assignment for stalled work, until `stop` turns true. A check that's
+/// underway is finished first, and shutdown waits for it. Nothing is spawned if no steps are
+/// configured.
+pub(crate) fn spawn(
+    state: State,
+    policy: ReclaimPolicy,
+    mut stop: watch::Receiver<bool>,
+) -> Option<JoinHandle<()>> {
+    if policy.steps.is_empty() {
+        info!("No reclaim policy configured; stalled assignments won't be chased up");
+        return None;
+    }
+
+    let tasks = state.tasks.clone();
+    let handle = tasks.spawn(async move {
+        let mut interval = tokio::time::interval(policy.check_every);
+        while !*stop.borrow() {
+            tokio::select! {
+                _ = interval.tick() => {}
+                changed = stop.changed() => {
+                    // A dropped sender can never say to stop, so take that as the signal.
+                    if changed.is_err() {
+                        break;
+                    }
+                    continue;
+                }
+            }
+            let span = info_span!("reclaim", cid = %correlation::new_id());
+            let checked = check_assignments(&state, &policy, Utc::now()).instrument(span);
+            if let Err(e) = checked.await {
+                error!("Failed to check for stalled assignments: {e}");
+            }
+        }
+        info!("Stopped checking for stalled assignments");
+    });
+    Some(handle)
+}
+
+#[tracing::instrument(skip_all)]
+async fn check_assignments(
+    state: &State,
+    policy: &ReclaimPolicy,
+    now: DateTime<Utc>,
+) -> Result<()> {
+    for mut student in state.db.list_students().await? {
+        if let Some(step) = policy.next_step(&student, now) {
+            if let Err(e) = take_step(state, &mut student, step, now).await {
+                error!("Failed to chase up {}: {e}", student.name);
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn take_step(
+    state: &State,
+    student: &mut Student,
+    step: Step,
+    now: DateTime<Utc>,
+) -> Result<()> {
+    let film = student.current_film.clone().unwrap_or_default();
+    let hours = student
+        .assigned_at
+        .map(|at| (now - at).num_hours())
+        .unwrap_or_default();
+    let id = student.slack_id.clone();
+
+    match step {
+        Step::Remind(n) => {
+            info!(
+                "Reminding {} about {film} (reminder #{})",
+                student.name,
+                n + 1
+            );
+            notify(state, &id, reminder(n, &id, &film, hours)).await;
+        }
+        Step::NotifyAdmins => {
+            warn!(
+                "{} has held {film} for {hours} hours; notifying admins",
+                student.name
+            );
+            let msg = format!("<@{id}> has held `{film}` for {hours} hours without delivering it.");
+            notify_admins(state, &msg).await;
+        }
+        Step::Reclaim => {
+            warn!(
+                "Reclaiming {film} from {} after {hours} hours",
+                student.name
+            );
+            let origin = Origin::system("scheduler");
+            state
+                .queue
+                .release(student, AuditAction::Reclaim, &origin)
+                .await?;
+
+            let msg = format!(
+                "<@{id}> You've held `{film}` for {hours} hours, so it's been handed back for someone else to pick up.
+When you're ready for more work, just type `request-work`."
+            );
+            notify(state, &id, msg).await;
+            let msg = format!("`{film}` was taken back from <@{id}> after {hours} hours.");
+            notify_admins(state, &msg).await;
+
+            Manager::new(state.clone(), origin).empty_wait_queue().await;
+            return Ok(());
+        }
+    }
+
+    student.escalation += 1;
+    state.db.update_escalation(student).await
+}
+
+fn reminder(n: usize, id: &str, film: &str, hours: i64) -> String {
+    match n {
+        0 => format!(
+            "Hey <@{id}>! Just checking in - you've had `{film}` for {hours} hours.
+Once you're done, type `deliver-work` to hand it off."
+        ),
+        1 => format!(
+            "<@{id}> `{film}` has been with you for {hours} hours now.
+Please deliver it soon, other students are waiting on it!"
+        ),
+        _ => format!(
+            "<@{id}> Final reminder: please deliver `{film}` as soon as you can.
+If you're stuck, let Sheree know!"
+        ),
+    }
+}
+
+async fn notify(state: &State, channel: &str, msg: String) {
+    let res = Response::new(channel.to_string(), msg, None);
+    if let Err(e) = state.slack.post(&res).await {
+        error!("Failed to message {channel}: {e}");
+    }
+}
+
+pub(crate) async fn notify_admins(state: &State, msg: &str) {
+    for admin in &state.admins {
+        notify(state, admin, msg.to_string()).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy() -> ReclaimPolicy {
+        ReclaimPolicy::new(&config::Reclaim {
+            check_minutes: 15,
+            remind_hours: vec![72, 48],
+            escalate_hours: Some(96),
+            reclaim_hours: Some(96),
+        })
+    }
+
+    #[test]
+    fn check_steps() {
+        let now = Utc::now();
+        let policy = policy();
+        let mut student = Student::default();
+        assert_eq!(None, policy.next_step(&student, now));
+
+        student.assign("a", now - Duration::hours(50));
+        assert_eq!(Some(Step::Remind(0)), policy.next_step(&student, now));
+
+        // Steps must be taken in order, even if several are overdue.
+        student.assigned_at = Some(now - Duration::days(30));
+        assert_eq!(Some(Step::Remind(0)), policy.next_step(&student, now));
+        student.escalation = 1;
+        assert_eq!(Some(Step::Remind(1)), policy.next_step(&student, now));
+        student.escalation = 2;
+        assert_eq!(Some(Step::NotifyAdmins), policy.next_step(&student, now));
+        student.escalation = 3;
+        assert_eq!(Some(Step::Reclaim), policy.next_step(&student, now));
+        student.escalation = 4;
+        assert_eq!(None, policy.next_step(&student, now));
+
+        student.escalation = 1;
+        student.assigned_at = Some(now - Duration::hours(60));
+        assert_eq!(None, policy.next_step(&student, now));
+
+        student.escalation = 0;
+        student.paused = true;
+        assert_eq!(None, policy.next_step(&student, now));
+    }
+
+    #[tokio::test]
+    async fn check_stop() {
+        let state = crate::server::InnerState::_new();
+        let (stop, stopped) = watch::channel(false);
+        let handle = spawn(state.clone(), policy(), stopped).unwrap();
+        assert_eq!(1, state.tasks.running());
+
+        stop.send(true).unwrap();
+        assert_eq!(0, state.tasks.wait(std::time::Duration::from_secs(5)).await);
+        assert!(handle.await.is_ok());
+
+        // Nothing runs without any steps to take.
+        let (_stop, stopped) = watch::channel(false);
+        let none = ReclaimPolicy::new(&config::Reclaim::default());
+        assert!(spawn(state, none, stopped).is_none());
+    }
+
+    #[test]
+    fn check_no_reclaim() {
+        let now = Utc::now();
+        let policy = ReclaimPolicy::new(&config::Reclaim {
+            check_minutes: 0,
+            remind_hours: vec![1],
+            ..Default::default()
+        });
+        let mut student = Student::default();
+        student.assign("a", now - Duration::days(30));
+        student.escalation = 1;
+
+        assert_eq!(None, policy.next_step(&student, now));
+    }
+}
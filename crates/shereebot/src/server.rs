@@ -1,4 +1,4 @@
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 
 use axum::{
     extract::Extension,
@@ -6,11 +6,25 @@ use axum::{
     routing::{get, post},
     Router,
 };
-use tracing::info;
-
-use crate::{config::Config, queue::Queue, store::Database, UserError};
+use tokio::{
+    signal::unix::{signal, SignalKind},
+    sync::watch,
+};
+use tracing::{error, info, warn};
+
+use crate::{
+    config::Config,
+    queue::Queue,
+    scheduler::{self, ReclaimPolicy},
+    slack::runner,
+    slack::{runner::Runner, FakeSlack, Messenger, WebApi},
+    store::Database,
+    utils::{rate_limit::RateLimiter, tasks::Tasks},
+    UserError,
+};
 mod handlers;
 mod interceptors;
+mod ready;
 
 /// Contains all server-wide stateful data.
 pub(crate) type State = Arc<InnerState>;
@@ -21,23 +35,34 @@ pub type Result<T> = std::result::Result<T, UserError>;
 
 pub(crate) struct InnerState {
     pub(crate) db: Database,
-    pub(crate) oauth_token: String,
-    pub(crate) req_client: reqwest::Client,
+    pub(crate) admins: Vec<String>,
+    pub(crate) admin_token: Option<String>,
+    pub(crate) slack: Arc<dyn Messenger>,
     pub(crate) queue: Queue,
+    pub(crate) limiter: RateLimiter,
+    /// Background work started by requests, which shutdown waits for.
+    pub(crate) tasks: Tasks,
+    /// Slack events waiting to be handled.
+    pub(crate) events: Runner,
 }
 
 impl InnerState {
     pub(crate) fn _new() -> State {
-        let v = reqwest::tls::Version::TLS_1_2;
-        let req_client = reqwest::Client::builder()
-            .min_tls_version(v)
-            .build()
-            .unwrap_or_default();
+        let slack = Arc::new(FakeSlack::default());
+        Self::offline(crate::store::new_memory(), Queue::_new(), slack)
+    }
+
+    /// State that never reaches Slack, for tests and simulations.
+    pub(crate) fn offline(db: Database, queue: Queue, slack: Arc<dyn Messenger>) -> State {
         Arc::new(Self {
-            db: crate::store::new_mock(),
-            oauth_token: "".to_string(),
-            queue: Queue::_new(),
-            req_client,
+            db,
+            admins: vec![],
+            admin_token: None,
+            queue,
+            limiter: RateLimiter::new(&Default::default()),
+            slack,
+            tasks: Tasks::default(),
+            events: Runner::new(4),
         })
     }
 }
@@ -49,20 +74,29 @@ impl std::fmt::Debug for InnerState {
 }
 
 async fn initialize_state(cfg: &Config) -> color_eyre::Result<State> {
-    let db = crate::store::new(&cfg.postgres)?;
+    if cfg.demo {
+        warn!("Running in demo mode. Nothing will be saved!");
+    }
+    let db = crate::store::connect(cfg)?;
     let oauth_token = cfg.token.to_string();
     let v = reqwest::tls::Version::TLS_1_2;
     let req_client = reqwest::Client::builder()
         .use_rustls_tls()
         .min_tls_version(v)
         .build()?;
-    let queue = Queue::from_db(db.clone()).await?;
+    let queue = Queue::from_db(db.clone(), &cfg.queue).await?;
+    let slack = Arc::new(WebApi::new(req_client, oauth_token));
+    csv_parser::observe_slack(crate::metrics::slack_call);
 
     let state = InnerState {
         db,
-        oauth_token,
-        req_client,
+        admins: cfg.admins.clone(),
+        admin_token: cfg.admin_token.clone(),
+        slack,
         queue,
+        limiter: RateLimiter::new(&cfg.rate_limit),
+        tasks: Tasks::default(),
+        events: Runner::new(cfg.server.event_workers),
     };
 
     Ok(Arc::new(state))
@@ -72,17 +106,74 @@ async fn initialize_state(cfg: &Config) -> color_eyre::Result<State> {
 pub async fn serve(cfg: &Config) -> color_eyre::Result<()> {
     let state = initialize_state(cfg).await?;
 
-    let app = new_router(state);
+    let (stop, stopped) = watch::channel(false);
+    scheduler::spawn(
+        state.clone(),
+        ReclaimPolicy::new(&cfg.reclaim),
+        stopped.clone(),
+    );
+
+    let replayed = runner::replay(&state).await?;
+    if replayed > 0 {
+        info!("Picking up {replayed} Slack event(s) left over from before the restart");
+    }
+    runner::spawn_reclaim(state.clone(), stopped);
+
+    let app = new_router(state.clone());
 
     info!("Serving shereebot at http://localhost:{}", cfg.server.port);
 
     axum::Server::bind(&cfg.server.address)
         .serve(app.into_make_service())
+        .with_graceful_shutdown(async {
+            shutdown_signal().await;
+            stop.send(true).ok();
+        })
         .await?;
 
+    // No more requests are coming, but the work they started may still be assigning films and
+    // messaging students about it.
+    let deadline = Duration::from_secs(cfg.server.shutdown_secs);
+    info!(
+        "Waiting up to {}s for {} background task(s) to finish",
+        deadline.as_secs(),
+        state.tasks.running()
+    );
+    match state.tasks.wait(deadline).await {
+        0 => info!("All background work finished"),
+        n => warn!("Shutting down with {n} background task(s) unfinished"),
+    }
+    match runner::release(&state).await {
+        Ok(0) => {}
+        Ok(n) => warn!("Left {n} Slack event(s) for the next server to handle"),
+        Err(e) => error!("Failed to give up claims on unhandled Slack events: {e}"),
+    }
+
     Ok(())
 }
 
+/// Resolves once the process is asked to stop, by SIGINT or SIGTERM. In-flight requests are
+/// then finished off, but no new ones are accepted.
+async fn shutdown_signal() {
+    let terminate = async {
+        match signal(SignalKind::terminate()) {
+            Ok(mut term) => {
+                term.recv().await;
+            }
+            Err(e) => {
+                error!("Can't listen for SIGTERM: {e}");
+                futures::future::pending::<()>().await;
+            }
+        }
+    };
+
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => info!("Received SIGINT"),
+        _ = terminate => info!("Received SIGTERM"),
+    }
+    info!("Shutting down: no longer accepting requests");
+}
+
 /// Initialize axum app and attach all routes.
 fn new_router(state: State) -> Router {
     let app = Router::new()
@@ -92,8 +183,24 @@ fn new_router(state: State) -> Router {
             get(handlers::list_films),
             // .post(handlers::insert_films::<T>),
         )
+        .route("/films/:name/history", get(handlers::film_history))
+        .route(
+            "/students/:slack_id/history",
+            get(handlers::student_history),
+        )
+        .route("/queue", get(handlers::list_jobs))
+        .route("/forecast", get(handlers::forecast))
+        .route("/admin/unassign", post(handlers::unassign))
+        .route("/admin/reassign", post(handlers::reassign))
+        .route("/admin/force-assign", post(handlers::force_assign))
+        .route("/admin/audit", get(handlers::audit_log))
+        .route("/admin/dry-run/:slack_id", get(handlers::dry_run))
+        .route("/admin/analytics", get(handlers::analytics))
+        .route("/admin/logs/:cid", get(handlers::logs))
         .route("/events", post(handlers::events_api_entrypoint))
         .route("/_health", get(health_check))
+        .route("/_ready", get(handlers::ready))
+        .route("/metrics", get(handlers::metrics))
         .route("/testing", post(handlers::testing))
         .layer(Extension(state));
 
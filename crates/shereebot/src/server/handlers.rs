@@ -1,14 +1,38 @@
-use axum::{body::Bytes, extract::Extension, http::StatusCode, response::Html, Json};
-use serde_json::Value;
-use tracing::{debug, error, info, trace};
+use async_trait::async_trait;
+use axum::{
+    body::Bytes,
+    extract::{Extension, FromRequest, Path, Query, RequestParts},
+    http::{
+        header::{AUTHORIZATION, CONTENT_TYPE},
+        StatusCode,
+    },
+    response::{Headers, Html, IntoResponse, Response},
+    Json,
+};
+use chrono::Utc;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use subtle::ConstantTimeEq;
+use tracing::{debug, error, info, trace, warn};
 
 use crate::{
-    server::{Result, State},
+    analytics,
+    audit::Origin,
+    forecast::{self, Forecast},
+    manager::Manager,
+    metrics,
+    queue::{DryRun, JobListing},
+    server::{
+        ready::{self, Readiness},
+        Result, State,
+    },
     slack::events::EventRequest,
+    slack::runner,
     slack::slash::{ResponseType, SlashResponse},
+    utils::correlation::{self, LogLine},
     Error,
 };
-use models::Film;
+use models::{Assignment, AuditEntry, Film, PendingEvent};
 
 /// Just for testing poorly documented slack endpoints.
 pub(super) async fn testing(body: Bytes) -> Result<Json<SlashResponse>> {
@@ -37,7 +61,8 @@ pub(super) async fn events_api_entrypoint(
     }
     trace!("req: {:?}", request.as_object());
 
-    let request: EventRequest = serde_json::from_value(request).map_err(|e| -> Error {
+    let payload = request;
+    let request: EventRequest = serde_json::from_value(payload.clone()).map_err(|e| -> Error {
         error!("{e}");
         e.into()
     })?;
@@ -47,7 +72,17 @@ pub(super) async fn events_api_entrypoint(
         return Ok((StatusCode::OK, "".to_string()));
     }
 
-    tokio::spawn(request.handle_event(state));
+    let event = PendingEvent {
+        id: request.event_id.clone(),
+        user: request.user().to_string(),
+        payload,
+        created_at: Utc::now(),
+    };
+    // Unless the event's been saved, have Slack send it again rather than risk losing it.
+    if let Err(e) = runner::receive(&state, event).await {
+        error!("Failed to save event {}: {e}", request.event_id);
+        return Ok((StatusCode::INTERNAL_SERVER_ERROR, "".to_string()));
+    }
 
     Ok((StatusCode::OK, "".to_string()))
 }
@@ -67,6 +102,225 @@ pub(super) async fn list_films(Extension(state): Extension<State>) -> Result<Jso
     }
 }
 
+/// Every assignment of a film, oldest first. Admins only, as it names the students.
+#[tracing::instrument(skip(state))]
+pub(super) async fn film_history(
+    _: AdminAuth,
+    Path(name): Path<String>,
+    Extension(state): Extension<State>,
+) -> Result<Json<Vec<Assignment>>> {
+    Ok(Json(state.db.get_film_history(&name).await?))
+}
+
+// --------------- Students Handlers --------------- //
+
+/// Every assignment a student has had, oldest first. Admins only.
+#[tracing::instrument(skip(state))]
+pub(super) async fn student_history(
+    _: AdminAuth,
+    Path(slack_id): Path<String>,
+    Extension(state): Extension<State>,
+) -> Result<Json<Vec<Assignment>>> {
+    Ok(Json(state.db.get_student_history(&slack_id).await?))
+}
+
+// --------------- Queue Handlers --------------- //
+
+/// Lists the jobs queue in order, flagging jobs that are projected to miss their due date.
+#[tracing::instrument]
+pub(super) async fn list_jobs(Extension(state): Extension<State>) -> Json<Vec<JobListing>> {
+    info!("Retrieving jobs queue...");
+    Json(state.queue.list_jobs().await)
+}
+
+/// When every film and class is projected to be finished, for the dashboard.
+#[tracing::instrument(skip(state))]
+pub(super) async fn forecast(Extension(state): Extension<State>) -> Result<Json<Forecast>> {
+    Ok(Json(forecast::forecast(&state.db, &state.queue).await?))
+}
+
+/// Whether the server can reach everything it depends on. Anything but a 200 means traffic
+/// shouldn't be sent here.
+#[tracing::instrument(skip(state))]
+pub(super) async fn ready(Extension(state): Extension<State>) -> (StatusCode, Json<Readiness>) {
+    let readiness = ready::check(&state).await;
+    if !readiness.ready {
+        warn!("Not ready: {:?}", readiness.checks);
+    }
+    let status = match readiness.ready {
+        true => StatusCode::OK,
+        false => StatusCode::SERVICE_UNAVAILABLE,
+    };
+    (status, Json(readiness))
+}
+
+/// Everything Prometheus scrapes, in its text format.
+#[tracing::instrument(skip(state))]
+pub(super) async fn metrics(Extension(state): Extension<State>) -> Result<Response> {
+    let text = metrics::gather(&state).await?;
+    let content_type = prometheus::TEXT_FORMAT;
+    Ok((Headers([(CONTENT_TYPE, content_type)]), text).into_response())
+}
+
+// --------------- Admin Handlers --------------- //
+
+/// Only lets requests through which carry the admin token as a bearer token.
+pub(super) struct AdminAuth;
+
+#[async_trait]
+impl<B: Send> FromRequest<B> for AdminAuth {
+    type Rejection = StatusCode;
+
+    async fn from_request(req: &mut RequestParts<B>) -> std::result::Result<Self, Self::Rejection> {
+        let Extension(state) = Extension::<State>::from_request(req)
+            .await
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        let expected = match state.admin_token {
+            Some(ref t) => t,
+            None => return Err(StatusCode::NOT_FOUND),
+        };
+
+        let token = req
+            .headers()
+            .and_then(|h| h.get(AUTHORIZATION))
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "));
+
+        match token {
+            // Compared in constant time, so the token can't be guessed a byte at a time.
+            Some(t) if bool::from(t.as_bytes().ct_eq(expected.as_bytes())) => Ok(Self),
+            _ => {
+                warn!("Rejected unauthorized admin request");
+                Err(StatusCode::UNAUTHORIZED)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub(super) struct UnassignRequest {
+    /// Slack id of the student to take the film from.
+    student: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub(super) struct AssignRequest {
+    film: String,
+    /// Slack id of the student to give the film to.
+    student: String,
+}
+
+/// Returns a student's film to the jobs queue.
+#[tracing::instrument(skip(state))]
+pub(super) async fn unassign(
+    _: AdminAuth,
+    Json(req): Json<UnassignRequest>,
+    Extension(state): Extension<State>,
+) -> Result<Json<Value>> {
+    let origin = Origin::api("POST /admin/unassign");
+    let msg = Manager::new(state, origin).unassign(&req.student).await?;
+    Ok(Json(json!({ "message": msg })))
+}
+
+/// Moves a film to a student who's eligible to work on it.
+#[tracing::instrument(skip(state))]
+pub(super) async fn reassign(
+    _: AdminAuth,
+    Json(req): Json<AssignRequest>,
+    Extension(state): Extension<State>,
+) -> Result<Json<Value>> {
+    let origin = Origin::api("POST /admin/reassign");
+    let msg = Manager::new(state, origin)
+        .reassign(&req.film, &req.student)
+        .await?;
+    Ok(Json(json!({ "message": msg })))
+}
+
+/// Gives a film to a student, bypassing the usual eligibility rules.
+#[tracing::instrument(skip(state))]
+pub(super) async fn force_assign(
+    _: AdminAuth,
+    Json(req): Json<AssignRequest>,
+    Extension(state): Extension<State>,
+) -> Result<Json<Value>> {
+    let origin = Origin::api("POST /admin/force-assign");
+    let msg = Manager::new(state, origin)
+        .force_assign(&req.film, &req.student)
+        .await?;
+    Ok(Json(json!({ "message": msg })))
+}
+
+/// How many audit log entries can be asked for at once.
+const MAX_AUDIT_LIMIT: i64 = 1000;
+
+#[derive(Debug, Deserialize)]
+pub(super) struct AuditQuery {
+    /// Slack id of a student.
+    student: Option<String>,
+    film: Option<String>,
+    limit: Option<i64>,
+}
+
+/// The latest audit log entries, newest first, optionally only those about a student or film.
+#[tracing::instrument(skip(state))]
+pub(super) async fn audit_log(
+    _: AdminAuth,
+    Query(q): Query<AuditQuery>,
+    Extension(state): Extension<State>,
+) -> Result<Json<Vec<AuditEntry>>> {
+    let limit = q.limit.unwrap_or(100);
+    if !(1..=MAX_AUDIT_LIMIT).contains(&limit) {
+        let e = format!("`limit` must be between 1 and {MAX_AUDIT_LIMIT}, not {limit}.");
+        return Err(Error::InvalidArg(e).into());
+    }
+    let entries = state
+        .db
+        .get_audit_log(q.student.as_deref(), q.film.as_deref(), limit)
+        .await?;
+    Ok(Json(entries))
+}
+
+/// Which job a student would be given if they requested work now, and why every other job would
+/// be passed over. Nothing is assigned.
+#[tracing::instrument(skip(state))]
+pub(super) async fn dry_run(
+    _: AdminAuth,
+    Path(slack_id): Path<String>,
+    Extension(state): Extension<State>,
+) -> Result<Json<DryRun>> {
+    Ok(Json(state.queue.dry_run(&slack_id).await?))
+}
+
+/// What was logged under an internal error's reference, oldest first.
+pub(super) async fn logs(_: AdminAuth, Path(cid): Path<String>) -> Json<Vec<LogLine>> {
+    Json(correlation::logs(&cid))
+}
+
+#[derive(Debug, Deserialize)]
+pub(super) struct AnalyticsQuery {
+    /// `json` (the default) or `csv`.
+    format: Option<String>,
+}
+
+/// How long jobs spend queued, held and waiting, and how many are delivered per day, by role,
+/// group and class.
+#[tracing::instrument(skip(state))]
+pub(super) async fn analytics(
+    _: AdminAuth,
+    Query(q): Query<AnalyticsQuery>,
+    Extension(state): Extension<State>,
+) -> Result<Response> {
+    let report = analytics::analyse(&state.db).await?;
+    match q.format.as_deref() {
+        None | Some("json") => Ok(Json(report).into_response()),
+        Some("csv") => {
+            let csv = report.to_csv().map_err(Error::Internal)?;
+            Ok((Headers([(CONTENT_TYPE, "text/csv")]), csv).into_response())
+        }
+        Some(other) => Err(Error::InvalidArg(format!("Unknown format `{other}`.")).into()),
+    }
+}
+
 // #[tracing::instrument(skip_all)]
 // pub(super) async fn insert_films<T: Client>(
 //     form: Form<SlashRequest>,
@@ -13,6 +13,8 @@ use tower_http::{
 };
 use tracing::{debug, Span};
 
+use crate::{metrics, utils::correlation};
+
 /// Attaches interceptors to router.
 pub(crate) fn attach(router: Router) -> Router {
     let middleware = ServiceBuilder::new()
@@ -24,8 +26,9 @@ pub(crate) fn attach(router: Router) -> Router {
         .layer(
             TraceLayer::new_for_http()
                 .make_span_with(|request: &Request<_>| {
-                    tracing::debug_span!(
+                    tracing::info_span!(
                         "request",
+                        cid = %correlation::new_id(),
                         uri = %request.uri().path(),
                         req = %request.method(),
                     )
@@ -33,6 +36,7 @@ pub(crate) fn attach(router: Router) -> Router {
                 .on_request(|_request: &Request<_>, _span: &Span| {})
                 .on_response(|response: &Response, latency: Duration, _span: &Span| {
                     let s = response.status();
+                    metrics::http_response(s.as_u16(), latency);
                     match latency.as_micros() {
                         0..=999 => debug!("[{}] Served in {}μs", s, latency.as_micros()),
                         1000..=999999 => debug!("[{}] Served in {}ms", s, latency.as_millis()),
@@ -0,0 +1,191 @@
+//! Readiness checks, so a deploy only sends traffic to an instance which can actually serve it.
+//!
+//! Unlike `/_health`, which only shows the server is up, every dependency is checked: the
+//! database, its schema, the in-memory queues and Slack.
+use std::{
+    collections::{BinaryHeap, HashSet},
+    future::Future,
+    time::Duration,
+};
+
+use futures::lock::Mutex;
+use serde::Serialize;
+use tokio::time::timeout;
+use tracing::warn;
+use uuid::Uuid;
+
+use crate::{queue::QueueItem, server::InnerState};
+
+/// What a check found if it passed, or why it failed.
+type Outcome = std::result::Result<String, String>;
+
+/// How long any one check gets before it's failed. The load balancer gives up after 6 seconds.
+const CHECK_TIMEOUT: Duration = Duration::from_secs(3);
+
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct Check {
+    pub(crate) name: &'static str,
+    pub(crate) ok: bool,
+    /// What was found, or why the check failed.
+    pub(crate) detail: String,
+    pub(crate) millis: u128,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct Readiness {
+    pub(crate) ready: bool,
+    pub(crate) checks: Vec<Check>,
+}
+
+/// Runs every check at once.
+pub(crate) async fn check(state: &InnerState) -> Readiness {
+    let (database, schema, queue, slack) = futures::join!(
+        run("database", database(state)),
+        run("schema", schema(state)),
+        run("queue", queue(state)),
+        run("slack", slack(state)),
+    );
+    let checks = vec![database, schema, queue, slack];
+
+    Readiness {
+        ready: checks.iter().all(|c| c.ok),
+        checks,
+    }
+}
+
+/// Times a check, failing it if it errors or takes too long.
+async fn run<F>(name: &'static str, check: F) -> Check
+where
+    F: Future<Output = Outcome>,
+{
+    let start = std::time::Instant::now();
+    let (ok, detail) = match timeout(CHECK_TIMEOUT, check).await {
+        Ok(Ok(detail)) => (true, detail),
+        Ok(Err(detail)) => (false, detail),
+        Err(_) => (
+            false,
+            format!("timed out after {}s", CHECK_TIMEOUT.as_secs()),
+        ),
+    };
+    Check {
+        name,
+        ok,
+        detail,
+        millis: start.elapsed().as_millis(),
+    }
+}
+
+/// A connection can be had from the pool, and used.
+async fn database(state: &InnerState) -> Outcome {
+    state.db.ping().await.map_err(|e| e.to_string())?;
+    let detail = match state.db.pool_status() {
+        Some(s) => format!("{} of {} connections free", s.available.max(0), s.max_size),
+        None => "connected".to_string(),
+    };
+    Ok(detail)
+}
+
+/// Every migration has been applied.
+async fn schema(state: &InnerState) -> Outcome {
+    let version = state.db.schema_version().await.map_err(|e| e.to_string())?;
+    let detail = format!("version {} of {}", version.applied, version.latest);
+    if !version.is_current() {
+        return Err(format!("{detail}: run the migrations"));
+    }
+    Ok(detail)
+}
+
+/// Both queues have been loaded and can be locked. Where they differ from the database is only
+/// warned about: during a deploy the other server's still changing it.
+async fn queue(state: &InnerState) -> Outcome {
+    // Read before taking any locks, so queue operations aren't held up by the database.
+    let stored = futures::try_join!(state.db.get_queue(false), state.db.get_queue(true));
+    let jobs = ids(&state.queue.jobs_q).await;
+    let waiters = ids(&state.queue.wait_q).await;
+
+    let mut detail = format!("{} job(s), {} waiting", jobs.len(), waiters.len());
+    let (stored_jobs, stored_waiters) = match stored {
+        Ok(stored) => stored,
+        Err(e) => return Ok(format!("{detail}; couldn't compare with the database: {e}")),
+    };
+    for (name, loaded, stored) in [
+        ("jobs_q", jobs, stored_jobs),
+        ("wait_q", waiters, stored_waiters),
+    ] {
+        let stored: HashSet<_> = stored.iter().map(|item| item.id).collect();
+        let differ = loaded.symmetric_difference(&stored).count();
+        if differ > 0 {
+            let warning = format!("the {name} differs from the database by {differ} item(s)");
+            warn!("Readiness: {warning}");
+            detail.push_str(&format!("; {warning}"));
+        }
+    }
+    Ok(detail)
+}
+
+/// The id of everything in a queue, only holding its lock long enough to read them.
+async fn ids(q: &Mutex<BinaryHeap<QueueItem>>) -> HashSet<Uuid> {
+    q.lock().await.iter().map(|item| item.id).collect()
+}
+
+/// Slack still accepts the bot's token.
+async fn slack(state: &InnerState) -> Outcome {
+    state.slack.auth_test().await.map_err(|e| e.to_string())?;
+    Ok("token accepted".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use async_trait::async_trait;
+    use models::{Film, Priority};
+
+    use super::*;
+    use crate::{
+        queue::{self, Queue},
+        slack::{app_mentions::Response, Messenger},
+        store, Error, Result,
+    };
+
+    /// Slack, after the bot's token has been revoked.
+    struct Revoked;
+
+    #[async_trait]
+    impl Messenger for Revoked {
+        async fn post(&self, _: &Response) -> Result<()> {
+            Ok(())
+        }
+
+        async fn auth_test(&self) -> Result<()> {
+            Err(Error::Unknown("token_revoked".to_string()))
+        }
+    }
+
+    #[tokio::test]
+    async fn check_ready() {
+        let state = InnerState::_new();
+        let readiness = check(&state).await;
+        assert!(readiness.ready, "{readiness:?}");
+        let names: Vec<_> = readiness.checks.iter().map(|c| c.name).collect();
+        assert_eq!(vec!["database", "schema", "queue", "slack"], names);
+
+        let state = InnerState::offline(store::new_memory(), Queue::_new(), Arc::new(Revoked));
+        let readiness = check(&state).await;
+        assert!(!readiness.ready);
+        let failed: Vec<_> = readiness.checks.iter().filter(|c| !c.ok).collect();
+        assert_eq!(1, failed.len());
+        assert_eq!("slack", failed[0].name);
+        assert!(failed[0].detail.contains("token_revoked"));
+
+        // A queue that differs from the database is only warned about, as another server may
+        // still be changing it.
+        let state = InnerState::_new();
+        let job = queue::new_job(&Film::new("a", Priority::High, 1), "");
+        state.queue.jobs_q.lock().await.push(job);
+        let readiness = check(&state).await;
+        assert!(readiness.ready, "{readiness:?}");
+        let queue = &readiness.checks[2];
+        assert!(queue.detail.contains("the jobs_q differs"), "{queue:?}");
+    }
+}
@@ -0,0 +1,476 @@
+//! Runs a whole term's worth of students through the manager, to check that a cohort can finish.
+//!
+//! Everything happens in memory on a single thread, with simulated time and a seeded random
+//! number generator, so the same scenario always plays out the same way.
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, BinaryHeap, HashSet},
+    fmt,
+    sync::Arc,
+    time::Duration,
+};
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::Serialize;
+
+use crate::{
+    audit::Origin,
+    integrity,
+    manager::Manager,
+    queue::Queue,
+    server::{InnerState, State},
+    slack::FakeSlack,
+    store::{self, Database},
+    Error, Result,
+};
+use models::{Film, Priority, Role, Student};
+
+/// How long spawned tasks, like draining the wait queue, get to finish after each step.
+const SETTLE: Duration = Duration::from_secs(30);
+
+/// The cohort to simulate, and how long students take.
+#[derive(Debug, Clone)]
+pub struct Scenario {
+    pub students: usize,
+    pub groups: usize,
+    pub films: usize,
+    pub seed: u64,
+    /// Most hours a student takes to work a stage.
+    pub max_work_hours: u64,
+    /// Most hours a student waits between delivering and asking for more work.
+    pub max_break_hours: u64,
+}
+
+impl Default for Scenario {
+    fn default() -> Self {
+        Self {
+            students: 12,
+            groups: 3,
+            films: 12,
+            seed: 0,
+            max_work_hours: 48,
+            max_break_hours: 24,
+        }
+    }
+}
+
+/// A student left waiting for work once nothing else could happen.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Stuck {
+    pub student: String,
+    pub group: i32,
+    pub role: Role,
+}
+
+/// A film that never made it through every stage.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Unfinished {
+    pub film: String,
+    pub group: i32,
+    pub role: Role,
+}
+
+/// Something that should never happen, and the step it was first seen after.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Violation {
+    pub step: usize,
+    pub issue: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Report {
+    pub seed: u64,
+    pub steps: usize,
+    /// Simulated hours until the last step.
+    pub hours: u64,
+    pub deliveries: usize,
+    pub deliveries_by_role: BTreeMap<Role, usize>,
+    pub films_finished: usize,
+    pub students_finished: usize,
+    /// Slack messages the bot sent.
+    pub messages: usize,
+    pub stuck: Vec<Stuck>,
+    pub unfinished: Vec<Unfinished>,
+    pub violations: Vec<Violation>,
+}
+
+impl Report {
+    /// Whether every film and student finished, without breaking any invariants.
+    pub fn is_clean(&self) -> bool {
+        self.stuck.is_empty() && self.unfinished.is_empty() && self.violations.is_empty()
+    }
+
+    /// Deliveries per simulated day.
+    pub fn throughput(&self) -> f64 {
+        let days = (self.hours.max(1) as f64) / 24.0;
+        self.deliveries as f64 / days
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Seed {}: {} steps over {:.1} days.",
+            self.seed,
+            self.steps,
+            self.hours as f64 / 24.0
+        )?;
+        writeln!(
+            f,
+            "{} deliveries ({:.2}/day), {} messages sent.",
+            self.deliveries,
+            self.throughput(),
+            self.messages
+        )?;
+        for (role, count) in &self.deliveries_by_role {
+            writeln!(f, "- {}: {count}", role.as_ref())?;
+        }
+        let (films, students) = (self.films_finished, self.students_finished);
+        write!(f, "{films} film(s) and {students} student(s) finished.")?;
+
+        for s in &self.stuck {
+            write!(
+                f,
+                "\nStuck: {} (group {}) waiting for {}",
+                s.student,
+                s.group,
+                s.role.as_ref()
+            )?;
+        }
+        for u in &self.unfinished {
+            write!(
+                f,
+                "\nUnfinished: {} (group {}) at {}",
+                u.film,
+                u.group,
+                u.role.as_ref()
+            )?;
+        }
+        for v in &self.violations {
+            write!(f, "\nViolation after step {}: {}", v.step, v.issue)?;
+        }
+        Ok(())
+    }
+}
+
+/// Plays out a scenario from start to finish.
+///
+/// The simulation gets its own single threaded runtime, so spawned tasks always run in the same
+/// order.
+pub async fn run(scenario: Scenario) -> Result<Report> {
+    let (tx, rx) = tokio::sync::oneshot::channel();
+    std::thread::spawn(move || {
+        let res = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .map_err(|e| Error::Unknown(e.to_string()))
+            .and_then(|rt| rt.block_on(simulate(scenario)));
+        tx.send(res).ok();
+    });
+    rx.await
+        .map_err(|_| Error::Unknown("Simulation thread panicked".to_string()))?
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Phase {
+    Idle,
+    Waiting,
+    Working,
+    Done,
+}
+
+struct Simulation {
+    db: Database,
+    state: State,
+    slack: Arc<FakeSlack>,
+    rng: StdRng,
+    scenario: Scenario,
+    /// Slack ids, indexed by student.
+    ids: Vec<String>,
+    phases: Vec<Phase>,
+    /// Upcoming (hour, sequence, student) events. The sequence keeps ties in a fixed order.
+    events: BinaryHeap<Reverse<(u64, usize, usize)>>,
+    now: u64,
+    seq: usize,
+    seen: HashSet<String>,
+    report: Report,
+}
+
+async fn simulate(scenario: Scenario) -> Result<Report> {
+    let mut sim = Simulation::new(scenario).await?;
+    while let Some(Reverse((hour, _, student))) = sim.events.pop() {
+        sim.now = hour;
+        sim.step(student).await?;
+    }
+    sim.finish().await
+}
+
+impl Simulation {
+    async fn new(scenario: Scenario) -> Result<Self> {
+        let db = store::new_memory();
+        let queue = Queue::from_db(db.clone(), &Default::default()).await?;
+        let slack = Arc::new(FakeSlack::default());
+        let state = InnerState::offline(db.clone(), queue, slack.clone());
+        let manager = Manager::new(state.clone(), Origin::system("simulation"));
+
+        let groups = scenario.groups.max(1);
+        for i in 0..scenario.films {
+            let mut film = Film::new(&format!("film-{i:02}"), Priority::High, group(i, groups));
+            film.class = "sim".to_string();
+            manager.insert_film(&film).await?;
+        }
+
+        let mut ids = vec![];
+        for i in 0..scenario.students {
+            let name = format!("student-{i:02}");
+            let mut student = db
+                .insert_student_from_csv(&name, group(i, groups), "sim")
+                .await?;
+            student.slack_id = format!("S{i:02}");
+            db.update_student(&student).await?;
+            ids.push(student.slack_id);
+        }
+
+        let mut sim = Self {
+            db,
+            state,
+            slack,
+            rng: StdRng::seed_from_u64(scenario.seed),
+            phases: vec![Phase::Idle; ids.len()],
+            ids,
+            events: BinaryHeap::new(),
+            now: 0,
+            seq: 0,
+            seen: HashSet::new(),
+            report: Report {
+                seed: scenario.seed,
+                steps: 0,
+                hours: 0,
+                deliveries: 0,
+                deliveries_by_role: BTreeMap::new(),
+                films_finished: 0,
+                students_finished: 0,
+                messages: 0,
+                stuck: vec![],
+                unfinished: vec![],
+                violations: vec![],
+            },
+            scenario,
+        };
+        for i in 0..sim.ids.len() {
+            let delay = sim.rng.gen_range(0..=sim.scenario.max_break_hours);
+            sim.schedule(i, delay);
+        }
+        Ok(sim)
+    }
+
+    fn manager(&self) -> Manager {
+        Manager::new(self.state.clone(), Origin::system("simulation"))
+    }
+
+    /// Waits for spawned tasks to finish, so the next step sees everything they did.
+    async fn settle(&self) -> Result<()> {
+        match self.state.tasks.wait(SETTLE).await {
+            0 => Ok(()),
+            n => Err(Error::Unknown(format!("{n} task(s) never finished"))),
+        }
+    }
+
+    fn schedule(&mut self, student: usize, delay: u64) {
+        self.events
+            .push(Reverse((self.now + delay, self.seq, student)));
+        self.seq += 1;
+    }
+
+    /// Has one student take their next action, then checks nothing's gone wrong.
+    async fn step(&mut self, i: usize) -> Result<()> {
+        self.report.steps += 1;
+        let id = self.ids[i].clone();
+        match self.phases[i] {
+            Phase::Idle => {
+                let ts = self.report.steps.to_string();
+                self.manager().request_work(&id, &ts, "sim").await;
+                self.settle().await?;
+                let student = self.db.get_student(&id).await?;
+                self.phases[i] = if student.current_role == Role::Done {
+                    Phase::Done
+                } else {
+                    Phase::Waiting
+                };
+                self.start_work(i, &student);
+            }
+            Phase::Working => {
+                let before = self.db.get_student(&id).await?;
+                let reply = self.manager().deliver_work(&id, "").await;
+                self.settle().await?;
+                let after = self.db.get_student(&id).await?;
+                if after.current_film.is_some() && after.current_film == before.current_film {
+                    // Trying again would only fail the same way, so they give up.
+                    self.violation(format!("{id} couldn't deliver: {reply}"));
+                    self.phases[i] = Phase::Done;
+                } else if after.current_role == Role::Done {
+                    self.delivered(before.current_role);
+                    self.phases[i] = Phase::Done;
+                } else {
+                    self.delivered(before.current_role);
+                    self.phases[i] = Phase::Idle;
+                    let hours = self.rng.gen_range(0..=self.scenario.max_break_hours);
+                    self.schedule(i, hours);
+                }
+            }
+            // Waiting students are picked up when someone delivers, and done students stop.
+            Phase::Waiting | Phase::Done => {}
+        }
+
+        // Deliveries hand films out to whoever's waiting.
+        for w in 0..self.ids.len() {
+            if self.phases[w] == Phase::Waiting {
+                let student = self.db.get_student(&self.ids[w]).await?;
+                self.start_work(w, &student);
+            }
+        }
+
+        self.check().await
+    }
+
+    /// Sets a student to work, if the bot has handed them a film.
+    fn start_work(&mut self, i: usize, student: &Student) {
+        if student.current_film.is_some() {
+            self.phases[i] = Phase::Working;
+            let hours = self.rng.gen_range(1..=self.scenario.max_work_hours.max(1));
+            self.schedule(i, hours);
+        }
+    }
+
+    fn delivered(&mut self, role: Role) {
+        self.report.deliveries += 1;
+        *self.report.deliveries_by_role.entry(role).or_default() += 1;
+    }
+
+    fn violation(&mut self, issue: String) {
+        if self.seen.insert(issue.clone()) {
+            let step = self.report.steps;
+            self.report.violations.push(Violation { step, issue });
+        }
+    }
+
+    /// Checks the store is consistent, and agrees with the queues and what students are doing.
+    async fn check(&mut self) -> Result<()> {
+        let report = integrity::check(&self.db, None).await?;
+        for finding in report.findings {
+            self.violation(finding.issue.to_string());
+        }
+
+        let waiting: Vec<_> = {
+            let wait_q = self.state.queue.wait_q.lock().await;
+            wait_q.iter().map(|w| w.student_slack_id.clone()).collect()
+        };
+        for (i, id) in self.ids.clone().iter().enumerate() {
+            let queued = waiting.iter().filter(|w| *w == id).count();
+            let expected = usize::from(self.phases[i] == Phase::Waiting);
+            if queued != expected {
+                self.violation(format!("{id} is in the wait queue {queued} time(s)"));
+            }
+        }
+
+        for student in self.db.list_students().await? {
+            let film = match &student.current_film {
+                Some(f) => self.db.get_film(f).await?,
+                None => continue,
+            };
+            match film {
+                Some(f) if f.current_role == student.current_role => {}
+                Some(f) => self.violation(format!(
+                    "{} is working {} on {}, which needs {}",
+                    student.slack_id,
+                    student.current_role.as_ref(),
+                    f.name,
+                    f.current_role.as_ref()
+                )),
+                None => self.violation(format!("{} holds a missing film", student.slack_id)),
+            }
+        }
+        Ok(())
+    }
+
+    async fn finish(self) -> Result<Report> {
+        let films = self.db.list_films().await?;
+        let students = self.db.list_students().await?;
+        let mut report = self.report;
+
+        report.hours = self.now;
+        report.messages = self.slack.sent().len();
+        report.films_finished = films
+            .iter()
+            .filter(|f| f.current_role == Role::Done)
+            .count();
+        report.students_finished = students
+            .iter()
+            .filter(|s| s.current_role == Role::Done)
+            .count();
+
+        let mut films: Vec<_> = films
+            .into_iter()
+            .filter(|f| f.current_role != Role::Done)
+            .map(|f| Unfinished {
+                film: f.name,
+                group: f.group_number,
+                role: f.current_role,
+            })
+            .collect();
+        films.sort_by(|a, b| a.film.cmp(&b.film));
+        report.unfinished = films;
+
+        let mut stuck: Vec<_> = students
+            .into_iter()
+            .filter(|s| s.current_role != Role::Done)
+            .map(|s| Stuck {
+                student: s.slack_id,
+                group: s.group_number,
+                role: s.current_role,
+            })
+            .collect();
+        stuck.sort_by(|a, b| a.student.cmp(&b.student));
+        report.stuck = stuck;
+
+        Ok(report)
+    }
+}
+
+/// Groups are numbered from 1, and handed out in turn.
+fn group(i: usize, groups: usize) -> i32 {
+    (i % groups) as i32 + 1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scenario(seed: u64) -> Scenario {
+        Scenario {
+            students: 8,
+            groups: 2,
+            films: 8,
+            seed,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn check_term() -> Result<()> {
+        let report = run(scenario(7)).await?;
+        assert!(report.is_clean(), "{report}");
+        assert_eq!(8 * 4, report.deliveries);
+        assert_eq!(8, report.films_finished);
+        assert_eq!(8, report.students_finished);
+        assert_eq!(Some(&8), report.deliveries_by_role.get(&Role::Sound));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn check_deterministic() -> Result<()> {
+        assert_eq!(run(scenario(3)).await?, run(scenario(3)).await?);
+        Ok(())
+    }
+}
@@ -1,10 +1,19 @@
+pub(crate) mod admin;
 pub mod app_mentions;
 pub mod events;
 pub mod message;
+pub(crate) mod runner;
 pub mod slash;
 
+use std::sync::{Mutex, PoisonError};
+use std::time::Instant;
+
+use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
 
+use crate::{metrics, Error, Result};
+use app_mentions::Response;
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct UserResponse {
     pub ok: bool,
@@ -16,3 +25,131 @@ pub struct SlackUser {
     pub id: String,
     pub real_name: String,
 }
+
+/// The part of every Web API response that says whether the call worked.
+#[derive(Debug, Deserialize)]
+struct ApiResponse {
+    ok: bool,
+    error: Option<String>,
+}
+
+/// Splits a message into the links it contains and the rest of its text.
+/// Slack wraps links as `<https://...>`, or `<https://...|label>`.
+pub(crate) fn parse_links(text: &str) -> (Vec<String>, String) {
+    let (links, words): (Vec<_>, Vec<_>) = text.split_whitespace().partition(|w| {
+        let w = w.trim_start_matches('<');
+        w.starts_with("https://") || w.starts_with("http://")
+    });
+
+    let links = links
+        .into_iter()
+        .map(|l| l.trim_start_matches('<').trim_end_matches('>'))
+        .map(|l| l.split('|').next().unwrap_or_default().to_string())
+        .collect();
+    (links, words.join(" "))
+}
+
+/// Where the bot's messages go. Tests and simulations use a fake, so nothing reaches Slack.
+#[async_trait]
+pub(crate) trait Messenger: Send + Sync + 'static {
+    /// Posts a message. The response's channel may be a user's slack id, to DM them.
+    async fn post(&self, res: &Response) -> Result<()>;
+    /// Checks that Slack still accepts the bot's token.
+    async fn auth_test(&self) -> Result<()>;
+}
+
+/// Slack's Web API.
+pub(crate) struct WebApi {
+    client: reqwest::Client,
+    token: String,
+}
+
+impl WebApi {
+    pub(crate) fn new(client: reqwest::Client, token: String) -> Self {
+        Self { client, token }
+    }
+
+    /// Calls a Web API method, recording how it went. Slack refuses calls with a 200 and
+    /// `ok: false`, so it's the body that says whether one worked.
+    async fn call(&self, method: &str, req: reqwest::RequestBuilder) -> Result<()> {
+        let start = Instant::now();
+        let res = match req.bearer_auth(&self.token).send().await {
+            Ok(res) => res.json::<ApiResponse>().await,
+            Err(e) => Err(e),
+        };
+        let ok = matches!(&res, Ok(r) if r.ok);
+        metrics::slack_call(method, start.elapsed(), ok);
+
+        match res? {
+            ApiResponse { ok: true, .. } => Ok(()),
+            ApiResponse { error, .. } => Err(Error::Unknown(format!(
+                "Slack refused {method}: {}",
+                error.unwrap_or_default()
+            ))),
+        }
+    }
+}
+
+#[async_trait]
+impl Messenger for WebApi {
+    async fn post(&self, res: &Response) -> Result<()> {
+        let req = self
+            .client
+            .post("https://slack.com/api/chat.postMessage")
+            .json(res);
+        self.call("chat.postMessage", req).await
+    }
+
+    async fn auth_test(&self) -> Result<()> {
+        let req = self.client.post("https://slack.com/api/auth.test");
+        self.call("auth.test", req).await
+    }
+}
+
+/// Keeps every message it's asked to post, instead of sending it.
+#[derive(Default)]
+pub(crate) struct FakeSlack {
+    sent: Mutex<Vec<Response>>,
+}
+
+impl FakeSlack {
+    /// Every message posted so far, oldest first.
+    pub(crate) fn sent(&self) -> Vec<Response> {
+        self.sent
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+}
+
+#[async_trait]
+impl Messenger for FakeSlack {
+    async fn post(&self, res: &Response) -> Result<()> {
+        self.sent
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(res.clone());
+        Ok(())
+    }
+
+    async fn auth_test(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_parse_links() {
+        let text = "<https://drive.google.com/a|project> audio needs  work https://b.com/c ";
+        let (links, notes) = parse_links(text);
+        assert_eq!(vec!["https://drive.google.com/a", "https://b.com/c"], links);
+        assert_eq!("audio needs work", notes);
+
+        let (links, notes) = parse_links("");
+        assert!(links.is_empty());
+        assert!(notes.is_empty());
+    }
+}
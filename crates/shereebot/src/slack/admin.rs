@@ -0,0 +1,262 @@
+//! Commands only admins may run, from either an app mention or a direct message.
+use std::str::FromStr;
+
+use strum::EnumString;
+use tracing::{info, warn};
+
+use crate::{
+    audit::Origin,
+    feasibility, forecast, integrity,
+    manager::{self, Manager},
+    server::State,
+    utils::correlation,
+    Result,
+};
+use models::{AuditAction, AuditEntry};
+
+const NOT_ADMIN: &str = "Sorry, only admins can run that command!";
+
+const ADMIN_ERR: &str = "I couldn't read your command :cry:
+Admin commands are:
+`unassign @student`
+`reassign @student film`
+`force-assign @student film`
+`audit @student` or `audit film`
+`check [--repair]`
+`feasibility`
+`dry-run @student`
+`forecast`
+`logs ref`";
+
+/// How many audit log entries the `audit` command shows.
+const AUDIT_LIMIT: i64 = 20;
+
+/// How many log lines the `logs` command shows, so the message stays readable.
+const LOGS_LIMIT: usize = 50;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, EnumString)]
+#[strum(ascii_case_insensitive)]
+pub(crate) enum AdminCommand {
+    /// Returns a student's film to the jobs queue.
+    Unassign,
+    /// Moves a film to a student who's eligible to work on it.
+    Reassign,
+    /// Gives a film to a student, whether or not they're eligible to work on it.
+    #[strum(serialize = "forceassign", serialize = "force-assign")]
+    ForceAssign,
+    /// Shows the latest changes made to a student or film.
+    Audit,
+    /// Looks for inconsistencies in the database, optionally repairing them.
+    Check,
+    /// Predicts whether every student can finish every role with the films there are.
+    Feasibility,
+    /// Shows which job a student would get if they requested work now, and why.
+    #[strum(serialize = "dryrun", serialize = "dry-run")]
+    DryRun,
+    /// Estimates when every film and class will be finished.
+    Forecast,
+    /// Shows what was logged under an internal error's reference.
+    Logs,
+}
+
+impl AdminCommand {
+    /// Splits "COMMAND ARGS" into an admin command and its arguments.
+    /// Returns None if the text isn't an admin command.
+    pub(crate) fn parse(text: &str) -> Option<(Self, &str)> {
+        let text = text.trim();
+        let (cmd, args) = text.split_once(char::is_whitespace).unwrap_or((text, ""));
+        let cmd = Self::from_str(cmd).ok()?;
+        Some((cmd, args.trim()))
+    }
+}
+
+/// Runs an admin command on behalf of whoever sent it, returning the message to send back.
+#[tracing::instrument(skip(state))]
+pub(crate) async fn run(state: &State, origin: &Origin, cmd: AdminCommand, args: &str) -> String {
+    let user = &origin.actor;
+    if !state.admins.iter().any(|a| a == user) {
+        warn!("{user} tried to run an admin command");
+        return NOT_ADMIN.to_string();
+    }
+
+    let res = match cmd {
+        AdminCommand::Audit => audit(state, args).await,
+        AdminCommand::Check => check(state, origin, args).await,
+        AdminCommand::Feasibility => feasibility::check(&state.db)
+            .await
+            .map(|report| report.to_string()),
+        AdminCommand::DryRun => dry_run(state, args).await,
+        AdminCommand::Forecast => forecast::forecast(&state.db, &state.queue)
+            .await
+            .map(|forecast| forecast.to_string()),
+        AdminCommand::Logs => Ok(logs(args)),
+        _ => override_assignment(state, origin, cmd, args).await,
+    };
+
+    res.unwrap_or_else(manager::report_error)
+}
+
+/// Unassigns, reassigns or force-assigns a film.
+async fn override_assignment(
+    state: &State,
+    origin: &Origin,
+    cmd: AdminCommand,
+    args: &str,
+) -> Result<String> {
+    let (student, film) = match parse_args(cmd, args) {
+        Some(a) => a,
+        None => return Ok(ADMIN_ERR.to_string()),
+    };
+    info!("Running {cmd:?} for {student}");
+
+    let manager = Manager::new(state.clone(), origin.clone());
+    match cmd {
+        AdminCommand::Unassign => manager.unassign(student).await,
+        AdminCommand::Reassign => manager.reassign(film, student).await,
+        AdminCommand::ForceAssign => manager.force_assign(film, student).await,
+        AdminCommand::Audit
+        | AdminCommand::Check
+        | AdminCommand::Feasibility
+        | AdminCommand::DryRun
+        | AdminCommand::Forecast
+        | AdminCommand::Logs => Ok(ADMIN_ERR.to_string()),
+    }
+}
+
+/// Lists the latest audit log entries for "@student" or "film".
+async fn audit(state: &State, args: &str) -> Result<String> {
+    if args.is_empty() {
+        return Ok(ADMIN_ERR.to_string());
+    }
+    let entries = match parse_mention(args) {
+        Some(student) => state.db.get_audit_log(Some(student), None, AUDIT_LIMIT),
+        None => state.db.get_audit_log(None, Some(args), AUDIT_LIMIT),
+    }
+    .await?;
+
+    if entries.is_empty() {
+        return Ok(format!("Nothing has happened to {args} yet."));
+    }
+    let mut msg = format!("Latest changes to {args}:");
+    entries.iter().for_each(|e| msg += &format_entry(e));
+    Ok(msg)
+}
+
+/// Runs the integrity check, repairing what it can if "--repair" is passed.
+async fn check(state: &State, origin: &Origin, args: &str) -> Result<String> {
+    let repair = match args {
+        "" => false,
+        "--repair" => true,
+        _ => return Ok(ADMIN_ERR.to_string()),
+    };
+    let entry = repair.then(|| origin.entry(AuditAction::Repair, None, None));
+    let report = integrity::check(&state.db, entry.as_ref()).await?;
+    if report.repaired() > 0 {
+        // The in-memory queues have to pick up whatever was repaired.
+        state.queue.reload().await?;
+    }
+    Ok(report.to_string())
+}
+
+/// Shows which job "@student" would get if they requested work now. Nothing is assigned.
+async fn dry_run(state: &State, args: &str) -> Result<String> {
+    match parse_mention(args) {
+        Some(student) => Ok(state.queue.dry_run(student).await?.to_string()),
+        None => Ok(ADMIN_ERR.to_string()),
+    }
+}
+
+/// Shows the latest lines logged under a reference from an internal error message.
+fn logs(args: &str) -> String {
+    let cid = args.trim_matches('`');
+    if cid.is_empty() || cid.contains(char::is_whitespace) {
+        return ADMIN_ERR.to_string();
+    }
+    let lines = correlation::logs(cid);
+    if lines.is_empty() {
+        return format!("Nothing's been logged under `{cid}`, or it's too old to still be kept.");
+    }
+
+    let skipped = lines.len().saturating_sub(LOGS_LIMIT);
+    let mut msg = format!("Logs for `{cid}`");
+    if skipped > 0 {
+        msg += &format!(" (the last {LOGS_LIMIT} of {} lines)", lines.len());
+    }
+    msg += ":\n```";
+    lines
+        .iter()
+        .skip(skipped)
+        .for_each(|l| msg += &format!("\n{l}"));
+    msg + "\n```"
+}
+
+fn format_entry(e: &AuditEntry) -> String {
+    let mut line = format!(
+        "\n`{}` {} by {}",
+        e.created_at.format("%Y-%m-%d %H:%M"),
+        e.action.as_ref(),
+        e.actor
+    );
+    if let Some(student) = &e.student {
+        line += &format!(" for <@{student}>");
+    }
+    if let Some(film) = &e.film {
+        line += &format!(" on `{film}`");
+    }
+    line + &format!(" ({})", e.source)
+}
+
+/// Parses "@student [film]" into a slack id and film name.
+fn parse_args(cmd: AdminCommand, args: &str) -> Option<(&str, &str)> {
+    let (mention, film) = args.split_once(char::is_whitespace).unwrap_or((args, ""));
+    let student = parse_mention(mention)?;
+    let film = film.trim();
+
+    match cmd {
+        AdminCommand::Unassign => film.is_empty().then(|| (student, film)),
+        _ => (!film.is_empty()).then(|| (student, film)),
+    }
+}
+
+/// Slack formats mentions as `<@U0LAN0Z89>`, or `<@U0LAN0Z89|name>`.
+fn parse_mention(mention: &str) -> Option<&str> {
+    let id = mention.strip_prefix("<@")?.strip_suffix('>')?;
+    let id = id.split('|').next()?;
+    (!id.is_empty()).then(|| id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_parse() {
+        let (cmd, args) = AdminCommand::parse("Force-Assign <@U1> Star Wars").unwrap();
+        assert_eq!(AdminCommand::ForceAssign, cmd);
+        assert_eq!(Some(("U1", "Star Wars")), parse_args(cmd, args));
+
+        let (cmd, args) = AdminCommand::parse("unassign <@U1|bob>").unwrap();
+        assert_eq!(AdminCommand::Unassign, cmd);
+        assert_eq!(Some(("U1", "")), parse_args(cmd, args));
+
+        assert!(AdminCommand::parse("request-work").is_none());
+        assert_eq!(None, parse_args(AdminCommand::Reassign, "<@U1>"));
+        assert_eq!(None, parse_args(AdminCommand::Unassign, "<@U1> Star Wars"));
+        assert_eq!(None, parse_args(AdminCommand::Unassign, "U1"));
+
+        let (cmd, args) = AdminCommand::parse("audit Star Wars").unwrap();
+        assert_eq!(AdminCommand::Audit, cmd);
+        assert_eq!("Star Wars", args);
+
+        let (cmd, args) = AdminCommand::parse("check --repair").unwrap();
+        assert_eq!(AdminCommand::Check, cmd);
+        assert_eq!("--repair", args);
+
+        let (cmd, _) = AdminCommand::parse("Feasibility").unwrap();
+        assert_eq!(AdminCommand::Feasibility, cmd);
+
+        let (cmd, args) = AdminCommand::parse("dry-run <@U1>").unwrap();
+        assert_eq!(AdminCommand::DryRun, cmd);
+        assert_eq!(Some("U1"), parse_mention(args));
+    }
+}
@@ -6,7 +6,8 @@ use serde::{Deserialize, Serialize};
 use strum::EnumString;
 use tracing::debug;
 
-use crate::{manager::Manager, server::State, Error, Result};
+use super::admin::{self, AdminCommand};
+use crate::{audit::Origin, manager::Manager, server::State, Error, Result};
 
 const HELLO: &str =
     ":wave: Hi! I'm ShereeBot. Sheree's brother built me to help her manage your film assignments!
@@ -14,18 +15,32 @@ For a list of my commands, type @ShereeBot help";
 
 const HELP: &str = "Sheree commands:
 `add-films [HIGH or LOW] [film1, film2, film3...]`
-
-To deliver your work, type `@ShereeBot deliver`.
+`unassign @student`
+`reassign @student film`
+`force-assign @student film`
+`audit @student` or `audit film`
+`check [--repair]`
+`feasibility`
+`dry-run @student`
+`forecast`
+`logs ref`
+
+To deliver your work, type `@ShereeBot deliver-work [links] [notes for the next stage]`.
 Once you're ready to move on to the next step, type `@ShereeBot request-work`.
-As soon as there's work ready to be picked up, I'll let you know!";
+As soon as there's work ready to be picked up, I'll let you know!
+To stop waiting for work, type `@ShereeBot cancel`.
+If you can't take on the film you were given, type `@ShereeBot drop-work`.
+If the previous stage of your film isn't finished, type `@ShereeBot send-back [what needs fixing]`.
+Once you've fixed a film sent back to you, type `@ShereeBot resolve-revision [links] [notes]`.";
 
 const CMD_ERR: &str = "I couldn't read your command :cry:
-Valid commands include `deliver-work`, and `request-work`!
+Valid commands include `deliver-work`, `request-work`, `cancel` and `drop-work`!
 Sheree can also run the `add-films` command!";
 
 /// Manager which handles all app_mention events.
 pub(crate) struct AppMention {
     state: State,
+    origin: Origin,
     text: String,
     ts: String,
     channel: String,
@@ -36,12 +51,13 @@ impl AppMention {
     #[rustfmt::skip]
     pub(crate) fn new(
         state: State,
+        origin: Origin,
         text: String,
         ts: String,
         channel: String,
         user: String,
     ) -> Self {
-        Self { state, text, ts, channel, user }
+        Self { state, origin, text, ts, channel, user }
     }
 
     /// Given an app_mention event, does the following:
@@ -59,20 +75,19 @@ impl AppMention {
             Err(e) => Response::new(channel, e.to_string(), Some(ts)),
         };
 
-        self.state
-            .req_client
-            .post("https://slack.com/api/chat.postMessage")
-            .bearer_auth(&self.state.oauth_token)
-            .json(&res)
-            .send()
-            .await?;
+        self.state.slack.post(&res).await?;
 
         Ok(())
     }
 
     async fn run_event(&self) -> Result<String> {
-        if self.text.split_whitespace().nth(1).is_none() {
-            return Ok(HELLO.to_string());
+        let rest = match self.text.trim().split_once(char::is_whitespace) {
+            Some((_, rest)) if !rest.trim().is_empty() => rest,
+            _ => return Ok(HELLO.to_string()),
+        };
+
+        if let Some((cmd, args)) = AdminCommand::parse(rest) {
+            return Ok(admin::run(&self.state, &self.origin, cmd, args).await);
         }
 
         let cmd = match self.parse_command() {
@@ -101,7 +116,7 @@ impl AppMention {
     }
 
     async fn run_command(&self, cmd: Command) -> Result<String> {
-        let manager = Manager::new(self.state.clone());
+        let manager = Manager::new(self.state.clone(), self.origin.clone());
         match cmd {
             Command::AddFilms => {
                 // <USER_ID> addfilms <PRI> <GROUP> <FILMS>
@@ -128,7 +143,26 @@ impl AppMention {
             Command::RequestWork => Ok(manager
                 .request_work(&self.user, &self.ts, &self.channel)
                 .await),
-            Command::DeliverWork => Ok(manager.deliver_work(&self.user).await),
+            Command::DeliverWork => {
+                // <USER_ID> deliver-work <LINKS AND NOTES>
+                let text: String =
+                    Itertools::intersperse(self.text.split_whitespace().skip(2), " ").collect();
+                Ok(manager.deliver_work(&self.user, &text).await)
+            }
+            Command::Cancel => Ok(manager.cancel(&self.user).await),
+            Command::DropWork => Ok(manager.drop_work(&self.user).await),
+            Command::SendBack => {
+                // <USER_ID> send-back <NOTE>
+                let note: String =
+                    Itertools::intersperse(self.text.split_whitespace().skip(2), " ").collect();
+                Ok(manager.send_back(&self.user, &note).await)
+            }
+            Command::ResolveRevision => {
+                // <USER_ID> resolve-revision <LINKS AND NOTES>
+                let text: String =
+                    Itertools::intersperse(self.text.split_whitespace().skip(2), " ").collect();
+                Ok(manager.resolve_revision(&self.user, &text).await)
+            }
         }
     }
 }
@@ -142,6 +176,13 @@ enum Command {
     RequestWork,
     #[strum(serialize = "deliverwork", serialize = "deliver-work")]
     DeliverWork,
+    Cancel,
+    #[strum(serialize = "dropwork", serialize = "drop-work")]
+    DropWork,
+    #[strum(serialize = "sendback", serialize = "send-back")]
+    SendBack,
+    #[strum(serialize = "resolverevision", serialize = "resolve-revision")]
+    ResolveRevision,
     Help,
 }
 
@@ -172,6 +213,7 @@ mod tests {
         let state = InnerState::_new();
         AppMention {
             state,
+            origin: Origin::slack("", ""),
             user: "".to_string(),
             ts: "".to_string(),
             channel: "".to_string(),
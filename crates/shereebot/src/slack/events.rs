@@ -3,7 +3,7 @@ use serde::{Deserialize, Serialize};
 use strum::AsRefStr;
 use tracing::{error, info};
 
-use crate::{server::State, Error, Result};
+use crate::{audit::Origin, server::State, Error, Result};
 
 /// This challenge is sent when the Event API first queries your event endpoint.
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -83,6 +83,13 @@ pub struct File {
 }
 
 impl EventRequest {
+    /// Slack id of whoever caused the event.
+    pub(crate) fn user(&self) -> &str {
+        match &self.event {
+            Event::AppMention { user, .. } | Event::Message { user, .. } => user,
+        }
+    }
+
     /// We handle events totally asynchronously, dispatching them back to slack via POST.
     ///
     /// Normally, we log errors right before reporting them to the user.
@@ -112,7 +119,9 @@ impl EventRequest {
             return Err(Error::Unreachable);
         };
 
-        let manager = super::message::Message::new(state, user, text, channel_type, subtype, files);
+        let origin = Origin::slack(&user, &self.event_id);
+        #[rustfmt::skip]
+        let manager = super::message::Message::new(state, origin, user, text, channel_type, subtype, files);
         manager.handle_event().await?;
         Ok(())
     }
@@ -125,7 +134,9 @@ impl EventRequest {
         } else {
             return Err(Error::Unreachable);
         };
-        let manager = super::app_mentions::AppMention::new(state.clone(), text, ts, channel, user);
+        let origin = Origin::slack(&user, &self.event_id);
+        #[rustfmt::skip]
+        let manager = super::app_mentions::AppMention::new(state.clone(), origin, text, ts, channel, user);
 
         manager.handle_event().await?;
 
@@ -3,10 +3,11 @@
 use tracing::info;
 
 use super::{
+    admin::{self, AdminCommand},
     app_mentions::Response,
     events::{ChannelType, File},
 };
-use crate::{manager::Manager, server::State, Result};
+use crate::{audit::Origin, manager::Manager, server::State, Result};
 
 const HELLO: &str =
     ":wave: Hi! I'm ShereeBot. Sheree's brother built me to help her manage your film assignments!
@@ -14,12 +15,17 @@ For a list of my commands, type `help`";
 
 const HELP: &str = "
 To request work, message me and say `request-work`.
-To deliver your work, message me and say `deliver-work`.
+To deliver your work, message me and say `deliver-work [links] [notes for the next stage]`.
+To stop waiting for work, message me and say `cancel`.
+If you can't take on the film you were given, message me and say `drop-work`.
+If the previous stage of your film isn't finished, say `send-back [what needs fixing]`.
+Once you've fixed a film sent back to you, say `resolve-revision [links] [notes]`.
 
 As soon as there's work ready to be picked up, I'll let you know!";
 
 pub(crate) struct Message {
     state: State,
+    origin: Origin,
     user: String,
     text: String,
     channel_type: ChannelType,
@@ -31,13 +37,14 @@ impl Message {
     #[rustfmt::skip]
     pub(crate) fn new(
         state: State,
+        origin: Origin,
         user: String,
         text: String,
         channel_type: ChannelType,
         subtype: Option<String>,
         files: Option<Vec<File>>,
     ) -> Self {
-        Self { state, user, text, channel_type, subtype, files }
+        Self { state, origin, user, text, channel_type, subtype, files }
     }
 
     /// Dispatches event and responds to user async.
@@ -45,18 +52,30 @@ impl Message {
     pub(crate) async fn handle_event(&self) -> Result<()> {
         info!("Handling message from {}: {}", self.user, self.text);
 
-        let manager = Manager::new(self.state.clone());
+        let manager = Manager::new(self.state.clone(), self.origin.clone());
 
-        let text = self.text.trim().to_lowercase();
+        // Commands are the first word, and anything after it is handed on as typed.
+        let text = self.text.trim();
+        let (cmd, rest) = text.split_once(char::is_whitespace).unwrap_or((text, ""));
+        let (cmd, rest) = (cmd.to_lowercase(), rest.trim());
 
         let mut msg = "".to_string();
-        msg = match text.as_ref() {
-            "request-work" => manager.request_work(&self.user, "0", &self.user).await,
-            "deliver-work" => manager.deliver_work(&self.user).await,
-            "help" => HELP.to_string(),
+        msg = match (cmd.as_ref(), rest) {
+            ("request-work", "") => manager.request_work(&self.user, "0", &self.user).await,
+            ("deliver-work", rest) => manager.deliver_work(&self.user, rest).await,
+            ("cancel", "") => manager.cancel(&self.user).await,
+            ("drop-work", "") => manager.drop_work(&self.user).await,
+            ("send-back", note) => manager.send_back(&self.user, note).await,
+            ("resolve-revision", rest) => manager.resolve_revision(&self.user, rest).await,
+            ("help", "") => HELP.to_string(),
             _ => msg,
         };
 
+        // Film names are case sensitive, so admin commands are parsed from the original text.
+        if let Some((cmd, args)) = AdminCommand::parse(&self.text) {
+            msg = admin::run(&self.state, &self.origin, cmd, args).await;
+        }
+
         if let Some(files) = &self.files {
             msg = manager.insert_from_files(files).await?
         }
@@ -72,13 +91,7 @@ impl Message {
 
     async fn send_response(&self, msg: String) -> Result<()> {
         let res = Response::new(self.user.clone(), msg, None);
-        self.state
-            .req_client
-            .post("https://slack.com/api/chat.postMessage")
-            .bearer_auth(&self.state.oauth_token)
-            .json(&res)
-            .send()
-            .await?;
+        self.state.slack.post(&res).await?;
         Ok(())
     }
 }
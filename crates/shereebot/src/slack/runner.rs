@@ -0,0 +1,336 @@
+//! Handles Slack events in the background, a few at a time.
+//!
+//! Events are saved before Slack is told they've arrived, claimed by the server which is handling
+//! them. A server renews its claims while it works through them, and gives up any it hasn't got
+//! to as it stops, so the next server can take them over. Claims left behind by a server which
+//! crashed are taken over once they expire. Either way, the old and new servers of a deploy don't
+//! both handle an event. An event being handled as the server stopped may be handled twice.
+//!
+//! Handled events are kept for a day, so Slack's retries of them are still ignored.
+//!
+//! Each user's events are handled one at a time, in the order they arrived, so a student's
+//! delivery and their next request can't race each other.
+use std::{
+    collections::{HashMap, VecDeque},
+    panic::AssertUnwindSafe,
+    sync::{Mutex, MutexGuard, PoisonError},
+};
+
+use chrono::{DateTime, Duration, Utc};
+use futures::FutureExt;
+use tokio::{
+    sync::{watch, Semaphore},
+    task::JoinHandle,
+};
+use tracing::{error, info, info_span, Instrument};
+
+use super::events::EventRequest;
+use crate::{server::State, Result};
+use models::PendingEvent;
+
+/// How often a server renews its claims, and takes over expired ones.
+const CLAIM_MINUTES: i64 = 5;
+/// How long a claim lasts without being renewed before another server may take it over. Long
+/// enough that a late renewal doesn't lose it.
+const CLAIM_EXPIRY_MINUTES: i64 = 3 * CLAIM_MINUTES;
+/// How long handled events are kept. Slack stops retrying well within this.
+const RETAIN_HOURS: i64 = 24;
+
+pub(crate) struct Runner {
+    /// Names this server in the events it claims.
+    id: String,
+    /// Events waiting on an earlier one from the same user, by user. A user has an entry for as
+    /// long as one of their events is being handled.
+    lanes: Mutex<HashMap<String, VecDeque<PendingEvent>>>,
+    /// Bounds how many events are handled at once, across every user.
+    workers: Semaphore,
+}
+
+impl Runner {
+    pub(crate) fn new(workers: usize) -> Self {
+        Self {
+            id: uuid::Uuid::new_v4().to_string(),
+            lanes: Mutex::default(),
+            workers: Semaphore::new(workers.max(1)),
+        }
+    }
+
+    fn lanes(&self) -> MutexGuard<'_, HashMap<String, VecDeque<PendingEvent>>> {
+        self.lanes.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Saves an event, then handles it in the background. Slack's retries of an event which is
+/// waiting, or was handled recently, are ignored.
+pub(crate) async fn receive(state: &State, event: PendingEvent) -> Result<()> {
+    if !state.db.insert_event(&event, &state.events.id).await? {
+        info!("Ignoring a retry of event {}", event.id);
+        return Ok(());
+    }
+    submit(state, event);
+    Ok(())
+}
+
+/// Handles every event left over from before the server last stopped, other than those another
+/// server's still handling. Returns how many there were.
+pub(crate) async fn replay(state: &State) -> Result<usize> {
+    replay_claimed_before(state, Utc::now() - Duration::minutes(CLAIM_EXPIRY_MINUTES)).await
+}
+
+/// Every `CLAIM_MINUTES`, renews this server's claims and takes over any which have expired,
+/// until `stop` turns true. Shutdown waits for it.
+pub(crate) fn spawn_reclaim(state: State, mut stop: watch::Receiver<bool>) -> JoinHandle<()> {
+    let every = std::time::Duration::from_secs(CLAIM_MINUTES as u64 * 60);
+    let tasks = state.tasks.clone();
+    tasks.spawn(async move {
+        // Leftovers were just replayed at startup.
+        let mut interval = tokio::time::interval_at(tokio::time::Instant::now() + every, every);
+        while !*stop.borrow() {
+            tokio::select! {
+                _ = interval.tick() => {}
+                changed = stop.changed() => {
+                    // A dropped sender can never say to stop, so take that as the signal.
+                    if changed.is_err() {
+                        break;
+                    }
+                    continue;
+                }
+            }
+            match reclaim(&state, Utc::now()).await {
+                Ok(0) => {}
+                Ok(n) => info!("Took over {n} Slack event(s) from a server which stopped"),
+                Err(e) => error!("Failed to take over expired Slack events: {e}"),
+            }
+        }
+    })
+}
+
+/// Renews this server's claims, then handles every event whose claim expired by `now`.
+async fn reclaim(state: &State, now: DateTime<Utc>) -> Result<usize> {
+    state.db.renew_events(&state.events.id).await?;
+    replay_claimed_before(state, now - Duration::minutes(CLAIM_EXPIRY_MINUTES)).await
+}
+
+/// Gives up this server's claims on the events it didn't get to, so the next server can take
+/// them over straight away. Returns how many there were.
+pub(crate) async fn release(state: &State) -> Result<u64> {
+    state.db.release_events(&state.events.id).await
+}
+
+/// Handles every event left over which nobody's claimed since `expired`.
+async fn replay_claimed_before(state: &State, expired: DateTime<Utc>) -> Result<usize> {
+    let events = state.db.claim_events(&state.events.id, expired).await?;
+    let count = events.len();
+    for event in events {
+        submit(state, event);
+    }
+    Ok(count)
+}
+
+/// Handles an event once every earlier one from the same user has been.
+fn submit(state: &State, event: PendingEvent) {
+    let mut lanes = state.events.lanes();
+    if let Some(waiting) = lanes.get_mut(&event.user) {
+        waiting.push_back(event);
+        return;
+    }
+    lanes.insert(event.user.clone(), VecDeque::new());
+    drop(lanes);
+
+    let s = state.clone();
+    state.tasks.spawn(run_lane(s, event));
+}
+
+/// Works through one user's events until none are left.
+async fn run_lane(state: State, mut event: PendingEvent) {
+    let user = event.user.clone();
+    loop {
+        {
+            let _worker = state.events.workers.acquire().await;
+            handle(&state, event).await;
+        }
+
+        let mut lanes = state.events.lanes();
+        match lanes.get_mut(&user).and_then(VecDeque::pop_front) {
+            Some(next) => event = next,
+            None => {
+                lanes.remove(&user);
+                return;
+            }
+        }
+    }
+}
+
+/// Handles an event and marks it handled, logging under the event's id so its logs can be found.
+/// Events which can't be read, or which panic, are marked handled too, so they can't hold up the
+/// rest of the user's events, or come back after a restart.
+async fn handle(state: &State, event: PendingEvent) {
+    let span = info_span!("event", cid = %event.id);
+    handle_in_span(state, event).instrument(span).await
+}
+
+async fn handle_in_span(state: &State, event: PendingEvent) {
+    let id = event.id;
+    match serde_json::from_value::<EventRequest>(event.payload) {
+        Ok(request) => {
+            let handled = AssertUnwindSafe(request.handle_event(state.clone()));
+            if handled.catch_unwind().await.is_err() {
+                error!("Handling event {id} panicked");
+            }
+        }
+        Err(e) => error!("Dropping unreadable event {id}: {e}"),
+    }
+
+    let forget_before = Utc::now() - Duration::hours(RETAIN_HOURS);
+    if let Err(e) = state.db.finish_event(&id, forget_before).await {
+        error!("Failed to mark event {id} handled: {e}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use chrono::Utc;
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::{
+        queue::Queue,
+        server::InnerState,
+        slack::{FakeSlack, Messenger},
+        store,
+    };
+
+    fn mention(user: &str, ts: usize) -> PendingEvent {
+        let payload = json!({
+            "token": "",
+            "team_id": "",
+            "api_app_id": "",
+            "event": {
+                "type": "app_mention",
+                "user": user,
+                "text": "<@BOT>",
+                "ts": ts.to_string(),
+                "channel": user,
+                "event_ts": ts.to_string(),
+            },
+            "type": "event_callback",
+            "authorizations": [{ "is_bot": false }],
+            "event_context": "",
+            "event_id": format!("{user}-{ts}"),
+            "event_time": 0,
+        });
+        event(&format!("{user}-{ts}"), user, payload)
+    }
+
+    fn event(id: &str, user: &str, payload: Value) -> PendingEvent {
+        PendingEvent {
+            id: id.to_string(),
+            user: user.to_string(),
+            payload,
+            created_at: Utc::now(),
+        }
+    }
+
+    #[tokio::test]
+    async fn check_runner() {
+        let slack = Arc::new(FakeSlack::default());
+        let messenger: Arc<dyn Messenger> = slack.clone();
+        let state = InnerState::offline(store::new_memory(), Queue::_new(), messenger);
+
+        // An unreadable event mustn't hold up the rest of its user's events.
+        receive(&state, event("bad", "U1", json!({})))
+            .await
+            .unwrap();
+        for ts in 1..=5 {
+            receive(&state, mention("U1", ts)).await.unwrap();
+            receive(&state, mention("U2", ts)).await.unwrap();
+        }
+        // Slack retrying an event that's still waiting changes nothing.
+        receive(&state, mention("U1", 5)).await.unwrap();
+
+        assert_eq!(0, state.tasks.wait(Duration::from_secs(5)).await);
+        assert!(state.events.lanes().is_empty());
+        assert!(state.db.list_events().await.unwrap().is_empty());
+
+        // Nor does it once the event's been handled.
+        receive(&state, mention("U2", 5)).await.unwrap();
+        assert_eq!(0, state.tasks.wait(Duration::from_secs(5)).await);
+
+        // Each user was answered once per event, in order.
+        let sent = slack.sent();
+        assert_eq!(10, sent.len());
+        for user in ["U1", "U2"] {
+            let replies: Vec<_> = sent
+                .iter()
+                .filter(|r| r.channel == user)
+                .filter_map(|r| r.thread_ts.clone())
+                .collect();
+            assert_eq!(vec!["1", "2", "3", "4", "5"], replies);
+        }
+    }
+
+    #[tokio::test]
+    async fn check_replay() {
+        let slack = Arc::new(FakeSlack::default());
+        let messenger: Arc<dyn Messenger> = slack.clone();
+        let db = store::new_memory();
+        for ts in 1..=3 {
+            db.insert_event(&mention("U1", ts), "old").await.unwrap();
+        }
+
+        // Events saved before a restart are picked up by the next server, but not while the
+        // server which saved them may still be handling them.
+        let state = InnerState::offline(db, Queue::_new(), messenger);
+        assert_eq!(0, replay(&state).await.unwrap());
+        let expired = Utc::now() + chrono::Duration::seconds(1);
+        assert_eq!(3, replay_claimed_before(&state, expired).await.unwrap());
+        assert_eq!(0, state.tasks.wait(Duration::from_secs(5)).await);
+        assert_eq!(3, slack.sent().len());
+        assert!(state.db.list_events().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn check_reclaim() {
+        let slack = Arc::new(FakeSlack::default());
+        let messenger: Arc<dyn Messenger> = slack.clone();
+        let db = store::new_memory();
+        for ts in 1..=2 {
+            db.insert_event(&mention("U1", ts), "crashed")
+                .await
+                .unwrap();
+        }
+        db.insert_event(&mention("U2", 1), "stopped").await.unwrap();
+        assert_eq!(1, db.release_events("stopped").await.unwrap());
+        let state = InnerState::offline(db, Queue::_new(), messenger);
+
+        // Events a server gave up as it stopped are taken over straight away.
+        assert_eq!(1, reclaim(&state, Utc::now()).await.unwrap());
+        assert_eq!(0, state.tasks.wait(Duration::from_secs(5)).await);
+        assert_eq!(1, slack.sent().len());
+
+        // Those of a server which crashed once its claim expires.
+        let expired = Utc::now() + chrono::Duration::minutes(CLAIM_EXPIRY_MINUTES + 1);
+        assert_eq!(2, reclaim(&state, expired).await.unwrap());
+        assert_eq!(0, state.tasks.wait(Duration::from_secs(5)).await);
+        assert_eq!(3, slack.sent().len());
+        assert!(state.db.list_events().await.unwrap().is_empty());
+
+        // This server gives up what it didn't get to, for the next to take over.
+        state
+            .db
+            .insert_event(&mention("U1", 3), &state.events.id)
+            .await
+            .unwrap();
+        assert_eq!(1, release(&state).await.unwrap());
+        let past = Utc::now() - chrono::Duration::minutes(1);
+        assert_eq!(1, state.db.claim_events("next", past).await.unwrap().len());
+
+        // The loop stops along with the server.
+        let (stop, stopped) = watch::channel(false);
+        spawn_reclaim(state.clone(), stopped);
+        stop.send(true).unwrap();
+        assert_eq!(0, state.tasks.wait(Duration::from_secs(5)).await);
+    }
+}
@@ -1,5 +1,7 @@
 use serde::{Deserialize, Serialize};
 
+// Only read by the slash command handlers, which are disabled for now.
+#[allow(dead_code)]
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct SlashRequest {
     pub token: String,        // KXcvC55555555peKWVe9axCl
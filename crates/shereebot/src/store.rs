@@ -2,17 +2,28 @@
 use std::collections::HashSet;
 
 use async_trait::async_trait;
+use chrono::{DateTime, Utc};
 use deadpool_postgres::Runtime::Tokio1;
 use tokio_postgres::NoTls;
 use uuid::Uuid;
 
-use crate::{queue::QueueItem, Result};
-use models::{Film, Priority, Role, Student};
+use crate::{
+    config::{Backend, Config},
+    integrity::Issue,
+    queue::QueueItem,
+    slack::UserResponse,
+    Result,
+};
+use models::{Assignment, AuditEntry, Delivery, Film, PendingEvent, Revision, Role, Student};
 
 pub mod postgres;
 pub use postgres::PostgresClient;
 
-pub mod mock;
+pub mod memory;
+pub use memory::MemoryClient;
+
+pub mod sqlite;
+pub use sqlite::SqliteClient;
 
 /// Server-facing API boundary.
 pub type Database = Box<dyn Client>;
@@ -27,15 +38,15 @@ pub trait Client: CloneClient + Send + Sync + 'static {
     async fn list_films(&self) -> Result<Vec<Film>>;
     /// Retrieves a film given its name.
     async fn get_film(&self, film_name: &str) -> Result<Option<Film>>;
-    /// Inserts an empty film with no roles worked.
-    async fn insert_film(&self, name: &str, group_number: i32, priority: Priority) -> Result<Film>;
+    /// Inserts a film with no roles worked.
+    async fn insert_film(&self, film: &Film) -> Result<Film>;
     /// Updates a film.
     async fn update_film(&self, film: &Film) -> Result<()>;
 
     /// Retrieves all films a student has worked on.
     async fn get_worked_films(&self, student_id: &Uuid) -> Result<HashSet<Film>>;
-    /// Inserts a shared student_film marker.
-    async fn insert_student_films(&self, s_id: &Uuid, f_id: &Uuid) -> Result<()>;
+    /// Inserts a shared student_film marker, noting which role the student worked.
+    async fn insert_student_films(&self, s_id: &Uuid, f_id: &Uuid, role: Role) -> Result<()>;
     /// Gets all films where given role is available AND student is not in its group.
     async fn get_films_exclusionary(&self, group: i32, role: Role) -> Result<Vec<Film>>;
 
@@ -43,6 +54,8 @@ pub trait Client: CloneClient + Send + Sync + 'static {
     async fn list_students(&self) -> Result<Vec<Student>>;
     /// Get a student from the database. If none, insert the student and return it.
     async fn get_student(&self, slack_id: &str) -> Result<Student>;
+    /// Gets a student by their Slack id, without inserting anyone.
+    async fn find_student(&self, slack_id: &str) -> Result<Option<Student>>;
     /// From csv upload
     async fn insert_student_from_csv(&self, name: &str, group: i32, class: &str)
         -> Result<Student>;
@@ -50,18 +63,178 @@ pub trait Client: CloneClient + Send + Sync + 'static {
     async fn insert_student(&self, slack_id: &str, name: &str) -> Result<Student>;
     /// Updates a students information.
     async fn update_student(&self, student: &Student) -> Result<()>;
+    /// Records how far a student's current assignment has been escalated.
+    /// Does nothing if the student has since moved on from that film.
+    async fn update_escalation(&self, student: &Student) -> Result<()>;
 
-    /// Inserts a job or student to the wait queue.
-    async fn get_queue(&self, wait: bool) -> Result<Vec<QueueItem>>;
     /// Gets all items from given queue.
-    async fn insert_to_queue(&self, q: QueueItem, wait: bool) -> Result<QueueItem>;
-    /// Deletes an item from the given queue.
-    async fn delete_from_queue(&self, id: &Uuid, wait: bool) -> Result<()>;
+    async fn get_queue(&self, wait: bool) -> Result<Vec<QueueItem>>;
+
+    // Each change below writes its audit entry in the same transaction, so the audit log only
+    // ever records changes that were actually made.
+
+    /// Inserts a job or student to the given queue, along with auditing it if asked.
+    async fn insert_to_queue(
+        &self,
+        q: QueueItem,
+        wait: bool,
+        audit: Option<&AuditEntry>,
+    ) -> Result<QueueItem>;
+    /// Hands a student a film, removing its job from the jobs queue and the student from the
+    /// wait queue, all at once. The film remembers when the job was queued, in case it's handed
+    /// back later, and the history when the job and student started waiting.
+    async fn assign_film(
+        &self,
+        student: &Student,
+        film: &Film,
+        job: &QueueItem,
+        audit: &AuditEntry,
+    ) -> Result<()>;
+    /// Moves a film from one student to another, all at once.
+    /// Fails if `from` isn't working on the film any more.
+    async fn transfer_film(
+        &self,
+        from: &Student,
+        to: &Student,
+        film: &Film,
+        audit: &AuditEntry,
+    ) -> Result<()>;
+    /// Returns a student's film to the jobs queue and clears their assignment, all at once.
+    /// Fails if the student isn't working on the film any more, e.g. they've just delivered it.
+    async fn release_film(
+        &self,
+        student: &Student,
+        film: &Film,
+        job: &QueueItem,
+        audit: &AuditEntry,
+    ) -> Result<()>;
+    /// Records a delivery: closes out its history, keeps anything handed on to the next stage,
+    /// moves the film and student on to their next roles, and queues up the film's next stage
+    /// if there is one, all at once.
+    async fn deliver_film(
+        &self,
+        student: &Student,
+        film: &Film,
+        job: Option<&QueueItem>,
+        delivery: Option<&Delivery>,
+        audit: &AuditEntry,
+    ) -> Result<()>;
+    /// Deletes an item from the given queue, along with auditing it if asked.
+    async fn delete_from_queue(
+        &self,
+        id: &Uuid,
+        wait: bool,
+        audit: Option<&AuditEntry>,
+    ) -> Result<()>;
+
+    /// Fixes an integrity issue and audits it, if the issue still holds once its rows have been
+    /// re-read in the same transaction. Returns whether anything was changed.
+    async fn repair(&self, issue: &Issue, audit: &AuditEntry) -> Result<bool>;
+
+    /// Sends a film back to an earlier stage and pauses its current assignment, all at once.
+    async fn send_back(&self, film: &Film, revision: &Revision, audit: &AuditEntry) -> Result<()>;
+    /// Gets the oldest unresolved revision a student has been asked to make.
+    async fn get_revision(&self, slack_id: &str) -> Result<Option<Revision>>;
+    /// Marks a revision as made, keeps anything handed back with the fix, and resumes the
+    /// paused assignment, all at once.
+    async fn resolve_revision(
+        &self,
+        film: &Film,
+        revision: &Revision,
+        delivery: Option<&Delivery>,
+        audit: &AuditEntry,
+    ) -> Result<()>;
+
+    /// Gets the latest delivery of a film's stage.
+    async fn get_delivery(&self, film_name: &str, role: Role) -> Result<Option<Delivery>>;
+
+    /// Every assignment of a film, oldest first.
+    async fn get_film_history(&self, film_name: &str) -> Result<Vec<Assignment>>;
+    /// Every assignment a student has had, oldest first.
+    async fn get_student_history(&self, slack_id: &str) -> Result<Vec<Assignment>>;
+    /// Gets every assignment ever handed out, oldest first.
+    async fn list_assignments(&self) -> Result<Vec<Assignment>>;
+
+    /// Appends an entry to the audit log, for anything that isn't audited along with the change
+    /// itself.
+    async fn insert_audit(&self, entry: &AuditEntry) -> Result<()>;
+    /// Gets the latest audit log entries, newest first, optionally only those about a given
+    /// student or film.
+    async fn get_audit_log(
+        &self,
+        student: Option<&str>,
+        film: Option<&str>,
+        limit: i64,
+    ) -> Result<Vec<AuditEntry>>;
+
+    /// Saves a Slack event, claimed by the server about to handle it. Returns false, saving
+    /// nothing, if it's been seen before, as happens when Slack retries it, even once it's been
+    /// handled.
+    async fn insert_event(&self, event: &PendingEvent, claimed_by: &str) -> Result<bool>;
+    /// Every Slack event still waiting to be handled, oldest first.
+    async fn list_events(&self) -> Result<Vec<PendingEvent>>;
+    /// Claims every Slack event still waiting to be handled which nobody's claimed since
+    /// `expired`, oldest first. No two servers are given the same event.
+    async fn claim_events(
+        &self,
+        claimed_by: &str,
+        expired: DateTime<Utc>,
+    ) -> Result<Vec<PendingEvent>>;
+    /// Renews a server's claims on the Slack events it's still handling, so they don't expire.
+    async fn renew_events(&self, claimed_by: &str) -> Result<()>;
+    /// Gives up a server's claims on the Slack events it hasn't handled, so another server can
+    /// take them over straight away. Returns how many there were.
+    async fn release_events(&self, claimed_by: &str) -> Result<u64>;
+    /// Marks a Slack event handled. Events handled before `forget_before` are deleted, as Slack
+    /// won't retry them any more.
+    async fn finish_event(&self, id: &str, forget_before: DateTime<Utc>) -> Result<()>;
+
+    /// When a student took a rate limited action since the given time, oldest first.
+    async fn list_uses(
+        &self,
+        slack_id: &str,
+        action: &str,
+        since: DateTime<Utc>,
+    ) -> Result<Vec<DateTime<Utc>>>;
+    /// Counts a use of a rate limited action, forgetting the student's uses of it from `expired`
+    /// or earlier, which no longer count against them.
+    async fn insert_use(
+        &self,
+        slack_id: &str,
+        action: &str,
+        at: DateTime<Utc>,
+        expired: DateTime<Utc>,
+    ) -> Result<()>;
+
+    /// Brings the database schema up to date. Safe to run repeatedly.
+    async fn migrate(&self) -> Result<()>;
+    /// Which schema version the database is on, and which it should be on.
+    async fn schema_version(&self) -> Result<SchemaVersion>;
+    /// Runs a trivial query, to show a connection can be had and used.
+    async fn ping(&self) -> Result<()>;
+
+    /// How many connections the pool holds and how many are free, for stores that pool them.
+    fn pool_status(&self) -> Option<deadpool_postgres::Status> {
+        None
+    }
 
     /// Drops database. Only works in test env.
     async fn drop_db(&self) -> Result<()>;
 }
 
+/// How far the database's schema has been migrated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+pub struct SchemaVersion {
+    pub applied: i64,
+    pub latest: i64,
+}
+
+impl SchemaVersion {
+    pub fn is_current(&self) -> bool {
+        self.applied == self.latest
+    }
+}
+
 /// Workaround to allow cloning trait.
 pub trait CloneClient {
     fn clone_box(&self) -> Database;
@@ -92,6 +265,49 @@ pub fn new(cfg: &deadpool_postgres::Config) -> Result<Database> {
     Ok(client)
 }
 
-pub fn new_mock() -> Database {
-    Box::new(mock::MockClient { success: true })
+/// Opens or creates a SQLite database file, and brings its schema up to date.
+pub fn new_sqlite(path: &str) -> Result<Database> {
+    Ok(Box::new(SqliteClient::open(path)?))
+}
+
+/// Connects to whichever store the config asks for.
+pub fn connect(cfg: &Config) -> Result<Database> {
+    if cfg.demo {
+        return Ok(new_memory());
+    }
+    match cfg.backend {
+        Backend::Postgres => new(&cfg.postgres),
+        Backend::Sqlite => new_sqlite(&cfg.sqlite_path),
+    }
+}
+
+/// A store that keeps everything in memory, for tests and demos.
+pub fn new_memory() -> Database {
+    Box::new(MemoryClient::new())
+}
+
+/// The error for taking a film from a student who's since moved on from it.
+fn not_assigned(student: &Student, film: &Film) -> crate::Error {
+    let e = format!("{} isn't working on `{}` any more", student.name, film.name);
+    crate::Error::NotFound(e)
+}
+
+/// Looks up a student's real name in Slack, for students the bot hasn't seen before.
+async fn lookup_name(slack_id: &str) -> Result<String> {
+    let version = reqwest::tls::Version::TLS_1_2;
+    let req_client = reqwest::Client::builder()
+        .min_tls_version(version)
+        .build()?;
+    let token = std::env::var("OAUTH_TOKEN").map_err(Into::<crate::Error>::into)?;
+    let req = format!("https://slack.com/api/users.info?user={slack_id}");
+
+    let start = std::time::Instant::now();
+    let res = req_client.post(req).bearer_auth(token).send().await;
+    let res = match res {
+        Ok(res) => res.json::<UserResponse>().await,
+        Err(e) => Err(e),
+    };
+    let ok = matches!(&res, Ok(r) if r.ok);
+    crate::metrics::slack_call("users.info", start.elapsed(), ok);
+    Ok(res?.user.real_name)
 }
@@ -0,0 +1,1010 @@
+//! A `Client` which keeps everything in memory, for tests and demos.
+//!
+//! It mirrors `PostgresClient` as closely as it can, down to the quirks: who worked a role is
+//! read back from the assignment history, and the wait_q doesn't remember film details. The one
+//! difference is that there's no Slack to look up students it hasn't seen, so they aren't
+//! inserted on the spot.
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use tracing::info;
+use uuid::Uuid;
+
+use crate::{
+    integrity::Issue,
+    queue::{self, QueueItem},
+    store::{Client, SchemaVersion},
+    Error, Result,
+};
+use models::{
+    Assignment, AuditEntry, Delivery, Film, Outcome, PendingEvent, Revision, Role, Roles, Student,
+};
+
+/// In-memory client. Clones share the same data.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryClient {
+    data: Arc<Mutex<Data>>,
+}
+
+/// Every "table". Rows are kept in insertion order, so listings are stable between runs.
+#[derive(Debug, Default)]
+struct Data {
+    films: Vec<Film>,
+    students: Vec<Student>,
+    /// Which role each student worked on each film, keyed by (student id, film id).
+    students_films: HashMap<(Uuid, Uuid), Role>,
+    jobs_q: Vec<QueueItem>,
+    wait_q: Vec<QueueItem>,
+    assignments: Vec<AssignmentRow>,
+    revisions: Vec<Revision>,
+    deliveries: Vec<Delivery>,
+    audit_log: Vec<AuditEntry>,
+    events: Vec<EventRow>,
+    /// When each student took each rate limited action, keyed by (slack id, action).
+    uses: HashMap<(String, String), Vec<DateTime<Utc>>>,
+}
+
+/// An assignment as stored, referring to its student and film by id.
+#[derive(Debug, Clone)]
+struct AssignmentRow {
+    id: Uuid,
+    student_id: Option<Uuid>,
+    film_id: Uuid,
+    role: Role,
+    assigned_at: DateTime<Utc>,
+    delivered_at: Option<DateTime<Utc>>,
+    outcome: Option<Outcome>,
+    queued_at: Option<DateTime<Utc>>,
+    waiting_since: Option<DateTime<Utc>>,
+}
+
+/// A Slack event as stored, along with who's handling it.
+#[derive(Debug, Clone)]
+struct EventRow {
+    event: PendingEvent,
+    handled_at: Option<DateTime<Utc>>,
+    claimed_by: Option<String>,
+    claimed_at: Option<DateTime<Utc>>,
+}
+
+impl MemoryClient {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn data(&self) -> MutexGuard<'_, Data> {
+        // Every write finishes before the lock is released, so a poisoned lock is still usable.
+        self.data.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Data {
+    fn film_mut(&mut self, name: &str) -> Option<&mut Film> {
+        self.films.iter_mut().find(|f| f.name == name)
+    }
+
+    fn film_id(&self, name: &str) -> Option<Uuid> {
+        self.films.iter().find(|f| f.name == name).map(|f| f.id)
+    }
+
+    fn student_id(&self, slack_id: &str) -> Option<Uuid> {
+        self.students
+            .iter()
+            .find(|s| s.slack_id == slack_id)
+            .map(|s| s.id)
+    }
+
+    fn student_mut(&mut self, id: &Uuid) -> Option<&mut Student> {
+        self.students.iter_mut().find(|s| &s.id == id)
+    }
+
+    /// A film, with who worked each role taken from the assignment history where it's known.
+    fn read_film(&self, film: &Film) -> Film {
+        let mut film = film.clone();
+        let worked = self.delivered(|a| a.film_id == film.id);
+        for (role, a) in worked {
+            if let Some(s) = a
+                .student_id
+                .and_then(|id| self.students.iter().find(|s| s.id == id))
+            {
+                film.roles.complete_role(role, s.name.clone());
+            }
+        }
+        film
+    }
+
+    /// A student, with which film they worked each role on taken from the assignment history.
+    fn read_student(&self, student: &Student) -> Student {
+        let mut student = student.clone();
+        let worked = self.delivered(|a| a.student_id == Some(student.id));
+        for (role, a) in worked {
+            if let Some(f) = self.films.iter().find(|f| f.id == a.film_id) {
+                student.roles.complete_role(role, f.name.clone());
+            }
+        }
+        student
+    }
+
+    /// The delivered assignment for each role matching `filter`.
+    /// Like the views, picks the greatest name when a role was worked more than once.
+    fn delivered(&self, filter: impl Fn(&AssignmentRow) -> bool) -> HashMap<Role, &AssignmentRow> {
+        let name = |a: &AssignmentRow| {
+            let student = self.students.iter().find(|s| Some(s.id) == a.student_id);
+            let film = self.films.iter().find(|f| f.id == a.film_id);
+            (
+                student.map(|s| s.name.clone()),
+                film.map(|f| f.name.clone()),
+            )
+        };
+        let mut worked: HashMap<Role, &AssignmentRow> = HashMap::new();
+        let rows = self.assignments.iter().filter(|a| filter(a));
+        for a in rows.filter(|a| a.outcome == Some(Outcome::Delivered)) {
+            match worked.get(&a.role) {
+                Some(prev) if name(prev) >= name(a) => {}
+                _ => {
+                    worked.insert(a.role, a);
+                }
+            }
+        }
+        worked
+    }
+
+    fn write_film_roles(&mut self, film: &Film) {
+        if let Some(f) = self.film_mut(&film.name) {
+            f.roles = film.roles.clone();
+            f.current_role = film.current_role;
+        }
+    }
+
+    fn write_student(&mut self, student: &Student) {
+        if let Some(s) = self.student_mut(&student.id) {
+            s.roles = student.roles.clone();
+            s.current_role = student.current_role;
+            s.current_film = student.current_film.clone();
+            s.slack_id = student.slack_id.clone();
+            s.assigned_at = student.assigned_at;
+            s.escalation = student.escalation;
+            s.paused = student.paused;
+        }
+    }
+
+    /// Closes out the history of the film a student is currently working on.
+    fn end_assignment(&mut self, student: &Student, outcome: Outcome) {
+        let film_id = match student.current_film.as_deref() {
+            Some(name) => self.film_id(name),
+            None => None,
+        };
+        let now = Utc::now();
+        for a in self.assignments.iter_mut() {
+            if a.student_id == Some(student.id) && Some(a.film_id) == film_id && a.outcome.is_none()
+            {
+                a.delivered_at = Some(now);
+                a.outcome = Some(outcome);
+            }
+        }
+    }
+
+    /// Sets a student's current film, along with its students_films record, and starts its
+    /// history. `queued` is when the job joined the jobs_q and the student the wait_q, where they
+    /// did.
+    fn set_assignment(
+        &mut self,
+        student: &Student,
+        film: &Film,
+        queued: (Option<DateTime<Utc>>, Option<DateTime<Utc>>),
+    ) {
+        let now = Utc::now();
+        if let Some(s) = self.student_mut(&student.id) {
+            s.current_film = Some(film.name.clone());
+            s.assigned_at = Some(now);
+            s.escalation = 0;
+            s.paused = false;
+        }
+        self.students_films
+            .entry((student.id, film.id))
+            .or_insert(film.current_role);
+        self.assignments.push(AssignmentRow {
+            id: Uuid::new_v4(),
+            student_id: Some(student.id),
+            film_id: film.id,
+            role: film.current_role,
+            assigned_at: now,
+            delivered_at: None,
+            outcome: None,
+            queued_at: queued.0,
+            waiting_since: queued.1,
+        });
+    }
+
+    /// Clears a student's current film, along with its students_films record. Fails if the
+    /// student has since moved on from the film, so a stale copy of them can't undo newer work.
+    fn clear_assignment(&mut self, student: &Student, film: &Film) -> Result<()> {
+        let s = self
+            .student_mut(&student.id)
+            .filter(|s| s.current_film.as_deref() == Some(film.name.as_str()))
+            .ok_or_else(|| super::not_assigned(student, film))?;
+        s.current_film = None;
+        s.assigned_at = None;
+        s.escalation = 0;
+        s.paused = false;
+
+        // The student never finished the film, so it doesn't count as worked.
+        self.students_films.remove(&(student.id, film.id));
+        Ok(())
+    }
+
+    /// Stores a job the way the jobs_q table would.
+    fn insert_job(&mut self, job: &QueueItem) {
+        self.jobs_q.push(QueueItem {
+            msg_ts: None,
+            channel: None,
+            effective_priority: None,
+            round: None,
+            ..job.clone()
+        });
+    }
+
+    fn to_assignment(&self, a: &AssignmentRow) -> Option<Assignment> {
+        let film = self.films.iter().find(|f| f.id == a.film_id)?;
+        let student = self.students.iter().find(|s| Some(s.id) == a.student_id)?;
+        Some(Assignment {
+            id: a.id,
+            film_name: film.name.clone(),
+            role: a.role,
+            student_id: student.id,
+            slack_id: student.slack_id.clone(),
+            student_name: student.name.clone(),
+            assigned_at: a.assigned_at,
+            delivered_at: a.delivered_at,
+            outcome: a.outcome,
+            queued_at: a.queued_at,
+            waiting_since: a.waiting_since,
+        })
+    }
+
+    fn history(&self, filter: impl Fn(&Assignment) -> bool) -> Vec<Assignment> {
+        let mut history: Vec<_> = self
+            .assignments
+            .iter()
+            .filter_map(|a| self.to_assignment(a))
+            .filter(|a| filter(a))
+            .collect();
+        history.sort_by_key(|a| a.assigned_at);
+        history
+    }
+}
+
+#[async_trait]
+impl Client for MemoryClient {
+    // ------------- Films ------------- //
+
+    async fn list_films(&self) -> Result<Vec<Film>> {
+        let data = self.data();
+        Ok(data.films.iter().map(|f| data.read_film(f)).collect())
+    }
+
+    async fn get_film(&self, film_name: &str) -> Result<Option<Film>> {
+        let data = self.data();
+        let film = data.films.iter().find(|f| f.name == film_name);
+        Ok(film.map(|f| data.read_film(f)))
+    }
+
+    async fn insert_film(&self, film: &Film) -> Result<Film> {
+        let mut data = self.data();
+        if data.film_id(&film.name).is_some() {
+            return Err(Error::Duplicate(film.name.clone()));
+        }
+
+        let inserted = Film {
+            id: Uuid::new_v4(),
+            roles: Roles::default(),
+            current_role: Role::default(),
+            queued_at: None,
+            ..film.clone()
+        };
+        data.films.push(inserted.clone());
+        info!("Inserted film: {}", inserted.name);
+
+        Ok(inserted)
+    }
+
+    async fn update_film(&self, film: &Film) -> Result<()> {
+        self.data().write_film_roles(film);
+        Ok(())
+    }
+
+    // ------------- Junction ------------- //
+
+    async fn get_worked_films(&self, student_id: &Uuid) -> Result<HashSet<Film>> {
+        let data = self.data();
+        let films = data
+            .films
+            .iter()
+            .filter(|f| data.students_films.contains_key(&(*student_id, f.id)))
+            .map(|f| data.read_film(f))
+            .collect();
+        Ok(films)
+    }
+
+    async fn insert_student_films(&self, s_id: &Uuid, f_id: &Uuid, role: Role) -> Result<()> {
+        let mut data = self.data();
+        if data.students_films.contains_key(&(*s_id, *f_id)) {
+            let e = format!("student {s_id} already worked film {f_id}");
+            return Err(Error::Duplicate(e));
+        }
+        data.students_films.insert((*s_id, *f_id), role);
+        Ok(())
+    }
+
+    async fn get_films_exclusionary(&self, group: i32, role: Role) -> Result<Vec<Film>> {
+        let data = self.data();
+        let films = data
+            .films
+            .iter()
+            .filter(|f| f.group_number != group)
+            .map(|f| data.read_film(f))
+            .filter(|f| f.roles.get(role).is_none())
+            .collect();
+        Ok(films)
+    }
+
+    // ------------- Students ------------- //
+
+    async fn list_students(&self) -> Result<Vec<Student>> {
+        let data = self.data();
+        Ok(data.students.iter().map(|s| data.read_student(s)).collect())
+    }
+
+    /// Unlike postgres, there's no slack to look unknown students up in. They're added with
+    /// their slack id for a name.
+    async fn get_student(&self, slack_id: &str) -> Result<Student> {
+        let student = self.find_student(slack_id).await?;
+        student.ok_or_else(|| Error::NotFound(format!("No student with id {slack_id}")))
+    }
+
+    async fn find_student(&self, slack_id: &str) -> Result<Option<Student>> {
+        let data = self.data();
+        let student = data.students.iter().find(|s| s.slack_id == slack_id);
+        Ok(student.map(|s| data.read_student(s)))
+    }
+
+    async fn insert_student_from_csv(
+        &self,
+        name: &str,
+        group: i32,
+        class: &str,
+    ) -> Result<Student> {
+        let student = Student {
+            name: name.to_string(),
+            group_number: group,
+            class: class.to_string(),
+            ..Default::default()
+        };
+        self.data().students.push(student.clone());
+        info!("Inserted student: {}", name);
+
+        Ok(student)
+    }
+
+    async fn insert_student(&self, slack_id: &str, name: &str) -> Result<Student> {
+        let student = Student {
+            slack_id: slack_id.to_string(),
+            name: name.to_string(),
+            ..Default::default()
+        };
+        self.data().students.push(student.clone());
+        info!("Inserted student: {}", name);
+
+        Ok(student)
+    }
+
+    async fn update_student(&self, student: &Student) -> Result<()> {
+        self.data().write_student(student);
+        Ok(())
+    }
+
+    async fn update_escalation(&self, student: &Student) -> Result<()> {
+        let mut data = self.data();
+        if let Some(s) = data.student_mut(&student.id) {
+            if s.current_film.is_some() && s.current_film == student.current_film {
+                s.escalation = student.escalation;
+            }
+        }
+        Ok(())
+    }
+
+    // ------------- Queue ------------- //
+
+    async fn get_queue(&self, wait: bool) -> Result<Vec<QueueItem>> {
+        let data = self.data();
+        Ok(if wait { &data.wait_q } else { &data.jobs_q }.clone())
+    }
+
+    async fn insert_to_queue(
+        &self,
+        q: QueueItem,
+        wait: bool,
+        audit: Option<&AuditEntry>,
+    ) -> Result<QueueItem> {
+        let mut data = self.data();
+        if wait {
+            // Waiters aren't tied to a film, so only who's waiting and where is kept.
+            data.wait_q.push(QueueItem {
+                priority: None,
+                effective_priority: None,
+                round: None,
+                group_number: None,
+                class: None,
+                deadline: None,
+                created_at: Utc::now(),
+                ..q.clone()
+            });
+        } else {
+            data.insert_job(&q);
+        }
+        data.audit_log.extend(audit.cloned());
+        Ok(q)
+    }
+
+    async fn assign_film(
+        &self,
+        student: &Student,
+        film: &Film,
+        job: &QueueItem,
+        audit: &AuditEntry,
+    ) -> Result<()> {
+        let mut data = self.data();
+        // They're no longer waiting, if they were. How long they waited is kept with the
+        // assignment.
+        let waiting = data
+            .wait_q
+            .iter()
+            .filter(|w| w.student_slack_id == student.slack_id);
+        let waiting_since = waiting.map(|w| w.created_at).min();
+        data.wait_q
+            .retain(|w| w.student_slack_id != student.slack_id);
+
+        data.set_assignment(student, film, (Some(job.created_at), waiting_since));
+        data.jobs_q.retain(|j| j.id != job.id);
+        if let Some(f) = data.film_mut(&film.name) {
+            f.queued_at = Some(job.created_at);
+        }
+        data.audit_log.push(audit.clone());
+        info!("Assigned {} to {}", film.name, student.name);
+
+        Ok(())
+    }
+
+    async fn transfer_film(
+        &self,
+        from: &Student,
+        to: &Student,
+        film: &Film,
+        audit: &AuditEntry,
+    ) -> Result<()> {
+        let mut data = self.data();
+        data.clear_assignment(from, film)?;
+        data.end_assignment(from, Outcome::Reassigned);
+        data.set_assignment(to, film, (None, None));
+        data.audit_log.push(audit.clone());
+        info!("Moved {} from {} to {}", film.name, from.name, to.name);
+
+        Ok(())
+    }
+
+    async fn release_film(
+        &self,
+        student: &Student,
+        film: &Film,
+        job: &QueueItem,
+        audit: &AuditEntry,
+    ) -> Result<()> {
+        let mut data = self.data();
+        data.clear_assignment(student, film)?;
+        data.end_assignment(student, Outcome::Released);
+        data.insert_job(job);
+        data.audit_log.push(audit.clone());
+        info!("Released {} from {}", film.name, student.name);
+
+        Ok(())
+    }
+
+    async fn deliver_film(
+        &self,
+        student: &Student,
+        film: &Film,
+        job: Option<&QueueItem>,
+        delivery: Option<&Delivery>,
+        audit: &AuditEntry,
+    ) -> Result<()> {
+        let mut data = self.data();
+        let now = Utc::now();
+        for a in data.assignments.iter_mut() {
+            if a.student_id == Some(student.id) && a.film_id == film.id && a.outcome.is_none() {
+                a.delivered_at = Some(now);
+                a.outcome = Some(Outcome::Delivered);
+            }
+        }
+
+        data.write_film_roles(film);
+        data.write_student(student);
+        if let Some(job) = job {
+            data.insert_job(job);
+        }
+        data.deliveries.extend(delivery.cloned());
+        data.audit_log.push(audit.clone());
+        info!("{} delivered {}", student.name, film.name);
+
+        Ok(())
+    }
+
+    async fn delete_from_queue(
+        &self,
+        id: &Uuid,
+        wait: bool,
+        audit: Option<&AuditEntry>,
+    ) -> Result<()> {
+        let mut data = self.data();
+        let q = if wait {
+            &mut data.wait_q
+        } else {
+            &mut data.jobs_q
+        };
+        q.retain(|j| &j.id != id);
+        data.audit_log.extend(audit.cloned());
+        Ok(())
+    }
+
+    async fn repair(&self, issue: &Issue, audit: &AuditEntry) -> Result<bool> {
+        let mut data = self.data();
+        let held = |data: &Data, film: &str| {
+            let mut students = data.students.iter();
+            students.any(|s| s.current_film.as_deref() == Some(film))
+        };
+        let job = |data: &Data, id: &Uuid| data.jobs_q.iter().find(|j| &j.id == id).cloned();
+
+        // Only makes the change if the issue still holds.
+        let repaired = match issue {
+            Issue::OrphanJob { id, .. } => {
+                job(&data, id).map_or(false, |j| data.film_id(&j.film_name).is_none())
+            }
+            Issue::StaleJob { id, .. } => job(&data, id).map_or(false, |j| {
+                let film = data.films.iter().find(|f| f.name == j.film_name);
+                film.map_or(false, |f| f.current_role != j.role)
+            }),
+            Issue::HeldJob { id, .. } => {
+                job(&data, id).map_or(false, |j| held(&data, &j.film_name))
+            }
+            Issue::DuplicateJob { id, .. } => job(&data, id).map_or(false, |j| {
+                let mut jobs = data.jobs_q.iter();
+                jobs.any(|o| o.film_name == j.film_name && o.role == j.role && o.id != j.id)
+            }),
+            Issue::AssignedWaiter { id, .. } => {
+                let waiter = data.wait_q.iter().find(|w| &w.id == id);
+                waiter.map_or(false, |w| {
+                    let mut students = data.students.iter();
+                    students.any(|s| s.slack_id == w.student_slack_id && s.current_film.is_some())
+                })
+            }
+            Issue::UnknownFilm { student, film } => {
+                let mut students = data.students.iter();
+                let holding = students
+                    .any(|s| &s.slack_id == student && s.current_film.as_ref() == Some(film));
+                holding && data.film_id(film).is_none()
+            }
+            Issue::Unqueued { film, .. } => {
+                let f = data.films.iter().find(|f| &f.name == film);
+                f.map_or(false, |f| f.current_role != Role::Done)
+                    && !data.jobs_q.iter().any(|j| &j.film_name == film)
+                    && !held(&data, film)
+            }
+            Issue::MissingHistory { .. } | Issue::SharedFilm { .. } => false,
+        };
+        if !repaired {
+            return Ok(false);
+        }
+
+        match issue {
+            Issue::OrphanJob { id, .. }
+            | Issue::StaleJob { id, .. }
+            | Issue::HeldJob { id, .. }
+            | Issue::DuplicateJob { id, .. } => data.jobs_q.retain(|j| &j.id != id),
+            Issue::AssignedWaiter { id, .. } => data.wait_q.retain(|w| &w.id != id),
+            Issue::UnknownFilm { student, .. } => {
+                for s in data.students.iter_mut().filter(|s| &s.slack_id == student) {
+                    s.unassign();
+                }
+            }
+            Issue::Unqueued { film, .. } => {
+                let f = data.films.iter().find(|f| &f.name == film);
+                let job = f.map(|f| queue::new_job(&data.read_film(f), ""));
+                if let Some(job) = job {
+                    data.insert_job(&job);
+                }
+            }
+            Issue::MissingHistory { .. } | Issue::SharedFilm { .. } => {}
+        }
+        data.audit_log.push(audit.clone());
+        Ok(true)
+    }
+
+    async fn send_back(&self, film: &Film, revision: &Revision, audit: &AuditEntry) -> Result<()> {
+        let mut data = self.data();
+        data.write_film_roles(film);
+
+        for s in data.students.iter_mut() {
+            if s.slack_id == revision.from {
+                s.paused = true;
+            }
+        }
+        for a in data.assignments.iter_mut() {
+            let delivered = a.outcome == Some(Outcome::Delivered);
+            if a.film_id == film.id && a.role == revision.role && delivered {
+                a.outcome = Some(Outcome::SentBack);
+            }
+        }
+        data.revisions.push(revision.clone());
+        data.audit_log.push(audit.clone());
+        info!("Sent {} back to {}", film.name, revision.role.as_ref());
+
+        Ok(())
+    }
+
+    async fn get_revision(&self, slack_id: &str) -> Result<Option<Revision>> {
+        let data = self.data();
+        let revision = data
+            .revisions
+            .iter()
+            .filter(|r| r.to == slack_id && r.resolved_at.is_none())
+            .filter(|r| data.film_id(&r.film_name).is_some())
+            .filter(|r| data.student_id(&r.from).is_some())
+            .min_by_key(|r| r.created_at);
+        Ok(revision.cloned())
+    }
+
+    async fn resolve_revision(
+        &self,
+        film: &Film,
+        revision: &Revision,
+        delivery: Option<&Delivery>,
+        audit: &AuditEntry,
+    ) -> Result<()> {
+        let mut data = self.data();
+        let now = Utc::now();
+        data.write_film_roles(film);
+        data.deliveries.extend(delivery.cloned());
+
+        if let Some(r) = data.revisions.iter_mut().find(|r| r.id == revision.id) {
+            r.resolved_at = Some(now);
+        }
+
+        // The fix counts as its own turn on the stage, starting when the film was sent back.
+        let student_id = data.student_id(&revision.to);
+        data.assignments.push(AssignmentRow {
+            id: Uuid::new_v4(),
+            student_id,
+            film_id: film.id,
+            role: revision.role,
+            assigned_at: revision.created_at,
+            delivered_at: Some(now),
+            outcome: Some(Outcome::Delivered),
+            queued_at: None,
+            waiting_since: None,
+        });
+
+        // The clock restarts now that the film's back with them.
+        for s in data.students.iter_mut() {
+            if s.slack_id == revision.from && s.current_film.as_ref() == Some(&film.name) {
+                s.paused = false;
+                s.assigned_at = Some(now);
+                s.escalation = 0;
+            }
+        }
+        data.audit_log.push(audit.clone());
+        info!("Resolved revision of {}", film.name);
+
+        Ok(())
+    }
+
+    async fn get_delivery(&self, film_name: &str, role: Role) -> Result<Option<Delivery>> {
+        let data = self.data();
+        let delivery = data
+            .deliveries
+            .iter()
+            .filter(|d| d.film_name == film_name && d.role == role)
+            .filter(|d| data.student_id(&d.student).is_some())
+            .max_by_key(|d| d.created_at);
+        Ok(delivery.cloned())
+    }
+
+    async fn get_film_history(&self, film_name: &str) -> Result<Vec<Assignment>> {
+        Ok(self.data().history(|a| a.film_name == film_name))
+    }
+
+    async fn get_student_history(&self, slack_id: &str) -> Result<Vec<Assignment>> {
+        Ok(self.data().history(|a| a.slack_id == slack_id))
+    }
+
+    async fn list_assignments(&self) -> Result<Vec<Assignment>> {
+        Ok(self.data().history(|_| true))
+    }
+
+    async fn insert_audit(&self, entry: &AuditEntry) -> Result<()> {
+        self.data().audit_log.push(entry.clone());
+        Ok(())
+    }
+
+    async fn get_audit_log(
+        &self,
+        student: Option<&str>,
+        film: Option<&str>,
+        limit: i64,
+    ) -> Result<Vec<AuditEntry>> {
+        let data = self.data();
+        let mut entries: Vec<_> = data
+            .audit_log
+            .iter()
+            .filter(|e| student.is_none() || e.student.as_deref() == student)
+            .filter(|e| film.is_none() || e.film.as_deref() == film)
+            .cloned()
+            .collect();
+        entries.sort_by_key(|e| std::cmp::Reverse(e.created_at));
+        entries.truncate(limit.max(0) as usize);
+        Ok(entries)
+    }
+
+    async fn insert_event(&self, event: &PendingEvent, claimed_by: &str) -> Result<bool> {
+        let mut data = self.data();
+        if data.events.iter().any(|e| e.event.id == event.id) {
+            return Ok(false);
+        }
+        data.events.push(EventRow {
+            event: event.clone(),
+            handled_at: None,
+            claimed_by: Some(claimed_by.to_string()),
+            claimed_at: Some(Utc::now()),
+        });
+        Ok(true)
+    }
+
+    async fn list_events(&self) -> Result<Vec<PendingEvent>> {
+        let mut events: Vec<_> = self
+            .data()
+            .events
+            .iter()
+            .filter(|e| e.handled_at.is_none())
+            .map(|e| e.event.clone())
+            .collect();
+        events.sort_by_key(|e| e.created_at);
+        Ok(events)
+    }
+
+    async fn claim_events(
+        &self,
+        claimed_by: &str,
+        expired: DateTime<Utc>,
+    ) -> Result<Vec<PendingEvent>> {
+        let now = Utc::now();
+        let mut events = vec![];
+        for row in self.data().events.iter_mut() {
+            let claimable = row.claimed_at.map_or(true, |at| at < expired);
+            if row.handled_at.is_none() && claimable {
+                row.claimed_by = Some(claimed_by.to_string());
+                row.claimed_at = Some(now);
+                events.push(row.event.clone());
+            }
+        }
+        events.sort_by_key(|e| e.created_at);
+        Ok(events)
+    }
+
+    async fn renew_events(&self, claimed_by: &str) -> Result<()> {
+        let now = Utc::now();
+        for row in self.data().events.iter_mut() {
+            if row.handled_at.is_none() && row.claimed_by.as_deref() == Some(claimed_by) {
+                row.claimed_at = Some(now);
+            }
+        }
+        Ok(())
+    }
+
+    async fn release_events(&self, claimed_by: &str) -> Result<u64> {
+        let mut released = 0;
+        for row in self.data().events.iter_mut() {
+            if row.handled_at.is_none() && row.claimed_by.as_deref() == Some(claimed_by) {
+                row.claimed_by = None;
+                row.claimed_at = None;
+                released += 1;
+            }
+        }
+        Ok(released)
+    }
+
+    async fn finish_event(&self, id: &str, forget_before: DateTime<Utc>) -> Result<()> {
+        let mut data = self.data();
+        if let Some(row) = data.events.iter_mut().find(|e| e.event.id == id) {
+            row.handled_at = Some(Utc::now());
+        }
+        data.events
+            .retain(|e| e.handled_at.map_or(true, |at| at >= forget_before));
+        Ok(())
+    }
+
+    async fn list_uses(
+        &self,
+        slack_id: &str,
+        action: &str,
+        since: DateTime<Utc>,
+    ) -> Result<Vec<DateTime<Utc>>> {
+        let key = (slack_id.to_string(), action.to_string());
+        let mut uses: Vec<_> = self.data().uses.get(&key).cloned().unwrap_or_default();
+        uses.retain(|&at| at > since);
+        uses.sort();
+        Ok(uses)
+    }
+
+    async fn insert_use(
+        &self,
+        slack_id: &str,
+        action: &str,
+        at: DateTime<Utc>,
+        expired: DateTime<Utc>,
+    ) -> Result<()> {
+        let key = (slack_id.to_string(), action.to_string());
+        let mut data = self.data();
+        let uses = data.uses.entry(key).or_default();
+        uses.retain(|&used| used > expired);
+        uses.push(at);
+        Ok(())
+    }
+
+    async fn migrate(&self) -> Result<()> {
+        Ok(())
+    }
+
+    async fn schema_version(&self) -> Result<SchemaVersion> {
+        // There's no schema to fall behind.
+        Ok(SchemaVersion {
+            applied: 0,
+            latest: 0,
+        })
+    }
+
+    async fn ping(&self) -> Result<()> {
+        // There's nothing to connect to.
+        Ok(())
+    }
+
+    async fn drop_db(&self) -> Result<()> {
+        *self.data() = Data::default();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audit::Origin;
+    use models::{AuditAction, Priority};
+
+    fn job(film: &Film) -> QueueItem {
+        crate::queue::new_job(film, "")
+    }
+
+    fn entry(action: AuditAction) -> AuditEntry {
+        Origin::system("test").entry(action, Some("U1"), Some("film"))
+    }
+
+    #[tokio::test]
+    async fn check_films() -> Result<()> {
+        let db = MemoryClient::new();
+        let a = db.insert_film(&Film::new("a", Priority::High, 1)).await?;
+        db.insert_film(&Film::new("b", Priority::Low, 2)).await?;
+        assert!(matches!(
+            db.insert_film(&Film::new("a", Priority::Low, 3)).await,
+            Err(Error::Duplicate(_))
+        ));
+        assert_eq!(Some(a), db.get_film("a").await?);
+
+        // Films in the student's own group are left out, as are those where the role's done.
+        let names = |films: Vec<Film>| films.into_iter().map(|f| f.name).collect::<Vec<_>>();
+        assert_eq!(
+            vec!["b"],
+            names(db.get_films_exclusionary(1, Role::Ae).await?)
+        );
+        assert_eq!(
+            vec!["a", "b"],
+            names(db.get_films_exclusionary(0, Role::Ae).await?)
+        );
+
+        let mut b = db.get_film("b").await?.unwrap();
+        b.increment_role("someone");
+        db.update_film(&b).await?;
+        assert!(db.get_films_exclusionary(1, Role::Ae).await?.is_empty());
+        assert_eq!(
+            vec!["b"],
+            names(db.get_films_exclusionary(1, Role::Editor).await?)
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn check_queue() -> Result<()> {
+        let db = MemoryClient::new();
+        let film = db.insert_film(&Film::new("a", Priority::High, 1)).await?;
+        let mut waiter = job(&film);
+        waiter.student_slack_id = "U1".to_string();
+
+        let j = db.insert_to_queue(job(&film), false, None).await?;
+        db.insert_to_queue(waiter, true, None).await?;
+
+        // Clones share the same data.
+        let copy = db.clone();
+        assert_eq!(vec![j.clone()], copy.get_queue(false).await?);
+        let waiters = copy.get_queue(true).await?;
+        assert_eq!(None, waiters[0].group_number);
+
+        db.delete_from_queue(&j.id, false, None).await?;
+        assert!(copy.get_queue(false).await?.is_empty());
+        assert_eq!(1, copy.get_queue(true).await?.len());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn check_assignments() -> Result<()> {
+        let db = MemoryClient::new();
+        // Students it hasn't seen aren't made up on the spot.
+        assert!(matches!(
+            db.get_student("U1").await,
+            Err(Error::NotFound(_))
+        ));
+        let a = db.insert_student("U1", "U1").await?;
+        let film = db
+            .insert_film(&Film::new("film", Priority::High, 1))
+            .await?;
+        let j = db.insert_to_queue(job(&film), false, None).await?;
+
+        db.assign_film(&a, &film, &j, &entry(AuditAction::Assign))
+            .await?;
+        assert!(db.get_queue(false).await?.is_empty());
+        let mut a = db.get_student("U1").await?;
+        assert_eq!(Some("film".to_string()), a.current_film);
+        assert!(db
+            .get_worked_films(&a.id)
+            .await?
+            .contains(&db.get_film("film").await?.unwrap()));
+
+        // Who worked what is read back from the history.
+        let mut film = db.get_film("film").await?.unwrap();
+        film.increment_role(&a.name);
+        a.increment_role(&film.name);
+        a.unassign();
+        db.deliver_film(&a, &film, None, None, &entry(AuditAction::Deliver))
+            .await?;
+        let audited: Vec<_> = db
+            .get_audit_log(Some("U1"), None, 10)
+            .await?
+            .into_iter()
+            .map(|e| e.action)
+            .collect();
+        assert_eq!(vec![AuditAction::Deliver, AuditAction::Assign], audited);
+
+        let film = db.get_film("film").await?.unwrap();
+        assert_eq!(Some("U1"), film.roles.get(Role::Ae));
+        assert_eq!(Role::Editor, film.current_role);
+        let a = db.get_student("U1").await?;
+        assert_eq!(Some("film"), a.roles.get(Role::Ae));
+        assert_eq!(None, a.current_film);
+
+        let history = db.get_film_history("film").await?;
+        assert_eq!(1, history.len());
+        assert_eq!(Some(Outcome::Delivered), history[0].outcome);
+        assert_eq!(history, db.get_student_history("U1").await?);
+
+        Ok(())
+    }
+}
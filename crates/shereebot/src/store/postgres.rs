@@ -5,12 +5,26 @@ use async_trait::async_trait;
 use chrono::{DateTime, Utc};
 use color_eyre::eyre::eyre;
 use deadpool_postgres::Pool;
-use tokio_postgres::Row;
+use tokio_postgres::{error::SqlState, GenericClient, IsolationLevel, Row};
 use tracing::{info, trace, warn};
 use uuid::Uuid;
 
-use crate::{queue::QueueItem, slack::UserResponse, store::Client, Error, Result};
-use models::{Film, Priority, Role, Roles, Student};
+use crate::{
+    integrity::Issue,
+    queue::{self, QueueItem},
+    store::{Client, SchemaVersion},
+    Error, Result,
+};
+use models::{
+    Assignment, AuditAction, AuditEntry, Delivery, Film, Outcome, PendingEvent, Priority, Revision,
+    Role, Roles, StageHours, Student,
+};
+
+/// Creates every table, view and index, then applies any migrations.
+const SCHEMA: &str = include_str!("../../../../schema.sql");
+
+/// The version `SCHEMA` records once it's been applied.
+const SCHEMA_VERSION: i64 = 5;
 
 /// Internal Postgres client.
 #[derive(Clone)]
@@ -41,9 +55,10 @@ impl Client for PostgresClient {
         let client = self.pool.get().await?;
 
         let stmt = "
-            SELECT f.id, f.name, f.priority, f.group_number, 
+            SELECT f.id, f.name, f.priority, f.group_number, f.class, f.due_date,
+                   f.ae_hours, f.editor_hours, f.sound_hours, f.finish_hours, f.queued_at,
                    r.ae, r.editor, r.sound, r.finish, r.current
-            FROM films as f, roles as r 
+            FROM films as f, film_roles as r 
             WHERE f.roles_id = r.id;";
         let stmt = client.prepare_cached(stmt).await?;
 
@@ -57,9 +72,10 @@ impl Client for PostgresClient {
         let client = self.pool.get().await?;
 
         let stmt = "
-            SELECT f.id, f.name, f.priority, f.group_number,
+            SELECT f.id, f.name, f.priority, f.group_number, f.class, f.due_date,
+                   f.ae_hours, f.editor_hours, f.sound_hours, f.finish_hours, f.queued_at,
                    r.ae, r.editor, r.sound, r.finish, r.current
-            FROM films as f, roles as r 
+            FROM films as f, film_roles as r 
             WHERE f.name = $1
             AND f.roles_id = r.id;";
         let stmt = client.prepare_cached(stmt).await?;
@@ -76,15 +92,16 @@ impl Client for PostgresClient {
         Ok(film)
     }
 
-    async fn insert_film(&self, name: &str, group_number: i32, priority: Priority) -> Result<Film> {
+    async fn insert_film(&self, film: &Film) -> Result<Film> {
         let mut client = self.pool.get().await?;
 
         let stmt = "INSERT INTO roles(id) VALUES($1) RETURNING id;";
         let stmt = client.prepare_cached(stmt).await?;
 
         let stmt2 = "
-            INSERT INTO films(id, name, priority, roles_id, group_number) 
-            VALUES($1, $2, $3, $4, $5);";
+            INSERT INTO films(id, name, priority, roles_id, group_number, class, due_date,
+                              ae_hours, editor_hours, sound_hours, finish_hours)
+            VALUES($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11);";
         let stmt2 = client.prepare_cached(stmt2).await?;
 
         let transaction = client.transaction().await?;
@@ -93,43 +110,38 @@ impl Client for PostgresClient {
         let id = Uuid::new_v4();
         let role_id: Uuid = transaction.query(&stmt, &[&id]).await?[0].get("id");
 
-        let mut film = Film::default();
-        let id = &film.id;
-        let p = priority.as_ref();
+        let inserted = Film {
+            id: Uuid::new_v4(),
+            roles: Roles::default(),
+            current_role: Role::default(),
+            ..film.clone()
+        };
+        let (name, p, hours) = (
+            &inserted.name,
+            inserted.priority.as_ref(),
+            &inserted.stage_hours,
+        );
 
-        let res = transaction
-            .query(&stmt2, &[&id, &name, &p, &role_id, &group_number])
-            .await;
-        if res.is_err() {
-            return Err(Error::Duplicate(name.to_string()));
+        #[rustfmt::skip]
+        let res = transaction.query(&stmt2, &[
+            &inserted.id, name, &p, &role_id, &inserted.group_number, &inserted.class,
+            &inserted.due_date,
+            &hours.ae, &hours.editor, &hours.sound, &hours.finish,
+        ]).await;
+        if let Err(e) = res {
+            return Err(duplicate(e, name));
         }
         transaction.commit().await?;
 
         info!("Inserted film: {}", name);
-        film.name = name.to_string();
 
-        Ok(film)
+        Ok(inserted)
     }
 
     async fn update_film(&self, film: &Film) -> Result<()> {
         let client = self.pool.get().await?;
 
-        let stmt = "
-            UPDATE roles
-            SET ae = $2, editor = $3, sound = $4, finish = $5, current = $6
-            WHERE id = (
-                SELECT roles_id FROM films WHERE name = $1);";
-        let stmt = client.prepare_cached(stmt).await?;
-
-        #[rustfmt::skip]
-        client.query(&stmt, &[
-            &film.name,
-            &film.roles.ae,
-            &film.roles.editor,
-            &film.roles.sound,
-            &film.roles.finish,
-            &film.current_role.as_ref(),
-        ]).await?;
+        update_film_roles(&**client, film).await?;
 
         info!("Updated film: {}", film.name);
 
@@ -142,10 +154,11 @@ impl Client for PostgresClient {
         let client = self.pool.get().await?;
 
         let stmt = "
-            SELECT f.id, f.name, f.priority, f.group_number, 
+            SELECT f.id, f.name, f.priority, f.group_number, f.class, f.due_date,
+                   f.ae_hours, f.editor_hours, f.sound_hours, f.finish_hours, f.queued_at,
                    r.ae, r.editor, r.sound, r.finish, r.current
             FROM films as f 
-                JOIN roles AS r ON f.roles_id = r.id 
+                JOIN film_roles AS r ON f.roles_id = r.id 
                 JOIN students_films on f.id = students_films.film_id
             WHERE students_films.student_id = $1;";
         let stmt = client.prepare_cached(stmt).await?;
@@ -159,13 +172,20 @@ impl Client for PostgresClient {
         Ok(res)
     }
 
-    async fn insert_student_films(&self, student_id: &Uuid, film_id: &Uuid) -> Result<()> {
+    async fn insert_student_films(
+        &self,
+        student_id: &Uuid,
+        film_id: &Uuid,
+        role: Role,
+    ) -> Result<()> {
         let client = self.pool.get().await?;
 
-        let stmt = "INSERT INTO students_films(student_id, film_id) VALUES($1, $2);";
+        let stmt = "INSERT INTO students_films(student_id, film_id, role) VALUES($1, $2, $3);";
         let stmt = client.prepare_cached(stmt).await?;
 
-        client.query(&stmt, &[&student_id, &film_id]).await?;
+        client
+            .query(&stmt, &[&student_id, &film_id, &role.as_ref()])
+            .await?;
         info!("Inserted into students_films");
 
         Ok(())
@@ -179,10 +199,11 @@ impl Client for PostgresClient {
 
         let stmt = format!(
             "
-            SELECT DISTINCT f.id, f.name, f.priority, f.group_number, 
+            SELECT DISTINCT f.id, f.name, f.priority, f.group_number, f.class, f.due_date,
+                   f.ae_hours, f.editor_hours, f.sound_hours, f.finish_hours, f.queued_at,
                    r.ae, r.editor, r.sound, r.finish, r.current
-            FROM films as f, roles as r 
-            WHERE f.group_number != $1 AND r.{role} IS NULL;"
+            FROM films as f, film_roles as r 
+            WHERE f.roles_id = r.id AND f.group_number != $1 AND r.{role} IS NULL;"
         );
         let stmt = client.prepare_cached(&stmt).await?;
 
@@ -200,9 +221,9 @@ impl Client for PostgresClient {
 
         let stmt = "
             SELECT s.id, s.name, s.slack_id, s.current_film, 
-                   s.group_number, s.class, r.ae, r.editor,
-                   r.sound, r.finish, r.current
-            FROM students as s, roles as r 
+                   s.group_number, s.class, s.assigned_at, s.escalation, s.paused,
+                   r.ae, r.editor, r.sound, r.finish, r.current
+            FROM students as s, student_roles as r 
             WHERE s.roles_id = r.id;";
         let stmt = client.prepare_cached(stmt).await?;
 
@@ -214,58 +235,56 @@ impl Client for PostgresClient {
 
     async fn get_student(&self, slack_id: &str) -> Result<Student> {
         info!("Retrieving student with id {slack_id}");
-        let client = self.pool.get().await?;
+        if let Some(student) = self.find_student(slack_id).await? {
+            return Ok(student);
+        }
 
+        // TODO: clean up this garbage
+        warn!("No user for id {slack_id}. Looking user up instead.");
+        let client = self.pool.get().await?;
+        let name = super::lookup_name(slack_id).await?;
         let stmt = "
             SELECT s.id, s.name, s.slack_id, s.current_film, 
-                   s.group_number, s.class, r.ae, r.editor,
-                   r.sound, r.finish, r.current
-            FROM students as s, roles as r 
-            WHERE s.slack_id = $1
+                   s.group_number, s.class, s.assigned_at, s.escalation, s.paused,
+                   r.ae, r.editor, r.sound, r.finish, r.current
+            FROM students as s, student_roles as r 
+            WHERE s.name = $1
             AND s.roles_id = r.id;";
         let stmt = client.prepare_cached(stmt).await?;
+        let mut rows = client.query(&stmt, &[&name]).await?;
 
-        let mut rows = client.query(&stmt, &[&slack_id]).await?;
-        // TODO: clean up this garbage
         if rows.is_empty() {
-            warn!("No user for id {slack_id}. Looking user up instead.");
-            let version = reqwest::tls::Version::TLS_1_2;
-            let req_client = reqwest::Client::builder()
-                .min_tls_version(version)
-                .build()?;
-            let token = std::env::var("OAUTH_TOKEN").map_err(Into::<Error>::into)?;
-            let req = format!("https://slack.com/api/users.info?user={slack_id}");
-
-            let res = req_client.post(req).bearer_auth(token).send().await?;
-            let res = res.json::<UserResponse>().await?;
-            let name = res.user.real_name;
-            let stmt = "
-                SELECT s.id, s.name, s.slack_id, s.current_film, 
-                       s.group_number, s.class, r.ae, r.editor,
-                       r.sound, r.finish, r.current
-                FROM students as s, roles as r 
-                WHERE s.name = $1
-                AND s.roles_id = r.id;";
-            let stmt = client.prepare_cached(stmt).await?;
-            let mut rows = client.query(&stmt, &[&name]).await?;
-
-            if rows.is_empty() {
-                return self.insert_student(slack_id, &name).await;
-            }
-
-            let row = rows.pop().expect("just checked empty");
-            let student = format_row_into_student(row)?;
-            info!("Retrieved student {}", student.name);
-            return Ok(student);
+            return self.insert_student(slack_id, &name).await;
         }
-        let row = rows.pop().expect("^^just checked if empty");
 
+        let row = rows.pop().expect("just checked empty");
         let student = format_row_into_student(row)?;
         info!("Retrieved student {}", student.name);
-
         Ok(student)
     }
 
+    async fn find_student(&self, slack_id: &str) -> Result<Option<Student>> {
+        let client = self.pool.get().await?;
+
+        let stmt = "
+            SELECT s.id, s.name, s.slack_id, s.current_film, 
+                   s.group_number, s.class, s.assigned_at, s.escalation, s.paused,
+                   r.ae, r.editor, r.sound, r.finish, r.current
+            FROM students as s, student_roles as r 
+            WHERE s.slack_id = $1
+            AND s.roles_id = r.id;";
+        let stmt = client.prepare_cached(stmt).await?;
+
+        match client.query_opt(&stmt, &[&slack_id]).await? {
+            Some(row) => {
+                let student = format_row_into_student(row)?;
+                info!("Retrieved student {}", student.name);
+                Ok(Some(student))
+            }
+            None => Ok(None),
+        }
+    }
+
     async fn insert_student_from_csv(
         &self,
         name: &str,
@@ -294,8 +313,8 @@ impl Client for PostgresClient {
             .query(&stmt2, &[&id, &name, &role_id, &group, &class])
             .await;
 
-        if res.is_err() {
-            return Err(Error::Duplicate(name.to_string()));
+        if let Err(e) = res {
+            return Err(duplicate(e, name));
         }
         transaction.commit().await?;
 
@@ -330,8 +349,8 @@ impl Client for PostgresClient {
             .query(&stmt2, &[&id, &name, &role_id, &slack_id])
             .await;
 
-        if res.is_err() {
-            return Err(Error::Duplicate(name.to_string()));
+        if let Err(e) = res {
+            return Err(duplicate(e, name));
         }
         transaction.commit().await?;
 
@@ -344,35 +363,29 @@ impl Client for PostgresClient {
 
     async fn update_student(&self, student: &Student) -> Result<()> {
         let mut client = self.pool.get().await?;
+        let transaction = client.transaction().await?;
 
-        let stmt = "
-            UPDATE roles
-            SET ae = $2, editor = $3, sound = $4, finish = $5, current = $6
-            WHERE id = (
-                SELECT roles_id FROM students WHERE id = $1);";
-        let stmt = client.prepare_cached(stmt).await?;
+        write_student(&*transaction, student).await?;
 
-        let stmt2 = "UPDATE students SET current_film = $2, slack_id = $3 WHERE id = $1";
-        let stmt2 = client.prepare_cached(stmt2).await?;
+        transaction.commit().await?;
 
-        let transaction = client.transaction().await?;
+        info!("Updated student: {}", student.name);
 
-        #[rustfmt::skip]
-        transaction.query(&stmt, &[
-            &student.id,
-            &student.roles.ae,
-            &student.roles.editor,
-            &student.roles.sound,
-            &student.roles.finish,
-            &student.current_role.as_ref(),
-        ]).await?;
+        Ok(())
+    }
 
-        let (id, film, slack_id) = (&student.id, &student.current_film, &student.slack_id);
-        transaction.query(&stmt2, &[&id, &film, &slack_id]).await?;
+    async fn update_escalation(&self, student: &Student) -> Result<()> {
+        let client = self.pool.get().await?;
 
-        transaction.commit().await?;
+        let stmt = "UPDATE students SET escalation = $2 WHERE id = $1 AND current_film = $3;";
+        let stmt = client.prepare_cached(stmt).await?;
 
-        info!("Updated student: {}", student.name);
+        let (id, film) = (&student.id, &student.current_film);
+        client
+            .query(&stmt, &[&id, &student.escalation, &film])
+            .await?;
+
+        info!("Escalated {} to level {}", student.name, student.escalation);
 
         Ok(())
     }
@@ -382,8 +395,11 @@ impl Client for PostgresClient {
     async fn get_queue(&self, wait: bool) -> Result<Vec<QueueItem>> {
         let client = self.pool.get().await?;
 
+        // Waiters aren't tied to a film, so wait_q has none of its details.
         let stmt = if wait {
-            "SELECT * from wait_q;"
+            "SELECT *, NULL::INTEGER AS group_number, NULL::TEXT AS class,
+                NULL::TIMESTAMPTZ AS deadline
+            FROM wait_q;"
         } else {
             "SELECT * from jobs_q;"
         };
@@ -404,12 +420,17 @@ impl Client for PostgresClient {
             };
             let msg_ts: Option<String> = row.get("msg_ts");
             let channel: Option<String> = row.get("channel");
+            let group_number: Option<i32> = row.get("group_number");
+            let class: Option<String> = row.get("class");
+            let deadline: Option<DateTime<Utc>> = row.get("deadline");
             let created_at: DateTime<Utc> = row.get("created_at");
 
             #[rustfmt::skip]
             let item = QueueItem {
-                id, student_slack_id, film_name, role,
-                priority, created_at, msg_ts, channel,
+                id, student_slack_id, film_name, role, group_number, class,
+                priority, deadline, created_at, msg_ts, channel,
+                effective_priority: None,
+                round: None,
             };
             res.push(item);
         }
@@ -420,16 +441,22 @@ impl Client for PostgresClient {
         Ok(res)
     }
 
-    async fn insert_to_queue(&self, q: QueueItem, wait: bool) -> Result<QueueItem> {
-        let client = self.pool.get().await?;
+    async fn insert_to_queue(
+        &self,
+        q: QueueItem,
+        wait: bool,
+        audit: Option<&AuditEntry>,
+    ) -> Result<QueueItem> {
+        let mut client = self.pool.get().await?;
+        let transaction = client.transaction().await?;
 
         if wait {
             let stmt = "INSERT INTO wait_q(id, student_slack_id, film_name, role, msg_ts, channel)
              VALUES($1, $2, $3, $4, $5, $6);";
-            let stmt = client.prepare_cached(stmt).await?;
+            let stmt = transaction.prepare_cached(stmt).await?;
 
             #[rustfmt::skip]
-            client.query(&stmt, &[
+            transaction.query(&stmt, &[
                 &q.id,
                 &q.student_slack_id,
                 &q.film_name,
@@ -438,21 +465,14 @@ impl Client for PostgresClient {
                 &q.channel,
             ]).await?;
         } else {
-            let stmt = "INSERT INTO jobs_q(id, student_slack_id, film_name, role, priority)
-             VALUES($1, $2, $3, $4, $5);";
-            let stmt = client.prepare_cached(stmt).await?;
-            let p = q.priority.map(|a| a.as_ref().to_string());
-
-            #[rustfmt::skip]
-            client.query(&stmt, &[
-                &q.id,
-                &q.student_slack_id,
-                &q.film_name,
-                &q.role.as_ref(),
-                &p,
-            ]).await?;
+            insert_job(&*transaction, &q).await?;
+        }
+        if let Some(entry) = audit {
+            insert_audit(&*transaction, entry).await?;
         }
 
+        transaction.commit().await?;
+
         if wait {
             info!("Inserted {} into the wait queue", q.student_slack_id);
         } else {
@@ -462,21 +482,668 @@ impl Client for PostgresClient {
         Ok(q)
     }
 
-    async fn delete_from_queue(&self, id: &Uuid, wait: bool) -> Result<()> {
-        let client = self.pool.get().await?;
+    async fn assign_film(
+        &self,
+        student: &Student,
+        film: &Film,
+        job: &QueueItem,
+        audit: &AuditEntry,
+    ) -> Result<()> {
+        let mut client = self.pool.get().await?;
+        let transaction = client.transaction().await?;
+
+        // They're no longer waiting, if they were. How long they waited is kept with the
+        // assignment.
+        let stmt = "DELETE FROM wait_q WHERE student_slack_id = $1 RETURNING created_at;";
+        let stmt = transaction.prepare_cached(stmt).await?;
+        let waited = transaction.query(&stmt, &[&student.slack_id]).await?;
+        let waiting_since = waited.iter().map(|row| row.get("created_at")).min();
+
+        let queued = (Some(job.created_at), waiting_since);
+        set_assignment(&*transaction, student, film, queued).await?;
+
+        let stmt = "DELETE FROM jobs_q WHERE id = $1;";
+        let stmt = transaction.prepare_cached(stmt).await?;
+        transaction.query(&stmt, &[&job.id]).await?;
+
+        let stmt = "UPDATE films SET queued_at = $2 WHERE id = $1;";
+        let stmt = transaction.prepare_cached(stmt).await?;
+        transaction
+            .query(&stmt, &[&film.id, &job.created_at])
+            .await?;
+        insert_audit(&*transaction, audit).await?;
+
+        transaction.commit().await?;
+
+        info!("Assigned {} to {}", film.name, student.name);
+
+        Ok(())
+    }
+
+    async fn transfer_film(
+        &self,
+        from: &Student,
+        to: &Student,
+        film: &Film,
+        audit: &AuditEntry,
+    ) -> Result<()> {
+        let mut client = self.pool.get().await?;
+        let transaction = client.transaction().await?;
+
+        end_assignment(&*transaction, from, Outcome::Reassigned).await?;
+        clear_assignment(&*transaction, from, film).await?;
+        set_assignment(&*transaction, to, film, (None, None)).await?;
+        insert_audit(&*transaction, audit).await?;
+
+        transaction.commit().await?;
+
+        info!("Moved {} from {} to {}", film.name, from.name, to.name);
+
+        Ok(())
+    }
+
+    async fn release_film(
+        &self,
+        student: &Student,
+        film: &Film,
+        job: &QueueItem,
+        audit: &AuditEntry,
+    ) -> Result<()> {
+        let mut client = self.pool.get().await?;
+        let transaction = client.transaction().await?;
+
+        end_assignment(&*transaction, student, Outcome::Released).await?;
+        clear_assignment(&*transaction, student, film).await?;
+        insert_job(&*transaction, job).await?;
+        insert_audit(&*transaction, audit).await?;
+
+        transaction.commit().await?;
+
+        info!("Released {} from {}", film.name, student.name);
+
+        Ok(())
+    }
+
+    async fn deliver_film(
+        &self,
+        student: &Student,
+        film: &Film,
+        job: Option<&QueueItem>,
+        delivery: Option<&Delivery>,
+        audit: &AuditEntry,
+    ) -> Result<()> {
+        let mut client = self.pool.get().await?;
+        let transaction = client.transaction().await?;
+
+        // The student's already been moved on, so close out the film they just delivered.
+        let stmt = "
+            UPDATE assignments
+            SET delivered_at = CURRENT_TIMESTAMP, outcome = $3
+            WHERE student_id = $1
+            AND film_id = $2
+            AND outcome IS NULL;";
+        let stmt = transaction.prepare_cached(stmt).await?;
+        let outcome = Outcome::Delivered.as_ref();
+        transaction
+            .query(&stmt, &[&student.id, &film.id, &outcome])
+            .await?;
+
+        update_film_roles(&*transaction, film).await?;
+        write_student(&*transaction, student).await?;
+        if let Some(job) = job {
+            insert_job(&*transaction, job).await?;
+        }
+        if let Some(delivery) = delivery {
+            insert_delivery(&*transaction, delivery).await?;
+        }
+        insert_audit(&*transaction, audit).await?;
+
+        transaction.commit().await?;
+
+        info!("{} delivered {}", student.name, film.name);
+
+        Ok(())
+    }
+
+    async fn delete_from_queue(
+        &self,
+        id: &Uuid,
+        wait: bool,
+        audit: Option<&AuditEntry>,
+    ) -> Result<()> {
+        let mut client = self.pool.get().await?;
+        let transaction = client.transaction().await?;
 
         let stmt = if wait {
             "DELETE FROM wait_q WHERE id = $1;"
         } else {
             "DELETE FROM jobs_q WHERE id = $1;"
         };
+        let stmt = transaction.prepare_cached(stmt).await?;
+
+        transaction.query(&stmt, &[&id]).await?;
+        if let Some(entry) = audit {
+            insert_audit(&*transaction, entry).await?;
+        }
+
+        transaction.commit().await?;
+
+        Ok(())
+    }
+
+    async fn repair(&self, issue: &Issue, audit: &AuditEntry) -> Result<bool> {
+        let mut client = self.pool.get().await?;
+        // Nothing the repair re-reads can change underneath it before it's committed.
+        let transaction = client
+            .build_transaction()
+            .isolation_level(IsolationLevel::Serializable)
+            .start()
+            .await?;
+
+        // Each statement only makes its change if the issue still holds.
+        let stmt = match issue {
+            Issue::OrphanJob { .. } => {
+                "DELETE FROM jobs_q WHERE id = $1
+                 AND NOT EXISTS (SELECT 1 FROM films WHERE name = jobs_q.film_name);"
+            }
+            Issue::StaleJob { .. } => {
+                "DELETE FROM jobs_q WHERE id = $1
+                 AND EXISTS (
+                     SELECT 1 FROM films as f JOIN roles as r ON f.roles_id = r.id
+                     WHERE f.name = jobs_q.film_name AND r.current <> jobs_q.role
+                 );"
+            }
+            Issue::HeldJob { .. } => {
+                "DELETE FROM jobs_q WHERE id = $1
+                 AND EXISTS (SELECT 1 FROM students WHERE current_film = jobs_q.film_name);"
+            }
+            Issue::DuplicateJob { .. } => {
+                "DELETE FROM jobs_q WHERE id = $1
+                 AND EXISTS (
+                     SELECT 1 FROM jobs_q as o
+                     WHERE o.film_name = jobs_q.film_name
+                     AND o.role = jobs_q.role
+                     AND o.id <> jobs_q.id
+                 );"
+            }
+            Issue::AssignedWaiter { .. } => {
+                "DELETE FROM wait_q WHERE id = $1
+                 AND EXISTS (
+                     SELECT 1 FROM students
+                     WHERE slack_id = wait_q.student_slack_id AND current_film IS NOT NULL
+                 );"
+            }
+            Issue::UnknownFilm { .. } => {
+                "UPDATE students
+                 SET current_film = NULL, assigned_at = NULL, escalation = 0, paused = FALSE
+                 WHERE slack_id = $1 AND current_film = $2
+                 AND NOT EXISTS (SELECT 1 FROM films WHERE name = $2);"
+            }
+            Issue::Unqueued { .. } => {
+                "SELECT f.id, f.name, f.priority, f.group_number, f.class, f.due_date,
+                        f.ae_hours, f.editor_hours, f.sound_hours, f.finish_hours, f.queued_at,
+                        r.ae, r.editor, r.sound, r.finish, r.current
+                 FROM films as f, film_roles as r
+                 WHERE f.name = $1
+                 AND f.roles_id = r.id
+                 AND r.current <> $2
+                 AND NOT EXISTS (SELECT 1 FROM jobs_q WHERE film_name = f.name)
+                 AND NOT EXISTS (SELECT 1 FROM students WHERE current_film = f.name);"
+            }
+            Issue::MissingHistory { .. } | Issue::SharedFilm { .. } => return Ok(false),
+        };
+        let stmt = transaction.prepare_cached(stmt).await?;
+
+        let repaired = match issue {
+            Issue::OrphanJob { id, .. }
+            | Issue::StaleJob { id, .. }
+            | Issue::HeldJob { id, .. }
+            | Issue::DuplicateJob { id, .. }
+            | Issue::AssignedWaiter { id, .. } => transaction.execute(&stmt, &[id]).await? > 0,
+            Issue::UnknownFilm { student, film } => {
+                transaction.execute(&stmt, &[student, film]).await? > 0
+            }
+            Issue::Unqueued { film, .. } => {
+                let done = Role::Done.as_ref();
+                match transaction.query_opt(&stmt, &[film, &done]).await? {
+                    Some(row) => {
+                        let film = format_row_into_film(row)?;
+                        insert_job(&*transaction, &queue::new_job(&film, "")).await?;
+                        true
+                    }
+                    None => false,
+                }
+            }
+            Issue::MissingHistory { .. } | Issue::SharedFilm { .. } => false,
+        };
+        if repaired {
+            insert_audit(&*transaction, audit).await?;
+            transaction.commit().await?;
+        }
+
+        Ok(repaired)
+    }
+
+    async fn send_back(&self, film: &Film, revision: &Revision, audit: &AuditEntry) -> Result<()> {
+        let mut client = self.pool.get().await?;
+        let transaction = client.transaction().await?;
+
+        update_film_roles(&*transaction, film).await?;
+
+        let stmt = "UPDATE students SET paused = TRUE WHERE slack_id = $1;";
+        let stmt = transaction.prepare_cached(stmt).await?;
+        transaction.query(&stmt, &[&revision.from]).await?;
+
+        let stmt = "
+            UPDATE assignments
+            SET outcome = $3
+            WHERE film_id = $1
+            AND role = $2
+            AND outcome = $4;";
+        let stmt = transaction.prepare_cached(stmt).await?;
+        let (sent_back, delivered) = (Outcome::SentBack.as_ref(), Outcome::Delivered.as_ref());
+        #[rustfmt::skip]
+        transaction.query(&stmt, &[
+            &film.id, &revision.role.as_ref(), &sent_back, &delivered,
+        ]).await?;
+
+        let stmt = "
+            INSERT INTO revisions(id, film_id, role, from_student, to_student, note, created_at)
+            VALUES($1,
+                   (SELECT id FROM films WHERE name = $2),
+                   $3,
+                   (SELECT id FROM students WHERE slack_id = $4),
+                   (SELECT id FROM students WHERE slack_id = $5),
+                   $6, $7);";
+        let stmt = transaction.prepare_cached(stmt).await?;
+
+        #[rustfmt::skip]
+        transaction.query(&stmt, &[
+            &revision.id,
+            &revision.film_name,
+            &revision.role.as_ref(),
+            &revision.from,
+            &revision.to,
+            &revision.note,
+            &revision.created_at,
+        ]).await?;
+        insert_audit(&*transaction, audit).await?;
+
+        transaction.commit().await?;
+
+        info!("Sent {} back to {}", film.name, revision.role.as_ref());
+
+        Ok(())
+    }
+
+    async fn get_revision(&self, slack_id: &str) -> Result<Option<Revision>> {
+        let client = self.pool.get().await?;
+
+        let stmt = "
+            SELECT v.id, f.name as film_name, v.role, fs.slack_id as from_slack_id,
+                   ts.slack_id as to_slack_id, v.note, v.created_at, v.resolved_at
+            FROM revisions as v, films as f, students as fs, students as ts
+            WHERE ts.slack_id = $1
+            AND v.resolved_at IS NULL
+            AND v.film_id = f.id
+            AND v.from_student = fs.id
+            AND v.to_student = ts.id
+            ORDER BY v.created_at
+            LIMIT 1;";
         let stmt = client.prepare_cached(stmt).await?;
 
-        client.query(&stmt, &[&id]).await?;
+        let rows = client.query(&stmt, &[&slack_id]).await?;
+        rows.into_iter()
+            .next()
+            .map(format_row_into_revision)
+            .transpose()
+    }
+
+    async fn resolve_revision(
+        &self,
+        film: &Film,
+        revision: &Revision,
+        delivery: Option<&Delivery>,
+        audit: &AuditEntry,
+    ) -> Result<()> {
+        let mut client = self.pool.get().await?;
+        let transaction = client.transaction().await?;
+
+        update_film_roles(&*transaction, film).await?;
+        if let Some(delivery) = delivery {
+            insert_delivery(&*transaction, delivery).await?;
+        }
+
+        let stmt = "UPDATE revisions SET resolved_at = CURRENT_TIMESTAMP WHERE id = $1;";
+        let stmt = transaction.prepare_cached(stmt).await?;
+        transaction.query(&stmt, &[&revision.id]).await?;
+
+        // The fix counts as its own turn on the stage, starting when the film was sent back.
+        let stmt = "
+            INSERT INTO assignments(id, student_id, film_id, role, assigned_at, delivered_at,
+                                    outcome)
+            VALUES($1,
+                   (SELECT id FROM students WHERE slack_id = $2),
+                   $3, $4, $5, CURRENT_TIMESTAMP, $6);";
+        let stmt = transaction.prepare_cached(stmt).await?;
+        #[rustfmt::skip]
+        transaction.query(&stmt, &[
+            &Uuid::new_v4(),
+            &revision.to,
+            &film.id,
+            &revision.role.as_ref(),
+            &revision.created_at,
+            &Outcome::Delivered.as_ref(),
+        ]).await?;
+
+        // The clock restarts now that the film's back with them.
+        let stmt = "
+            UPDATE students
+            SET paused = FALSE, assigned_at = CURRENT_TIMESTAMP, escalation = 0
+            WHERE slack_id = $1
+            AND current_film = $2;";
+        let stmt = transaction.prepare_cached(stmt).await?;
+        transaction
+            .query(&stmt, &[&revision.from, &film.name])
+            .await?;
+        insert_audit(&*transaction, audit).await?;
+
+        transaction.commit().await?;
+
+        info!("Resolved revision of {}", film.name);
 
         Ok(())
     }
 
+    async fn get_delivery(&self, film_name: &str, role: Role) -> Result<Option<Delivery>> {
+        let client = self.pool.get().await?;
+
+        let stmt = "
+            SELECT d.id, f.name as film_name, d.role, s.slack_id, d.links, d.notes, d.created_at
+            FROM deliveries as d, films as f, students as s
+            WHERE f.name = $1
+            AND d.role = $2
+            AND d.film_id = f.id
+            AND d.student_id = s.id
+            ORDER BY d.created_at DESC
+            LIMIT 1;";
+        let stmt = client.prepare_cached(stmt).await?;
+
+        let rows = client.query(&stmt, &[&film_name, &role.as_ref()]).await?;
+        rows.into_iter()
+            .next()
+            .map(format_row_into_delivery)
+            .transpose()
+    }
+
+    async fn get_film_history(&self, film_name: &str) -> Result<Vec<Assignment>> {
+        let client = self.pool.get().await?;
+
+        let stmt = "
+            SELECT a.id, f.name as film_name, a.role, a.student_id, s.slack_id,
+                   s.name as student_name, a.assigned_at, a.delivered_at, a.outcome,
+                   a.queued_at, a.waiting_since
+            FROM assignments as a, films as f, students as s
+            WHERE f.name = $1
+            AND a.film_id = f.id
+            AND a.student_id = s.id
+            ORDER BY a.assigned_at;";
+        let stmt = client.prepare_cached(stmt).await?;
+
+        let rows = client.query(&stmt, &[&film_name]).await?;
+        rows.into_iter().map(format_row_into_assignment).collect()
+    }
+
+    async fn get_student_history(&self, slack_id: &str) -> Result<Vec<Assignment>> {
+        let client = self.pool.get().await?;
+
+        let stmt = "
+            SELECT a.id, f.name as film_name, a.role, a.student_id, s.slack_id,
+                   s.name as student_name, a.assigned_at, a.delivered_at, a.outcome,
+                   a.queued_at, a.waiting_since
+            FROM assignments as a, films as f, students as s
+            WHERE s.slack_id = $1
+            AND a.film_id = f.id
+            AND a.student_id = s.id
+            ORDER BY a.assigned_at;";
+        let stmt = client.prepare_cached(stmt).await?;
+
+        let rows = client.query(&stmt, &[&slack_id]).await?;
+        rows.into_iter().map(format_row_into_assignment).collect()
+    }
+
+    async fn list_assignments(&self) -> Result<Vec<Assignment>> {
+        let client = self.pool.get().await?;
+
+        let stmt = "
+            SELECT a.id, f.name as film_name, a.role, a.student_id, s.slack_id,
+                   s.name as student_name, a.assigned_at, a.delivered_at, a.outcome,
+                   a.queued_at, a.waiting_since
+            FROM assignments as a, films as f, students as s
+            WHERE a.film_id = f.id
+            AND a.student_id = s.id
+            ORDER BY a.assigned_at;";
+        let stmt = client.prepare_cached(stmt).await?;
+
+        let rows = client.query(&stmt, &[]).await?;
+        rows.into_iter().map(format_row_into_assignment).collect()
+    }
+
+    async fn insert_audit(&self, entry: &AuditEntry) -> Result<()> {
+        let client = self.pool.get().await?;
+        insert_audit(&**client, entry).await
+    }
+
+    async fn get_audit_log(
+        &self,
+        student: Option<&str>,
+        film: Option<&str>,
+        limit: i64,
+    ) -> Result<Vec<AuditEntry>> {
+        let client = self.pool.get().await?;
+
+        let stmt = "
+            SELECT * FROM audit_log
+            WHERE ($1::TEXT IS NULL OR student = $1)
+            AND ($2::TEXT IS NULL OR film = $2)
+            ORDER BY created_at DESC
+            LIMIT $3;";
+        let stmt = client.prepare_cached(stmt).await?;
+
+        let rows = client.query(&stmt, &[&student, &film, &limit]).await?;
+        rows.into_iter().map(format_row_into_audit_entry).collect()
+    }
+
+    // ------------- Events ------------- //
+
+    async fn insert_event(&self, event: &PendingEvent, claimed_by: &str) -> Result<bool> {
+        let client = self.pool.get().await?;
+
+        let stmt = "
+            INSERT INTO slack_events(id, slack_user, payload, created_at, claimed_by, claimed_at)
+            VALUES($1, $2, $3, $4, $5, $6)
+            ON CONFLICT (id) DO NOTHING;";
+        let stmt = client.prepare_cached(stmt).await?;
+
+        #[rustfmt::skip]
+        let inserted = client.execute(&stmt, &[
+            &event.id,
+            &event.user,
+            &event.payload,
+            &event.created_at,
+            &claimed_by,
+            &Utc::now(),
+        ]).await?;
+
+        Ok(inserted > 0)
+    }
+
+    async fn list_events(&self) -> Result<Vec<PendingEvent>> {
+        let client = self.pool.get().await?;
+
+        let stmt = "SELECT * FROM slack_events WHERE handled_at IS NULL ORDER BY created_at;";
+        let stmt = client.prepare_cached(stmt).await?;
+
+        let rows = client.query(&stmt, &[]).await?;
+        Ok(rows.into_iter().map(format_row_into_event).collect())
+    }
+
+    async fn claim_events(
+        &self,
+        claimed_by: &str,
+        expired: DateTime<Utc>,
+    ) -> Result<Vec<PendingEvent>> {
+        let client = self.pool.get().await?;
+
+        // Rows another server's claiming at the same time are skipped, rather than claimed by
+        // both once it's done.
+        let stmt = "
+            WITH claimable AS (
+                SELECT id FROM slack_events
+                WHERE handled_at IS NULL AND (claimed_at IS NULL OR claimed_at < $2)
+                FOR UPDATE SKIP LOCKED
+            )
+            UPDATE slack_events e SET claimed_by = $1, claimed_at = $3
+            FROM claimable c WHERE e.id = c.id
+            RETURNING e.*;";
+        let stmt = client.prepare_cached(stmt).await?;
+
+        let rows = client
+            .query(&stmt, &[&claimed_by, &expired, &Utc::now()])
+            .await?;
+        let mut events: Vec<_> = rows.into_iter().map(format_row_into_event).collect();
+        events.sort_by_key(|e| e.created_at);
+        Ok(events)
+    }
+
+    async fn renew_events(&self, claimed_by: &str) -> Result<()> {
+        let client = self.pool.get().await?;
+
+        let stmt = "
+            UPDATE slack_events SET claimed_at = $2
+            WHERE claimed_by = $1 AND handled_at IS NULL;";
+        let stmt = client.prepare_cached(stmt).await?;
+        client.execute(&stmt, &[&claimed_by, &Utc::now()]).await?;
+
+        Ok(())
+    }
+
+    async fn release_events(&self, claimed_by: &str) -> Result<u64> {
+        let client = self.pool.get().await?;
+
+        let stmt = "
+            UPDATE slack_events SET claimed_by = NULL, claimed_at = NULL
+            WHERE claimed_by = $1 AND handled_at IS NULL;";
+        let stmt = client.prepare_cached(stmt).await?;
+
+        Ok(client.execute(&stmt, &[&claimed_by]).await?)
+    }
+
+    async fn finish_event(&self, id: &str, forget_before: DateTime<Utc>) -> Result<()> {
+        let client = self.pool.get().await?;
+
+        let stmt = "UPDATE slack_events SET handled_at = $2 WHERE id = $1;";
+        let stmt = client.prepare_cached(stmt).await?;
+        client.execute(&stmt, &[&id, &Utc::now()]).await?;
+
+        let stmt = "DELETE FROM slack_events WHERE handled_at < $1;";
+        let stmt = client.prepare_cached(stmt).await?;
+        client.execute(&stmt, &[&forget_before]).await?;
+
+        Ok(())
+    }
+
+    // ------------- Rate limits ------------- //
+
+    async fn list_uses(
+        &self,
+        slack_id: &str,
+        action: &str,
+        since: DateTime<Utc>,
+    ) -> Result<Vec<DateTime<Utc>>> {
+        let client = self.pool.get().await?;
+
+        let stmt = "
+            SELECT used_at FROM rate_limit_uses
+            WHERE slack_id = $1 AND action = $2 AND used_at > $3
+            ORDER BY used_at;";
+        let stmt = client.prepare_cached(stmt).await?;
+
+        let rows = client.query(&stmt, &[&slack_id, &action, &since]).await?;
+        Ok(rows.into_iter().map(|row| row.get("used_at")).collect())
+    }
+
+    async fn insert_use(
+        &self,
+        slack_id: &str,
+        action: &str,
+        at: DateTime<Utc>,
+        expired: DateTime<Utc>,
+    ) -> Result<()> {
+        let mut client = self.pool.get().await?;
+        let transaction = client.transaction().await?;
+
+        let stmt = "
+            DELETE FROM rate_limit_uses
+            WHERE slack_id = $1 AND action = $2 AND used_at <= $3;";
+        let stmt = transaction.prepare_cached(stmt).await?;
+        transaction
+            .execute(&stmt, &[&slack_id, &action, &expired])
+            .await?;
+
+        let stmt = "INSERT INTO rate_limit_uses(slack_id, action, used_at) VALUES($1, $2, $3);";
+        let stmt = transaction.prepare_cached(stmt).await?;
+        transaction
+            .execute(&stmt, &[&slack_id, &action, &at])
+            .await?;
+
+        transaction.commit().await?;
+        Ok(())
+    }
+
+    async fn migrate(&self) -> Result<()> {
+        // Skip the psql commands which create and connect to the database.
+        let start = SCHEMA.find("DO $schema$").unwrap_or_default();
+        let client = self.pool.get().await?;
+        client.batch_execute(&SCHEMA[start..]).await?;
+
+        info!("Applied schema");
+        Ok(())
+    }
+
+    async fn schema_version(&self) -> Result<SchemaVersion> {
+        let client = self.pool.get().await?;
+
+        // Databases from before versions were recorded have no table to read it from.
+        let stmt = "SELECT to_regclass('schema_version') IS NOT NULL;";
+        let stmt = client.prepare_cached(stmt).await?;
+        let recorded: bool = client.query_one(&stmt, &[]).await?.get(0);
+
+        let mut applied = 0;
+        if recorded {
+            let stmt = "SELECT MAX(version) FROM schema_version;";
+            let stmt = client.prepare_cached(stmt).await?;
+            let version: Option<i32> = client.query_one(&stmt, &[]).await?.get(0);
+            applied = version.unwrap_or_default().into();
+        }
+
+        Ok(SchemaVersion {
+            applied,
+            latest: SCHEMA_VERSION,
+        })
+    }
+
+    async fn ping(&self) -> Result<()> {
+        let client = self.pool.get().await?;
+        let stmt = client.prepare_cached("SELECT 1;").await?;
+        client.query_one(&stmt, &[]).await?;
+        Ok(())
+    }
+
+    fn pool_status(&self) -> Option<deadpool_postgres::Status> {
+        Some(self.pool.status())
+    }
+
     async fn drop_db(&self) -> Result<()> {
         let environment = std::env::var("ENVIRONMENT")?;
         if environment != "test" {
@@ -496,6 +1163,229 @@ impl Client for PostgresClient {
 
 // ------------- Helpers ------------- //
 
+/// Only a broken unique constraint means the row already exists. Anything else is a real error.
+fn duplicate(e: tokio_postgres::Error, name: &str) -> Error {
+    match e.code() {
+        Some(&SqlState::UNIQUE_VIOLATION) => Error::Duplicate(name.to_string()),
+        _ => e.into(),
+    }
+}
+
+/// Writes a film's roles and current role. Works both inside and outside of a transaction.
+async fn update_film_roles<C: GenericClient>(client: &C, film: &Film) -> Result<()> {
+    let stmt = "
+        UPDATE roles
+        SET ae = $2, editor = $3, sound = $4, finish = $5, current = $6
+        WHERE id = (
+            SELECT roles_id FROM films WHERE name = $1);";
+    let stmt = client.prepare(stmt).await?;
+
+    #[rustfmt::skip]
+    client.query(&stmt, &[
+        &film.name,
+        &film.roles.ae,
+        &film.roles.editor,
+        &film.roles.sound,
+        &film.roles.finish,
+        &film.current_role.as_ref(),
+    ]).await?;
+
+    Ok(())
+}
+
+/// Writes a student's roles and assignment. Works both inside and outside of a transaction.
+async fn write_student<C: GenericClient>(client: &C, student: &Student) -> Result<()> {
+    let stmt = "
+        UPDATE roles
+        SET ae = $2, editor = $3, sound = $4, finish = $5, current = $6
+        WHERE id = (
+            SELECT roles_id FROM students WHERE id = $1);";
+    let stmt = client.prepare(stmt).await?;
+
+    #[rustfmt::skip]
+    client.query(&stmt, &[
+        &student.id,
+        &student.roles.ae,
+        &student.roles.editor,
+        &student.roles.sound,
+        &student.roles.finish,
+        &student.current_role.as_ref(),
+    ]).await?;
+
+    let stmt = "
+        UPDATE students
+        SET current_film = $2, slack_id = $3, assigned_at = $4, escalation = $5,
+            paused = $6
+        WHERE id = $1";
+    let stmt = client.prepare(stmt).await?;
+
+    let (id, film, slack_id) = (&student.id, &student.current_film, &student.slack_id);
+    let (assigned_at, escalation) = (&student.assigned_at, &student.escalation);
+    #[rustfmt::skip]
+    client.query(&stmt, &[
+        &id, &film, &slack_id, &assigned_at, &escalation, &student.paused,
+    ]).await?;
+
+    Ok(())
+}
+
+/// Closes out the history of the film a student is currently working on.
+async fn end_assignment<C: GenericClient>(
+    client: &C,
+    student: &Student,
+    outcome: Outcome,
+) -> Result<()> {
+    let stmt = "
+        UPDATE assignments
+        SET delivered_at = CURRENT_TIMESTAMP, outcome = $3
+        WHERE student_id = $1
+        AND film_id = (SELECT id FROM films WHERE name = $2)
+        AND outcome IS NULL;";
+    let stmt = client.prepare(stmt).await?;
+
+    #[rustfmt::skip]
+    client.query(&stmt, &[
+        &student.id, &student.current_film, &outcome.as_ref(),
+    ]).await?;
+
+    Ok(())
+}
+
+/// Sets a student's current film, along with its students_films record, and starts its history.
+/// `queued` is when the job joined the jobs_q and the student the wait_q, where they did.
+async fn set_assignment<C: GenericClient>(
+    client: &C,
+    student: &Student,
+    film: &Film,
+    queued: (Option<DateTime<Utc>>, Option<DateTime<Utc>>),
+) -> Result<()> {
+    let stmt = "
+        UPDATE students
+        SET current_film = $2, assigned_at = CURRENT_TIMESTAMP, escalation = 0,
+            paused = FALSE
+        WHERE id = $1;";
+    let stmt = client.prepare(stmt).await?;
+    client.query(&stmt, &[&student.id, &film.name]).await?;
+
+    let role = film.current_role.as_ref();
+    let stmt = "
+        INSERT INTO students_films(student_id, film_id, role) VALUES($1, $2, $3)
+        ON CONFLICT DO NOTHING;";
+    let stmt = client.prepare(stmt).await?;
+    client.query(&stmt, &[&student.id, &film.id, &role]).await?;
+
+    let stmt = "
+        INSERT INTO assignments(id, student_id, film_id, role, queued_at, waiting_since)
+        VALUES($1, $2, $3, $4, $5, $6);";
+    let stmt = client.prepare(stmt).await?;
+    #[rustfmt::skip]
+    client.query(&stmt, &[
+        &Uuid::new_v4(), &student.id, &film.id, &role, &queued.0, &queued.1,
+    ]).await?;
+    Ok(())
+}
+
+/// Clears a student's current film, along with its students_films record. Fails if the student
+/// has since moved on from the film, so a stale copy of them can't undo newer work.
+async fn clear_assignment<C: GenericClient>(
+    client: &C,
+    student: &Student,
+    film: &Film,
+) -> Result<()> {
+    let stmt = "
+        UPDATE students
+        SET current_film = NULL, assigned_at = NULL, escalation = 0, paused = FALSE
+        WHERE id = $1 AND current_film = $2;";
+    let stmt = client.prepare(stmt).await?;
+    if client.execute(&stmt, &[&student.id, &film.name]).await? == 0 {
+        return Err(super::not_assigned(student, film));
+    }
+
+    // The student never finished the film, so it doesn't count as worked.
+    let stmt = "DELETE FROM students_films WHERE student_id = $1 AND film_id = $2;";
+    let stmt = client.prepare(stmt).await?;
+    client.execute(&stmt, &[&student.id, &film.id]).await?;
+    Ok(())
+}
+
+/// Inserts a job into the jobs_q. Works both inside and outside of a transaction.
+async fn insert_audit<C: GenericClient>(client: &C, entry: &AuditEntry) -> Result<()> {
+    let stmt = "
+        INSERT INTO audit_log(id, action, actor, source, student, film, before, after,
+                              created_at)
+        VALUES($1, $2, $3, $4, $5, $6, $7, $8, $9);";
+    let stmt = client.prepare(stmt).await?;
+
+    #[rustfmt::skip]
+    client.query(&stmt, &[
+        &entry.id,
+        &entry.action.as_ref(),
+        &entry.actor,
+        &entry.source,
+        &entry.student,
+        &entry.film,
+        &entry.before,
+        &entry.after,
+        &entry.created_at,
+    ]).await?;
+
+    Ok(())
+}
+
+async fn insert_delivery<C: GenericClient>(client: &C, delivery: &Delivery) -> Result<()> {
+    let stmt = "
+        INSERT INTO deliveries(id, film_id, role, student_id, links, notes, created_at)
+        VALUES($1,
+               (SELECT id FROM films WHERE name = $2),
+               $3,
+               (SELECT id FROM students WHERE slack_id = $4),
+               $5, $6, $7);";
+    let stmt = client.prepare(stmt).await?;
+
+    #[rustfmt::skip]
+    client.query(&stmt, &[
+        &delivery.id,
+        &delivery.film_name,
+        &delivery.role.as_ref(),
+        &delivery.student,
+        &delivery.links,
+        &delivery.notes,
+        &delivery.created_at,
+    ]).await?;
+
+    info!(
+        "Recorded delivery of {} for {}",
+        delivery.role.as_ref(),
+        delivery.film_name
+    );
+
+    Ok(())
+}
+
+async fn insert_job<C: GenericClient>(client: &C, q: &QueueItem) -> Result<()> {
+    let stmt = "
+        INSERT INTO jobs_q(id, student_slack_id, film_name, role, priority, deadline,
+                           group_number, class, created_at)
+        VALUES($1, $2, $3, $4, $5, $6, $7, $8, $9);";
+    let stmt = client.prepare(stmt).await?;
+    let p = q.priority.map(|a| a.as_ref().to_string());
+
+    #[rustfmt::skip]
+    client.query(&stmt, &[
+        &q.id,
+        &q.student_slack_id,
+        &q.film_name,
+        &q.role.as_ref(),
+        &p,
+        &q.deadline,
+        &q.group_number,
+        &q.class,
+        &q.created_at,
+    ]).await?;
+
+    Ok(())
+}
+
 fn format_row_into_film(row: Row) -> Result<Film> {
     trace!("formatting film row {row:?}");
     let id: Uuid = row.get("id");
@@ -503,6 +1393,9 @@ fn format_row_into_film(row: Row) -> Result<Film> {
     let priority = Priority::from_str(row.get("priority"))?;
     let current_role = Role::from_str(row.get("current"))?;
     let group_number: i32 = row.get("group_number");
+    let class: String = row.get("class");
+    let due_date: Option<DateTime<Utc>> = row.get("due_date");
+    let queued_at: Option<DateTime<Utc>> = row.get("queued_at");
 
     let ae: Option<String> = row.get("ae");
     let editor: Option<String> = row.get("editor");
@@ -510,6 +1403,12 @@ fn format_row_into_film(row: Row) -> Result<Film> {
     let finish: Option<String> = row.get("finish");
 
     let roles = Roles::new(ae, editor, sound, finish);
+    let stage_hours = StageHours::new(
+        row.get("ae_hours"),
+        row.get("editor_hours"),
+        row.get("sound_hours"),
+        row.get("finish_hours"),
+    );
     Ok(Film {
         id,
         name,
@@ -517,6 +1416,80 @@ fn format_row_into_film(row: Row) -> Result<Film> {
         priority,
         roles,
         group_number,
+        class,
+        due_date,
+        stage_hours,
+        queued_at,
+    })
+}
+
+fn format_row_into_audit_entry(row: Row) -> Result<AuditEntry> {
+    trace!("formatting audit row {row:?}");
+    Ok(AuditEntry {
+        id: row.get("id"),
+        action: AuditAction::from_str(row.get("action"))?,
+        actor: row.get("actor"),
+        source: row.get("source"),
+        student: row.get("student"),
+        film: row.get("film"),
+        before: row.get("before"),
+        after: row.get("after"),
+        created_at: row.get("created_at"),
+    })
+}
+
+fn format_row_into_event(row: Row) -> PendingEvent {
+    trace!("formatting event row {row:?}");
+    PendingEvent {
+        id: row.get("id"),
+        user: row.get("slack_user"),
+        payload: row.get("payload"),
+        created_at: row.get("created_at"),
+    }
+}
+
+fn format_row_into_assignment(row: Row) -> Result<Assignment> {
+    trace!("formatting assignment row {row:?}");
+    let outcome: Option<&str> = row.get("outcome");
+    Ok(Assignment {
+        id: row.get("id"),
+        film_name: row.get("film_name"),
+        role: Role::from_str(row.get("role"))?,
+        student_id: row.get("student_id"),
+        slack_id: row.get("slack_id"),
+        student_name: row.get("student_name"),
+        assigned_at: row.get("assigned_at"),
+        delivered_at: row.get("delivered_at"),
+        outcome: outcome.map(Outcome::from_str).transpose()?,
+        queued_at: row.get("queued_at"),
+        waiting_since: row.get("waiting_since"),
+    })
+}
+
+fn format_row_into_delivery(row: Row) -> Result<Delivery> {
+    trace!("formatting delivery row {row:?}");
+    Ok(Delivery {
+        id: row.get("id"),
+        film_name: row.get("film_name"),
+        role: Role::from_str(row.get("role"))?,
+        student: row.get("slack_id"),
+        links: row.get("links"),
+        notes: row.get("notes"),
+        created_at: row.get("created_at"),
+    })
+}
+
+fn format_row_into_revision(row: Row) -> Result<Revision> {
+    trace!("formatting revision row {row:?}");
+    Ok(Revision {
+        id: row.get("id"),
+        film_name: row.get("film_name"),
+        role: Role::from_str(row.get("role"))?,
+        from: row.get("from_slack_id"),
+        to: row.get("to_slack_id"),
+        note: row.get("note"),
+        created_at: row.get("created_at"),
+        resolved_at: row.get("resolved_at"),
     })
 }
 
@@ -534,6 +1507,9 @@ fn format_row_into_student(row: Row) -> Result<Student> {
     let finish: Option<String> = row.get("finish");
     let group_number: i32 = row.get("group_number");
     let class: String = row.get("class");
+    let assigned_at: Option<DateTime<Utc>> = row.get("assigned_at");
+    let escalation: i32 = row.get("escalation");
+    let paused: bool = row.get("paused");
 
     let roles = Roles::new(ae, editor, sound, finish);
 
@@ -541,6 +1517,7 @@ fn format_row_into_student(row: Row) -> Result<Student> {
     let student = Student { 
         id, name, slack_id, current_film, 
         current_role, roles, group_number, class,
+        assigned_at, escalation, paused,
     };
 
     Ok(student)
@@ -0,0 +1,1550 @@
+use std::{
+    collections::HashSet,
+    str::FromStr,
+    sync::{Arc, Mutex, PoisonError},
+};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use color_eyre::eyre::eyre;
+use rusqlite::{params, Connection, Params, Row, TransactionBehavior};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::{
+    integrity::Issue,
+    queue::{self, QueueItem},
+    store::{Client, SchemaVersion},
+    Error, Result,
+};
+use models::{
+    Assignment, AuditAction, AuditEntry, Delivery, Film, Outcome, PendingEvent, Priority, Revision,
+    Role, Roles, StageHours, Student,
+};
+
+/// Each step brings the database up to the next version, which is kept in `user_version`.
+/// Add new steps to the end, and never change one that's been released.
+const MIGRATIONS: &[Migration] = &[
+    Migration::Sql(include_str!("../../../../schema.sqlite.sql")),
+    // Slack events which have been acknowledged but not handled yet, so they survive a restart.
+    Migration::Sql(
+        "CREATE TABLE IF NOT EXISTS slack_events (
+            id              TEXT PRIMARY KEY,
+            slack_user      TEXT NOT NULL,
+            payload         TEXT NOT NULL,
+            created_at      TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+        );",
+    ),
+    // When students took actions which are rate limited, so the limits survive a restart.
+    Migration::Sql(
+        "CREATE TABLE IF NOT EXISTS rate_limit_uses (
+            slack_id        TEXT NOT NULL,
+            action          TEXT NOT NULL,
+            used_at         TEXT NOT NULL
+        );
+        CREATE INDEX IF NOT EXISTS rate_limit_uses_idx ON rate_limit_uses (slack_id, action);",
+    ),
+    // When each assignment's job joined the jobs_q, and its student the wait_q, for analytics.
+    Migration::AddColumns(&[
+        ("assignments", "queued_at", "TEXT"),
+        ("assignments", "waiting_since", "TEXT"),
+    ]),
+    // Handled Slack events are kept so retries are still ignored, and claimed by whichever
+    // server is handling them.
+    Migration::AddColumns(&[
+        ("slack_events", "handled_at", "TEXT"),
+        ("slack_events", "claimed_by", "TEXT"),
+        ("slack_events", "claimed_at", "TEXT"),
+    ]),
+];
+
+/// One step in `MIGRATIONS`. Every step can be applied again without harm.
+enum Migration {
+    Sql(&'static str),
+    /// (table, column, type) to add. SQLite has no `ADD COLUMN IF NOT EXISTS`, so columns which
+    /// are already there are skipped.
+    AddColumns(&'static [(&'static str, &'static str, &'static str)]),
+}
+
+const FILM_COLUMNS: &str = "
+    f.id, f.name, f.priority, f.group_number, f.class, f.due_date,
+    f.ae_hours, f.editor_hours, f.sound_hours, f.finish_hours, f.queued_at,
+    r.ae, r.editor, r.sound, r.finish, r.current";
+
+const STUDENT_COLUMNS: &str = "
+    s.id, s.name, s.slack_id, s.current_film,
+    s.group_number, s.class, s.assigned_at, s.escalation, s.paused,
+    r.ae, r.editor, r.sound, r.finish, r.current";
+
+/// Internal SQLite client.
+///
+/// SQLite only allows one writer at a time, so a single connection is shared behind a lock.
+/// Queries run on tokio's blocking threads.
+#[derive(Clone)]
+pub struct SqliteClient {
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl SqliteClient {
+    /// Opens or creates the database file at `path`, and brings its schema up to date.
+    pub(crate) fn open(path: &str) -> Result<Self> {
+        let mut conn = Connection::open(path)?;
+        conn.pragma_update(None, "foreign_keys", true)?;
+        migrate(&mut conn)?;
+        info!("Opened sqlite database at {path}");
+
+        Ok(Self {
+            conn: Arc::new(Mutex::new(conn)),
+        })
+    }
+
+    /// Runs `f` with the connection on a blocking thread.
+    async fn run<T, F>(&self, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> Result<T> + Send + 'static,
+    {
+        let conn = self.conn.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut conn = conn.lock().unwrap_or_else(PoisonError::into_inner);
+            f(&mut conn)
+        })
+        .await?
+    }
+}
+// Axum requires that we implement debug to use this in state.
+impl std::fmt::Debug for SqliteClient {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SqliteClient")
+            .field("conn", &"<connection>")
+            .finish()
+    }
+}
+
+#[async_trait]
+impl Client for SqliteClient {
+    // ------------- Films ------------- //
+
+    async fn list_films(&self) -> Result<Vec<Film>> {
+        info!("Retrieving all films");
+        self.run(|conn| {
+            let stmt = format!(
+                "SELECT {FILM_COLUMNS}
+                FROM films as f, film_roles as r
+                WHERE f.roles_id = r.id;"
+            );
+            query(conn, &stmt, [], format_row_into_film)
+        })
+        .await
+    }
+
+    async fn get_film(&self, name: &str) -> Result<Option<Film>> {
+        let name = name.to_string();
+        self.run(move |conn| {
+            let stmt = format!(
+                "SELECT {FILM_COLUMNS}
+                FROM films as f, film_roles as r
+                WHERE f.name = ?1
+                AND f.roles_id = r.id;"
+            );
+            let film = query(conn, &stmt, [&name], format_row_into_film)?.pop();
+            if film.is_some() {
+                info!("Retrieved film {name}");
+            }
+            Ok(film)
+        })
+        .await
+    }
+
+    async fn insert_film(&self, film: &Film) -> Result<Film> {
+        let film = film.clone();
+        self.run(move |conn| {
+            let transaction = conn.transaction()?;
+
+            let role_id = Uuid::new_v4();
+            transaction.execute("INSERT INTO roles(id) VALUES(?1);", [&role_id])?;
+
+            let inserted = Film {
+                id: Uuid::new_v4(),
+                roles: Roles::default(),
+                current_role: Role::default(),
+                ..film
+            };
+            let hours = &inserted.stage_hours;
+
+            // The whole insert should fail if the film already exists.
+            let stmt = "
+                INSERT INTO films(id, name, priority, roles_id, group_number, class, due_date,
+                                  ae_hours, editor_hours, sound_hours, finish_hours)
+                VALUES(?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11);";
+            #[rustfmt::skip]
+            let res = transaction.execute(stmt, params![
+                inserted.id, inserted.name, inserted.priority.as_ref(), role_id,
+                inserted.group_number, inserted.class, inserted.due_date,
+                hours.ae, hours.editor, hours.sound, hours.finish,
+            ]);
+            if let Err(e) = res {
+                return Err(duplicate(e, &inserted.name));
+            }
+            transaction.commit()?;
+
+            info!("Inserted film: {}", inserted.name);
+
+            Ok(inserted)
+        })
+        .await
+    }
+
+    async fn update_film(&self, film: &Film) -> Result<()> {
+        let film = film.clone();
+        self.run(move |conn| {
+            update_film_roles(conn, &film)?;
+            info!("Updated film: {}", film.name);
+            Ok(())
+        })
+        .await
+    }
+
+    // ------------- Junction ------------- //
+
+    async fn get_worked_films(&self, student_id: &Uuid) -> Result<HashSet<Film>> {
+        let student_id = *student_id;
+        self.run(move |conn| {
+            let stmt = format!(
+                "SELECT {FILM_COLUMNS}
+                FROM films as f
+                    JOIN film_roles AS r ON f.roles_id = r.id
+                    JOIN students_films on f.id = students_films.film_id
+                WHERE students_films.student_id = ?1;"
+            );
+            let films = query(conn, &stmt, [student_id], format_row_into_film)?;
+            info!("Retrieved {} worked films", films.len());
+            Ok(films.into_iter().collect())
+        })
+        .await
+    }
+
+    async fn insert_student_films(
+        &self,
+        student_id: &Uuid,
+        film_id: &Uuid,
+        role: Role,
+    ) -> Result<()> {
+        let (student_id, film_id) = (*student_id, *film_id);
+        self.run(move |conn| {
+            let stmt = "INSERT INTO students_films(student_id, film_id, role) VALUES(?1, ?2, ?3);";
+            conn.execute(stmt, params![student_id, film_id, role.as_ref()])?;
+            info!("Inserted into students_films");
+            Ok(())
+        })
+        .await
+    }
+
+    async fn get_films_exclusionary(&self, group: i32, role: Role) -> Result<Vec<Film>> {
+        info!("Retrieving eligible films");
+        let role = role.as_ref().to_lowercase();
+        self.run(move |conn| {
+            let stmt = format!(
+                "SELECT DISTINCT {FILM_COLUMNS}
+                FROM films as f, film_roles as r
+                WHERE f.roles_id = r.id AND f.group_number != ?1 AND r.{role} IS NULL;"
+            );
+            query(conn, &stmt, [group], format_row_into_film)
+        })
+        .await
+    }
+
+    // ------------- Students ------------- //
+
+    async fn list_students(&self) -> Result<Vec<Student>> {
+        info!("Retrieving list of students");
+        self.run(|conn| {
+            let stmt = format!(
+                "SELECT {STUDENT_COLUMNS}
+                FROM students as s, student_roles as r
+                WHERE s.roles_id = r.id;"
+            );
+            query(conn, &stmt, [], format_row_into_student)
+        })
+        .await
+    }
+
+    async fn get_student(&self, slack_id: &str) -> Result<Student> {
+        info!("Retrieving student with id {slack_id}");
+        if let Some(student) = self.find_student(slack_id).await? {
+            return Ok(student);
+        }
+
+        warn!("No user for id {slack_id}. Looking user up instead.");
+        let name = super::lookup_name(slack_id).await?;
+        let student = self
+            .run({
+                let name = name.clone();
+                move |conn| {
+                    let stmt = format!(
+                        "SELECT {STUDENT_COLUMNS}
+                        FROM students as s, student_roles as r
+                        WHERE s.name = ?1
+                        AND s.roles_id = r.id;"
+                    );
+                    Ok(query(conn, &stmt, [&name], format_row_into_student)?.pop())
+                }
+            })
+            .await?;
+        match student {
+            Some(student) => {
+                info!("Retrieved student {}", student.name);
+                Ok(student)
+            }
+            None => self.insert_student(slack_id, &name).await,
+        }
+    }
+
+    async fn find_student(&self, slack_id: &str) -> Result<Option<Student>> {
+        let slack_id = slack_id.to_string();
+        self.run(move |conn| {
+            let stmt = format!(
+                "SELECT {STUDENT_COLUMNS}
+                FROM students as s, student_roles as r
+                WHERE s.slack_id = ?1
+                AND s.roles_id = r.id;"
+            );
+            let student = query(conn, &stmt, [&slack_id], format_row_into_student)?.pop();
+            if let Some(s) = &student {
+                info!("Retrieved student {}", s.name);
+            }
+            Ok(student)
+        })
+        .await
+    }
+
+    async fn insert_student_from_csv(
+        &self,
+        name: &str,
+        group: i32,
+        class: &str,
+    ) -> Result<Student> {
+        let student = Student {
+            name: name.to_string(),
+            group_number: group,
+            class: class.to_string(),
+            ..Default::default()
+        };
+        self.run(move |conn| {
+            let transaction = conn.transaction()?;
+
+            let role_id = Uuid::new_v4();
+            transaction.execute("INSERT INTO roles(id) VALUES(?1);", [&role_id])?;
+
+            // The whole insert should fail if the student already exists.
+            let stmt = "INSERT INTO students(id, name, roles_id, group_number, class)
+                        VALUES(?1, ?2, ?3, ?4, ?5);";
+            #[rustfmt::skip]
+            let res = transaction.execute(stmt, params![
+                student.id, student.name, role_id, student.group_number, student.class,
+            ]);
+            if let Err(e) = res {
+                return Err(duplicate(e, &student.name));
+            }
+            transaction.commit()?;
+
+            info!("Inserted student: {}", student.name);
+
+            Ok(student)
+        })
+        .await
+    }
+
+    async fn insert_student(&self, slack_id: &str, name: &str) -> Result<Student> {
+        let student = Student {
+            slack_id: slack_id.to_string(),
+            name: name.to_string(),
+            ..Default::default()
+        };
+        self.run(move |conn| {
+            let transaction = conn.transaction()?;
+
+            let role_id = Uuid::new_v4();
+            transaction.execute("INSERT INTO roles(id) VALUES(?1);", [&role_id])?;
+
+            // The whole insert should fail if the student already exists.
+            let stmt = "INSERT INTO students(id, name, roles_id, slack_id) VALUES(?1, ?2, ?3, ?4);";
+            #[rustfmt::skip]
+            let res = transaction.execute(stmt, params![
+                student.id, student.name, role_id, student.slack_id,
+            ]);
+            if let Err(e) = res {
+                return Err(duplicate(e, &student.name));
+            }
+            transaction.commit()?;
+
+            info!("Inserted student: {}", student.name);
+
+            Ok(student)
+        })
+        .await
+    }
+
+    async fn update_student(&self, student: &Student) -> Result<()> {
+        let student = student.clone();
+        self.run(move |conn| {
+            let transaction = conn.transaction()?;
+            write_student(&transaction, &student)?;
+            transaction.commit()?;
+
+            info!("Updated student: {}", student.name);
+
+            Ok(())
+        })
+        .await
+    }
+
+    async fn update_escalation(&self, student: &Student) -> Result<()> {
+        let student = student.clone();
+        self.run(move |conn| {
+            let stmt = "UPDATE students SET escalation = ?2 WHERE id = ?1 AND current_film = ?3;";
+            #[rustfmt::skip]
+            conn.execute(stmt, params![
+                student.id, student.escalation, student.current_film,
+            ])?;
+
+            info!("Escalated {} to level {}", student.name, student.escalation);
+
+            Ok(())
+        })
+        .await
+    }
+
+    // ------------- Queue ------------- //
+
+    async fn get_queue(&self, wait: bool) -> Result<Vec<QueueItem>> {
+        let res = self
+            .run(move |conn| {
+                // Waiters aren't tied to a film, so wait_q has none of its details.
+                let stmt = if wait {
+                    "SELECT *, NULL AS group_number, NULL AS class, NULL AS deadline
+                    FROM wait_q;"
+                } else {
+                    "SELECT * from jobs_q;"
+                };
+                query(conn, stmt, [], format_row_into_queue_item)
+            })
+            .await?;
+
+        let s = if wait { "wait" } else { "jobs" };
+        info!("Retrieved {s} queue");
+
+        Ok(res)
+    }
+
+    async fn insert_to_queue(
+        &self,
+        q: QueueItem,
+        wait: bool,
+        audit: Option<&AuditEntry>,
+    ) -> Result<QueueItem> {
+        let (item, audit) = (q.clone(), audit.cloned());
+        self.run(move |conn| {
+            let transaction = conn.transaction()?;
+            if wait {
+                let stmt = "
+                    INSERT INTO wait_q(id, student_slack_id, film_name, role, msg_ts, channel,
+                                       created_at)
+                    VALUES(?1, ?2, ?3, ?4, ?5, ?6, ?7);";
+                #[rustfmt::skip]
+                transaction.execute(stmt, params![
+                    item.id,
+                    item.student_slack_id,
+                    item.film_name,
+                    item.role.as_ref(),
+                    item.msg_ts,
+                    item.channel,
+                    Utc::now(),
+                ])?;
+            } else {
+                insert_job(&transaction, &item)?;
+            }
+            if let Some(entry) = audit {
+                insert_audit(&transaction, &entry)?;
+            }
+            transaction.commit()?;
+            Ok(())
+        })
+        .await?;
+
+        if wait {
+            info!("Inserted {} into the wait queue", q.student_slack_id);
+        } else {
+            info!("Inserted {} into the jobs queue", q.film_name);
+        }
+
+        Ok(q)
+    }
+
+    async fn assign_film(
+        &self,
+        student: &Student,
+        film: &Film,
+        job: &QueueItem,
+        audit: &AuditEntry,
+    ) -> Result<()> {
+        let (student, film, job) = (student.clone(), film.clone(), job.clone());
+        let audit = audit.clone();
+        self.run(move |conn| {
+            let transaction = conn.transaction()?;
+
+            // They're no longer waiting, if they were. How long they waited is kept with the
+            // assignment.
+            let stmt = "SELECT MIN(created_at) FROM wait_q WHERE student_slack_id = ?1;";
+            let waiting_since = transaction.query_row(stmt, [&student.slack_id], |r| r.get(0))?;
+            let stmt = "DELETE FROM wait_q WHERE student_slack_id = ?1;";
+            transaction.execute(stmt, [&student.slack_id])?;
+
+            let queued = (Some(job.created_at), waiting_since);
+            set_assignment(&transaction, &student, &film, queued)?;
+            transaction.execute("DELETE FROM jobs_q WHERE id = ?1;", [job.id])?;
+            let stmt = "UPDATE films SET queued_at = ?2 WHERE id = ?1;";
+            transaction.execute(stmt, params![film.id, job.created_at])?;
+            insert_audit(&transaction, &audit)?;
+
+            transaction.commit()?;
+
+            info!("Assigned {} to {}", film.name, student.name);
+
+            Ok(())
+        })
+        .await
+    }
+
+    async fn transfer_film(
+        &self,
+        from: &Student,
+        to: &Student,
+        film: &Film,
+        audit: &AuditEntry,
+    ) -> Result<()> {
+        let (from, to, film, audit) = (from.clone(), to.clone(), film.clone(), audit.clone());
+        self.run(move |conn| {
+            let transaction = conn.transaction()?;
+
+            end_assignment(&transaction, &from, Outcome::Reassigned)?;
+            clear_assignment(&transaction, &from, &film)?;
+            set_assignment(&transaction, &to, &film, (None, None))?;
+            insert_audit(&transaction, &audit)?;
+
+            transaction.commit()?;
+
+            info!("Moved {} from {} to {}", film.name, from.name, to.name);
+
+            Ok(())
+        })
+        .await
+    }
+
+    async fn release_film(
+        &self,
+        student: &Student,
+        film: &Film,
+        job: &QueueItem,
+        audit: &AuditEntry,
+    ) -> Result<()> {
+        let (student, film, job) = (student.clone(), film.clone(), job.clone());
+        let audit = audit.clone();
+        self.run(move |conn| {
+            let transaction = conn.transaction()?;
+
+            end_assignment(&transaction, &student, Outcome::Released)?;
+            clear_assignment(&transaction, &student, &film)?;
+            insert_job(&transaction, &job)?;
+            insert_audit(&transaction, &audit)?;
+
+            transaction.commit()?;
+
+            info!("Released {} from {}", film.name, student.name);
+
+            Ok(())
+        })
+        .await
+    }
+
+    async fn deliver_film(
+        &self,
+        student: &Student,
+        film: &Film,
+        job: Option<&QueueItem>,
+        delivery: Option<&Delivery>,
+        audit: &AuditEntry,
+    ) -> Result<()> {
+        let (student, film, job) = (student.clone(), film.clone(), job.cloned());
+        let (delivery, audit) = (delivery.cloned(), audit.clone());
+        self.run(move |conn| {
+            let transaction = conn.transaction()?;
+
+            // The student's already been moved on, so close out the film they just delivered.
+            let stmt = "
+                UPDATE assignments
+                SET delivered_at = ?3, outcome = ?4
+                WHERE student_id = ?1
+                AND film_id = ?2
+                AND outcome IS NULL;";
+            #[rustfmt::skip]
+            transaction.execute(stmt, params![
+                student.id, film.id, Utc::now(), Outcome::Delivered.as_ref(),
+            ])?;
+
+            update_film_roles(&transaction, &film)?;
+            write_student(&transaction, &student)?;
+            if let Some(job) = job {
+                insert_job(&transaction, &job)?;
+            }
+            if let Some(delivery) = delivery {
+                insert_delivery(&transaction, &delivery)?;
+            }
+            insert_audit(&transaction, &audit)?;
+
+            transaction.commit()?;
+
+            info!("{} delivered {}", student.name, film.name);
+
+            Ok(())
+        })
+        .await
+    }
+
+    async fn delete_from_queue(
+        &self,
+        id: &Uuid,
+        wait: bool,
+        audit: Option<&AuditEntry>,
+    ) -> Result<()> {
+        let (id, audit) = (*id, audit.cloned());
+        self.run(move |conn| {
+            let transaction = conn.transaction()?;
+            let stmt = if wait {
+                "DELETE FROM wait_q WHERE id = ?1;"
+            } else {
+                "DELETE FROM jobs_q WHERE id = ?1;"
+            };
+            transaction.execute(stmt, [id])?;
+            if let Some(entry) = audit {
+                insert_audit(&transaction, &entry)?;
+            }
+            transaction.commit()?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn repair(&self, issue: &Issue, audit: &AuditEntry) -> Result<bool> {
+        let (issue, audit) = (issue.clone(), audit.clone());
+        self.run(move |conn| {
+            // Takes the write lock up front, so nothing the repair re-reads can change under it.
+            let transaction = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+
+            // Each statement only makes its change if the issue still holds.
+            let repaired = match &issue {
+                Issue::OrphanJob { id, .. } => {
+                    let stmt = "
+                        DELETE FROM jobs_q WHERE id = ?1
+                        AND NOT EXISTS (SELECT 1 FROM films WHERE name = jobs_q.film_name);";
+                    transaction.execute(stmt, [id])? > 0
+                }
+                Issue::StaleJob { id, .. } => {
+                    let stmt = "
+                        DELETE FROM jobs_q WHERE id = ?1
+                        AND EXISTS (
+                            SELECT 1 FROM films as f JOIN roles as r ON f.roles_id = r.id
+                            WHERE f.name = jobs_q.film_name AND r.current <> jobs_q.role
+                        );";
+                    transaction.execute(stmt, [id])? > 0
+                }
+                Issue::HeldJob { id, .. } => {
+                    let stmt = "
+                        DELETE FROM jobs_q WHERE id = ?1
+                        AND EXISTS (SELECT 1 FROM students WHERE current_film = jobs_q.film_name);";
+                    transaction.execute(stmt, [id])? > 0
+                }
+                Issue::DuplicateJob { id, .. } => {
+                    let stmt = "
+                        DELETE FROM jobs_q WHERE id = ?1
+                        AND EXISTS (
+                            SELECT 1 FROM jobs_q as o
+                            WHERE o.film_name = jobs_q.film_name
+                            AND o.role = jobs_q.role
+                            AND o.id <> jobs_q.id
+                        );";
+                    transaction.execute(stmt, [id])? > 0
+                }
+                Issue::AssignedWaiter { id, .. } => {
+                    let stmt = "
+                        DELETE FROM wait_q WHERE id = ?1
+                        AND EXISTS (
+                            SELECT 1 FROM students
+                            WHERE slack_id = wait_q.student_slack_id AND current_film IS NOT NULL
+                        );";
+                    transaction.execute(stmt, [id])? > 0
+                }
+                Issue::UnknownFilm { student, film } => {
+                    let stmt = "
+                        UPDATE students
+                        SET current_film = NULL, assigned_at = NULL, escalation = 0, paused = FALSE
+                        WHERE slack_id = ?1 AND current_film = ?2
+                        AND NOT EXISTS (SELECT 1 FROM films WHERE name = ?2);";
+                    transaction.execute(stmt, [student, film])? > 0
+                }
+                Issue::Unqueued { film, .. } => {
+                    let stmt = format!(
+                        "SELECT {FILM_COLUMNS}
+                        FROM films as f, film_roles as r
+                        WHERE f.name = ?1
+                        AND f.roles_id = r.id
+                        AND r.current <> ?2
+                        AND NOT EXISTS (SELECT 1 FROM jobs_q WHERE film_name = f.name)
+                        AND NOT EXISTS (SELECT 1 FROM students WHERE current_film = f.name);"
+                    );
+                    let params = [film.as_str(), Role::Done.as_ref()];
+                    match query(&transaction, &stmt, params, format_row_into_film)?.pop() {
+                        Some(film) => {
+                            insert_job(&transaction, &queue::new_job(&film, ""))?;
+                            true
+                        }
+                        None => false,
+                    }
+                }
+                Issue::MissingHistory { .. } | Issue::SharedFilm { .. } => false,
+            };
+            if repaired {
+                insert_audit(&transaction, &audit)?;
+                transaction.commit()?;
+            }
+            Ok(repaired)
+        })
+        .await
+    }
+
+    async fn send_back(&self, film: &Film, revision: &Revision, audit: &AuditEntry) -> Result<()> {
+        let (film, revision, audit) = (film.clone(), revision.clone(), audit.clone());
+        self.run(move |conn| {
+            let transaction = conn.transaction()?;
+
+            update_film_roles(&transaction, &film)?;
+
+            let stmt = "UPDATE students SET paused = TRUE WHERE slack_id = ?1;";
+            transaction.execute(stmt, [&revision.from])?;
+
+            let stmt = "
+                UPDATE assignments
+                SET outcome = ?3
+                WHERE film_id = ?1
+                AND role = ?2
+                AND outcome = ?4;";
+            let (sent_back, delivered) = (Outcome::SentBack.as_ref(), Outcome::Delivered.as_ref());
+            #[rustfmt::skip]
+            transaction.execute(stmt, params![
+                film.id, revision.role.as_ref(), sent_back, delivered,
+            ])?;
+
+            let stmt = "
+                INSERT INTO revisions(id, film_id, role, from_student, to_student, note,
+                                      created_at)
+                VALUES(?1,
+                       (SELECT id FROM films WHERE name = ?2),
+                       ?3,
+                       (SELECT id FROM students WHERE slack_id = ?4),
+                       (SELECT id FROM students WHERE slack_id = ?5),
+                       ?6, ?7);";
+            #[rustfmt::skip]
+            transaction.execute(stmt, params![
+                revision.id,
+                revision.film_name,
+                revision.role.as_ref(),
+                revision.from,
+                revision.to,
+                revision.note,
+                revision.created_at,
+            ])?;
+            insert_audit(&transaction, &audit)?;
+
+            transaction.commit()?;
+
+            info!("Sent {} back to {}", film.name, revision.role.as_ref());
+
+            Ok(())
+        })
+        .await
+    }
+
+    async fn get_revision(&self, slack_id: &str) -> Result<Option<Revision>> {
+        let slack_id = slack_id.to_string();
+        self.run(move |conn| {
+            let stmt = "
+                SELECT v.id, f.name as film_name, v.role, fs.slack_id as from_slack_id,
+                       ts.slack_id as to_slack_id, v.note, v.created_at, v.resolved_at
+                FROM revisions as v, films as f, students as fs, students as ts
+                WHERE ts.slack_id = ?1
+                AND v.resolved_at IS NULL
+                AND v.film_id = f.id
+                AND v.from_student = fs.id
+                AND v.to_student = ts.id
+                ORDER BY v.created_at
+                LIMIT 1;";
+            Ok(query(conn, stmt, [&slack_id], format_row_into_revision)?.pop())
+        })
+        .await
+    }
+
+    async fn resolve_revision(
+        &self,
+        film: &Film,
+        revision: &Revision,
+        delivery: Option<&Delivery>,
+        audit: &AuditEntry,
+    ) -> Result<()> {
+        let (film, revision, delivery) = (film.clone(), revision.clone(), delivery.cloned());
+        let audit = audit.clone();
+        self.run(move |conn| {
+            let now = Utc::now();
+            let transaction = conn.transaction()?;
+
+            update_film_roles(&transaction, &film)?;
+            if let Some(delivery) = delivery {
+                insert_delivery(&transaction, &delivery)?;
+            }
+
+            let stmt = "UPDATE revisions SET resolved_at = ?2 WHERE id = ?1;";
+            transaction.execute(stmt, params![revision.id, now])?;
+
+            // The fix counts as its own turn on the stage, starting when the film was sent back.
+            let stmt = "
+                INSERT INTO assignments(id, student_id, film_id, role, assigned_at, delivered_at,
+                                        outcome)
+                VALUES(?1,
+                       (SELECT id FROM students WHERE slack_id = ?2),
+                       ?3, ?4, ?5, ?6, ?7);";
+            #[rustfmt::skip]
+            transaction.execute(stmt, params![
+                Uuid::new_v4(),
+                revision.to,
+                film.id,
+                revision.role.as_ref(),
+                revision.created_at,
+                now,
+                Outcome::Delivered.as_ref(),
+            ])?;
+
+            // The clock restarts now that the film's back with them.
+            let stmt = "
+                UPDATE students
+                SET paused = FALSE, assigned_at = ?3, escalation = 0
+                WHERE slack_id = ?1
+                AND current_film = ?2;";
+            transaction.execute(stmt, params![revision.from, film.name, now])?;
+            insert_audit(&transaction, &audit)?;
+
+            transaction.commit()?;
+
+            info!("Resolved revision of {}", film.name);
+
+            Ok(())
+        })
+        .await
+    }
+
+    async fn get_delivery(&self, film_name: &str, role: Role) -> Result<Option<Delivery>> {
+        let film_name = film_name.to_string();
+        self.run(move |conn| {
+            let stmt = "
+                SELECT d.id, f.name as film_name, d.role, s.slack_id, d.links, d.notes,
+                       d.created_at
+                FROM deliveries as d, films as f, students as s
+                WHERE f.name = ?1
+                AND d.role = ?2
+                AND d.film_id = f.id
+                AND d.student_id = s.id
+                ORDER BY d.created_at DESC
+                LIMIT 1;";
+            let params = params![film_name, role.as_ref()];
+            Ok(query(conn, stmt, params, format_row_into_delivery)?.pop())
+        })
+        .await
+    }
+
+    async fn get_film_history(&self, film_name: &str) -> Result<Vec<Assignment>> {
+        let film_name = film_name.to_string();
+        self.run(move |conn| {
+            let stmt = "
+                SELECT a.id, f.name as film_name, a.role, a.student_id, s.slack_id,
+                       s.name as student_name, a.assigned_at, a.delivered_at, a.outcome,
+                       a.queued_at, a.waiting_since
+                FROM assignments as a, films as f, students as s
+                WHERE f.name = ?1
+                AND a.film_id = f.id
+                AND a.student_id = s.id
+                ORDER BY a.assigned_at;";
+            query(conn, stmt, [&film_name], format_row_into_assignment)
+        })
+        .await
+    }
+
+    async fn get_student_history(&self, slack_id: &str) -> Result<Vec<Assignment>> {
+        let slack_id = slack_id.to_string();
+        self.run(move |conn| {
+            let stmt = "
+                SELECT a.id, f.name as film_name, a.role, a.student_id, s.slack_id,
+                       s.name as student_name, a.assigned_at, a.delivered_at, a.outcome,
+                       a.queued_at, a.waiting_since
+                FROM assignments as a, films as f, students as s
+                WHERE s.slack_id = ?1
+                AND a.film_id = f.id
+                AND a.student_id = s.id
+                ORDER BY a.assigned_at;";
+            query(conn, stmt, [&slack_id], format_row_into_assignment)
+        })
+        .await
+    }
+
+    async fn list_assignments(&self) -> Result<Vec<Assignment>> {
+        self.run(move |conn| {
+            let stmt = "
+                SELECT a.id, f.name as film_name, a.role, a.student_id, s.slack_id,
+                       s.name as student_name, a.assigned_at, a.delivered_at, a.outcome,
+                       a.queued_at, a.waiting_since
+                FROM assignments as a, films as f, students as s
+                WHERE a.film_id = f.id
+                AND a.student_id = s.id
+                ORDER BY a.assigned_at;";
+            query(conn, stmt, [], format_row_into_assignment)
+        })
+        .await
+    }
+
+    async fn insert_audit(&self, entry: &AuditEntry) -> Result<()> {
+        let entry = entry.clone();
+        self.run(move |conn| insert_audit(conn, &entry)).await
+    }
+
+    async fn get_audit_log(
+        &self,
+        student: Option<&str>,
+        film: Option<&str>,
+        limit: i64,
+    ) -> Result<Vec<AuditEntry>> {
+        let (student, film) = (student.map(str::to_string), film.map(str::to_string));
+        self.run(move |conn| {
+            let stmt = "
+                SELECT * FROM audit_log
+                WHERE (?1 IS NULL OR student = ?1)
+                AND (?2 IS NULL OR film = ?2)
+                ORDER BY created_at DESC
+                LIMIT ?3;";
+            let params = params![student, film, limit];
+            query(conn, stmt, params, format_row_into_audit_entry)
+        })
+        .await
+    }
+
+    // ------------- Events ------------- //
+
+    async fn insert_event(&self, event: &PendingEvent, claimed_by: &str) -> Result<bool> {
+        let (event, claimed_by) = (event.clone(), claimed_by.to_string());
+        self.run(move |conn| {
+            let stmt = "
+                INSERT OR IGNORE INTO slack_events(id, slack_user, payload, created_at,
+                                                   claimed_by, claimed_at)
+                VALUES(?1, ?2, ?3, ?4, ?5, ?6);";
+            #[rustfmt::skip]
+            let inserted = conn.execute(stmt, params![
+                event.id,
+                event.user,
+                event.payload,
+                event.created_at,
+                claimed_by,
+                Utc::now(),
+            ])?;
+            Ok(inserted > 0)
+        })
+        .await
+    }
+
+    async fn list_events(&self) -> Result<Vec<PendingEvent>> {
+        self.run(|conn| {
+            let stmt = "SELECT * FROM slack_events WHERE handled_at IS NULL ORDER BY created_at;";
+            query(conn, stmt, [], format_row_into_event)
+        })
+        .await
+    }
+
+    async fn claim_events(
+        &self,
+        claimed_by: &str,
+        expired: DateTime<Utc>,
+    ) -> Result<Vec<PendingEvent>> {
+        let claimed_by = claimed_by.to_string();
+        self.run(move |conn| {
+            // Taking the write lock up front means nobody else can claim the same rows.
+            let transaction = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+            let stmt = "
+                SELECT * FROM slack_events
+                WHERE handled_at IS NULL AND (claimed_at IS NULL OR claimed_at < ?1)
+                ORDER BY created_at;";
+            let events = query(&transaction, stmt, [expired], format_row_into_event)?;
+
+            let stmt = "UPDATE slack_events SET claimed_by = ?2, claimed_at = ?3 WHERE id = ?1;";
+            for event in &events {
+                transaction.execute(stmt, params![event.id, claimed_by, Utc::now()])?;
+            }
+            transaction.commit()?;
+
+            Ok(events)
+        })
+        .await
+    }
+
+    async fn renew_events(&self, claimed_by: &str) -> Result<()> {
+        let claimed_by = claimed_by.to_string();
+        self.run(move |conn| {
+            let stmt = "
+                UPDATE slack_events SET claimed_at = ?2
+                WHERE claimed_by = ?1 AND handled_at IS NULL;";
+            conn.execute(stmt, params![claimed_by, Utc::now()])?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn release_events(&self, claimed_by: &str) -> Result<u64> {
+        let claimed_by = claimed_by.to_string();
+        self.run(move |conn| {
+            let stmt = "
+                UPDATE slack_events SET claimed_by = NULL, claimed_at = NULL
+                WHERE claimed_by = ?1 AND handled_at IS NULL;";
+            Ok(conn.execute(stmt, [claimed_by])? as u64)
+        })
+        .await
+    }
+
+    async fn finish_event(&self, id: &str, forget_before: DateTime<Utc>) -> Result<()> {
+        let id = id.to_string();
+        self.run(move |conn| {
+            let stmt = "UPDATE slack_events SET handled_at = ?2 WHERE id = ?1;";
+            conn.execute(stmt, params![id, Utc::now()])?;
+            let stmt = "DELETE FROM slack_events WHERE handled_at < ?1;";
+            conn.execute(stmt, [forget_before])?;
+            Ok(())
+        })
+        .await
+    }
+
+    // ------------- Rate limits ------------- //
+
+    async fn list_uses(
+        &self,
+        slack_id: &str,
+        action: &str,
+        since: DateTime<Utc>,
+    ) -> Result<Vec<DateTime<Utc>>> {
+        let (slack_id, action) = (slack_id.to_string(), action.to_string());
+        self.run(move |conn| {
+            let stmt = "
+                SELECT used_at FROM rate_limit_uses
+                WHERE slack_id = ?1 AND action = ?2 AND used_at > ?3
+                ORDER BY used_at;";
+            query(conn, stmt, params![slack_id, action, since], |row| {
+                Ok(row.get("used_at")?)
+            })
+        })
+        .await
+    }
+
+    async fn insert_use(
+        &self,
+        slack_id: &str,
+        action: &str,
+        at: DateTime<Utc>,
+        expired: DateTime<Utc>,
+    ) -> Result<()> {
+        let (slack_id, action) = (slack_id.to_string(), action.to_string());
+        self.run(move |conn| {
+            let transaction = conn.transaction()?;
+
+            let stmt = "
+                DELETE FROM rate_limit_uses
+                WHERE slack_id = ?1 AND action = ?2 AND used_at <= ?3;";
+            transaction.execute(stmt, params![slack_id, action, expired])?;
+            let stmt = "INSERT INTO rate_limit_uses(slack_id, action, used_at) VALUES(?1, ?2, ?3);";
+            transaction.execute(stmt, params![slack_id, action, at])?;
+
+            transaction.commit()?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn migrate(&self) -> Result<()> {
+        self.run(migrate).await
+    }
+
+    async fn schema_version(&self) -> Result<SchemaVersion> {
+        self.run(|conn| {
+            let applied = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+            Ok(SchemaVersion {
+                applied,
+                latest: MIGRATIONS.len() as i64,
+            })
+        })
+        .await
+    }
+
+    async fn ping(&self) -> Result<()> {
+        self.run(|conn| {
+            conn.query_row("SELECT 1;", [], |row| row.get::<_, i64>(0))?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn drop_db(&self) -> Result<()> {
+        let environment = std::env::var("ENVIRONMENT")?;
+        if environment != "test" {
+            return Err(Error::Internal(eyre!("Cannot drop db outside of testing")));
+        }
+
+        self.run(|conn| {
+            let stmt = "
+                SELECT type, name FROM sqlite_master
+                WHERE type IN ('view', 'table') AND name NOT LIKE 'sqlite_%'
+                ORDER BY type DESC;";
+            let objects = query(conn, stmt, [], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+            })?;
+
+            conn.pragma_update(None, "foreign_keys", false)?;
+            for (kind, name) in objects {
+                conn.execute_batch(&format!("DROP {kind} IF EXISTS {name};"))?;
+            }
+            conn.pragma_update(None, "foreign_keys", true)?;
+            conn.pragma_update(None, "user_version", 0)?;
+
+            Ok(())
+        })
+        .await
+    }
+}
+
+// ------------- Helpers ------------- //
+
+/// Applies every migration the database hasn't seen yet, each in its own transaction.
+fn migrate(conn: &mut Connection) -> Result<()> {
+    let version: usize = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+
+    for (i, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        let transaction = conn.transaction()?;
+        match migration {
+            Migration::Sql(sql) => transaction.execute_batch(sql)?,
+            Migration::AddColumns(columns) => {
+                for (table, column, kind) in columns.iter() {
+                    let stmt = format!("SELECT name FROM pragma_table_info('{table}');");
+                    let existing = query(&transaction, &stmt, [], |row| Ok(row.get(0)?))?;
+                    if !existing.iter().any(|c: &String| c == column) {
+                        let stmt = format!("ALTER TABLE {table} ADD COLUMN {column} {kind};");
+                        transaction.execute_batch(&stmt)?;
+                    }
+                }
+            }
+        }
+        transaction.pragma_update(None, "user_version", i + 1)?;
+        transaction.commit()?;
+        info!("Applied migration {}", i + 1);
+    }
+
+    Ok(())
+}
+
+/// Runs a query and formats every row it returns.
+fn query<T, P: Params>(
+    conn: &Connection,
+    stmt: &str,
+    params: P,
+    format: impl Fn(&Row) -> Result<T>,
+) -> Result<Vec<T>> {
+    let mut stmt = conn.prepare_cached(stmt)?;
+    let mut rows = stmt.query(params)?;
+
+    let mut res = vec![];
+    while let Some(row) = rows.next()? {
+        res.push(format(row)?);
+    }
+    Ok(res)
+}
+
+/// Only a broken unique constraint means the row already exists. Anything else is a real error.
+fn duplicate(e: rusqlite::Error, name: &str) -> Error {
+    match e {
+        rusqlite::Error::SqliteFailure(ref f, _)
+            if f.extended_code == rusqlite::ffi::SQLITE_CONSTRAINT_UNIQUE =>
+        {
+            Error::Duplicate(name.to_string())
+        }
+        e => e.into(),
+    }
+}
+
+/// Writes a film's roles and current role. Works both inside and outside of a transaction.
+fn update_film_roles(conn: &Connection, film: &Film) -> Result<()> {
+    let stmt = "
+        UPDATE roles
+        SET ae = ?2, editor = ?3, sound = ?4, finish = ?5, current = ?6
+        WHERE id = (
+            SELECT roles_id FROM films WHERE name = ?1);";
+
+    #[rustfmt::skip]
+    conn.execute(stmt, params![
+        film.name,
+        film.roles.ae,
+        film.roles.editor,
+        film.roles.sound,
+        film.roles.finish,
+        film.current_role.as_ref(),
+    ])?;
+
+    Ok(())
+}
+
+/// Writes a student's roles and assignment. Works both inside and outside of a transaction.
+fn write_student(conn: &Connection, student: &Student) -> Result<()> {
+    let stmt = "
+        UPDATE roles
+        SET ae = ?2, editor = ?3, sound = ?4, finish = ?5, current = ?6
+        WHERE id = (
+            SELECT roles_id FROM students WHERE id = ?1);";
+
+    #[rustfmt::skip]
+    conn.execute(stmt, params![
+        student.id,
+        student.roles.ae,
+        student.roles.editor,
+        student.roles.sound,
+        student.roles.finish,
+        student.current_role.as_ref(),
+    ])?;
+
+    let stmt = "
+        UPDATE students
+        SET current_film = ?2, slack_id = ?3, assigned_at = ?4, escalation = ?5,
+            paused = ?6
+        WHERE id = ?1";
+
+    #[rustfmt::skip]
+    conn.execute(stmt, params![
+        student.id, student.current_film, student.slack_id, student.assigned_at,
+        student.escalation, student.paused,
+    ])?;
+
+    Ok(())
+}
+
+/// Closes out the history of the film a student is currently working on.
+fn end_assignment(conn: &Connection, student: &Student, outcome: Outcome) -> Result<()> {
+    let stmt = "
+        UPDATE assignments
+        SET delivered_at = ?3, outcome = ?4
+        WHERE student_id = ?1
+        AND film_id = (SELECT id FROM films WHERE name = ?2)
+        AND outcome IS NULL;";
+
+    #[rustfmt::skip]
+    conn.execute(stmt, params![
+        student.id, student.current_film, Utc::now(), outcome.as_ref(),
+    ])?;
+
+    Ok(())
+}
+
+/// Sets a student's current film, along with its students_films record, and starts its history.
+/// `queued` is when the job joined the jobs_q and the student the wait_q, where they did.
+fn set_assignment(
+    conn: &Connection,
+    student: &Student,
+    film: &Film,
+    queued: (Option<DateTime<Utc>>, Option<DateTime<Utc>>),
+) -> Result<()> {
+    let now = Utc::now();
+    let stmt = "
+        UPDATE students
+        SET current_film = ?2, assigned_at = ?3, escalation = 0, paused = FALSE
+        WHERE id = ?1;";
+    conn.execute(stmt, params![student.id, film.name, now])?;
+
+    let role = film.current_role.as_ref();
+    let stmt = "
+        INSERT INTO students_films(student_id, film_id, role) VALUES(?1, ?2, ?3)
+        ON CONFLICT DO NOTHING;";
+    conn.execute(stmt, params![student.id, film.id, role])?;
+
+    let stmt = "
+        INSERT INTO assignments(id, student_id, film_id, role, assigned_at, queued_at,
+                                waiting_since)
+        VALUES(?1, ?2, ?3, ?4, ?5, ?6, ?7);";
+    #[rustfmt::skip]
+    conn.execute(stmt, params![
+        Uuid::new_v4(), student.id, film.id, role, now, queued.0, queued.1,
+    ])?;
+    Ok(())
+}
+
+/// Clears a student's current film, along with its students_films record. Fails if the student
+/// has since moved on from the film, so a stale copy of them can't undo newer work.
+fn clear_assignment(conn: &Connection, student: &Student, film: &Film) -> Result<()> {
+    let stmt = "
+        UPDATE students
+        SET current_film = NULL, assigned_at = NULL, escalation = 0, paused = FALSE
+        WHERE id = ?1 AND current_film = ?2;";
+    if conn.execute(stmt, params![student.id, film.name])? == 0 {
+        return Err(super::not_assigned(student, film));
+    }
+
+    // The student never finished the film, so it doesn't count as worked.
+    let stmt = "DELETE FROM students_films WHERE student_id = ?1 AND film_id = ?2;";
+    conn.execute(stmt, params![student.id, film.id])?;
+    Ok(())
+}
+
+/// Inserts a job into the jobs_q. Works both inside and outside of a transaction.
+fn insert_audit(conn: &Connection, entry: &AuditEntry) -> Result<()> {
+    let stmt = "
+        INSERT INTO audit_log(id, action, actor, source, student, film, before, after,
+                              created_at)
+        VALUES(?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9);";
+    #[rustfmt::skip]
+    conn.execute(stmt, params![
+        entry.id,
+        entry.action.as_ref(),
+        entry.actor,
+        entry.source,
+        entry.student,
+        entry.film,
+        entry.before,
+        entry.after,
+        entry.created_at,
+    ])?;
+    Ok(())
+}
+
+fn insert_delivery(conn: &Connection, delivery: &Delivery) -> Result<()> {
+    let stmt = "
+        INSERT INTO deliveries(id, film_id, role, student_id, links, notes, created_at)
+        VALUES(?1,
+               (SELECT id FROM films WHERE name = ?2),
+               ?3,
+               (SELECT id FROM students WHERE slack_id = ?4),
+               ?5, ?6, ?7);";
+    #[rustfmt::skip]
+    conn.execute(stmt, params![
+        delivery.id,
+        delivery.film_name,
+        delivery.role.as_ref(),
+        delivery.student,
+        serde_json::to_string(&delivery.links)?,
+        delivery.notes,
+        delivery.created_at,
+    ])?;
+
+    info!(
+        "Recorded delivery of {} for {}",
+        delivery.role.as_ref(),
+        delivery.film_name
+    );
+
+    Ok(())
+}
+
+fn insert_job(conn: &Connection, q: &QueueItem) -> Result<()> {
+    let stmt = "
+        INSERT INTO jobs_q(id, student_slack_id, film_name, role, priority, deadline,
+                           group_number, class, created_at)
+        VALUES(?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9);";
+    let p = q.priority.map(|a| a.as_ref().to_string());
+
+    #[rustfmt::skip]
+    conn.execute(stmt, params![
+        q.id,
+        q.student_slack_id,
+        q.film_name,
+        q.role.as_ref(),
+        p,
+        q.deadline,
+        q.group_number,
+        q.class,
+        q.created_at,
+    ])?;
+
+    Ok(())
+}
+
+fn format_row_into_film(row: &Row) -> Result<Film> {
+    let current: String = row.get("current")?;
+    let priority: String = row.get("priority")?;
+
+    let roles = Roles::new(
+        row.get("ae")?,
+        row.get("editor")?,
+        row.get("sound")?,
+        row.get("finish")?,
+    );
+    let stage_hours = StageHours::new(
+        row.get("ae_hours")?,
+        row.get("editor_hours")?,
+        row.get("sound_hours")?,
+        row.get("finish_hours")?,
+    );
+    Ok(Film {
+        id: row.get("id")?,
+        name: row.get("name")?,
+        current_role: Role::from_str(&current)?,
+        priority: Priority::from_str(&priority)?,
+        roles,
+        group_number: row.get("group_number")?,
+        class: row.get("class")?,
+        due_date: row.get("due_date")?,
+        stage_hours,
+        queued_at: row.get("queued_at")?,
+    })
+}
+
+fn format_row_into_student(row: &Row) -> Result<Student> {
+    let current: String = row.get("current")?;
+
+    let roles = Roles::new(
+        row.get("ae")?,
+        row.get("editor")?,
+        row.get("sound")?,
+        row.get("finish")?,
+    );
+    Ok(Student {
+        id: row.get("id")?,
+        name: row.get("name")?,
+        slack_id: row.get("slack_id")?,
+        current_film: row.get("current_film")?,
+        current_role: Role::from_str(&current)?,
+        roles,
+        group_number: row.get("group_number")?,
+        class: row.get("class")?,
+        assigned_at: row.get("assigned_at")?,
+        escalation: row.get("escalation")?,
+        paused: row.get("paused")?,
+    })
+}
+
+fn format_row_into_queue_item(row: &Row) -> Result<QueueItem> {
+    let role: String = row.get("role")?;
+    let priority: Option<String> = row.get("priority")?;
+    let created_at: DateTime<Utc> = row.get("created_at")?;
+
+    Ok(QueueItem {
+        id: row.get("id")?,
+        student_slack_id: row.get("student_slack_id")?,
+        film_name: row.get("film_name")?,
+        role: Role::from_str(&role)?,
+        group_number: row.get("group_number")?,
+        class: row.get("class")?,
+        priority: priority.as_deref().map(Priority::from_str).transpose()?,
+        effective_priority: None,
+        round: None,
+        deadline: row.get("deadline")?,
+        msg_ts: row.get("msg_ts")?,
+        channel: row.get("channel")?,
+        created_at,
+    })
+}
+
+fn format_row_into_audit_entry(row: &Row) -> Result<AuditEntry> {
+    let action: String = row.get("action")?;
+    Ok(AuditEntry {
+        id: row.get("id")?,
+        action: AuditAction::from_str(&action)?,
+        actor: row.get("actor")?,
+        source: row.get("source")?,
+        student: row.get("student")?,
+        film: row.get("film")?,
+        before: row.get("before")?,
+        after: row.get("after")?,
+        created_at: row.get("created_at")?,
+    })
+}
+
+fn format_row_into_event(row: &Row) -> Result<PendingEvent> {
+    Ok(PendingEvent {
+        id: row.get("id")?,
+        user: row.get("slack_user")?,
+        payload: row.get("payload")?,
+        created_at: row.get("created_at")?,
+    })
+}
+
+fn format_row_into_assignment(row: &Row) -> Result<Assignment> {
+    let role: String = row.get("role")?;
+    let outcome: Option<String> = row.get("outcome")?;
+    Ok(Assignment {
+        id: row.get("id")?,
+        film_name: row.get("film_name")?,
+        role: Role::from_str(&role)?,
+        student_id: row.get("student_id")?,
+        slack_id: row.get("slack_id")?,
+        student_name: row.get("student_name")?,
+        assigned_at: row.get("assigned_at")?,
+        delivered_at: row.get("delivered_at")?,
+        outcome: outcome.as_deref().map(Outcome::from_str).transpose()?,
+        queued_at: row.get("queued_at")?,
+        waiting_since: row.get("waiting_since")?,
+    })
+}
+
+fn format_row_into_delivery(row: &Row) -> Result<Delivery> {
+    let role: String = row.get("role")?;
+    let links: String = row.get("links")?;
+    Ok(Delivery {
+        id: row.get("id")?,
+        film_name: row.get("film_name")?,
+        role: Role::from_str(&role)?,
+        student: row.get("slack_id")?,
+        links: serde_json::from_str(&links)?,
+        notes: row.get("notes")?,
+        created_at: row.get("created_at")?,
+    })
+}
+
+fn format_row_into_revision(row: &Row) -> Result<Revision> {
+    let role: String = row.get("role")?;
+    Ok(Revision {
+        id: row.get("id")?,
+        film_name: row.get("film_name")?,
+        role: Role::from_str(&role)?,
+        from: row.get("from_slack_id")?,
+        to: row.get("to_slack_id")?,
+        note: row.get("note")?,
+        created_at: row.get("created_at")?,
+        resolved_at: row.get("resolved_at")?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    // Migrating an up to date database does nothing.
+    fn check_migrate() -> Result<()> {
+        let mut conn = Connection::open_in_memory()?;
+        migrate(&mut conn)?;
+        migrate(&mut conn)?;
+
+        let version: usize = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+        assert_eq!(MIGRATIONS.len(), version);
+
+        let stmt = "SELECT name FROM sqlite_master WHERE type = 'view' ORDER BY name;";
+        let views = query(&conn, stmt, [], |row| Ok(row.get::<_, String>(0)?))?;
+        assert_eq!(vec!["film_roles", "student_roles"], views);
+
+        Ok(())
+    }
+}
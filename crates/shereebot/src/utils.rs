@@ -1,6 +1,14 @@
 pub mod config;
+pub(crate) mod correlation;
 pub mod errors;
 pub mod logger;
+pub(crate) mod rate_limit;
+pub(crate) mod tasks;
+
+/// A duration in hours, to the nearest minute.
+pub(crate) fn hours(d: chrono::Duration) -> f64 {
+    d.num_minutes() as f64 / 60.0
+}
 
 // Macro stuff:
 // $(), == repeating field
@@ -2,39 +2,163 @@ use std::{env, net::SocketAddr};
 
 use color_eyre::Result;
 use serde::Deserialize;
+use strum::{AsRefStr, EnumString};
+
+use crate::queue::JobOrdering;
 
 #[derive(Deserialize)]
 pub struct Config {
     pub server: Server,
+    /// Which database the bot keeps its state in.
+    pub backend: Backend,
     pub postgres: deadpool_postgres::Config,
+    /// Path to the database file, when using SQLite.
+    pub sqlite_path: String,
+    pub queue: Queue,
+    pub reclaim: Reclaim,
+    pub rate_limit: RateLimit,
+    /// Slack ids of the users allowed to run admin commands.
+    pub admins: Vec<String>,
+    /// Bearer token for the admin REST endpoints. They're disabled if unset.
+    pub admin_token: Option<String>,
     pub token: String,
+    /// Keeps everything in memory instead of Postgres, for trying the bot out. Nothing is saved.
+    pub demo: bool,
 }
 
 #[derive(Deserialize)]
 pub struct Server {
     pub address: SocketAddr,
     pub port: String,
+    /// Seconds to wait for background work to finish when shutting down.
+    pub shutdown_secs: u64,
+    /// How many Slack events are handled at once.
+    pub event_workers: usize,
+}
+
+/// Which database the bot keeps its state in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, AsRefStr, EnumString, Deserialize)]
+#[strum(serialize_all = "lowercase", ascii_case_insensitive)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    /// A Postgres server, set up with schema.sql.
+    Postgres,
+    /// A single file, set up by the bot itself.
+    Sqlite,
+}
+
+impl Default for Backend {
+    fn default() -> Self {
+        Self::Postgres
+    }
+}
+
+#[derive(Deserialize, Default)]
+pub struct Queue {
+    /// Hours a job waits before its priority is raised by a level. Aging is disabled if unset.
+    pub aging_hours: Option<i64>,
+    /// How jobs of the same priority are ordered.
+    pub ordering: JobOrdering,
+}
+
+/// When to chase up students who've held onto a film for too long.
+#[derive(Deserialize, Default)]
+pub struct Reclaim {
+    /// Minutes between checks for stalled assignments.
+    pub check_minutes: u64,
+    /// Hours after assignment at which the student is reminded, in ascending order.
+    pub remind_hours: Vec<i64>,
+    /// Hours after assignment at which admins are notified.
+    pub escalate_hours: Option<i64>,
+    /// Hours after assignment at which the film is taken back. Films are never taken back if unset.
+    pub reclaim_hours: Option<i64>,
+}
+
+/// How often students may cancel requests for work or hand back films.
+#[derive(Deserialize, Default)]
+pub struct RateLimit {
+    /// Uses of each command allowed per window. Zero disables the limit.
+    pub limit: usize,
+    pub window_hours: i64,
 }
 
 pub fn new() -> Result<Config> {
     let port = env::var("SERVER_PORT")?;
     let address = SocketAddr::from(([0, 0, 0, 0], port.parse()?));
-    let pg_port = env::var("POSTGRES_PORT")?.parse::<u16>()?;
+    let pg_port = optional_var("POSTGRES_PORT")?;
 
-    let server = Server { port, address };
+    let shutdown_secs = optional_var("SHUTDOWN_DEADLINE_SECS")?.unwrap_or(30);
+    let event_workers = optional_var("EVENT_WORKERS")?.unwrap_or(4);
+    let server = Server {
+        port,
+        address,
+        shutdown_secs,
+        event_workers,
+    };
     let postgres = deadpool_postgres::Config {
         user: env::var("POSTGRES_USER").ok(),
         host: env::var("POSTGRES_HOST").ok(),
         password: env::var("POSTGRES_PASSWORD").ok(),
         dbname: env::var("POSTGRES_DBNAME").ok(),
-        port: Some(pg_port),
+        port: pg_port,
         ..Default::default()
     };
+    let backend = optional_var("DATABASE_BACKEND")?.unwrap_or_default();
+    let sqlite_path = optional_var("SQLITE_PATH")?.unwrap_or_else(|| "shereebot.db".to_string());
+    let queue = Queue {
+        aging_hours: optional_var("QUEUE_AGING_HOURS")?,
+        ordering: optional_var("QUEUE_ORDERING")?.unwrap_or_default(),
+    };
+    let reclaim = Reclaim {
+        check_minutes: optional_var("RECLAIM_CHECK_MINUTES")?.unwrap_or(15),
+        remind_hours: list_var("RECLAIM_REMIND_HOURS")?,
+        escalate_hours: optional_var("RECLAIM_ESCALATE_HOURS")?,
+        reclaim_hours: optional_var("RECLAIM_AFTER_HOURS")?,
+    };
+    let rate_limit = RateLimit {
+        limit: optional_var("STUDENT_ACTION_LIMIT")?.unwrap_or(3),
+        window_hours: optional_var("STUDENT_ACTION_WINDOW_HOURS")?.unwrap_or(24),
+    };
+    let admins = list_var("ADMIN_SLACK_IDS")?;
+    let admin_token = optional_var("ADMIN_API_TOKEN")?;
     let token = env::var("OAUTH_TOKEN")?;
 
     Ok(Config {
         server,
+        backend,
         postgres,
+        sqlite_path,
+        queue,
+        reclaim,
+        rate_limit,
+        admins,
+        admin_token,
         token,
+        demo: false,
     })
 }
+
+/// Parses an optional, comma separated environment variable.
+fn list_var<T>(key: &str) -> Result<Vec<T>>
+where
+    T: std::str::FromStr,
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    let v = env::var(key).unwrap_or_default();
+    let items = v.split(',').map(str::trim).filter(|i| !i.is_empty());
+    Ok(items
+        .map(str::parse)
+        .collect::<std::result::Result<_, _>>()?)
+}
+
+/// Parses an optional environment variable, treating empty values as unset.
+fn optional_var<T>(key: &str) -> Result<Option<T>>
+where
+    T: std::str::FromStr,
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    match env::var(key) {
+        Ok(v) if !v.is_empty() => Ok(Some(v.parse()?)),
+        _ => Ok(None),
+    }
+}
@@ -0,0 +1,204 @@
+//! Correlation ids, which tie together everything logged while handling one Slack event or HTTP
+//! request, including any work it spawns, so an error a user reports can be matched to its logs.
+//!
+//! Any span with a `cid` field starts a correlation, and everything logged inside it is tagged
+//! with the id. The latest lines are kept in memory, so admins can look them up from Slack.
+use std::{
+    collections::VecDeque,
+    fmt::{self, Write},
+    sync::{Mutex, MutexGuard, PoisonError},
+};
+
+use chrono::{DateTime, Utc};
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use tracing::{
+    field::{Field, Visit},
+    span, Event, Subscriber,
+};
+use tracing_subscriber::{
+    layer::Context,
+    registry::{LookupSpan, Scope},
+    Layer, Registry,
+};
+use uuid::Uuid;
+
+/// How many log lines are kept, across every correlation id.
+const KEPT_LINES: usize = 10_000;
+
+static LINES: Lazy<Mutex<VecDeque<LogLine>>> = Lazy::new(Mutex::default);
+
+/// Something logged under a correlation id.
+#[derive(Debug, Clone, Serialize)]
+pub struct LogLine {
+    pub cid: String,
+    pub at: DateTime<Utc>,
+    pub level: String,
+    pub target: String,
+    pub message: String,
+}
+
+impl fmt::Display for LogLine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let at = self.at.format("%Y-%m-%d %H:%M:%S%.3f");
+        write!(f, "{at} {} {}: {}", self.level, self.target, self.message)
+    }
+}
+
+/// A new id to correlate by, short enough for a user to read out.
+pub(crate) fn new_id() -> String {
+    let mut id = Uuid::new_v4().to_simple().to_string();
+    id.truncate(8);
+    id
+}
+
+/// The correlation id of whatever's running now, if any.
+pub(crate) fn current() -> Option<String> {
+    let id = tracing::Span::current().id()?;
+    tracing::dispatcher::get_default(|dispatch| {
+        let registry = dispatch.downcast_ref::<Registry>()?;
+        find_cid(registry.span(&id)?.scope())
+    })
+}
+
+/// Points a user at the logs for what went wrong, e.g. `` (ref `1a2b3c4d`)``, if anything's
+/// being correlated.
+pub(crate) fn reference() -> String {
+    current().map_or_else(String::new, |cid| format!(" (ref `{cid}`)"))
+}
+
+/// Every line still kept which was logged under the given correlation id, oldest first.
+pub(crate) fn logs(cid: &str) -> Vec<LogLine> {
+    lines().iter().filter(|l| l.cid == cid).cloned().collect()
+}
+
+fn lines() -> MutexGuard<'static, VecDeque<LogLine>> {
+    LINES.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// A span's correlation id, kept in its extensions.
+struct Cid(String);
+
+/// The correlation id of the nearest span that has one.
+fn find_cid<'a, R: LookupSpan<'a>>(scope: Scope<'a, R>) -> Option<String> {
+    scope
+        .into_iter()
+        .find_map(|span| span.extensions().get::<Cid>().map(|c| c.0.clone()))
+}
+
+/// Tags spans with their correlation ids, and keeps whatever's logged under them.
+pub(crate) struct CorrelationLayer;
+
+impl<S> Layer<S> for CorrelationLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &span::Attributes<'_>, id: &span::Id, ctx: Context<'_, S>) {
+        let mut visitor = CidVisitor(None);
+        attrs.record(&mut visitor);
+        if let (Some(cid), Some(span)) = (visitor.0, ctx.span(id)) {
+            span.extensions_mut().insert(Cid(cid));
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let cid = match ctx.event_scope(event).and_then(find_cid) {
+            Some(cid) => cid,
+            None => return,
+        };
+        let mut visitor = MessageVisitor::default();
+        event.record(&mut visitor);
+
+        let meta = event.metadata();
+        let line = LogLine {
+            cid,
+            at: Utc::now(),
+            level: meta.level().to_string(),
+            target: meta.target().to_string(),
+            message: visitor.message + &visitor.fields,
+        };
+
+        let mut lines = lines();
+        if lines.len() == KEPT_LINES {
+            lines.pop_front();
+        }
+        lines.push_back(line);
+    }
+}
+
+/// Picks out a span's `cid` field.
+struct CidVisitor(Option<String>);
+
+impl Visit for CidVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "cid" {
+            self.0 = Some(value.to_string());
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "cid" {
+            self.0 = Some(format!("{value:?}"));
+        }
+    }
+}
+
+/// Formats an event's message, followed by the rest of its fields.
+#[derive(Default)]
+struct MessageVisitor {
+    message: String,
+    fields: String,
+}
+
+impl Visit for MessageVisitor {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        match field.name() {
+            "message" => write!(self.message, "{value:?}"),
+            name => write!(self.fields, " {name}={value:?}"),
+        }
+        .ok();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tracing::{info, info_span, Instrument};
+    use tracing_subscriber::layer::SubscriberExt;
+
+    use super::*;
+    use crate::utils::tasks::Tasks;
+
+    #[tokio::test]
+    async fn check_correlation() {
+        let subscriber = Registry::default().with(CorrelationLayer);
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let cid = new_id();
+        assert_eq!(8, cid.len());
+        assert_eq!(None, current());
+        assert_eq!("", reference());
+        info!("logged outside of any correlation");
+
+        let tasks = Tasks::default();
+        let span = info_span!("request", cid = %cid);
+        async {
+            info!(film = "a", "handling");
+            assert_eq!(format!(" (ref `{cid}`)"), reference());
+            // Spans and tasks started along the way carry the id too.
+            let child = info_span!("child");
+            async {
+                assert_eq!(Some(cid.clone()), current());
+                info!("nested");
+            }
+            .instrument(child)
+            .await;
+            tasks.spawn(async { info!("spawned") }).await.unwrap();
+        }
+        .instrument(span)
+        .await;
+
+        let messages: Vec<_> = logs(&cid).into_iter().map(|l| l.message).collect();
+        assert_eq!(vec![r#"handling film="a""#, "nested", "spawned"], messages);
+        assert!(logs(&new_id()).is_empty());
+    }
+}
@@ -2,6 +2,8 @@ use axum::{http::StatusCode, response::IntoResponse, Json};
 use color_eyre::eyre;
 use serde_json::json;
 
+use super::correlation;
+
 /// All possible application errors.
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
@@ -21,6 +23,8 @@ pub enum Error {
     #[error(transparent)]
     Repository(#[from] tokio_postgres::Error),
     #[error(transparent)]
+    Sqlite(#[from] rusqlite::Error),
+    #[error(transparent)]
     Server(#[from] axum::Error),
     #[error(transparent)]
     Pool(#[from] deadpool_postgres::PoolError),
@@ -78,7 +82,10 @@ impl IntoResponse for UserError {
             E::InvalidArg(_) | E::Duplicate(_) | E::NotFound(_) => {
                 format!("{}", self)
             }
-            _ => "Internal Error! Please let Michael know.".to_string(),
+            _ => format!(
+                "Internal Error! Please let Michael know.{}",
+                correlation::reference()
+            ),
         };
 
         // TODO: Change into slack response
@@ -11,6 +11,8 @@ use tracing_subscriber::{
     EnvFilter, Registry,
 };
 
+use super::correlation::CorrelationLayer;
+
 static INIT: Once = Once::new();
 
 /// Installs logger and error reporter, using `RUST_LOG` and `RUST_BACKTRACE` as filters.
@@ -104,7 +106,8 @@ impl Logger {
         let registry = Registry::default()
             .with(self.env_filter)
             // .with(console_layer)
-            .with(ErrorLayer::default());
+            .with(ErrorLayer::default())
+            .with(CorrelationLayer);
 
         if environment == "local" || environment == "production" {
             let local_fmt = fmt::layer().event_format(local_fmt).with_writer(writer);
@@ -0,0 +1,121 @@
+//! Limits how often students can back out of work they've asked for.
+//!
+//! Uses are kept in the store, so every instance shares the limits and they survive a deploy.
+use chrono::{DateTime, Duration, Utc};
+use strum::AsRefStr;
+use tracing::error;
+
+use crate::{config, store::Database, Error, Result};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, AsRefStr)]
+#[strum(serialize_all = "UPPERCASE")]
+pub(crate) enum Action {
+    /// Withdrawing a request for work.
+    Cancel,
+    /// Handing back a film.
+    Drop,
+}
+
+pub(crate) struct RateLimiter {
+    /// Uses of each action allowed per window. Zero disables the limit.
+    limit: usize,
+    window: Duration,
+}
+
+impl RateLimiter {
+    pub(crate) fn new(cfg: &config::RateLimit) -> Self {
+        Self {
+            limit: cfg.limit,
+            window: Duration::hours(cfg.window_hours),
+        }
+    }
+
+    /// Checks whether a student may take an action, without counting it.
+    pub(crate) async fn check(
+        &self,
+        db: &Database,
+        slack_id: &str,
+        action: Action,
+        now: DateTime<Utc>,
+    ) -> Result<()> {
+        if self.limit == 0 {
+            return Ok(());
+        }
+        let used = db
+            .list_uses(slack_id, action.as_ref(), now - self.window)
+            .await?;
+
+        if used.len() < self.limit {
+            return Ok(());
+        }
+        let hours = (used[0] + self.window - now).num_hours() + 1;
+        let e = format!("You've done that too many times recently. Try again in {hours} hour(s).");
+        Err(Error::InvalidArg(e))
+    }
+
+    /// Counts a use of an action against the student's limit. The action's already been taken,
+    /// so failing to count it is only logged.
+    pub(crate) async fn record(
+        &self,
+        db: &Database,
+        slack_id: &str,
+        action: Action,
+        now: DateTime<Utc>,
+    ) {
+        if self.limit == 0 {
+            return;
+        }
+        let res = db
+            .insert_use(slack_id, action.as_ref(), now, now - self.window)
+            .await;
+        if let Err(e) = res {
+            error!("Failed to count {slack_id}'s {action:?} against their limit: {e}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store;
+
+    #[tokio::test]
+    async fn check_limit() {
+        let db = store::new_memory();
+        let limiter = RateLimiter::new(&config::RateLimit {
+            limit: 2,
+            window_hours: 24,
+        });
+        let now = Utc::now();
+
+        limiter
+            .record(&db, "a", Action::Drop, now - Duration::hours(30))
+            .await;
+        limiter
+            .record(&db, "a", Action::Drop, now - Duration::hours(2))
+            .await;
+        assert!(limiter.check(&db, "a", Action::Drop, now).await.is_ok());
+
+        limiter
+            .record(&db, "a", Action::Drop, now - Duration::hours(1))
+            .await;
+        assert!(limiter.check(&db, "a", Action::Drop, now).await.is_err());
+        assert!(limiter.check(&db, "a", Action::Cancel, now).await.is_ok());
+        assert!(limiter.check(&db, "b", Action::Drop, now).await.is_ok());
+
+        // Old uses fall out of the window.
+        let later = now + Duration::hours(23);
+        assert!(limiter.check(&db, "a", Action::Drop, later).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn check_disabled() {
+        let db = store::new_memory();
+        let limiter = RateLimiter::new(&config::RateLimit::default());
+        let now = Utc::now();
+        for _ in 0..10 {
+            limiter.record(&db, "a", Action::Cancel, now).await;
+        }
+        assert!(limiter.check(&db, "a", Action::Cancel, now).await.is_ok());
+    }
+}
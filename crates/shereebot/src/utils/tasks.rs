@@ -0,0 +1,98 @@
+//! Keeps count of background work, so the server can let it finish before shutting down.
+use std::{
+    future::Future,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use tokio::{sync::Notify, task::JoinHandle};
+use tracing::Instrument;
+
+/// Spawns tasks, and waits for every one of them to finish.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Tasks {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    running: AtomicUsize,
+    finished: Notify,
+}
+
+/// Counts a task as finished once dropped, even if it panicked.
+struct Guard(Arc<Inner>);
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        if self.0.running.fetch_sub(1, Ordering::SeqCst) == 1 {
+            // Leaves a permit behind if nobody's waiting yet, so the last task can't be missed.
+            self.0.finished.notify_one();
+        }
+    }
+}
+
+impl Tasks {
+    /// Spawns a task which shutdown will wait for. It runs in the current span, so it's logged
+    /// under the same correlation id as whatever spawned it.
+    pub(crate) fn spawn<F>(&self, task: F) -> JoinHandle<()>
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        self.inner.running.fetch_add(1, Ordering::SeqCst);
+        let guard = Guard(self.inner.clone());
+        tokio::spawn(
+            async move {
+                let _guard = guard;
+                task.await;
+            }
+            .instrument(tracing::Span::current()),
+        )
+    }
+
+    /// How many tasks are still running.
+    pub(crate) fn running(&self) -> usize {
+        self.inner.running.load(Ordering::SeqCst)
+    }
+
+    /// Waits for every task to finish, including any they spawn along the way, giving up after
+    /// `deadline`. Returns how many were left running.
+    pub(crate) async fn wait(&self, deadline: Duration) -> usize {
+        let finished = async {
+            while self.running() > 0 {
+                self.inner.finished.notified().await;
+            }
+        };
+        tokio::time::timeout(deadline, finished).await.ok();
+        self.running()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn check_wait() {
+        let tasks = Tasks::default();
+        assert_eq!(0, tasks.wait(Duration::ZERO).await);
+
+        let (tx, rx) = tokio::sync::oneshot::channel::<()>();
+        let nested = tasks.clone();
+        tasks.spawn(async move {
+            rx.await.ok();
+            // Follow-up work spawned while draining is waited for too.
+            nested.spawn(tokio::time::sleep(Duration::from_millis(20)));
+        });
+        tasks.spawn(async { panic!("panicked tasks still count as finished") });
+
+        // One task is stuck until it's told to go on.
+        assert_eq!(1, tasks.wait(Duration::from_millis(20)).await);
+
+        tx.send(()).unwrap();
+        assert_eq!(0, tasks.wait(Duration::from_secs(5)).await);
+    }
+}
@@ -1,12 +1,19 @@
-use std::{env, process::Command, sync::Once};
+use std::{env, path::PathBuf, process::Command, sync::Once};
 
-use chrono::Utc;
+use chrono::{DateTime, Utc};
 use color_eyre::{Help, Result};
 use deadpool_postgres::Runtime::Tokio1;
-use models::{Priority, Role};
+use models::{
+    AuditAction, AuditEntry, Delivery, Film, Outcome, PendingEvent, Priority, Revision, Role,
+    StageHours,
+};
 use serial_test::serial;
-use shbot::{logger, queue::QueueItem, store::Database};
-use tokio::test;
+use shbot::{
+    integrity::{self, Issue},
+    logger,
+    queue::QueueItem,
+    store::Database,
+};
 use tracing::info;
 
 static INIT: Once = Once::new();
@@ -22,7 +29,8 @@ fn pg_conf() -> deadpool_postgres::Config {
     }
 }
 
-async fn setup() -> Result<Database> {
+/// Starts the postgres container once, and resets the database.
+async fn setup_postgres() -> Result<Database> {
     INIT.call_once(|| {
         env::set_var("ENVIRONMENT", "test");
         // set to trace to debug things
@@ -63,14 +71,113 @@ async fn setup() -> Result<Database> {
     Ok(db)
 }
 
-#[test]
-#[serial]
-async fn films() -> Result<()> {
-    let db = setup().await?;
+/// The store a test runs against. Every test runs against each of them.
+enum Backend {
+    Postgres,
+    /// A fresh database file, removed once the test is done.
+    Sqlite(PathBuf),
+}
+
+impl Backend {
+    fn sqlite() -> Self {
+        let file = format!("shbot-test-{}.db", uuid::Uuid::new_v4());
+        Self::Sqlite(env::temp_dir().join(file))
+    }
+
+    async fn setup(&self) -> Result<Database> {
+        match self {
+            Self::Postgres => setup_postgres().await,
+            Self::Sqlite(path) => Ok(shbot::store::new_sqlite(&path.to_string_lossy())?),
+        }
+    }
+
+    /// Runs a statement directly against the database, bypassing the store.
+    async fn execute(&self, stmt: &str) -> Result<()> {
+        match self {
+            Self::Postgres => {
+                let pool = pg_conf().create_pool(Some(Tokio1), tokio_postgres::NoTls)?;
+                pool.get().await?.execute(stmt, &[]).await?;
+            }
+            Self::Sqlite(path) => {
+                rusqlite::Connection::open(path)?.execute(stmt, [])?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Backend {
+    fn drop(&mut self) {
+        if let Self::Sqlite(path) = self {
+            std::fs::remove_file(path).ok();
+        }
+    }
+}
+
+/// Generates a test per backend for each of the given tests.
+macro_rules! backends {
+    ($($name:ident),* $(,)?) => {
+        mod postgres {
+            use super::*;
+            $(
+                #[tokio::test]
+                #[serial]
+                async fn $name() -> Result<()> {
+                    super::$name(Backend::Postgres).await
+                }
+            )*
+        }
 
-    db.insert_film("b", 1, Priority::High).await?;
-    let mut film = db.insert_film("a", 1, Priority::High).await?;
+        mod sqlite {
+            use super::*;
+            $(
+                #[tokio::test]
+                async fn $name() -> Result<()> {
+                    super::$name(Backend::sqlite()).await
+                }
+            )*
+        }
+    };
+}
+
+backends!(
+    films,
+    students,
+    queue,
+    wait_queue,
+    assignments,
+    revisions,
+    deliveries,
+    history,
+    audit,
+    integrity,
+    schema,
+    events,
+    rate_limits,
+);
+
+/// An audit entry for a change made in a test.
+fn change(action: AuditAction) -> AuditEntry {
+    AuditEntry {
+        id: uuid::Uuid::new_v4(),
+        action,
+        actor: "test".to_string(),
+        source: "system:test".to_string(),
+        student: None,
+        film: Some("film".to_string()),
+        before: None,
+        after: None,
+        created_at: Utc::now(),
+    }
+}
+
+async fn films(backend: Backend) -> Result<()> {
+    let db = backend.setup().await?;
+
+    db.insert_film(&Film::new("b", Priority::High, 1)).await?;
+    let mut film = db.insert_film(&Film::new("a", Priority::Low, 1)).await?;
     assert_eq!("a", film.name);
+    assert_eq!(Priority::Low, film.priority);
 
     film.roles.ae = Some("mikatpt".to_string());
 
@@ -83,13 +190,27 @@ async fn films() -> Result<()> {
     let films = db.list_films().await?;
     assert_eq!(2, films.len());
 
+    // Only a film with the same name counts as a duplicate.
+    let dup = db.insert_film(&Film::new("a", Priority::High, 1)).await;
+    assert!(matches!(dup, Err(shbot::Error::Duplicate(_))));
+
+    let due = Utc::now().date().and_hms(0, 0, 0);
+    let mut scheduled = Film::new("c", Priority::High, 2);
+    scheduled.class = "b".to_string();
+    scheduled.due_date = Some(due);
+    scheduled.stage_hours = StageHours::new(Some(24), None, Some(12), None);
+    db.insert_film(&scheduled).await?;
+
+    let film = db.get_film("c").await?.unwrap();
+    assert_eq!("b", film.class);
+    assert_eq!(Some(due), film.due_date);
+    assert_eq!(scheduled.stage_hours, film.stage_hours);
+
     Ok(())
 }
 
-#[tokio::test]
-#[serial]
-async fn students() -> Result<()> {
-    let db = setup().await?;
+async fn students(backend: Backend) -> Result<()> {
+    let db = backend.setup().await?;
 
     // this hits slack api, if it fails say why.
     let id = "U038V25S1MJ";
@@ -106,23 +227,34 @@ async fn students() -> Result<()> {
     let sts = db.list_students().await?;
     assert_eq!(1, sts.len());
 
-    let film = db.insert_film("a", 0, Priority::High).await?;
-    let film2 = db.insert_film("b", 0, Priority::High).await?;
+    let film = db.insert_film(&Film::new("a", Priority::High, 0)).await?;
+    let film2 = db.insert_film(&Film::new("b", Priority::High, 0)).await?;
 
-    db.insert_student_films(&student.id, &film.id).await?;
-    db.insert_student_films(&student.id, &film2.id).await?;
+    db.insert_student_films(&student.id, &film.id, Role::Ae)
+        .await?;
+    db.insert_student_films(&student.id, &film2.id, Role::Editor)
+        .await?;
 
     let films = db.get_worked_films(&student.id).await?;
     assert!(films.contains(&film));
     assert!(films.contains(&film2));
 
+    let mut student = db.get_student(id).await?;
+    student.assign("b", Utc::now());
+    db.update_student(&student).await?;
+    student.escalation = 2;
+    db.update_escalation(&student).await?;
+
+    let student = db.get_student(id).await?;
+    assert_eq!(Some("b".to_string()), student.current_film);
+    assert_eq!(2, student.escalation);
+    assert!(student.assigned_at.is_some());
+
     Ok(())
 }
 
-#[test]
-#[serial]
-async fn queue() -> Result<()> {
-    let db = setup().await?;
+async fn queue(backend: Backend) -> Result<()> {
+    let db = backend.setup().await?;
 
     let date_str = "Tue, 1 Jul 2003 10:52:37 +0200";
     let date = chrono::DateTime::parse_from_rfc2822(date_str).unwrap();
@@ -133,7 +265,12 @@ async fn queue() -> Result<()> {
         student_slack_id: "U038V25S1MJ".to_string(),
         film_name: "test".to_string(),
         role: Role::Ae,
+        group_number: Some(1),
+        class: Some("a".to_string()),
         priority: Some(Priority::High),
+        effective_priority: None,
+        round: None,
+        deadline: Some(date),
         msg_ts: None,
         channel: None,
         created_at: date,
@@ -142,13 +279,16 @@ async fn queue() -> Result<()> {
     let mut wait_q = job_q.clone();
     wait_q.id = uuid::Uuid::new_v4();
     wait_q.priority = None;
+    wait_q.deadline = None;
+    wait_q.group_number = None;
+    wait_q.class = None;
     wait_q.msg_ts = Some("1234".to_string());
     wait_q.channel = Some("ASD".to_string());
 
-    db.insert_to_queue(wait_q.clone(), true).await?;
-    db.insert_to_queue(job_q.clone(), false).await?;
-    db.delete_from_queue(&wait_q.id, true).await?;
-    db.delete_from_queue(&job_q.id, true).await?;
+    db.insert_to_queue(wait_q.clone(), true, None).await?;
+    db.insert_to_queue(job_q.clone(), false, None).await?;
+    db.delete_from_queue(&wait_q.id, true, None).await?;
+    db.delete_from_queue(&job_q.id, true, None).await?;
 
     let mut jobs = db.get_queue(false).await?;
     let mut j = jobs.pop().unwrap();
@@ -160,3 +300,586 @@ async fn queue() -> Result<()> {
 
     Ok(())
 }
+
+/// Waiters aren't tied to a film, so loading them mustn't expect a film's details.
+async fn wait_queue(backend: Backend) -> Result<()> {
+    let db = backend.setup().await?;
+
+    let waiter = QueueItem {
+        id: uuid::Uuid::new_v4(),
+        student_slack_id: "U1".to_string(),
+        film_name: "".to_string(),
+        role: Role::Editor,
+        group_number: None,
+        class: None,
+        priority: None,
+        effective_priority: None,
+        round: None,
+        deadline: None,
+        msg_ts: Some("1234".to_string()),
+        channel: Some("ASD".to_string()),
+        created_at: Utc::now(),
+    };
+    db.insert_to_queue(waiter.clone(), true, None).await?;
+
+    let mut waiters = db.get_queue(true).await?;
+    assert_eq!(1, waiters.len());
+    let mut w = waiters.pop().unwrap();
+    w.created_at = waiter.created_at;
+    assert_eq!(waiter, w);
+
+    Ok(())
+}
+
+async fn assignments(backend: Backend) -> Result<()> {
+    let db = backend.setup().await?;
+
+    let a = db.insert_student("U1", "a").await?;
+    let b = db.insert_student("U2", "b").await?;
+    let film = db
+        .insert_film(&Film::new("film", Priority::High, 0))
+        .await?;
+
+    let job = QueueItem {
+        id: uuid::Uuid::new_v4(),
+        student_slack_id: "".to_string(),
+        film_name: film.name.clone(),
+        role: Role::Ae,
+        group_number: Some(0),
+        class: None,
+        priority: Some(Priority::High),
+        effective_priority: None,
+        round: None,
+        deadline: None,
+        msg_ts: None,
+        channel: None,
+        created_at: Utc::now(),
+    };
+    db.insert_to_queue(job.clone(), false, None).await?;
+
+    // Assigning takes the job off the queue.
+    db.assign_film(&a, &film, &job, &change(AuditAction::Assign))
+        .await?;
+    assert!(db.get_queue(false).await?.is_empty());
+    let a = db.get_student("U1").await?;
+    assert_eq!(Some("film".to_string()), a.current_film);
+    let film = db.get_film("film").await?.unwrap();
+    assert!(db.get_worked_films(&a.id).await?.contains(&film));
+    // The film remembers when it was queued, down to postgres' precision.
+    let queued_at = film.queued_at.unwrap();
+    assert!((queued_at - job.created_at).num_milliseconds().abs() < 1);
+
+    // Transferring moves the film and its history over.
+    db.transfer_film(&a, &b, &film, &change(AuditAction::Reassign))
+        .await?;
+    let a = db.get_student("U1").await?;
+    let b = db.get_student("U2").await?;
+    assert_eq!(None, a.current_film);
+    assert_eq!(Some("film".to_string()), b.current_film);
+    assert!(db.get_worked_films(&a.id).await?.is_empty());
+    assert!(db.get_worked_films(&b.id).await?.contains(&film));
+
+    // Releasing puts it back on the queue.
+    db.release_film(&b, &film, &job, &change(AuditAction::Unassign))
+        .await?;
+    let stale = b;
+    let b = db.get_student("U2").await?;
+    assert_eq!(None, b.current_film);
+    assert_eq!(1, db.get_queue(false).await?.len());
+
+    // A stale copy of the student can't release or hand on a film they've moved on from.
+    assert!(db
+        .release_film(&stale, &film, &job, &change(AuditAction::Unassign))
+        .await
+        .is_err());
+    assert!(db
+        .transfer_film(&stale, &a, &film, &change(AuditAction::Reassign))
+        .await
+        .is_err());
+    assert_eq!(1, db.get_queue(false).await?.len());
+    assert_eq!(None, db.get_student("U1").await?.current_film);
+
+    // Each change is audited along with it, and a failed one leaves nothing behind.
+    let audited: Vec<_> = db
+        .get_audit_log(None, Some("film"), 10)
+        .await?
+        .into_iter()
+        .map(|e| e.action)
+        .collect();
+    let expected = vec![
+        AuditAction::Unassign,
+        AuditAction::Reassign,
+        AuditAction::Assign,
+    ];
+    assert_eq!(expected, audited);
+
+    let history = db.get_film_history("film").await?;
+    let outcomes: Vec<_> = history
+        .iter()
+        .map(|a| (a.slack_id.as_str(), a.outcome))
+        .collect();
+    assert_eq!(
+        vec![
+            ("U1", Some(Outcome::Reassigned)),
+            ("U2", Some(Outcome::Released))
+        ],
+        outcomes
+    );
+    assert!(history.iter().all(|a| a.delivered_at.is_some()));
+
+    Ok(())
+}
+
+async fn revisions(backend: Backend) -> Result<()> {
+    let db = backend.setup().await?;
+
+    let a = db.insert_student("U1", "a").await?;
+    let mut b = db.insert_student("U2", "b").await?;
+    let mut film = db
+        .insert_film(&Film::new("film", Priority::High, 0))
+        .await?;
+
+    film.increment_role(&a.name);
+    db.update_film(&film).await?;
+    b.assign("film", Utc::now());
+    db.update_student(&b).await?;
+
+    film.reopen(Role::Ae);
+    let revision = Revision {
+        id: uuid::Uuid::new_v4(),
+        film_name: film.name.clone(),
+        role: Role::Ae,
+        from: b.slack_id.clone(),
+        to: a.slack_id.clone(),
+        note: "audio is missing".to_string(),
+        created_at: Utc::now(),
+        resolved_at: None,
+    };
+    db.send_back(&film, &revision, &change(AuditAction::SendBack))
+        .await?;
+
+    let b = db.get_student("U2").await?;
+    assert!(b.paused);
+    let film = db.get_film("film").await?.unwrap();
+    assert_eq!(Role::Ae, film.current_role);
+    assert_eq!(None, film.roles.ae);
+
+    let mut r = db.get_revision("U1").await?.unwrap();
+    r.created_at = revision.created_at;
+    assert_eq!(revision, r);
+    assert!(db.get_revision("U2").await?.is_none());
+
+    let mut film = film;
+    film.increment_role(&a.name);
+    db.resolve_revision(&film, &revision, None, &change(AuditAction::Deliver))
+        .await?;
+
+    let b = db.get_student("U2").await?;
+    assert!(!b.paused);
+    assert_eq!(Some("film".to_string()), b.current_film);
+    let film = db.get_film("film").await?.unwrap();
+    assert_eq!(Role::Editor, film.current_role);
+    assert_eq!(Some("a".to_string()), film.roles.ae);
+    assert!(db.get_revision("U1").await?.is_none());
+
+    Ok(())
+}
+
+async fn deliveries(backend: Backend) -> Result<()> {
+    let db = backend.setup().await?;
+
+    let student = db.insert_student("U1", "a").await?;
+    let film = db
+        .insert_film(&Film::new("film", Priority::High, 0))
+        .await?;
+
+    let mut delivery = Delivery {
+        id: uuid::Uuid::new_v4(),
+        film_name: "film".to_string(),
+        role: Role::Ae,
+        student: "U1".to_string(),
+        links: vec!["https://a.com".to_string(), "https://b.com".to_string()],
+        notes: "first cut".to_string(),
+        created_at: Utc::now() - chrono::Duration::hours(1),
+    };
+    db.deliver_film(
+        &student,
+        &film,
+        None,
+        Some(&delivery),
+        &change(AuditAction::Deliver),
+    )
+    .await?;
+
+    // Only the latest delivery of a stage is handed on.
+    delivery.id = uuid::Uuid::new_v4();
+    delivery.notes = "fixed the audio".to_string();
+    delivery.created_at = Utc::now();
+    db.deliver_film(
+        &student,
+        &film,
+        None,
+        Some(&delivery),
+        &change(AuditAction::Deliver),
+    )
+    .await?;
+
+    let mut d = db.get_delivery("film", Role::Ae).await?.unwrap();
+    d.created_at = delivery.created_at;
+    assert_eq!(delivery, d);
+    assert!(db.get_delivery("film", Role::Editor).await?.is_none());
+
+    // A delivery that fails leaves nothing behind for the next stage.
+    let job = QueueItem {
+        id: uuid::Uuid::new_v4(),
+        student_slack_id: "".to_string(),
+        film_name: "film".to_string(),
+        role: Role::Editor,
+        priority: Some(Priority::High),
+        effective_priority: None,
+        round: None,
+        deadline: None,
+        group_number: Some(0),
+        class: None,
+        msg_ts: None,
+        channel: None,
+        created_at: Utc::now(),
+    };
+    db.insert_to_queue(job.clone(), false, None).await?;
+    let mut failed = delivery.clone();
+    failed.id = uuid::Uuid::new_v4();
+    failed.role = Role::Editor;
+    let res = db
+        .deliver_film(
+            &student,
+            &film,
+            Some(&job),
+            Some(&failed),
+            &change(AuditAction::Deliver),
+        )
+        .await;
+    assert!(res.is_err());
+    assert!(db.get_delivery("film", Role::Editor).await?.is_none());
+
+    Ok(())
+}
+
+async fn history(backend: Backend) -> Result<()> {
+    let db = backend.setup().await?;
+
+    let mut a = db.insert_student("U1", "a").await?;
+    let film = db
+        .insert_film(&Film::new("film", Priority::High, 0))
+        .await?;
+    let job = QueueItem {
+        id: uuid::Uuid::new_v4(),
+        student_slack_id: "".to_string(),
+        film_name: film.name.clone(),
+        role: Role::Ae,
+        group_number: Some(0),
+        class: None,
+        priority: Some(Priority::High),
+        effective_priority: None,
+        round: None,
+        deadline: None,
+        msg_ts: None,
+        channel: None,
+        created_at: Utc::now(),
+    };
+    db.insert_to_queue(job.clone(), false, None).await?;
+    let waiter = QueueItem {
+        id: uuid::Uuid::new_v4(),
+        student_slack_id: "U1".to_string(),
+        film_name: "".to_string(),
+        ..job.clone()
+    };
+    db.insert_to_queue(waiter, true, None).await?;
+    let waiting = db.get_queue(true).await?[0].created_at;
+    db.assign_film(&a, &film, &job, &change(AuditAction::Assign))
+        .await?;
+
+    let history = db.get_student_history("U1").await?;
+    assert_eq!(1, history.len());
+    assert_eq!(None, history[0].outcome);
+    assert_eq!(Role::Ae, history[0].role);
+    assert_eq!(a.id, history[0].student_id);
+    // Assigning takes the student out of the wait_q, and keeps when they joined both queues.
+    assert!(db.get_queue(true).await?.is_empty());
+    let close = |at: Option<DateTime<Utc>>, want: DateTime<Utc>| {
+        at.map_or(false, |at| (at - want).num_milliseconds().abs() < 1)
+    };
+    assert!(close(history[0].queued_at, job.created_at));
+    assert!(close(history[0].waiting_since, waiting));
+
+    // Deliver, and queue up the next stage.
+    let mut film = db.get_film("film").await?.unwrap();
+    film.increment_role(&a.name);
+    a.increment_role(&film.name);
+    a.unassign();
+    let mut next = job.clone();
+    next.id = uuid::Uuid::new_v4();
+    next.role = Role::Editor;
+    db.deliver_film(&a, &film, Some(&next), None, &change(AuditAction::Deliver))
+        .await?;
+
+    let history = db.get_film_history("film").await?;
+    assert_eq!(1, history.len());
+    assert_eq!(Some(Outcome::Delivered), history[0].outcome);
+    assert!(history[0].delivered_at.is_some());
+    assert_eq!(1, db.get_queue(false).await?.len());
+    assert_eq!(history, db.list_assignments().await?);
+
+    // Who worked what is read back from the history.
+    let film = db.get_film("film").await?.unwrap();
+    assert_eq!(Some("a".to_string()), film.roles.ae);
+    assert_eq!(Role::Editor, film.current_role);
+    let a = db.get_student("U1").await?;
+    assert_eq!(Some("film".to_string()), a.roles.ae);
+    assert_eq!(None, a.current_film);
+
+    Ok(())
+}
+
+async fn audit(backend: Backend) -> Result<()> {
+    let db = backend.setup().await?;
+
+    let entry = |action, student: Option<&str>, film: Option<&str>| AuditEntry {
+        id: uuid::Uuid::new_v4(),
+        action,
+        actor: "U1".to_string(),
+        source: "slack:Ev1".to_string(),
+        student: student.map(str::to_string),
+        film: film.map(str::to_string),
+        before: None,
+        after: Some(serde_json::json!({ "film_name": "film" })),
+        created_at: Utc::now(),
+    };
+    db.insert_audit(&entry(AuditAction::Enqueue, None, Some("film")))
+        .await?;
+    db.insert_audit(&entry(AuditAction::Assign, Some("U1"), Some("film")))
+        .await?;
+    db.insert_audit(&entry(AuditAction::Wait, Some("U2"), None))
+        .await?;
+
+    // Newest first.
+    let log = db.get_audit_log(None, Some("film"), 10).await?;
+    assert_eq!(2, log.len());
+    assert_eq!(AuditAction::Assign, log[0].action);
+    assert_eq!(
+        Some(serde_json::json!({ "film_name": "film" })),
+        log[0].after
+    );
+
+    let log = db.get_audit_log(Some("U2"), None, 10).await?;
+    assert_eq!(1, log.len());
+    assert_eq!(AuditAction::Wait, log[0].action);
+    assert_eq!(1, db.get_audit_log(None, None, 1).await?.len());
+
+    // The log can only be appended to.
+    assert!(backend.execute("DELETE FROM audit_log").await.is_err());
+    assert!(backend
+        .execute("UPDATE audit_log SET actor = 'U2'")
+        .await
+        .is_err());
+
+    Ok(())
+}
+
+async fn integrity(backend: Backend) -> Result<()> {
+    let db = backend.setup().await?;
+
+    let a = db.insert_student("U1", "a").await?;
+    let film = db.insert_film(&Film::new("a", Priority::High, 0)).await?;
+    db.insert_film(&Film::new("b", Priority::High, 0)).await?;
+
+    let job = |film: &str, slack_id: &str| QueueItem {
+        id: uuid::Uuid::new_v4(),
+        student_slack_id: slack_id.to_string(),
+        film_name: film.to_string(),
+        role: Role::Ae,
+        group_number: Some(0),
+        class: None,
+        priority: Some(Priority::High),
+        effective_priority: None,
+        round: None,
+        deadline: None,
+        msg_ts: None,
+        channel: None,
+        created_at: Utc::now(),
+    };
+    let assigned = job("a", "");
+    db.insert_to_queue(assigned.clone(), false, None).await?;
+    db.assign_film(&a, &film, &assigned, &change(AuditAction::Assign))
+        .await?;
+    // Only film b is out of place so far.
+    assert_eq!(1, integrity::check(&db, None).await?.findings.len());
+
+    // Film b was never queued, nobody made film c, and U1 is already working.
+    let orphan = db.insert_to_queue(job("c", ""), false, None).await?;
+    let waiter = db.insert_to_queue(job("", "U1"), true, None).await?;
+
+    let repair = change(AuditAction::Repair);
+    let report = integrity::check(&db, Some(&repair)).await?;
+    let issues: Vec<_> = report.findings.iter().map(|f| f.issue.clone()).collect();
+    assert_eq!(
+        vec![
+            Issue::OrphanJob {
+                id: orphan.id,
+                film: "c".to_string()
+            },
+            Issue::Unqueued {
+                film: "b".to_string(),
+                role: Role::Ae
+            },
+            Issue::AssignedWaiter {
+                id: waiter.id,
+                student: "U1".to_string(),
+                film: "a".to_string()
+            },
+        ],
+        issues
+    );
+    assert_eq!(3, report.repaired());
+    let audited = db.get_audit_log(None, None, 100).await?;
+    let repairs = audited.iter().filter(|e| e.action == AuditAction::Repair);
+    assert_eq!(3, repairs.count());
+
+    // Repairs re-read their rows first, so ones which have already been made change nothing.
+    for issue in &issues {
+        assert!(!db.repair(issue, &repair).await?);
+    }
+    assert_eq!(
+        audited.len(),
+        db.get_audit_log(None, None, 100).await?.len()
+    );
+    assert!(integrity::check(&db, None).await?.is_clean());
+    let jobs = db.get_queue(false).await?;
+    assert_eq!(
+        vec!["b"],
+        jobs.iter()
+            .map(|j| j.film_name.as_str())
+            .collect::<Vec<_>>()
+    );
+    assert!(db.get_queue(true).await?.is_empty());
+
+    // A job for a film U1 is working on, and a second job for film b.
+    let held = db.insert_to_queue(job("a", ""), false, None).await?;
+    let duplicate = db.insert_to_queue(job("b", ""), false, None).await?;
+    let report = integrity::check(&db, Some(&repair)).await?;
+    let issues: Vec<_> = report.findings.iter().map(|f| f.issue.clone()).collect();
+    assert_eq!(
+        vec![
+            Issue::HeldJob {
+                id: held.id,
+                film: "a".to_string(),
+                student: "U1".to_string()
+            },
+            Issue::DuplicateJob {
+                id: duplicate.id,
+                film: "b".to_string()
+            },
+        ],
+        issues
+    );
+    assert_eq!(2, report.repaired());
+    assert!(integrity::check(&db, None).await?.is_clean());
+
+    Ok(())
+}
+
+async fn schema(backend: Backend) -> Result<()> {
+    let db = backend.setup().await?;
+    db.ping().await?;
+    let version = db.schema_version().await?;
+    assert!(version.is_current());
+    assert!(version.applied > 0);
+
+    // Forget the migrations were ever applied, then apply them again.
+    match backend {
+        Backend::Postgres => backend.execute("DELETE FROM schema_version;").await?,
+        Backend::Sqlite(_) => backend.execute("PRAGMA user_version = 0;").await?,
+    }
+    let behind = db.schema_version().await?;
+    assert_eq!(0, behind.applied);
+    assert!(!behind.is_current());
+
+    db.migrate().await?;
+    assert_eq!(version, db.schema_version().await?);
+
+    Ok(())
+}
+
+async fn events(backend: Backend) -> Result<()> {
+    let db = backend.setup().await?;
+
+    let now = Utc::now().date().and_hms(12, 0, 0);
+    let event = |id: &str, minutes: i64| PendingEvent {
+        id: id.to_string(),
+        user: "U1".to_string(),
+        payload: serde_json::json!({ "event_id": id }),
+        created_at: now + chrono::Duration::minutes(minutes),
+    };
+    assert!(db.insert_event(&event("b", 1), "s1").await?);
+    assert!(db.insert_event(&event("a", 0), "s1").await?);
+    // Slack retrying an event doesn't save it twice.
+    assert!(!db.insert_event(&event("a", 2), "s1").await?);
+
+    let events = db.list_events().await?;
+    assert_eq!(vec![event("a", 0), event("b", 1)], events);
+
+    // Another server can't take events over until the claim on them has expired, and then
+    // only one server gets them.
+    let past = Utc::now() - chrono::Duration::minutes(5);
+    assert!(db.claim_events("s2", past).await?.is_empty());
+    let later = Utc::now() + chrono::Duration::seconds(1);
+    assert_eq!(events, db.claim_events("s2", later).await?);
+    assert!(db.claim_events("s3", past).await?.is_empty());
+
+    // Renewed claims don't expire, and released ones are free to be taken at once.
+    let renewed = Utc::now();
+    db.renew_events("s2").await?;
+    assert!(db.claim_events("s3", renewed).await?.is_empty());
+    assert_eq!(2, db.release_events("s2").await?);
+    assert_eq!(events, db.claim_events("s3", past).await?);
+    assert!(db.claim_events("s2", past).await?.is_empty());
+
+    // Handled events are kept, so retries are still ignored, until they're old enough to go.
+    db.finish_event("a", past).await?;
+    let ids: Vec<_> = db.list_events().await?.into_iter().map(|e| e.id).collect();
+    assert_eq!(vec!["b"], ids);
+    assert!(!db.insert_event(&event("a", 2), "s1").await?);
+    assert!(db
+        .claim_events("s3", later)
+        .await?
+        .iter()
+        .all(|e| e.id == "b"));
+
+    db.finish_event("b", later).await?;
+    assert!(db.list_events().await?.is_empty());
+    assert!(db.insert_event(&event("a", 3), "s1").await?);
+
+    Ok(())
+}
+
+async fn rate_limits(backend: Backend) -> Result<()> {
+    let db = backend.setup().await?;
+    let now = Utc::now();
+    let hours = |h: i64| now - chrono::Duration::hours(h);
+
+    db.insert_use("U1", "DROP", hours(30), hours(54)).await?;
+    db.insert_use("U1", "DROP", hours(2), hours(26)).await?;
+    db.insert_use("U1", "CANCEL", hours(1), hours(25)).await?;
+    db.insert_use("U2", "DROP", hours(1), hours(25)).await?;
+
+    // Only uses since the given time count, oldest first.
+    let uses = db.list_uses("U1", "DROP", hours(24)).await?;
+    assert_eq!(1, uses.len());
+    assert!((uses[0] - hours(2)).num_milliseconds().abs() < 1);
+
+    // Recording a use forgets the ones which have expired.
+    db.insert_use("U1", "DROP", now, hours(24)).await?;
+    assert_eq!(2, db.list_uses("U1", "DROP", hours(48)).await?.len());
+    assert_eq!(1, db.list_uses("U1", "CANCEL", hours(48)).await?.len());
+
+    Ok(())
+}
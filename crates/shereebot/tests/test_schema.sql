@@ -21,6 +21,15 @@ DO $schema$ BEGIN
         name            TEXT NOT NULL UNIQUE,
         priority        TEXT NOT NULL DEFAULT 'HIGH',
         group_number    INTEGER NOT NULL DEFAULT 0,
+        class           TEXT NOT NULL DEFAULT '',
+        due_date        TIMESTAMPTZ,
+        -- Target hours for each stage.
+        ae_hours        INTEGER,
+        editor_hours    INTEGER,
+        sound_hours     INTEGER,
+        finish_hours    INTEGER,
+        -- When the film joined the jobs queue for its current stage.
+        queued_at       TIMESTAMPTZ,
         created_at      TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
         updated_at      TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
     );
@@ -33,6 +42,11 @@ DO $schema$ BEGIN
         current_film    TEXT,
         group_number    INTEGER NOT NULL DEFAULT 0,
         class           TEXT NOT NULL DEFAULT '0',
+        assigned_at     TIMESTAMPTZ,
+        -- Reminders and escalations sent for the current film.
+        escalation      INTEGER NOT NULL DEFAULT 0,
+        -- Set while the current film is sent back to an earlier stage.
+        paused          BOOLEAN NOT NULL DEFAULT FALSE,
         created_at      TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
         updated_at      TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
     );
@@ -45,6 +59,11 @@ DO $schema$ BEGIN
         priority            TEXT DEFAULT 'High',
         msg_ts              TEXT, -- not relevant
         channel             TEXT, -- not relevant
+        -- Latest time work can start without missing the film's due date.
+        deadline            TIMESTAMPTZ,
+        -- The film's group and class, to keep the queue fair between groups.
+        group_number        INTEGER,
+        class               TEXT,
         created_at          TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
     );
 
@@ -60,6 +79,32 @@ DO $schema$ BEGIN
     );
 
 
+    ---- Migrations ----
+    -- Bring tables created by older versions of this schema up to date.
+
+    ALTER TABLE films ADD COLUMN IF NOT EXISTS due_date TIMESTAMPTZ;
+    ALTER TABLE films ADD COLUMN IF NOT EXISTS ae_hours INTEGER;
+    ALTER TABLE films ADD COLUMN IF NOT EXISTS editor_hours INTEGER;
+    ALTER TABLE films ADD COLUMN IF NOT EXISTS sound_hours INTEGER;
+    ALTER TABLE films ADD COLUMN IF NOT EXISTS finish_hours INTEGER;
+    ALTER TABLE jobs_q ADD COLUMN IF NOT EXISTS deadline TIMESTAMPTZ;
+    ALTER TABLE films ADD COLUMN IF NOT EXISTS class TEXT NOT NULL DEFAULT '';
+    ALTER TABLE jobs_q ADD COLUMN IF NOT EXISTS group_number INTEGER;
+    ALTER TABLE jobs_q ADD COLUMN IF NOT EXISTS class TEXT;
+    ALTER TABLE students ADD COLUMN IF NOT EXISTS assigned_at TIMESTAMPTZ;
+    ALTER TABLE students ADD COLUMN IF NOT EXISTS escalation INTEGER NOT NULL DEFAULT 0;
+    ALTER TABLE films ADD COLUMN IF NOT EXISTS queued_at TIMESTAMPTZ;
+    ALTER TABLE students ADD COLUMN IF NOT EXISTS paused BOOLEAN NOT NULL DEFAULT FALSE;
+
+    -- Bump this along with SCHEMA_VERSION in the postgres store whenever a migration is added,
+    -- so a running server can tell whether its database is up to date.
+    CREATE TABLE IF NOT EXISTS schema_version (
+        version         INTEGER NOT NULL
+    );
+    DELETE FROM schema_version;
+    INSERT INTO schema_version VALUES (5);
+
+
     ---- Join tables ----
 
     CREATE TABLE IF NOT EXISTS students_films (
@@ -70,6 +115,147 @@ DO $schema$ BEGIN
     );
 
 
+    ---- History ----
+
+    -- Films sent back to an earlier stage because it wasn't finished.
+    CREATE TABLE IF NOT EXISTS revisions (
+        id              UUID PRIMARY KEY,
+        film_id         UUID REFERENCES films,
+        role            TEXT NOT NULL,
+        -- The student holding the film, and the one who worked `role`.
+        from_student    UUID REFERENCES students,
+        to_student      UUID REFERENCES students,
+        note            TEXT NOT NULL DEFAULT '',
+        created_at      TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+        resolved_at     TIMESTAMPTZ
+    );
+
+    -- One student's turn working one stage of a film.
+    CREATE TABLE IF NOT EXISTS assignments (
+        id              UUID PRIMARY KEY,
+        student_id      UUID REFERENCES students,
+        film_id         UUID REFERENCES films,
+        role            TEXT NOT NULL,
+        assigned_at     TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+        -- When the student handed the film on, however that happened.
+        delivered_at    TIMESTAMPTZ,
+        -- DELIVERED, SENT_BACK, RELEASED or REASSIGNED. NULL while in progress.
+        outcome         TEXT,
+        -- When the job joined the jobs_q, and when the student joined the wait_q, if they did.
+        queued_at       TIMESTAMPTZ,
+        waiting_since   TIMESTAMPTZ
+    );
+    -- Added in version 4. Migrated here, as the table's created after the migrations above.
+    ALTER TABLE assignments ADD COLUMN IF NOT EXISTS queued_at TIMESTAMPTZ;
+    ALTER TABLE assignments ADD COLUMN IF NOT EXISTS waiting_since TIMESTAMPTZ;
+
+    -- What each student handed on to the next stage.
+    CREATE TABLE IF NOT EXISTS deliveries (
+        id              UUID PRIMARY KEY,
+        film_id         UUID REFERENCES films,
+        role            TEXT NOT NULL,
+        student_id      UUID REFERENCES students,
+        links           TEXT[] NOT NULL DEFAULT '{}',
+        notes           TEXT NOT NULL DEFAULT '',
+        created_at      TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+    );
+
+
+    -- Append-only record of every change to the bot's state, and who made it.
+    CREATE TABLE IF NOT EXISTS audit_log (
+        id              UUID PRIMARY KEY,
+        action          TEXT NOT NULL,
+        -- Slack id of whoever acted, or the part of the bot acting on its own.
+        actor           TEXT NOT NULL,
+        -- The Slack event or API call the change came from.
+        source          TEXT NOT NULL,
+        -- Slack id of the student affected, and the film.
+        student         TEXT,
+        film            TEXT,
+        before          JSONB,
+        after           JSONB,
+        created_at      TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+    );
+
+
+    ---- Events ----
+
+    -- Slack events which have been acknowledged, so they survive a restart. Handled events are
+    -- kept for a while, so Slack's late retries of them are still recognised.
+    CREATE TABLE IF NOT EXISTS slack_events (
+        -- Slack's event id, which its retries share.
+        id              TEXT PRIMARY KEY,
+        slack_user      TEXT NOT NULL,
+        payload         JSONB NOT NULL,
+        created_at      TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+        handled_at      TIMESTAMPTZ,
+        -- Which server is handling the event, and since when. Another only takes it over once
+        -- the claim has expired.
+        claimed_by      TEXT,
+        claimed_at      TIMESTAMPTZ
+    );
+    -- Added in version 5. Migrated here, as the table's created after the migrations above.
+    ALTER TABLE slack_events ADD COLUMN IF NOT EXISTS handled_at TIMESTAMPTZ;
+    ALTER TABLE slack_events ADD COLUMN IF NOT EXISTS claimed_by TEXT;
+    ALTER TABLE slack_events ADD COLUMN IF NOT EXISTS claimed_at TIMESTAMPTZ;
+
+
+    ---- Rate limits ----
+
+    -- When students took actions which are rate limited, so every instance shares the limits
+    -- and they survive a deploy. Uses older than the limit's window are cleared out as new ones
+    -- are recorded.
+    CREATE TABLE IF NOT EXISTS rate_limit_uses (
+        slack_id        TEXT NOT NULL,
+        action          TEXT NOT NULL,
+        used_at         TIMESTAMPTZ NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS rate_limit_uses_idx ON rate_limit_uses (slack_id, action);
+
+
+    ---- Views ----
+    RAISE INFO 'Creating views';
+
+    -- Roles, with who worked each stage taken from the assignment history where it's known.
+    CREATE OR REPLACE VIEW film_roles AS
+        SELECT r.id, r.current,
+               COALESCE(h.ae, r.ae) as ae,
+               COALESCE(h.editor, r.editor) as editor,
+               COALESCE(h.sound, r.sound) as sound,
+               COALESCE(h.finish, r.finish) as finish
+        FROM films as f
+            JOIN roles as r ON f.roles_id = r.id
+            LEFT JOIN (
+                SELECT a.film_id,
+                       MAX(s.name) FILTER (WHERE a.role = 'AE') as ae,
+                       MAX(s.name) FILTER (WHERE a.role = 'EDITOR') as editor,
+                       MAX(s.name) FILTER (WHERE a.role = 'SOUND') as sound,
+                       MAX(s.name) FILTER (WHERE a.role = 'FINISH') as finish
+                FROM assignments as a JOIN students as s ON a.student_id = s.id
+                WHERE a.outcome = 'DELIVERED'
+                GROUP BY a.film_id
+            ) as h ON h.film_id = f.id;
+
+    CREATE OR REPLACE VIEW student_roles AS
+        SELECT r.id, r.current,
+               COALESCE(h.ae, r.ae) as ae,
+               COALESCE(h.editor, r.editor) as editor,
+               COALESCE(h.sound, r.sound) as sound,
+               COALESCE(h.finish, r.finish) as finish
+        FROM students as s
+            JOIN roles as r ON s.roles_id = r.id
+            LEFT JOIN (
+                SELECT a.student_id,
+                       MAX(f.name) FILTER (WHERE a.role = 'AE') as ae,
+                       MAX(f.name) FILTER (WHERE a.role = 'EDITOR') as editor,
+                       MAX(f.name) FILTER (WHERE a.role = 'SOUND') as sound,
+                       MAX(f.name) FILTER (WHERE a.role = 'FINISH') as finish
+                FROM assignments as a JOIN films as f ON a.film_id = f.id
+                WHERE a.outcome = 'DELIVERED'
+                GROUP BY a.student_id
+            ) as h ON h.student_id = s.id;
+
+
     ---- Indices ----
     RAISE INFO 'Creating indices';
 
@@ -79,6 +265,18 @@ DO $schema$ BEGIN
     CREATE INDEX IF NOT EXISTS std_slack_id_idx
         ON students(slack_id);
 
+    CREATE INDEX IF NOT EXISTS assignments_film_idx
+        ON assignments(film_id);
+
+    CREATE INDEX IF NOT EXISTS assignments_student_idx
+        ON assignments(student_id);
+
+    CREATE INDEX IF NOT EXISTS audit_log_student_idx
+        ON audit_log(student, created_at);
+
+    CREATE INDEX IF NOT EXISTS audit_log_film_idx
+        ON audit_log(film, created_at);
+
 
     ---- Triggers ----
     RAISE INFO 'Creating triggers';
@@ -108,6 +306,19 @@ DO $schema$ BEGIN
         ON students 
         FOR EACH ROW
         EXECUTE PROCEDURE update_timestamp();
+
+    CREATE OR REPLACE FUNCTION reject_audit_log_change()
+    RETURNS TRIGGER AS $$
+    BEGIN
+        RAISE EXCEPTION 'audit_log is append-only';
+    END;
+    $$ language 'plpgsql';
+
+    DROP TRIGGER IF EXISTS audit_log_append_only ON audit_log;
+    CREATE TRIGGER audit_log_append_only BEFORE UPDATE OR DELETE
+        ON audit_log
+        FOR EACH ROW
+        EXECUTE PROCEDURE reject_audit_log_change();
 END $schema$;
 
 TRUNCATE TABLE roles CASCADE;
@@ -115,4 +326,10 @@ TRUNCATE TABLE films CASCADE;
 TRUNCATE TABLE jobs_q CASCADE;
 TRUNCATE TABLE wait_q CASCADE;
 TRUNCATE TABLE students_films CASCADE;
+TRUNCATE TABLE revisions CASCADE;
+TRUNCATE TABLE deliveries CASCADE;
+TRUNCATE TABLE assignments CASCADE;
+TRUNCATE TABLE audit_log CASCADE;
+TRUNCATE TABLE slack_events CASCADE;
+TRUNCATE TABLE rate_limit_uses CASCADE;
 TRUNCATE TABLE students CASCADE;
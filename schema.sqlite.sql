@@ -0,0 +1,246 @@
+---- Schema ----
+-- The SQLite equivalent of schema.sql, applied by the bot itself as migration 1.
+-- UUIDs are stored as 16 byte blobs, timestamps as RFC 3339 text and booleans as 0 or 1.
+
+---- Tables ----
+
+CREATE TABLE IF NOT EXISTS roles (
+    id              BLOB PRIMARY KEY,
+    current         TEXT NOT NULL DEFAULT 'AE',
+    -- The film/student who worked this role.
+    ae              TEXT,
+    editor          TEXT,
+    sound           TEXT,
+    finish          TEXT,
+    updated_at      TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE IF NOT EXISTS films (
+    id              BLOB PRIMARY KEY,
+    roles_id        BLOB REFERENCES roles,
+    name            TEXT NOT NULL UNIQUE,
+    priority        TEXT NOT NULL DEFAULT 'HIGH',
+    group_number    INTEGER NOT NULL DEFAULT 0,
+    class           TEXT NOT NULL DEFAULT '',
+    due_date        TEXT,
+    -- Target hours for each stage.
+    ae_hours        INTEGER,
+    editor_hours    INTEGER,
+    sound_hours     INTEGER,
+    finish_hours    INTEGER,
+    -- When the film joined the jobs queue for its current stage.
+    queued_at       TEXT,
+    created_at      TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at      TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE IF NOT EXISTS students (
+    id              BLOB PRIMARY KEY,
+    name            TEXT NOT NULL DEFAULT '',
+    roles_id        BLOB REFERENCES roles,
+    slack_id        TEXT NOT NULL DEFAULT '',
+    current_film    TEXT,
+    group_number    INTEGER NOT NULL DEFAULT 0,
+    class           TEXT NOT NULL DEFAULT '0',
+    assigned_at     TEXT,
+    -- Reminders and escalations sent for the current film.
+    escalation      INTEGER NOT NULL DEFAULT 0,
+    -- Set while the current film is sent back to an earlier stage.
+    paused          INTEGER NOT NULL DEFAULT 0,
+    created_at      TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at      TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE IF NOT EXISTS jobs_q (
+    id                  BLOB PRIMARY KEY,
+    student_slack_id    TEXT NOT NULL,
+    film_name           TEXT NOT NULL,
+    role                TEXT NOT NULL,
+    priority            TEXT DEFAULT 'High',
+    msg_ts              TEXT, -- not relevant
+    channel             TEXT, -- not relevant
+    -- Latest time work can start without missing the film's due date.
+    deadline            TEXT,
+    -- The film's group and class, to keep the queue fair between groups.
+    group_number        INTEGER,
+    class               TEXT,
+    created_at          TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE IF NOT EXISTS wait_q (
+    id                  BLOB PRIMARY KEY,
+    student_slack_id    TEXT NOT NULL,
+    film_name           TEXT NOT NULL,
+    role                TEXT NOT NULL,
+    priority            TEXT, -- not relevant
+    msg_ts              TEXT,
+    channel             TEXT,
+    created_at          TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+
+---- Join tables ----
+
+CREATE TABLE IF NOT EXISTS students_films (
+    student_id  BLOB REFERENCES students,
+    film_id     BLOB REFERENCES films,
+    role        TEXT,
+    CONSTRAINT students_films_pk PRIMARY KEY (student_id, film_id)
+);
+
+
+---- History ----
+
+-- Films sent back to an earlier stage because it wasn't finished.
+CREATE TABLE IF NOT EXISTS revisions (
+    id              BLOB PRIMARY KEY,
+    film_id         BLOB REFERENCES films,
+    role            TEXT NOT NULL,
+    -- The student holding the film, and the one who worked `role`.
+    from_student    BLOB REFERENCES students,
+    to_student      BLOB REFERENCES students,
+    note            TEXT NOT NULL DEFAULT '',
+    created_at      TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    resolved_at     TEXT
+);
+
+-- One student's turn working one stage of a film.
+CREATE TABLE IF NOT EXISTS assignments (
+    id              BLOB PRIMARY KEY,
+    student_id      BLOB REFERENCES students,
+    film_id         BLOB REFERENCES films,
+    role            TEXT NOT NULL,
+    assigned_at     TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    -- When the student handed the film on, however that happened.
+    delivered_at    TEXT,
+    -- DELIVERED, SENT_BACK, RELEASED or REASSIGNED. NULL while in progress.
+    outcome         TEXT
+);
+
+-- What each student handed on to the next stage.
+CREATE TABLE IF NOT EXISTS deliveries (
+    id              BLOB PRIMARY KEY,
+    film_id         BLOB REFERENCES films,
+    role            TEXT NOT NULL,
+    student_id      BLOB REFERENCES students,
+    -- A JSON array of links.
+    links           TEXT NOT NULL DEFAULT '[]',
+    notes           TEXT NOT NULL DEFAULT '',
+    created_at      TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+
+-- Append-only record of every change to the bot's state, and who made it.
+CREATE TABLE IF NOT EXISTS audit_log (
+    id              BLOB PRIMARY KEY,
+    action          TEXT NOT NULL,
+    -- Slack id of whoever acted, or the part of the bot acting on its own.
+    actor           TEXT NOT NULL,
+    -- The Slack event or API call the change came from.
+    source          TEXT NOT NULL,
+    -- Slack id of the student affected, and the film.
+    student         TEXT,
+    film            TEXT,
+    before          TEXT,
+    after           TEXT,
+    created_at      TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+
+---- Views ----
+
+-- Roles, with who worked each stage taken from the assignment history where it's known.
+CREATE VIEW IF NOT EXISTS film_roles AS
+    SELECT r.id, r.current,
+           COALESCE(h.ae, r.ae) as ae,
+           COALESCE(h.editor, r.editor) as editor,
+           COALESCE(h.sound, r.sound) as sound,
+           COALESCE(h.finish, r.finish) as finish
+    FROM films as f
+        JOIN roles as r ON f.roles_id = r.id
+        LEFT JOIN (
+            SELECT a.film_id,
+                   MAX(s.name) FILTER (WHERE a.role = 'AE') as ae,
+                   MAX(s.name) FILTER (WHERE a.role = 'EDITOR') as editor,
+                   MAX(s.name) FILTER (WHERE a.role = 'SOUND') as sound,
+                   MAX(s.name) FILTER (WHERE a.role = 'FINISH') as finish
+            FROM assignments as a JOIN students as s ON a.student_id = s.id
+            WHERE a.outcome = 'DELIVERED'
+            GROUP BY a.film_id
+        ) as h ON h.film_id = f.id;
+
+CREATE VIEW IF NOT EXISTS student_roles AS
+    SELECT r.id, r.current,
+           COALESCE(h.ae, r.ae) as ae,
+           COALESCE(h.editor, r.editor) as editor,
+           COALESCE(h.sound, r.sound) as sound,
+           COALESCE(h.finish, r.finish) as finish
+    FROM students as s
+        JOIN roles as r ON s.roles_id = r.id
+        LEFT JOIN (
+            SELECT a.student_id,
+                   MAX(f.name) FILTER (WHERE a.role = 'AE') as ae,
+                   MAX(f.name) FILTER (WHERE a.role = 'EDITOR') as editor,
+                   MAX(f.name) FILTER (WHERE a.role = 'SOUND') as sound,
+                   MAX(f.name) FILTER (WHERE a.role = 'FINISH') as finish
+            FROM assignments as a JOIN films as f ON a.film_id = f.id
+            WHERE a.outcome = 'DELIVERED'
+            GROUP BY a.student_id
+        ) as h ON h.student_id = s.id;
+
+
+---- Indices ----
+
+CREATE UNIQUE INDEX IF NOT EXISTS film_name_idx
+    ON films(name);
+
+CREATE INDEX IF NOT EXISTS std_slack_id_idx
+    ON students(slack_id);
+
+CREATE INDEX IF NOT EXISTS assignments_film_idx
+    ON assignments(film_id);
+
+CREATE INDEX IF NOT EXISTS assignments_student_idx
+    ON assignments(student_id);
+
+CREATE INDEX IF NOT EXISTS audit_log_student_idx
+    ON audit_log(student, created_at);
+
+CREATE INDEX IF NOT EXISTS audit_log_film_idx
+    ON audit_log(film, created_at);
+
+
+---- Triggers ----
+
+CREATE TRIGGER IF NOT EXISTS auto_update_films_timestamp AFTER UPDATE
+    ON films
+    FOR EACH ROW
+    BEGIN
+        UPDATE films SET updated_at = CURRENT_TIMESTAMP WHERE id = NEW.id;
+    END;
+
+CREATE TRIGGER IF NOT EXISTS auto_update_roles_timestamp AFTER UPDATE
+    ON roles
+    FOR EACH ROW
+    BEGIN
+        UPDATE roles SET updated_at = CURRENT_TIMESTAMP WHERE id = NEW.id;
+    END;
+
+CREATE TRIGGER IF NOT EXISTS auto_update_students_timestamp AFTER UPDATE
+    ON students
+    FOR EACH ROW
+    BEGIN
+        UPDATE students SET updated_at = CURRENT_TIMESTAMP WHERE id = NEW.id;
+    END;
+
+CREATE TRIGGER IF NOT EXISTS audit_log_no_update BEFORE UPDATE
+    ON audit_log
+    BEGIN
+        SELECT RAISE(ABORT, 'audit_log is append-only');
+    END;
+
+CREATE TRIGGER IF NOT EXISTS audit_log_no_delete BEFORE DELETE
+    ON audit_log
+    BEGIN
+        SELECT RAISE(ABORT, 'audit_log is append-only');
+    END;